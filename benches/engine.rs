@@ -0,0 +1,77 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use avalon_tg_bot::ai::Strategy;
+use avalon_tg_bot::game::{MAX_PLAYERS, MIN_PLAYERS};
+use avalon_tg_bot::simulation::{self, Strategies};
+
+// Games played before the timing to find the events and the allocations of an average game
+const PROFILED_GAMES: usize = 100;
+
+const STRATEGIES: [(&str, Strategies); 2] = [
+    ("ai", Strategies { good: Strategy::Ai, evil: Strategy::Ai }),
+    ("random", Strategies { good: Strategy::Random, evil: Strategy::Random }),
+];
+
+// Counts the allocations of the whole process. The runtime has a single thread, so
+// the count between two points of a benchmark only has the allocations of the games
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Returns the events of an average game and the allocations per event
+fn profile(runtime: &Runtime, players: usize, strategies: Strategies) -> (u64, f64) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let events = runtime.block_on(async {
+        let mut events = 0;
+        for _ in 0..PROFILED_GAMES {
+            events += simulation::play(players, strategies).await.unwrap().events;
+        }
+        events
+    });
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    ((events / PROFILED_GAMES) as u64, allocations as f64 / events as f64)
+}
+
+// Criterion reports the throughput in events per second, the games differ in length only a little
+fn engine_games(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let mut group = c.benchmark_group("game");
+    for players in MIN_PLAYERS..=MAX_PLAYERS {
+        for (name, strategies) in STRATEGIES {
+            let (events, allocations) = profile(&runtime, players, strategies);
+            println!("{} players, {}: {} events per game, {:.1} allocations per event", players, name, events, allocations);
+
+            group.throughput(Throughput::Elements(events));
+            group.bench_with_input(BenchmarkId::new(name, players), &players, |b, &players| {
+                b.to_async(&runtime).iter(|| simulation::play(players, strategies));
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, engine_games);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+use std::process::Command;
+
+// Commit of the build shown by /about. The builds outside of the git checkout may pass it in GIT_COMMIT
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let commit = std::env::var("GIT_COMMIT").ok()
+        .or_else(|| {
+            let output = Command::new("git").args(["rev-parse", "--short", "HEAD"]).output().ok()?;
+            output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+}
@@ -0,0 +1,18 @@
+#![no_main]
+
+// Run with: cargo +nightly fuzz run game_commands -- -dict=fuzz/commands.dict
+use avalon_tg_bot::{game, script};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte chooses the number of players, the rest is the script of
+// "<seat> <command>" lines sent by the players
+fuzz_target!(|data: &[u8]| {
+    let Some((first, commands)) = data.split_first() else {
+        return;
+    };
+    let players = game::MIN_PLAYERS + *first as usize % (game::MAX_PLAYERS - game::MIN_PLAYERS + 1);
+    let commands = String::from_utf8_lossy(commands);
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(script::run(players, &commands));
+});
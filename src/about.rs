@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use teloxide::prelude::*;
+
+use crate::game::MIN_PLAYERS_FOR_MERMAID;
+use crate::theme::THEMES;
+use crate::BotCtx;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Set by build.rs
+const COMMIT: &str = env!("GIT_COMMIT");
+// Languages of the command descriptions in the Telegram menu
+const LANGUAGES: [&str; 2] = ["en", "ru"];
+
+fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+fn render(uptime: Duration, integrations: &[&str]) -> String {
+    let themes = THEMES.iter().map(|theme| theme.to_string()).collect::<Vec<_>>();
+    let integrations = if integrations.is_empty() { "none".to_string() } else { integrations.join(", ") };
+    format!("🤖 Avalon bot {} (commit {})\n\
+             Mermaid: from {} players, optional\n\
+             Themes: {}\n\
+             Languages: {}\n\
+             Integrations: {}\n\
+             Uptime: {}",
+            VERSION, COMMIT, MIN_PLAYERS_FOR_MERMAID, themes.join(", "), LANGUAGES.join(", "), integrations,
+            format_uptime(uptime))
+}
+
+// The bot and its forks may run as several instances, the reply tells which build answers
+pub async fn handle(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let mut integrations = Vec::new();
+    if ctx.webapp.is_some() {
+        integrations.push("web app");
+    }
+    if ctx.cluster.is_some() {
+        integrations.push("cluster");
+    }
+    ctx.bot.send_message(message.chat.id, render(ctx.started_at.elapsed(), &integrations)).await?;
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_about_has_version_and_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(59)), "0m");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 5 * 60)), "3h 5m");
+        assert_eq!(format_uptime(Duration::from_secs(2 * 86400 + 60)), "2d 0h 1m");
+
+        let about = render(Duration::from_secs(600), &["cluster"]);
+        assert!(about.starts_with(&format!("🤖 Avalon bot {} (commit ", VERSION)));
+        assert!(about.contains("Mermaid: from 7 players"));
+        assert!(about.contains("Languages: en, ru"));
+        assert!(about.ends_with("Integrations: cluster\nUptime: 10m"));
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use teloxide::types::ChatId;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+// Levels accepted by /admin loglevel
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+// Filter of the log which is changed without the restart, so the games in memory are not lost
+pub type LogFilter = reload::Handle<EnvFilter, Registry>;
+
+#[derive(Clone)]
+pub struct AdminConfig {
+    // Owners of the bot instance. Admin commands are disabled if there are none
+    pub admin_ids: Vec<ChatId>,
+    // Set by main when the log is initialized
+    pub log_filter: Option<LogFilter>,
+    // Chat which gets /feedback of the players
+    pub feedback_chat: Option<ChatId>,
+}
+
+impl AdminConfig {
+    pub fn is_admin(&self, chat_id: ChatId) -> bool {
+        self.admin_ids.contains(&chat_id)
+    }
+
+    // The admins get the feedback if there is no chat for it
+    pub fn feedback_chats(&self) -> Vec<ChatId> {
+        match self.feedback_chat {
+            Some(chat) => vec![chat],
+            None => self.admin_ids.clone(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum AdminCommand {
+    Games,
+    Stop(u32),
+    Broadcast(String),
+    LogLevel(String),
+    // Archives the leaderboard and starts the stats from zero
+    CloseSeason,
+    // Average rating of each feature set, see rating.rs
+    Ratings,
+}
+
+impl AdminCommand {
+    // Parses "games", "stop <id>", "broadcast <text>", "loglevel <level>", "close_season" or "ratings"
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let args = args.trim();
+        let (command, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let rest = rest.trim();
+        match command {
+            "games" => Ok(AdminCommand::Games),
+            "stop" => rest.parse::<u32>()
+                .map(AdminCommand::Stop)
+                .map_err(|_| format!("Invalid game id '{}'", rest)),
+            "broadcast" if !rest.is_empty() => Ok(AdminCommand::Broadcast(rest.to_string())),
+            "broadcast" => Err("Specify the message to broadcast".to_string()),
+            "loglevel" if LOG_LEVELS.contains(&rest) => Ok(AdminCommand::LogLevel(rest.to_string())),
+            "loglevel" => Err(format!("Log level is one of: {}", LOG_LEVELS.join(", "))),
+            "close_season" => Ok(AdminCommand::CloseSeason),
+            "ratings" => Ok(AdminCommand::Ratings),
+            _ => Err("Usage: /admin games | stop <id> | broadcast <text> | loglevel <level> | close_season | ratings".to_string()),
+        }
+    }
+}
+
+pub struct SessionSummary {
+    pub id: u32,
+    pub age: Duration,
+    pub players: usize,
+    pub started: bool,
+}
+
+pub fn render_games(sessions: &[SessionSummary]) -> String {
+    if sessions.is_empty() {
+        return "No active games".to_string();
+    }
+
+    let lines = sessions.iter()
+        .map(|session| {
+            let state = if session.started { "playing" } else { "lobby" };
+            format!("#{} - {} players, {}, {} min", session.id, session.players, state, session.age.as_secs() / 60)
+        })
+        .collect::<Vec<_>>();
+
+    format!("Active games:\n{}", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_admin_command() {
+        assert_eq!(AdminCommand::parse("games"), Ok(AdminCommand::Games));
+        assert_eq!(AdminCommand::parse("stop 12"), Ok(AdminCommand::Stop(12)));
+        assert_eq!(AdminCommand::parse("broadcast  Bot will restart soon "),
+                   Ok(AdminCommand::Broadcast("Bot will restart soon".to_string())));
+        assert!(AdminCommand::parse("stop x").is_err());
+        assert!(AdminCommand::parse("broadcast").is_err());
+        assert_eq!(AdminCommand::parse("loglevel debug"), Ok(AdminCommand::LogLevel("debug".to_string())));
+        assert!(AdminCommand::parse("loglevel verbose").is_err());
+        assert!(AdminCommand::parse("loglevel").is_err());
+        assert_eq!(AdminCommand::parse("close_season"), Ok(AdminCommand::CloseSeason));
+        assert_eq!(AdminCommand::parse("ratings"), Ok(AdminCommand::Ratings));
+        assert!(AdminCommand::parse("").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+
+use crate::game::{GameClient, GameEvent, MissionVote, Phase, Team, TeamVote, ID};
+use crate::journal::Move;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Strategy {
+    // Neutral action applied for a player who did not act in time
+    Default,
+    // Bot player which plays for its own team
+    Ai,
+    // Votes and claims at random, a baseline for the balance simulation
+    Random,
+}
+
+// Phase opened by the event and the players who should act in it
+pub fn prompted_seats(event: &GameEvent, players: usize) -> Option<(Phase, Vec<ID>)> {
+    match event {
+        GameEvent::Turn(crown_id, _) => Some((Phase::TeamSuggestion, vec![*crown_id])),
+        GameEvent::TeamSuggested(_) => Some((Phase::TeamVote, (0..players as ID).collect())),
+        GameEvent::TeamApproved(team) => Some((Phase::Mission, team.clone())),
+        GameEvent::Mermaid(mermaid_id) => Some((Phase::MermaidCheck, vec![*mermaid_id])),
+        GameEvent::MermaidResult(mermaid_id, _, _) => Some((Phase::MermaidWord, vec![*mermaid_id])),
+        GameEvent::BadLastChance(_, guesser) => Some((Phase::MerlinGuess, vec![*guesser])),
+        _ => None,
+    }
+}
+
+fn random_team(me: ID, players: usize, team_size: usize) -> Vec<ID> {
+    let mut rng = rand::thread_rng();
+    let mut team = (0..players as ID)
+        .filter(|id| *id != me)
+        .choose_multiple(&mut rng, team_size.saturating_sub(1));
+    team.push(me);
+    team.shuffle(&mut rng);
+    team
+}
+
+fn random_player<F: Fn(ID) -> bool>(players: usize, filter: F) -> Option<ID> {
+    (0..players as ID)
+        .filter(|id| filter(*id))
+        .choose(&mut rand::thread_rng())
+}
+
+// Move of the player at the seat, None if nothing is expected in the phase
+pub async fn choose(cli: &GameClient, id: ID, phase: Phase, strategy: Strategy) -> Result<Option<Move>, String> {
+    let roles = cli.get_player_roles().await;
+    let players = roles.len();
+    let is_good = roles[id as usize].is_good();
+
+    let chosen = match phase {
+        Phase::TeamSuggestion => {
+            let team_size = cli.get_expected_team_size().await;
+            Move::SuggestTeam(id, random_team(id, players, team_size))
+        }
+        Phase::TeamVote => {
+            let vote = if strategy == Strategy::Random && rand::thread_rng().gen() {
+                TeamVote::Reject
+            } else {
+                TeamVote::Approve
+            };
+            Move::TeamVote(id, vote)
+        }
+        Phase::Mission => {
+            let fail = match strategy {
+                Strategy::Default => false,
+                Strategy::Ai => !is_good,
+                Strategy::Random => !is_good && rand::thread_rng().gen(),
+            };
+            Move::Mission(id, if fail { MissionVote::Fail } else { MissionVote::Success })
+        }
+        Phase::MermaidCheck => {
+            let checked = random_player(players, |other| other != id)
+                .ok_or("Nobody to check")?;
+            Move::MermaidCheck(checked)
+        }
+        Phase::MermaidWord => {
+            let checked = cli.get_mermaid_checked().await.ok_or("Nobody was checked")?;
+            let checked_is_good = roles[checked as usize].is_good();
+            // Evil AI covers its teammates and blames good players
+            let tell_truth = match strategy {
+                Strategy::Default => true,
+                Strategy::Ai => is_good,
+                Strategy::Random => rand::thread_rng().gen(),
+            };
+            let word = if checked_is_good == tell_truth { Team::Good } else { Team::Bad };
+            Move::MermaidWord(word)
+        }
+        Phase::MerlinGuess => {
+            let guess = random_player(players, |other| roles[other as usize].is_good())
+                .ok_or("Nobody to guess")?;
+            Move::NameMerlin(guess)
+        }
+        Phase::Finished => return Ok(None),
+    };
+    Ok(Some(chosen))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+
+    #[tokio::test]
+    async fn test_ai_plays_full_game() {
+        for players in 5..=7 {
+            let (mut game, mut cli) = Game::setup(players);
+
+            let game_fut = async {
+                game.start().await.unwrap();
+            };
+
+            let ai_fut = async {
+                loop {
+                    let event = cli.recv_event().await.unwrap();
+                    if let GameEvent::GameResult(_) = event {
+                        break;
+                    }
+
+                    if let Some((phase, seats)) = prompted_seats(&event, players) {
+                        for id in seats {
+                            let chosen = choose(&cli, id, phase, Strategy::Ai).await.unwrap().unwrap();
+                            chosen.apply(&mut cli).await.unwrap();
+                        }
+                    }
+                }
+            };
+
+            tokio::join!(game_fut, ai_fut);
+        }
+    }
+}
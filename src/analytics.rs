@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use teloxide::prelude::*;
+use teloxide::types::InputFile;
+
+use crate::game::{GameOptions, GameResult, Role};
+use crate::journal::LogEntry;
+use crate::BotCtx;
+
+// Finished game as it is in the log of its lobby
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayedGame {
+    pub roles: Vec<Role>,
+    pub options: GameOptions,
+    pub result: GameResult,
+}
+
+// The log of the lobby has every game played in it, one after another. The engine state
+// logged again after a failure has the same roles, so the game is counted once
+pub fn played_games(log: &[LogEntry]) -> Vec<PlayedGame> {
+    let mut games = Vec::new();
+    let mut started = None;
+    for entry in log {
+        match entry {
+            LogEntry::Started(info) => started = Some((info.roles().to_vec(), info.options().clone())),
+            LogEntry::Event(crate::game::GameEvent::GameResult(result)) => {
+                if let Some((roles, options)) = started.take() {
+                    games.push(PlayedGame { roles, options, result: result.clone() });
+                }
+            }
+            _ => {}
+        }
+    }
+    games
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Tally {
+    games: u32,
+    wins: u32,
+}
+
+impl Tally {
+    fn add(&mut self, won: bool) {
+        self.games += 1;
+        self.wins += won as u32;
+    }
+
+    fn rate(&self) -> u32 {
+        self.wins * 100 / self.games.max(1)
+    }
+}
+
+// Short name of the rules, the win rates of different rules are not mixed in the export
+pub fn variant(options: &GameOptions, players: usize) -> String {
+    let mut parts = match options.roles.is_empty() {
+        true => vec!["no optional roles".to_string()],
+        false => vec![options.roles.iter().map(|role| role.to_string()).collect::<Vec<_>>().join("+")],
+    };
+    if options.has_mermaid(players) {
+        parts.push("mermaid".to_string());
+    }
+    if options.max_try_count != GameOptions::default().max_try_count {
+        parts.push(format!("{} tries", options.max_try_count));
+    }
+    parts.join(", ")
+}
+
+// Win rates of the roles, which are the seats of the role, and of the good team
+#[derive(Debug, Default)]
+pub struct Analytics {
+    // Variant, players and role
+    roles: BTreeMap<(String, usize, String), Tally>,
+    // Variant and players
+    good_team: BTreeMap<(String, usize), Tally>,
+}
+
+impl Analytics {
+    pub fn add(&mut self, game: &PlayedGame) {
+        let players = game.roles.len();
+        let variant = variant(&game.options, players);
+        let good_won = game.result == GameResult::GoodWins;
+        self.good_team.entry((variant.clone(), players)).or_default().add(good_won);
+        for role in &game.roles {
+            self.roles.entry((variant.clone(), players, role.to_string())).or_default().add(role.is_good() == good_won);
+        }
+    }
+
+    pub fn games(&self) -> u32 {
+        self.good_team.values().map(|tally| tally.games).sum()
+    }
+
+    // Summary over all the variants, the export has them apart
+    pub fn render(&self) -> String {
+        if self.good_team.is_empty() {
+            return "No finished games yet".to_string();
+        }
+
+        let mut roles = BTreeMap::<&str, Tally>::new();
+        for ((_, _, role), tally) in &self.roles {
+            let total = roles.entry(role).or_default();
+            total.games += tally.games;
+            total.wins += tally.wins;
+        }
+        let mut sizes = BTreeMap::<usize, Tally>::new();
+        for ((_, players), tally) in &self.good_team {
+            let total = sizes.entry(*players).or_default();
+            total.games += tally.games;
+            total.wins += tally.wins;
+        }
+
+        let mut lines = vec![format!("📈 Analytics of {} finished games", self.games()), "Win rate by role:".to_string()];
+        lines.extend(roles.iter().map(|(role, tally)| format!("{} - {}% of {}", role, tally.rate(), tally.games)));
+        lines.push("Good team win rate by players:".to_string());
+        lines.extend(sizes.iter().map(|(players, tally)| format!("{} players - {}% of {}", players, tally.rate(), tally.games)));
+        lines.join("\n")
+    }
+
+    // The good team rows have "good team" as the role
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec!["variant,players,role,games,wins,win_rate".to_string()];
+        let good_team = self.good_team.iter().map(|((variant, players), tally)| (variant, *players, "good team", tally));
+        let roles = self.roles.iter().map(|((variant, players, role), tally)| (variant, *players, role.as_str(), tally));
+        for (variant, players, role, tally) in good_team.chain(roles) {
+            lines.push(format!("\"{}\",{},{},{},{},{}", variant, players, role, tally.games, tally.wins, tally.rate()));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+fn collect(ctx: &BotCtx) -> Result<Analytics, String> {
+    let mut analytics = Analytics::default();
+    for id in ctx.storage.load_session_ids().map_err(|e| e.to_string())? {
+        let log = ctx.storage.load_log(id).map_err(|e| format!("Failed to load the log of game {}: {}", id, e))?;
+        for game in played_games(&log) {
+            analytics.add(&game);
+        }
+    }
+    Ok(analytics)
+}
+
+// Admin only, "csv" sends the breakdown by the variants as a file
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    if !ctx.admin.is_admin(chat_id) {
+        ctx.bot.send_message(chat_id, "Unknown command").await?;
+        return respond(());
+    }
+
+    let analytics = match collect(ctx) {
+        Ok(analytics) => analytics,
+        Err(e) => {
+            tracing::warn!("Failed to collect analytics: {}", e);
+            ctx.bot.send_message(chat_id, "Analytics are not available now").await?;
+            return respond(());
+        }
+    };
+    match args.trim() {
+        "" => {
+            ctx.bot.send_message(chat_id, analytics.render()).await?;
+        }
+        "csv" => {
+            let document = InputFile::memory(analytics.to_csv().into_bytes()).file_name("analytics.csv");
+            ctx.bot.send_document(chat_id, document).await?;
+        }
+        _ => {
+            ctx.bot.send_message(chat_id, "Use /analytics or /analytics csv").await?;
+        }
+    }
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{self, GameEvent};
+
+    #[tokio::test]
+    async fn test_games_of_log_are_counted_once() {
+        let (_, cli) = game::Game::setup(5);
+        let started = LogEntry::Started(cli.snapshot().await);
+        let result = |result| LogEntry::Event(GameEvent::GameResult(result));
+        // The second game was restarted after a failure, the third one is not finished
+        let log = vec![
+            started.clone(), result(GameResult::GoodWins),
+            started.clone(), started.clone(), result(GameResult::BadWins),
+            started.clone(),
+        ];
+        let games = played_games(&log);
+        assert_eq!(games.iter().map(|game| game.result.clone()).collect::<Vec<_>>(),
+                   vec![GameResult::GoodWins, GameResult::BadWins]);
+
+        let mut analytics = Analytics::default();
+        for game in &games {
+            analytics.add(game);
+        }
+        assert_eq!(analytics.games(), 2);
+        let text = analytics.render();
+        assert!(text.contains("Merlin - 50% of 2"));
+        assert!(text.contains("5 players - 50% of 2"));
+        let csv = analytics.to_csv();
+        assert!(csv.starts_with("variant,players,role,games,wins,win_rate\n\"Percival+Morgen+Oberon\",5,good team,2,1,50\n"));
+        assert!(csv.contains("\"Percival+Morgen+Oberon\",5,Merlin,2,1,50\n"));
+    }
+}
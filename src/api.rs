@@ -0,0 +1,499 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
+use tracing::Instrument;
+
+use crate::game::{self, GameClient, GameEvent, MissionVote, Phase, Role, Team, TeamVote, ID};
+use crate::journal::Move;
+use crate::media;
+use crate::theme::Theme;
+
+// Finished games are dropped when this number is reached
+const MAX_GAMES: usize = 100;
+// Events kept for the WebSocket clients which are slower than the game
+const EVENT_BUFFER: usize = 64;
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum ApiError {
+    NoSuchGame,
+    InvalidPlayers(usize),
+    TooManyGames,
+    GameIsFull,
+    NotStarted,
+    Unauthorized,
+    NotYourTurn,
+    NoSuchPlayer(ID),
+    // The engine didn't accept the action
+    Rejected(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::NoSuchGame => write!(f, "No such game"),
+            ApiError::InvalidPlayers(players) => write!(f, "The game can't be played by {} players, expected {} to {}",
+                                                        players, game::MIN_PLAYERS, game::MAX_PLAYERS),
+            ApiError::TooManyGames => write!(f, "Too many games are running"),
+            ApiError::GameIsFull => write!(f, "All seats are taken"),
+            ApiError::NotStarted => write!(f, "The game is not started yet"),
+            ApiError::Unauthorized => write!(f, "Invalid token"),
+            ApiError::NotYourTurn => write!(f, "It's not your turn for that"),
+            ApiError::NoSuchPlayer(id) => write!(f, "There is no player {}", id),
+            ApiError::Rejected(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ApiError::NoSuchGame => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::TooManyGames => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::GameIsFull | ApiError::NotStarted | ApiError::NotYourTurn => StatusCode::CONFLICT,
+            ApiError::InvalidPlayers(_) | ApiError::NoSuchPlayer(_) | ApiError::Rejected(_) => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(json!({ "error": self.to_string() }))).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewGame {
+    pub players: usize,
+}
+
+#[derive(Deserialize)]
+pub struct JoinRequest {
+    pub name: String,
+}
+
+// Token is sent as `Authorization: Bearer <token>` with the actions of the seat
+#[derive(Serialize, Debug)]
+pub struct Joined {
+    pub seat: ID,
+    pub token: String,
+}
+
+// Same actions as the Telegram commands, with the ids of the seats as player ids
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    SuggestTeam { team: Vec<ID> },
+    TeamVote { vote: TeamVote },
+    Mission { vote: MissionVote },
+    MermaidCheck { player: ID },
+    MermaidWord { team: Team },
+    NameMerlin { player: ID },
+}
+
+impl Action {
+    fn targets(&self) -> Vec<ID> {
+        match self {
+            Action::SuggestTeam { team } => team.clone(),
+            Action::MermaidCheck { player } | Action::NameMerlin { player } => vec![*player],
+            Action::TeamVote { .. } | Action::Mission { .. } | Action::MermaidWord { .. } => Vec::new(),
+        }
+    }
+
+    fn into_move(self, seat: ID) -> Move {
+        match self {
+            Action::SuggestTeam { team } => Move::SuggestTeam(seat, team),
+            Action::TeamVote { vote } => Move::TeamVote(seat, vote),
+            Action::Mission { vote } => Move::Mission(seat, vote),
+            Action::MermaidCheck { player } => Move::MermaidCheck(player),
+            Action::MermaidWord { team } => Move::MermaidWord(team),
+            Action::NameMerlin { player } => Move::NameMerlin(player),
+        }
+    }
+}
+
+// Public state of the game, the same for all players
+#[derive(Serialize, Debug)]
+pub struct GameState {
+    pub id: u32,
+    pub seats: usize,
+    pub players: Vec<String>,
+    pub board: Option<Board>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Board {
+    pub phase: Phase,
+    pub crown: ID,
+    pub mermaid: ID,
+    pub team_size: usize,
+    pub team: Vec<ID>,
+    pub try_count: u8,
+    pub missions: Vec<MissionVote>,
+    pub waiting_for: Vec<ID>,
+}
+
+// What only the player of the seat knows
+#[derive(Serialize, Debug)]
+pub struct SeatView {
+    pub seat: ID,
+    pub name: String,
+    pub role: Role,
+    pub description: String,
+}
+
+struct Player {
+    name: String,
+    token: String,
+}
+
+struct ApiGame {
+    seats: usize,
+    players: Vec<Player>,
+    cli: Option<GameClient>,
+    engine: Option<AbortHandle>,
+    // Events sent so far, so clients which connect later get the whole game
+    history: Vec<GameEvent>,
+    events: broadcast::Sender<GameEvent>,
+    // Seats which acted since the last event. The engine counts every action it gets,
+    // so repeated ones are stopped here
+    acted: HashSet<ID>,
+}
+
+impl ApiGame {
+    fn seat(&self, token: &str) -> Result<ID, ApiError> {
+        self.players.iter()
+            .position(|player| player.token == token)
+            .map(|seat| seat as ID)
+            .ok_or(ApiError::Unauthorized)
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self.history.last(), Some(GameEvent::GameResult(_)))
+    }
+}
+
+#[derive(Default)]
+struct Games {
+    last_id: u32,
+    games: HashMap<u32, ApiGame>,
+}
+
+impl Games {
+    fn get(&mut self, id: u32) -> Result<&mut ApiGame, ApiError> {
+        self.games.get_mut(&id).ok_or(ApiError::NoSuchGame)
+    }
+}
+
+// Games of the API are separate from the Telegram ones and are not saved
+#[derive(Clone, Default)]
+pub struct ApiState {
+    games: Arc<Mutex<Games>>,
+}
+
+impl ApiState {
+    pub async fn create(&self, players: usize) -> Result<u32, ApiError> {
+        if !(game::MIN_PLAYERS..=game::MAX_PLAYERS).contains(&players) {
+            return Err(ApiError::InvalidPlayers(players));
+        }
+
+        let mut games = self.games.lock().await;
+        if games.games.len() >= MAX_GAMES {
+            games.games.retain(|_, game| !game.finished());
+        }
+        if games.games.len() >= MAX_GAMES {
+            return Err(ApiError::TooManyGames);
+        }
+
+        games.last_id += 1;
+        let id = games.last_id;
+        games.games.insert(id, ApiGame {
+            seats: players,
+            players: Vec::new(),
+            cli: None,
+            engine: None,
+            history: Vec::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            acted: HashSet::new(),
+        });
+        Ok(id)
+    }
+
+    // Takes the next free seat. The game starts when the last one is taken
+    pub async fn join(&self, id: u32, name: String) -> Result<Joined, ApiError> {
+        let mut games = self.games.lock().await;
+        let game = games.get(id)?;
+        if game.players.len() == game.seats {
+            return Err(ApiError::GameIsFull);
+        }
+
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), TOKEN_LENGTH);
+        game.players.push(Player { name, token: token.clone() });
+        let seat = (game.players.len() - 1) as ID;
+
+        if game.players.len() == game.seats {
+            let (engine, cli) = game::Game::setup(game.seats);
+            let span = tracing::info_span!("api", game = id);
+            game.engine = Some(span.in_scope(|| game::spawn_engine(engine)));
+            game.cli = Some(cli.clone());
+            tokio::spawn(pump_events(self.clone(), id, cli).instrument(span));
+        }
+        Ok(Joined { seat, token })
+    }
+
+    pub async fn state(&self, id: u32) -> Result<GameState, ApiError> {
+        let mut games = self.games.lock().await;
+        let game = games.get(id)?;
+        let board = match &game.cli {
+            Some(cli) => Some(Board {
+                phase: cli.get_phase().await,
+                crown: cli.get_crown_id().await,
+                mermaid: cli.get_mermaid_id().await,
+                team_size: cli.get_expected_team_size().await,
+                team: cli.get_current_team().await,
+                try_count: cli.get_try_count().await,
+                missions: cli.get_mission_results().await,
+                waiting_for: cli.get_waiting_for().await,
+            }),
+            None => None,
+        };
+        Ok(GameState {
+            id,
+            seats: game.seats,
+            players: game.players.iter().map(|player| player.name.clone()).collect(),
+            board,
+        })
+    }
+
+    pub async fn seat_view(&self, id: u32, token: &str) -> Result<SeatView, ApiError> {
+        let mut games = self.games.lock().await;
+        let game = games.get(id)?;
+        let seat = game.seat(token)?;
+        let cli = game.cli.as_ref().ok_or(ApiError::NotStarted)?;
+        let role = cli.get_player_roles().await.swap_remove(seat as usize);
+        Ok(SeatView {
+            seat,
+            name: game.players[seat as usize].name.clone(),
+            description: media::role_description(&role, Theme::Classic),
+            role,
+        })
+    }
+
+    // Checks that the seat is expected to act in the current phase like the Telegram commands do
+    pub async fn act(&self, id: u32, token: &str, action: Action) -> Result<(), ApiError> {
+        let mut games = self.games.lock().await;
+        let game = games.get(id)?;
+        let seat = game.seat(token)?;
+        let mut cli = game.cli.clone().ok_or(ApiError::NotStarted)?;
+
+        if let Some(target) = action.targets().into_iter().find(|target| *target as usize >= game.seats) {
+            return Err(ApiError::NoSuchPlayer(target));
+        }
+        let accepted = action.into_move(seat);
+        if game.acted.contains(&seat)
+            || cli.get_phase().await != accepted.phase()
+            || !cli.get_waiting_for().await.contains(&seat) {
+            return Err(ApiError::NotYourTurn);
+        }
+
+        accepted.apply(&mut cli).await.map_err(ApiError::Rejected)?;
+        game.acted.insert(seat);
+        Ok(())
+    }
+
+    // Events are streamed to the players only, each one gets the events its seat may see
+    pub async fn subscribe(&self, id: u32, token: &str)
+        -> Result<(ID, Vec<GameEvent>, broadcast::Receiver<GameEvent>), ApiError> {
+        let mut games = self.games.lock().await;
+        let game = games.get(id)?;
+        let seat = game.seat(token)?;
+        Ok((seat, game.history.clone(), game.events.subscribe()))
+    }
+}
+
+// Same split as the private messages of game_msg.rs: only the Mermaid holder sees the team of the checked player
+fn is_visible(event: &GameEvent, seat: ID) -> bool {
+    match event {
+        GameEvent::MermaidResult(holder, ..) => *holder == seat,
+        _ => true,
+    }
+}
+
+// Keeps the events of the engine for the WebSocket clients until the end of the game
+async fn pump_events(api: ApiState, id: u32, mut cli: GameClient) {
+    loop {
+        let event = match cli.recv_event().await.map_err(|e| e.to_string()) {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("API game {} stopped processing events: {}", id, e);
+                break;
+            }
+        };
+
+        let mut games = api.games.lock().await;
+        let Ok(game) = games.get(id) else {
+            break;
+        };
+        game.acted.clear();
+        game.history.push(event.clone());
+        // Nobody may be listening
+        let _ = game.events.send(event);
+        if game.finished() {
+            game.engine = None;
+            break;
+        }
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Result<&str, ApiError> {
+    headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(ApiError::Unauthorized)
+}
+
+async fn create_game(State(api): State<ApiState>, Json(request): Json<NewGame>) -> Result<impl IntoResponse, ApiError> {
+    let id = api.create(request.players).await?;
+    Ok((StatusCode::CREATED, Json(json!({ "id": id }))))
+}
+
+async fn game_state(State(api): State<ApiState>, Path(id): Path<u32>) -> Result<Json<GameState>, ApiError> {
+    api.state(id).await.map(Json)
+}
+
+async fn join_game(State(api): State<ApiState>, Path(id): Path<u32>, Json(request): Json<JoinRequest>)
+    -> Result<Json<Joined>, ApiError> {
+    api.join(id, request.name).await.map(Json)
+}
+
+async fn my_seat(State(api): State<ApiState>, Path(id): Path<u32>, headers: HeaderMap) -> Result<Json<SeatView>, ApiError> {
+    api.seat_view(id, bearer_token(&headers)?).await.map(Json)
+}
+
+async fn submit_action(State(api): State<ApiState>, Path(id): Path<u32>, headers: HeaderMap, Json(action): Json<Action>)
+    -> Result<StatusCode, ApiError> {
+    api.act(id, bearer_token(&headers)?, action).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Events of the game as JSON text messages, starting from the first one
+async fn game_events(State(api): State<ApiState>, Path(id): Path<u32>, headers: HeaderMap, ws: WebSocketUpgrade)
+    -> Result<Response, ApiError> {
+    let (seat, history, events) = api.subscribe(id, bearer_token(&headers)?).await?;
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, seat, history, events)))
+}
+
+async fn stream_events(mut socket: WebSocket, seat: ID, history: Vec<GameEvent>,
+                       mut events: broadcast::Receiver<GameEvent>) {
+    for event in history.iter().filter(|event| is_visible(event, seat)) {
+        if send_event(&mut socket, event).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // The client reconnects and gets the whole history again
+            Err(RecvError::Lagged(missed)) => {
+                tracing::warn!("API client missed {} events", missed);
+                return;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if is_visible(&event, seat) && send_event(&mut socket, &event).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn send_event(socket: &mut WebSocket, event: &GameEvent) -> Result<(), axum::Error> {
+    socket.send(WsMessage::Text(serde_json::to_string(event).unwrap())).await
+}
+
+// Server for clients other than Telegram, e.g. a web client
+pub fn spawn_server(address: SocketAddr) {
+    let app = Router::new()
+        .route("/api/games", post(create_game))
+        .route("/api/games/:id", get(game_state))
+        .route("/api/games/:id/seats", post(join_game))
+        .route("/api/games/:id/seats/me", get(my_seat))
+        .route("/api/games/:id/actions", post(submit_action))
+        .route("/api/games/:id/events", get(game_events))
+        .with_state(ApiState::default());
+
+    tokio::spawn(async move {
+        tracing::info!("Serving the game API on {}", address);
+        if let Err(e) = axum::Server::bind(&address).serve(app.into_make_service()).await {
+            tracing::error!("API server error: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_game_is_played_through_api() {
+        let api = ApiState::default();
+        assert_eq!(api.create(1).await, Err(ApiError::InvalidPlayers(1)));
+
+        let id = api.create(3).await.unwrap();
+        let mut players = Vec::new();
+        for name in ["Alice", "Bob"] {
+            players.push(api.join(id, name.to_string()).await.unwrap());
+        }
+        assert_eq!(api.subscribe(id, "wrong").await.unwrap_err(), ApiError::Unauthorized);
+        let (seat, _, mut events) = api.subscribe(id, &players[0].token).await.unwrap();
+        assert_eq!(seat, 0);
+        players.push(api.join(id, "Carol".to_string()).await.unwrap());
+        assert_eq!(api.join(id, "Dave".to_string()).await.unwrap_err(), ApiError::GameIsFull);
+        assert_eq!(api.seat_view(id, "wrong").await.unwrap_err(), ApiError::Unauthorized);
+        assert_eq!(api.seat_view(id, &players[1].token).await.unwrap().name, "Bob");
+
+        let GameEvent::Turn(crown, team_size) = events.recv().await.unwrap() else {
+            panic!("The game didn't start with a turn");
+        };
+        let team = (0..team_size as ID).collect::<Vec<_>>();
+        let other = &players[(crown as usize + 1) % players.len()];
+        let crown = &players[crown as usize];
+
+        let suggestion = Action::SuggestTeam { team: team.clone() };
+        assert_eq!(api.act(id, &other.token, suggestion.clone()).await, Err(ApiError::NotYourTurn));
+        assert_eq!(api.act(id, &crown.token, Action::SuggestTeam { team: vec![0, 9] }).await,
+                   Err(ApiError::NoSuchPlayer(9)));
+        api.act(id, &crown.token, suggestion.clone()).await.unwrap();
+        // The engine has not processed the suggestion yet
+        assert_eq!(api.act(id, &crown.token, suggestion).await, Err(ApiError::NotYourTurn));
+
+        assert_eq!(events.recv().await.unwrap(), GameEvent::TeamSuggested(team.clone()));
+        let vote = Action::TeamVote { vote: TeamVote::Approve };
+        api.act(id, &crown.token, vote.clone()).await.unwrap();
+        assert_eq!(api.act(id, &crown.token, vote).await, Err(ApiError::NotYourTurn));
+
+        let state = api.state(id).await.unwrap();
+        assert_eq!(state.players, vec!["Alice", "Bob", "Carol"]);
+        let board = state.board.unwrap();
+        assert_eq!(board.phase, Phase::TeamVote);
+        assert_eq!(board.team, team);
+        assert_eq!(board.waiting_for.len(), 2);
+    }
+
+    #[test]
+    fn test_mermaid_result_is_visible_to_holder_only() {
+        let result = GameEvent::MermaidResult(2, 0, Team::Bad);
+        assert!(is_visible(&result, 2));
+        assert!(!is_visible(&result, 0));
+        assert!(is_visible(&GameEvent::MermaidSays(2, 0, Team::Good), 0));
+    }
+}
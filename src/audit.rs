@@ -0,0 +1,120 @@
+use std::fmt;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::game::{Role, TeamVote};
+use crate::journal::Move;
+use crate::GameInfo;
+
+// Telegram limit is 4096 characters, the audit of a long game is sent in several messages
+const MAX_TEXT: usize = 4000;
+
+// Who can see the audit after the end of the game, chosen by the leader in /settings
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AuditAccess {
+    Leader,
+    Players,
+}
+
+impl fmt::Display for AuditAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditAccess::Leader => write!(f, "leader only"),
+            AuditAccess::Players => write!(f, "all players"),
+        }
+    }
+}
+
+impl AuditAccess {
+    pub fn next(self) -> Self {
+        match self {
+            AuditAccess::Leader => AuditAccess::Players,
+            AuditAccess::Players => AuditAccess::Leader,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct AuditRecord {
+    // Since the start of the game
+    elapsed: Duration,
+    action: Move,
+    // Made for the player by the timeout or AI
+    auto: bool,
+}
+
+// Every move accepted by the engine in the order it came, so the players can settle
+// who voted first or whether a vote came after the time was up
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    started: Instant,
+    records: Vec<AuditRecord>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self { started: Instant::now(), records: Vec::new() }
+    }
+
+    pub fn record(&mut self, action: Move, auto: bool) {
+        self.records.push(AuditRecord { elapsed: self.started.elapsed(), action, auto });
+    }
+
+    // Messages of the audit, the lines of a message are not split between them
+    pub fn render(&self, game_id: u32, info: &GameInfo) -> Vec<String> {
+        let mut pages = vec![format!("🔎 Audit of game #{}, time since the start:", game_id)];
+        if self.records.is_empty() {
+            pages[0].push_str("\nNo moves were made");
+        }
+        for record in &self.records {
+            let auto = if record.auto { " (auto)" } else { "" };
+            let line = format!("{} {}{}", format_elapsed(record.elapsed), describe(info, &record.action), auto);
+            match pages.last_mut() {
+                Some(page) if page.len() + line.len() < MAX_TEXT => {
+                    page.push('\n');
+                    page.push_str(&line);
+                }
+                _ => pages.push(line),
+            }
+        }
+        pages
+    }
+}
+
+// Minutes, seconds and milliseconds, e.g. 12:05.250
+fn format_elapsed(elapsed: Duration) -> String {
+    let millis = elapsed.as_millis();
+    format!("{:02}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+fn describe(info: &GameInfo, action: &Move) -> String {
+    let theme = info.theme.table();
+    let name = |id: &u8| info.players.get(*id as usize)
+        .and_then(|chat_id| info.user_names.get(chat_id))
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| id.to_string());
+    match action {
+        Move::SuggestTeam(from, team) => {
+            let team = team.iter().map(name).collect::<Vec<_>>().join(", ");
+            format!("{} suggested the team: {}", name(from), team)
+        }
+        Move::TeamVote(from, TeamVote::Approve) => format!("{} voted ✅ for the team", name(from)),
+        Move::TeamVote(from, TeamVote::Reject) => format!("{} voted ❌ against the team", name(from)),
+        Move::Mission(from, vote) => format!("{} played {} {} on the mission", name(from), theme.mission(vote), vote),
+        Move::MermaidCheck(checked) => format!("{} {} checked {}", theme.mermaid_icon, theme.mermaid, name(checked)),
+        Move::MermaidWord(word) => format!("{} {} holder said: {}", theme.mermaid_icon, theme.mermaid, theme.team(word)),
+        Move::NameMerlin(id) => format!("{} was named as {}", name(id), theme.role(&Role::Merlin)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elapsed_time_format() {
+        assert_eq!(format_elapsed(Duration::from_millis(725_250)), "12:05.250");
+        assert_eq!(format_elapsed(Duration::from_millis(7)), "00:00.007");
+    }
+}
@@ -0,0 +1,95 @@
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::session::SessionCommand;
+use crate::{users, BotCtx};
+
+// Callback data of the name buttons starts with it, e.g. "ban 42"
+pub const PREFIX: &str = "ban ";
+
+fn data(chat_id: ChatId) -> String {
+    format!("{}{}", PREFIX, chat_id)
+}
+
+fn parse(data: &str) -> Option<ChatId> {
+    data.strip_prefix(PREFIX)?.parse().ok().map(ChatId)
+}
+
+// Lobby members except the leader, one button per name
+fn keyboard(ctx: &BotCtx, members: &[ChatId]) -> InlineKeyboardMarkup {
+    let names = users::disambiguate(members, &ctx.users);
+    let rows = members.iter()
+        .map(|member| vec![InlineKeyboardButton::callback(format!("🚫 {}", names[member]), data(*member))]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+// Public invite links reach strangers, so the leader can send one out of the lobby for good
+pub async fn show(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some((session, _)) = crate::lobby_settings(ctx, chat_id) else {
+        ctx.bot.send_message(chat_id, "Only game leader can ban players before the start").await?;
+        return respond(());
+    };
+
+    let members = crate::lobby_members(ctx, session.id).into_iter()
+        .filter(|member| *member != chat_id)
+        .collect::<Vec<_>>();
+    if members.is_empty() {
+        ctx.bot.send_message(chat_id, "Nobody joined the lobby yet").await?;
+        return respond(());
+    }
+    ctx.bot.send_message(chat_id, "Tap the name to remove the player from the lobby. They can't join it again")
+        .reply_markup(keyboard(ctx, &members))
+        .await?;
+    respond(())
+}
+
+// Name button of the /ban menu was pressed. Returns the text of the popup
+pub async fn ban(ctx: &mut BotCtx, query: &CallbackQuery, data: &str) -> ResponseResult<Option<&'static str>>
+{
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some(banned) = parse(data) else {
+        tracing::warn!("Unexpected ban button from {}: {}", chat_id, data);
+        return Ok(None);
+    };
+    let Some((session, _)) = crate::lobby_settings(ctx, chat_id) else {
+        return Ok(Some("Only game leader can ban players before the start"));
+    };
+    if banned == chat_id {
+        return Ok(Some("You can't ban yourself"));
+    }
+
+    session.send(SessionCommand::Ban(banned));
+    let waiting = ctx.other_games.remove(banned, session.id);
+    let active = ctx.user_games.get(&banned) == Some(&session.id);
+    if active {
+        crate::switcher::leave(ctx, banned);
+    }
+    if waiting || active {
+        ctx.bot.send_message(banned, "The leader removed you from the game").await?;
+    }
+
+    if let Some(message) = &query.message {
+        let members = crate::lobby_members(ctx, session.id).into_iter()
+            .filter(|member| *member != chat_id)
+            .collect::<Vec<_>>();
+        let text = format!("{} is banned from the lobby", crate::get_display_name(ctx, banned));
+        ctx.bot.edit_message_text(message.chat.id, message.id, text)
+            .reply_markup(keyboard(ctx, &members))
+            .await?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ban_button_is_parsed() {
+        assert_eq!(parse(&data(ChatId(42))), Some(ChatId(42)));
+        assert_eq!(parse("ban me"), None);
+        assert_eq!(parse("seats up 1"), None);
+    }
+}
@@ -0,0 +1,261 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use avalon_tg_bot::ai::{self, Strategy};
+use avalon_tg_bot::commands::GameAction;
+use avalon_tg_bot::game::{self, GameClient, GameEvent, GameResult, MissionVote, Phase, Role, ID};
+use avalon_tg_bot::journal::Move;
+use avalon_tg_bot::simulation::{self, Strategies, Table};
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// Plays a game of Avalon in the terminal. Every seat is controlled from the keyboard
+/// with the bot commands without the slash, e.g. team_approve, or by the AI
+#[derive(Parser, Debug)]
+#[command(about, args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Number of players
+    #[arg(short, long, default_value_t = 5)]
+    players: usize,
+    /// Seat controlled by the AI, can be repeated: --ai 1 --ai 2
+    #[arg(long)]
+    ai: Vec<ID>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Plays many games between AI players and prints the win rates of the teams
+    Simulate {
+        /// Games for each number of players
+        #[arg(short, long, default_value_t = 1000)]
+        games: usize,
+        /// Number of players, can be repeated. All supported numbers by default
+        #[arg(short, long)]
+        players: Vec<usize>,
+        /// Strategy of the good players
+        #[arg(long, value_enum, default_value_t = StrategyArg::Ai)]
+        good: StrategyArg,
+        /// Strategy of the evil players
+        #[arg(long, value_enum, default_value_t = StrategyArg::Ai)]
+        evil: StrategyArg,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StrategyArg {
+    /// Approves every team and supports every mission
+    Default,
+    /// Plays for its own team
+    Ai,
+    /// Votes and claims at random
+    Random,
+}
+
+impl From<StrategyArg> for Strategy {
+    fn from(strategy: StrategyArg) -> Self {
+        match strategy {
+            StrategyArg::Default => Strategy::Default,
+            StrategyArg::Ai => Strategy::Ai,
+            StrategyArg::Random => Strategy::Random,
+        }
+    }
+}
+
+fn check_players(players: usize) -> Result<(), String> {
+    if !(game::MIN_PLAYERS..=game::MAX_PLAYERS).contains(&players) {
+        return Err(format!("The game is for {} to {} players", game::MIN_PLAYERS, game::MAX_PLAYERS));
+    }
+    Ok(())
+}
+
+fn seats(ids: &[ID]) -> String {
+    ids.iter().map(ID::to_string).collect::<Vec<_>>().join(", ")
+}
+
+fn describe(event: &GameEvent) -> String {
+    match event {
+        GameEvent::Turn(crown_id, team_size) => {
+            format!("Player {} has the crown and suggests a team of {}", crown_id, team_size)
+        }
+        GameEvent::TeamSuggested(team) => format!("Suggested team: {}", seats(team)),
+        GameEvent::TeamVote(votes) => {
+            let votes = votes.iter().enumerate()
+                .map(|(id, vote)| format!("{} {}", id, vote))
+                .collect::<Vec<_>>();
+            format!("Votes: {}", votes.join(", "))
+        }
+        GameEvent::TeamApproved(team) => format!("Team {} goes on the mission", seats(team)),
+        GameEvent::TeamRejected(try_count) => {
+            format!("The team is rejected, try {} of {}", try_count, game::MAX_TRY_COUNT)
+        }
+        GameEvent::MissionResult(votes) => {
+            let fails = votes.iter().filter(|vote| **vote == MissionVote::Fail).count();
+            format!("Mission result: {} success, {} fail", votes.len() - fails, fails)
+        }
+        GameEvent::Mermaid(mermaid_id) => format!("Player {} has the mermaid", mermaid_id),
+        GameEvent::MermaidResult(mermaid_id, checked_id, team) => {
+            format!("Mermaid shows player {} that player {} is {}", mermaid_id, checked_id, team)
+        }
+        GameEvent::MermaidSays(mermaid_id, checked_id, team) => {
+            format!("Player {} says that player {} is {}", mermaid_id, checked_id, team)
+        }
+        GameEvent::BadLastChance(bad_team, guesser) => {
+            format!("Good team completed the missions. Bad team {} has the last chance, player {} names Merlin",
+                    seats(bad_team), guesser)
+        }
+        GameEvent::Merlin(merlin_id) => format!("Merlin was player {}", merlin_id),
+        GameEvent::GameResult(GameResult::GoodWins) => "Good team wins!".to_string(),
+        GameEvent::GameResult(GameResult::BadWins) => "Bad team wins!".to_string(),
+    }
+}
+
+fn hint(phase: Phase) -> &'static str {
+    match phase {
+        Phase::TeamSuggestion => "suggest_N to add or remove player N, suggest_finish",
+        Phase::TeamVote => "team_approve or team_reject",
+        Phase::Mission => "mission_success or mission_fail",
+        Phase::MermaidCheck => "mermaid_N",
+        Phase::MermaidWord => "say_good or say_bad",
+        Phase::MerlinGuess => "merlin_N",
+        Phase::Finished => "nothing",
+    }
+}
+
+// Reads the commands of the seat until the engine accepts its move
+async fn play_seat<I>(cli: &mut GameClient, input: &mut I, roles: &[Role], seat: ID, phase: Phase)
+    -> Result<(), Box<dyn Error>>
+    where I: Iterator<Item = io::Result<String>> {
+    let mut selection: Vec<ID> = Vec::new();
+    loop {
+        print!("[{} {}] {}> ", seat, roles[seat as usize], hint(phase));
+        io::stdout().flush()?;
+        let line = input.next().ok_or("Input is closed")??;
+        let line = line.trim();
+        let action = match GameAction::parse(&format!("/{}", line.trim_start_matches('/'))) {
+            Ok(action) if action.phase() == phase => action,
+            Ok(_) => {
+                println!("Expected {}", hint(phase));
+                continue;
+            }
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+
+        let chosen = match action {
+            GameAction::ToggleSuggestion(id) => {
+                match selection.iter().position(|selected| *selected == id) {
+                    Some(pos) => {
+                        selection.remove(pos);
+                    }
+                    None => selection.push(id),
+                }
+                println!("Selected: {}", seats(&selection));
+                continue;
+            }
+            GameAction::FinishSuggestion => Move::SuggestTeam(seat, selection.clone()),
+            GameAction::SuggestTeam(team) => Move::SuggestTeam(seat, team),
+            GameAction::TeamVote(vote) => Move::TeamVote(seat, vote),
+            GameAction::MissionVote(vote) => Move::Mission(seat, vote),
+            GameAction::MermaidCheck(id) => Move::MermaidCheck(id),
+            GameAction::MermaidWord(word) => Move::MermaidWord(word),
+            GameAction::NameMerlin(id) => Move::NameMerlin(id),
+        };
+        let targets = match &chosen {
+            Move::SuggestTeam(_, team) => team.clone(),
+            Move::MermaidCheck(id) | Move::NameMerlin(id) => vec![*id],
+            _ => Vec::new(),
+        };
+        if let Some(id) = targets.into_iter().find(|id| *id as usize >= roles.len()) {
+            println!("There is no player {}", id);
+            continue;
+        }
+
+        match chosen.apply(cli).await {
+            Ok(()) => return Ok(()),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+async fn play<I>(players: usize, ai_seats: &[ID], input: &mut I) -> Result<GameResult, Box<dyn Error>>
+    where I: Iterator<Item = io::Result<String>> {
+    let (engine, mut cli) = game::Game::setup(players);
+    let _engine = game::spawn_engine(engine);
+
+    let roles = cli.get_player_roles().await;
+    for (seat, role) in roles.iter().enumerate() {
+        let controller = if ai_seats.contains(&(seat as ID)) { "AI" } else { "you" };
+        println!("Player {}: {} ({})", seat, role, controller);
+    }
+
+    loop {
+        let event = cli.recv_event().await?;
+        println!("{}", describe(&event));
+        if let GameEvent::GameResult(result) = event {
+            return Ok(result);
+        }
+
+        let Some((phase, prompted)) = ai::prompted_seats(&event, players) else {
+            continue;
+        };
+        for seat in prompted {
+            if !ai_seats.contains(&seat) {
+                play_seat(&mut cli, input, &roles, seat, phase).await?;
+                continue;
+            }
+            if let Some(chosen) = ai::choose(&cli, seat, phase, Strategy::Ai).await? {
+                println!("AI {}: {:?}", seat, chosen);
+                chosen.apply(&mut cli).await?;
+            }
+        }
+    }
+}
+
+async fn simulate(games: usize, mut players: Vec<usize>, strategies: Strategies) -> Result<(), Box<dyn Error>> {
+    if players.is_empty() {
+        players = (game::MIN_PLAYERS..=game::MAX_PLAYERS).collect();
+    }
+    let mut rows = Vec::new();
+    for players in players {
+        check_players(players)?;
+        rows.push(simulation::simulate(players, games, strategies).await?);
+    }
+    println!("Good players: {:?}, evil players: {:?}", strategies.good, strategies.evil);
+    print!("{}", Table(rows));
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    if let Some(Command::Simulate { games, players, good, evil }) = args.command {
+        return simulate(games, players, Strategies { good: good.into(), evil: evil.into() }).await;
+    }
+
+    check_players(args.players)?;
+    if let Some(seat) = args.ai.iter().find(|seat| **seat as usize >= args.players) {
+        return Err(format!("There is no seat {}", seat).into());
+    }
+
+    play(args.players, &args.ai, &mut io::stdin().lock().lines()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ai_and_keyboard_seats() {
+        let all = (0..5).collect::<Vec<ID>>();
+        play(5, &all, &mut std::iter::empty()).await.unwrap();
+
+        // The keyboard seat gets no input, so the game stops at its first move
+        let input = ["hello", "merlin_1"].map(|line| Ok(line.to_string()));
+        let result = play(5, &all[1..], &mut input.into_iter()).await;
+        assert_eq!(result.unwrap_err().to_string(), "Input is closed");
+    }
+}
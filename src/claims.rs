@@ -0,0 +1,123 @@
+use teloxide::prelude::*;
+
+use crate::game::{Role, Team};
+use crate::outbox::Outbox;
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameInfo, GameSession};
+
+// Table talk of one game is short, the flood of claims is not kept
+const MAX_CLAIMS: usize = 50;
+// Roles the players can claim, Good2 is the same card as Good
+const ROLES: [Role; 8] = [Role::Merlin, Role::Percival, Role::Good, Role::Mordred, Role::Morgen, Role::Assassin, Role::Oberon, Role::Bad];
+const USAGE: &str = "Usage: /claim <role> or /claim mermaid <name> good|bad";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Said {
+    Role(Role),
+    // What the mermaid showed about the checked seat
+    Mermaid(u8, Team),
+}
+
+// Public claim of the player, shown in /status and revealed as truth or lie in the summary
+#[derive(Clone, Debug, PartialEq)]
+pub struct Claim {
+    pub seat: u8,
+    pub said: Said,
+}
+
+impl Claim {
+    pub fn is_true(&self, roles: &[Role]) -> bool {
+        match &self.said {
+            Said::Role(Role::Good) => matches!(roles[self.seat as usize], Role::Good | Role::Good2),
+            Said::Role(role) => roles[self.seat as usize] == *role,
+            Said::Mermaid(checked, team) => roles[*checked as usize].is_good() == (*team == Team::Good),
+        }
+    }
+}
+
+fn parse_role(info: &GameInfo, text: &str) -> Option<Role> {
+    let theme = info.theme.table();
+    ROLES.into_iter().find(|role| {
+        role.to_string().eq_ignore_ascii_case(text) || theme.role(role).to_lowercase() == text.to_lowercase()
+    })
+}
+
+fn parse(info: &GameInfo, text: &str) -> Result<Said, String> {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("mermaid ") else {
+        return parse_role(info, text).map(Said::Role).ok_or_else(|| format!("Unknown role '{}'. {}", text, USAGE));
+    };
+    let (name, team) = rest.trim().rsplit_once(' ').ok_or(USAGE)?;
+    let team = match team.to_lowercase().as_str() {
+        "good" => Team::Good,
+        "bad" => Team::Bad,
+        _ => return Err(USAGE.to_string()),
+    };
+    let name = name.trim();
+    let seat = info.players.iter()
+        .position(|player| info.user_names.get(player).is_some_and(|player_name| player_name.to_lowercase() == name.to_lowercase()))
+        .ok_or_else(|| format!("There is no player {} in the game", name))?;
+    Ok(Said::Mermaid(seat as u8, team))
+}
+
+// /claim of the player, the session checks it against the names of the game
+pub async fn handle(ctx: &mut BotCtx, message: &Message, text: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    if text.trim().is_empty() {
+        ctx.bot.send_message(chat_id, USAGE).await?;
+        return respond(());
+    }
+    let Some(session) = crate::get_game_session_without_cleanup(ctx, message) else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    session.send(SessionCommand::Claim { chat_id, text: text.to_string() });
+
+    respond(())
+}
+
+// Called by the session task. The claim is announced to everybody and kept until the end of the game
+pub async fn add(session: &mut GameSession, chat_id: ChatId, text: &str) {
+    let mut outbox = Outbox::default();
+    let info = session.info.clone().filter(|_| !session.finished);
+    let seat = info.as_ref().and_then(|info| info.players.iter().position(|player| *player == chat_id));
+    let (Some(info), Some(seat)) = (info, seat) else {
+        outbox.send(chat_id, "Claims are made by the players during the game");
+        outbox.flush(&session.bot).await;
+        return;
+    };
+    if session.claims.len() >= MAX_CLAIMS {
+        outbox.send(chat_id, "Too many claims in this game");
+        outbox.flush(&session.bot).await;
+        return;
+    }
+
+    match parse(&info, text) {
+        Ok(said) => {
+            let claim = Claim { seat: seat as u8, said };
+            let line = crate::game_msg::claim_line(&info, &claim);
+            session.claims.push(claim);
+            crate::send_everybody(&session.bot, &info, &line).await;
+        }
+        Err(e) => {
+            outbox.send(chat_id, e);
+            outbox.flush(&session.bot).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claims_are_checked_against_roles() {
+        let roles = [Role::Merlin, Role::Good2, Role::Assassin];
+        let claim = |seat, said| Claim { seat, said };
+        assert!(claim(0, Said::Role(Role::Merlin)).is_true(&roles));
+        assert!(claim(1, Said::Role(Role::Good)).is_true(&roles));
+        assert!(!claim(2, Said::Role(Role::Percival)).is_true(&roles));
+        assert!(claim(0, Said::Mermaid(2, Team::Bad)).is_true(&roles));
+        assert!(!claim(2, Said::Mermaid(0, Team::Bad)).is_true(&roles));
+    }
+}
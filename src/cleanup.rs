@@ -0,0 +1,170 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::BotCtx;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// Callback data of the ready check button
+pub const READY: &str = "lobby_ready";
+
+// Last time the lobby member sent anything to the bot
+#[derive(Clone, Copy, Debug)]
+pub struct Activity {
+    since: Instant,
+    // The ready check was sent after that
+    checked: bool,
+}
+
+impl Activity {
+    pub fn now() -> Self {
+        Self { since: Instant::now(), checked: false }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum IdleAction {
+    ReadyCheck,
+    Kick,
+}
+
+// The member is asked if they are still here in the middle of the idle time
+fn idle_action(activity: &Activity, idle: Duration) -> Option<IdleAction> {
+    let elapsed = activity.since.elapsed();
+    if elapsed >= idle {
+        Some(IdleAction::Kick)
+    } else if elapsed >= idle / 2 && !activity.checked {
+        Some(IdleAction::ReadyCheck)
+    } else {
+        None
+    }
+}
+
+// Removes the finished games and the lobbies nobody touched for `ttl`,
+// so players are not kept in them forever. Lobby members idle for `lobby_idle` are dropped
+pub fn spawn_cleanup(ctx_arc: Arc<Mutex<BotCtx>>, ttl: Duration, lobby_idle: Option<Duration>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let ctx = &mut *ctx_arc.lock().await;
+            remove_stale_sessions(ctx, ttl).await;
+            if let Some(idle) = lobby_idle {
+                remove_idle_members(ctx, idle).await;
+            }
+        }
+    })
+}
+
+// Stale joins would keep the lobby from the number of players the leader waits for.
+// The leader is never removed, and nobody is removed while the start countdown runs
+pub async fn remove_idle_members(ctx: &mut BotCtx, idle: Duration) {
+    let members = ctx.user_games.iter()
+        .filter(|(chat_id, game_id)| {
+            !ctx.countdowns.contains_key(game_id) && ctx.game_sessions.get(game_id)
+                .is_some_and(|session| session.leader != **chat_id && !session.status().started)
+        })
+        .map(|(chat_id, game_id)| (*chat_id, *game_id))
+        .collect::<Vec<_>>();
+
+    for (chat_id, game_id) in members {
+        // Members restored after the restart are counted from now
+        let activity = ctx.lobby_activity.entry(chat_id).or_insert_with(Activity::now);
+        match idle_action(activity, idle) {
+            Some(IdleAction::ReadyCheck) => {
+                activity.checked = true;
+                let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("✋ I'm here", READY)]]);
+                let text = format!("Are you still here? You will be removed from the lobby in {} minutes without an answer",
+                                   (idle - idle / 2).as_secs().div_ceil(60));
+                let _ = ctx.bot.send_message(chat_id, text).reply_markup(keyboard).await;
+            }
+            Some(IdleAction::Kick) => {
+                ctx.lobby_activity.remove(&chat_id);
+                crate::switcher::leave(ctx, chat_id);
+                let text = "You were removed from the lobby for inactivity. Use the invite link to join again";
+                let _ = ctx.bot.send_message(chat_id, text).await;
+                if let Some(leader) = ctx.game_sessions.get(&game_id).map(|session| session.leader) {
+                    if !ctx.muted.contains(leader) {
+                        let name = crate::get_display_name(ctx, chat_id);
+                        let _ = ctx.bot.send_message(leader, format!("{} was removed from the lobby for inactivity", name)).await;
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    let user_games = &ctx.user_games;
+    ctx.lobby_activity.retain(|chat_id, _| user_games.contains_key(chat_id));
+}
+
+pub async fn remove_stale_sessions(ctx: &mut BotCtx, ttl: Duration) {
+    let mut finished = Vec::new();
+    let mut expired = Vec::new();
+    for (id, session) in &ctx.game_sessions {
+        let status = session.status();
+        // The players were told about the crash, they are freed right away
+        if status.crashed || !session.is_running() {
+            finished.push(*id);
+            continue;
+        }
+        // Finished games are kept for a while for /restart and /transcript
+        if status.idle_since.elapsed() < ttl {
+            continue;
+        }
+        if status.finished {
+            finished.push(*id);
+        } else if !status.started {
+            expired.push(*id);
+        }
+    }
+
+    for id in finished.iter().chain(&expired) {
+        if let Some(session) = ctx.game_sessions.remove(id) {
+            session.cancel();
+            ctx.countdowns.remove(id);
+            ctx.storage.save_session(session.id, session.leader, true);
+            crate::cluster::release(ctx, session.id);
+        }
+        ctx.other_games.remove_game(*id);
+    }
+    if !finished.is_empty() || !expired.is_empty() {
+        tracing::info!("Removed finished games {:?} and expired lobbies {:?}", finished, expired);
+    }
+
+    // Also drops the players of the games which were not restored after restart
+    let stale = ctx.user_games.iter()
+        .filter(|(_, id)| !ctx.game_sessions.contains_key(id))
+        .map(|(chat_id, id)| (*chat_id, *id))
+        .collect::<Vec<_>>();
+    for (chat_id, id) in stale {
+        crate::switcher::leave(ctx, chat_id);
+        if expired.contains(&id) {
+            let text = "The game was closed because it was not started for too long. Use /new_game to create a new one";
+            let _ = ctx.bot.send_message(chat_id, text).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_member_is_checked_then_kicked() {
+        let idle = Duration::from_secs(600);
+        let mut activity = Activity::now();
+        assert_eq!(idle_action(&activity, idle), None);
+        tokio::time::advance(Duration::from_secs(300)).await;
+        assert_eq!(idle_action(&activity, idle), Some(IdleAction::ReadyCheck));
+        activity.checked = true;
+        assert_eq!(idle_action(&activity, idle), None);
+        tokio::time::advance(Duration::from_secs(300)).await;
+        assert_eq!(idle_action(&activity, idle), Some(IdleAction::Kick));
+    }
+}
@@ -0,0 +1,198 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::types::UpdateKind;
+use teloxide::update_listeners::{webhooks, UpdateListener};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::config::ClusterConfig;
+use crate::storage::StoreResult;
+use crate::{Bot, BotCtx};
+
+// Requests forwarded between the instances are checked with the webhook secret
+pub const SECRET_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+// This instance among the others sharing the storage. Each game is run by the instance
+// holding its lease, the updates of its players received by other instances are forwarded to it
+#[derive(Clone)]
+pub struct Cluster {
+    pub instance_id: String,
+    // Internal URL of the http server of this instance
+    pub url: String,
+    pub lease: Duration,
+    pub secret: String,
+    client: reqwest::Client,
+}
+
+impl Cluster {
+    pub fn new(config: &ClusterConfig, secret: String) -> Self {
+        Self {
+            instance_id: config.instance_id.clone(),
+            url: config.url.clone(),
+            lease: Duration::from_secs(config.lease_secs),
+            secret,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // Takes or renews the lease of the game. Returns the url of the instance which owns it
+    pub fn claim(&self, ctx: &BotCtx, game_id: u32) -> StoreResult<Option<String>> {
+        ctx.storage.claim_game(game_id, &self.instance_id, &self.url, self.lease)
+    }
+}
+
+// Lets other instances take the game without waiting for the lease to expire
+pub fn release(ctx: &BotCtx, game_id: u32) {
+    if let Some(cluster) = &ctx.cluster {
+        ctx.storage.release_game(game_id, &cluster.instance_id);
+    }
+}
+
+#[derive(Clone)]
+pub struct RemoteOwner {
+    url: String,
+    cluster: Cluster,
+}
+
+// Chat of the player who sent the update. The buttons and the poll answers are routed by the user,
+// as their private chat is the one the game knows
+fn sender(update: &Update) -> Option<(ChatId, Option<&str>)> {
+    match &update.kind {
+        UpdateKind::Message(message) => Some((message.chat.id, message.text())),
+        UpdateKind::CallbackQuery(query) => Some((ChatId(query.from.id.0 as i64), None)),
+        UpdateKind::PollAnswer(answer) => Some((ChatId(answer.user.id.0 as i64), None)),
+        _ => None,
+    }
+}
+
+// The game the update is about: the current game of the player, whose owner also answers
+// that they are already in the game, or the one they join with the invite link
+fn target_game(ctx: &BotCtx, chat_id: ChatId, text: Option<&str>) -> StoreResult<Option<u32>> {
+    if let Some(game_id) = ctx.user_games.get(&chat_id) {
+        return Ok(Some(*game_id));
+    }
+    if let Some(game_id) = ctx.storage.load_user_game(chat_id)? {
+        return Ok(Some(game_id));
+    }
+    Ok(text
+        .and_then(|text| text.strip_prefix("/start "))
+        .and_then(|param| param.trim().parse().ok()))
+}
+
+// Finds the instance which runs the game of the update. Games without a running owner are
+// taken over by this instance, updates of other games are handled here too
+pub async fn remote_owner(update: Update, ctx: Arc<Mutex<BotCtx>>) -> Option<RemoteOwner> {
+    let ctx = &mut *ctx.lock().await;
+    let cluster = ctx.cluster.clone()?;
+    let (chat_id, text) = sender(&update)?;
+
+    let game_id = match target_game(ctx, chat_id, text) {
+        Ok(game_id) => game_id?,
+        Err(e) => {
+            tracing::warn!("Failed to find the game of {}: {}", chat_id, e);
+            return None;
+        }
+    };
+    if ctx.game_sessions.contains_key(&game_id) {
+        return None;
+    }
+
+    match cluster.claim(ctx, game_id) {
+        Ok(Some(url)) => Some(RemoteOwner { url, cluster }),
+        Ok(None) => {
+            take_over(ctx, game_id).await;
+            None
+        }
+        Err(e) => {
+            tracing::warn!("Failed to claim game {}: {}", game_id, e);
+            None
+        }
+    }
+}
+
+// Restores the game which was run by another instance from the storage
+async fn take_over(ctx: &mut BotCtx, game_id: u32) {
+    let state = match ctx.storage.load() {
+        Ok(state) => state,
+        Err(e) => {
+            tracing::warn!("Failed to load game {}: {}", game_id, e);
+            release(ctx, game_id);
+            return;
+        }
+    };
+    let Some(stored) = state.sessions.into_iter().find(|session| session.id == game_id) else {
+        // Finished games are not restored
+        release(ctx, game_id);
+        return;
+    };
+
+    tracing::info!("Taking over game {}", game_id);
+    ctx.users.extend(state.users);
+    ctx.user_games.extend(state.user_games.into_iter().filter(|(_, id)| *id == game_id));
+    crate::restore_session(ctx, stored).await;
+}
+
+pub async fn forward(update: Update, owner: RemoteOwner) -> ResponseResult<()> {
+    let url = format!("{}/internal/update", owner.url.trim_end_matches('/'));
+    let result = owner.cluster.client.post(&url)
+        .header(SECRET_HEADER, &owner.cluster.secret)
+        .json(&update)
+        .send().await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = result {
+        tracing::warn!("Failed to forward update {} to {}: {}", update.id, url, e);
+    }
+    respond(())
+}
+
+// Renews the leases of the games of this instance. A game whose lease was taken by another
+// instance, e.g. after this one was paused for too long, is dropped without saving,
+// so two instances never run it at once
+pub fn spawn_lease_renewal(ctx_arc: Arc<Mutex<BotCtx>>, cluster: Cluster) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(cluster.lease / 3);
+        loop {
+            interval.tick().await;
+            renew_leases(&mut *ctx_arc.lock().await, &cluster);
+        }
+    })
+}
+
+fn renew_leases(ctx: &mut BotCtx, cluster: &Cluster) {
+    let ids = ctx.game_sessions.keys().cloned().collect::<Vec<_>>();
+    for id in ids {
+        match cluster.claim(ctx, id) {
+            Ok(None) => {}
+            Ok(Some(url)) => {
+                tracing::info!("Game {} was taken over by {}", id, url);
+                if let Some(session) = ctx.game_sessions.remove(&id) {
+                    session.cancel();
+                }
+                ctx.user_games.retain(|_, game_id| *game_id != id);
+            }
+            Err(e) => tracing::warn!("Failed to renew the lease of game {}: {}", id, e),
+        }
+    }
+}
+
+// All instances set the same webhook and none of them deletes it on stop,
+// so the others keep receiving updates during the restart
+pub async fn webhook_listener(bot: &Bot, options: webhooks::Options, secret: String)
+    -> ResponseResult<impl UpdateListener<Err = Infallible>> {
+    bot.set_webhook(options.url.clone()).secret_token(secret).await?;
+
+    let address = options.address;
+    let (listener, stopped, router) = webhooks::axum_no_setup(options);
+    tokio::spawn(async move {
+        let server = axum::Server::bind(&address)
+            .serve(router.into_make_service())
+            .with_graceful_shutdown(stopped);
+        if let Err(e) = server.await {
+            tracing::error!("Webhook server error: {}", e);
+        }
+    });
+    Ok(listener)
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+use crate::game::{GameClient, MissionVote, Phase, Team, TeamVote, ID};
+
+// Game actions sent as commands with the argument in the name, like /team_approve or /suggest_2.
+// Player ids are indexes of the players in the game
+#[derive(PartialEq, Debug, Clone)]
+pub enum GameAction {
+    FinishSuggestion,
+    // Adds the player to the suggested team or removes them from it
+    ToggleSuggestion(u8),
+    // Whole team chosen in the web app, see webapp.rs
+    SuggestTeam(Vec<u8>),
+    TeamVote(TeamVote),
+    MissionVote(MissionVote),
+    MermaidCheck(u8),
+    MermaidWord(Team),
+    NameMerlin(u8),
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ParseError {
+    UnknownCommand,
+    MissingArgument { command: &'static str, expected: &'static str },
+    InvalidArgument { command: &'static str, argument: String, expected: &'static str },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand => write!(f, "Unknown command"),
+            ParseError::MissingArgument { command, expected } => {
+                write!(f, "{} needs an argument: {}", command, expected)
+            }
+            ParseError::InvalidArgument { command, argument, expected } => {
+                write!(f, "Invalid argument '{}' of {}, expected {}", argument, command, expected)
+            }
+        }
+    }
+}
+
+const PLAYER_ID: &str = "player number";
+
+impl GameAction {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        // Commands in groups are sent as /team_approve@bot_name
+        let word = text.split(|c: char| c.is_whitespace() || c == '@').next().unwrap_or_default();
+        let (command, argument) = match word.split_once('_') {
+            Some((command, argument)) => (command, Some(argument)),
+            None => (word, None),
+        };
+
+        match command {
+            "/suggest" => match argument {
+                Some("finish") => Ok(GameAction::FinishSuggestion),
+                argument => parse_player("/suggest", argument).map(GameAction::ToggleSuggestion),
+            },
+            "/team" => parse_word("/team", argument, "approve or reject", |vote| match vote {
+                "approve" => Some(TeamVote::Approve),
+                "reject" => Some(TeamVote::Reject),
+                _ => None,
+            }).map(GameAction::TeamVote),
+            "/mission" => parse_word("/mission", argument, "success or fail", |vote| match vote {
+                "success" => Some(MissionVote::Success),
+                "fail" => Some(MissionVote::Fail),
+                _ => None,
+            }).map(GameAction::MissionVote),
+            "/mermaid" => parse_player("/mermaid", argument).map(GameAction::MermaidCheck),
+            "/say" => parse_word("/say", argument, "good or bad", |word| match word {
+                "good" => Some(Team::Good),
+                "bad" => Some(Team::Bad),
+                _ => None,
+            }).map(GameAction::MermaidWord),
+            "/merlin" => parse_player("/merlin", argument).map(GameAction::NameMerlin),
+            _ => Err(ParseError::UnknownCommand),
+        }
+    }
+
+    // Phase of the game in which the action is accepted
+    pub fn phase(&self) -> Phase {
+        match self {
+            GameAction::FinishSuggestion | GameAction::ToggleSuggestion(_) | GameAction::SuggestTeam(_) => Phase::TeamSuggestion,
+            GameAction::TeamVote(_) => Phase::TeamVote,
+            GameAction::MissionVote(_) => Phase::Mission,
+            GameAction::MermaidCheck(_) => Phase::MermaidCheck,
+            GameAction::MermaidWord(_) => Phase::MermaidWord,
+            GameAction::NameMerlin(_) => Phase::MerlinGuess,
+        }
+    }
+}
+
+// The engine doesn't check who sends the mermaid and Merlin choices, so the sender
+// is checked against the actors of the current phase before anything reaches the engine
+pub async fn is_allowed(cli: &GameClient, seat: ID, action: &GameAction) -> bool {
+    let phase = action.phase();
+    if cli.get_phase().await != phase {
+        return false;
+    }
+    match phase {
+        Phase::TeamVote => true,
+        Phase::Mission => cli.get_current_team().await.contains(&seat),
+        _ => cli.get_waiting_for().await.contains(&seat),
+    }
+}
+
+fn parse_player(command: &'static str, argument: Option<&str>) -> Result<u8, ParseError> {
+    parse_word(command, argument, PLAYER_ID, |id| id.parse::<u8>().ok())
+}
+
+fn parse_word<T>(command: &'static str, argument: Option<&str>, expected: &'static str,
+                 parse: impl Fn(&str) -> Option<T>) -> Result<T, ParseError> {
+    match argument {
+        None | Some("") => Err(ParseError::MissingArgument { command, expected }),
+        Some(argument) => parse(argument).ok_or_else(|| ParseError::InvalidArgument {
+            command,
+            argument: argument.to_string(),
+            expected,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_action() {
+        assert_eq!(GameAction::parse("/suggest_finish"), Ok(GameAction::FinishSuggestion));
+        assert_eq!(GameAction::parse("/suggest_3"), Ok(GameAction::ToggleSuggestion(3)));
+        assert_eq!(GameAction::parse("/team_approve"), Ok(GameAction::TeamVote(TeamVote::Approve)));
+        assert_eq!(GameAction::parse("/team_reject@avalon_bot"), Ok(GameAction::TeamVote(TeamVote::Reject)));
+        assert_eq!(GameAction::parse("/mission_fail"), Ok(GameAction::MissionVote(MissionVote::Fail)));
+        assert_eq!(GameAction::parse("/mermaid_2"), Ok(GameAction::MermaidCheck(2)));
+        assert_eq!(GameAction::parse("/say_good"), Ok(GameAction::MermaidWord(Team::Good)));
+        assert_eq!(GameAction::parse("/merlin_0"), Ok(GameAction::NameMerlin(0)));
+
+        assert_eq!(GameAction::parse("/hello"), Err(ParseError::UnknownCommand));
+        assert_eq!(GameAction::parse("/team"),
+                   Err(ParseError::MissingArgument { command: "/team", expected: "approve or reject" }));
+        assert_eq!(GameAction::parse("/suggest_"),
+                   Err(ParseError::MissingArgument { command: "/suggest", expected: PLAYER_ID }));
+        assert_eq!(GameAction::parse("/merlin_x"), Err(ParseError::InvalidArgument {
+            command: "/merlin",
+            argument: "x".to_string(),
+            expected: PLAYER_ID,
+        }));
+        assert_eq!(GameAction::parse("/mission_maybe").unwrap_err().to_string(),
+                   "Invalid argument 'maybe' of /mission, expected success or fail");
+    }
+}
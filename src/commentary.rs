@@ -0,0 +1,187 @@
+use teloxide::prelude::*;
+use teloxide::types::ParseMode;
+
+use crate::game::{self, GameEvent, MissionVote};
+use crate::game_msg::{self, GameMessage};
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameSession};
+
+// Missions won by a team to win the game
+const MISSIONS_TO_WIN: usize = 3;
+
+// Board as the spectators see it, built from the public events only
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Board {
+    players: usize,
+    missions: Vec<MissionVote>,
+    try_count: u8,
+    max_try_count: u8,
+}
+
+impl Board {
+    pub fn new(players: usize, max_try_count: u8) -> Self {
+        Self { players, missions: Vec::new(), try_count: 1, max_try_count }
+    }
+
+    fn update(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::TeamApproved(_) => self.try_count = 1,
+            GameEvent::TeamRejected(count) => self.try_count = *count,
+            GameEvent::MissionResult(votes) => {
+                let fails = votes.iter().filter(|vote| **vote == MissionVote::Fail).count();
+                self.missions.push(match fails < game::fails_required(self.missions.len() + 1, self.players) {
+                    true => MissionVote::Success,
+                    false => MissionVote::Fail,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+// Events the spectators may know about. Only the Mermaid holder sees the team of the checked player
+fn spectator_event(event: &GameEvent) -> Option<&GameEvent> {
+    match event {
+        GameEvent::MermaidResult(..) => None,
+        event => Some(event),
+    }
+}
+
+// Hooks of the commentary, each one adds its line to the public event when it has something to say
+type Hook = fn(&Board, &GameEvent) -> Option<String>;
+const HOOKS: [Hook; 3] = [reject_track, streak, odds];
+
+fn reject_track(board: &Board, event: &GameEvent) -> Option<String> {
+    let GameEvent::TeamRejected(count) = event else {
+        return None;
+    };
+    (*count + 1 == board.max_try_count).then(|| "⚠️ One more rejected team and the bad team wins".to_string())
+}
+
+fn streak(board: &Board, event: &GameEvent) -> Option<String> {
+    let (GameEvent::MissionResult(_), Some(last)) = (event, board.missions.last()) else {
+        return None;
+    };
+    let length = board.missions.iter().rev().take_while(|mission| *mission == last).count();
+    match (length, last) {
+        (1, _) => None,
+        (length, MissionVote::Success) => Some(format!("🔥 {} missions in a row succeeded", length)),
+        (length, MissionVote::Fail) => Some(format!("💀 {} missions in a row failed", length)),
+    }
+}
+
+// Chance of the good team to win. The missions left succeed as often as the finished ones did,
+// with one success and one fail added so the first mission doesn't decide everything, and the
+// assassin names a random good player as Merlin
+pub fn good_chance(players: usize, missions: &[MissionVote]) -> f64 {
+    fn win(success: f64, successes: usize, fails: usize) -> f64 {
+        if successes == MISSIONS_TO_WIN {
+            return 1.0;
+        }
+        if fails == MISSIONS_TO_WIN {
+            return 0.0;
+        }
+        success * win(success, successes + 1, fails) + (1.0 - success) * win(success, successes, fails + 1)
+    }
+
+    let successes = missions.iter().filter(|mission| **mission == MissionVote::Success).count();
+    let fails = missions.len() - successes;
+    let success = (successes + 1) as f64 / (missions.len() + 2) as f64;
+    win(success, successes, fails) * (1.0 - 1.0 / game::good_players(players) as f64)
+}
+
+fn odds(board: &Board, event: &GameEvent) -> Option<String> {
+    let GameEvent::MissionResult(_) = event else {
+        return None;
+    };
+    let chance = good_chance(board.players, &board.missions);
+    Some(format!("📊 Good team wins with {:.0}% chance, bad team with {:.0}%", chance * 100.0, (1.0 - chance) * 100.0))
+}
+
+// The public messages of the event with the lines of the hooks
+fn comment(board: &mut Board, event: &GameEvent, messages: &[GameMessage]) -> Option<String> {
+    let event = spectator_event(event)?;
+    board.update(event);
+    let mut parts = game_msg::public_text(messages).into_iter().collect::<Vec<_>>();
+    let lines = HOOKS.iter().filter_map(|hook| hook(board, event)).collect::<Vec<_>>();
+    if !lines.is_empty() {
+        parts.push(format!("🎙 {}", lines.join("\n🎙 ")));
+    }
+    (!parts.is_empty()).then(|| parts.join("\n\n"))
+}
+
+pub async fn send(session: &GameSession, text: &str) {
+    let Some(chat_id) = session.commentator else {
+        return;
+    };
+    if let Err(e) = session.bot.send_message(chat_id, text).parse_mode(ParseMode::Html).await {
+        tracing::warn!("Failed to send the commentary of game {} to {}: {}", session.id, chat_id, e);
+    }
+}
+
+// Called for every event of the game before its messages are sent to the players
+pub async fn on_event(session: &mut GameSession, event: &GameEvent, messages: &[GameMessage]) {
+    if session.commentator.is_none() {
+        return;
+    }
+    if let Some(text) = comment(&mut session.commentary, event, messages) {
+        send(session, &text).await;
+    }
+}
+
+// The leader sends /commentator in the chat of the stream, /commentator off stops the commentary
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let Some(user) = message.from() else {
+        return respond(());
+    };
+    let leader = ChatId(user.id.0 as i64);
+    let session = ctx.user_games.get(&leader).and_then(|game_id| ctx.game_sessions.get(game_id)).cloned();
+    let reply = match (session, args.trim()) {
+        (None, _) => "Create a lobby with /new_game first".to_string(),
+        (Some(session), _) if session.leader != leader => "Only game leader can choose the commentator chat".to_string(),
+        (Some(session), "off") => {
+            session.send(SessionCommand::SetCommentator(None));
+            "The games of the lobby are not commented anymore".to_string()
+        }
+        (Some(session), "") => {
+            tracing::info!("Game {} is commented in {}", session.id, message.chat.id);
+            session.send(SessionCommand::SetCommentator(Some(message.chat.id)));
+            format!("The games of lobby #{} are commented here, without the secrets of the players", session.id)
+        }
+        _ => "Usage: /commentator or /commentator off".to_string(),
+    };
+    ctx.bot.send_message(message.chat.id, reply).await?;
+
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Team;
+
+    #[test]
+    fn test_good_chance() {
+        assert_eq!(format!("{:.2}", good_chance(5, &[])), "0.33");
+        let won = [MissionVote::Success, MissionVote::Success];
+        assert!(good_chance(5, &won) > good_chance(5, &won[..1]));
+        assert_eq!(good_chance(5, &[MissionVote::Fail, MissionVote::Fail, MissionVote::Fail]), 0.0);
+    }
+
+    #[test]
+    fn test_hooks_comment_public_events() {
+        let mut board = Board::new(5, 5);
+        let success = GameEvent::MissionResult(vec![MissionVote::Success; 2]);
+        let text = comment(&mut board, &success, &[]).unwrap();
+        assert!(text.starts_with("🎙 📊 Good team wins with 59% chance"), "{}", text);
+        let text = comment(&mut board, &success, &[]).unwrap();
+        assert!(text.starts_with("🎙 🔥 2 missions in a row succeeded\n🎙 📊"), "{}", text);
+
+        assert_eq!(comment(&mut board, &GameEvent::TeamRejected(3), &[]), None);
+        assert_eq!(comment(&mut board, &GameEvent::TeamRejected(4), &[]).as_deref(),
+                   Some("🎙 ⚠️ One more rejected team and the bad team wins"));
+        assert_eq!(comment(&mut board, &GameEvent::MermaidResult(0, 1, Team::Bad), &[]), None);
+        assert_eq!(board.missions, vec![MissionVote::Success; 2]);
+    }
+}
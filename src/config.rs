@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use serde::Deserialize;
+use teloxide::types::ChatId;
+
+use crate::admin::AdminConfig;
+use crate::media::MediaConfig;
+use crate::nudge::NudgeConfig;
+use crate::reports::ReportsConfig;
+use crate::storage::StorageBackend;
+use crate::timeout::TimeoutSettings;
+use crate::webapp::WebAppConfig;
+
+const DEFAULT_CONFIG_PATH: &str = "avalon.toml";
+// A week, so the durations in minutes can't overflow
+const MAX_MINUTES: u64 = 7 * 24 * 60;
+
+/// The Resistance Avalon Telegram bot
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    /// Path to the TOML configuration file [default: avalon.toml]
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Telegram bot token. TELOXIDE_TOKEN environment variable is used if it is not set anywhere
+    #[arg(long)]
+    pub token: Option<String>,
+    /// Path to the SQLite database
+    #[arg(long)]
+    pub db: Option<String>,
+    /// Log level: error, warn, info, debug or trace
+    #[arg(long)]
+    pub log_level: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameOptions {
+    // How long the game may wait for a player before the reminder
+    pub nudge_secs: u64,
+    // Also tell everybody who is holding the game up
+    pub nudge_group: bool,
+    // Show "typing" in the chats of the players the game waits for
+    pub typing: bool,
+    // off, auto or ai
+    pub timeout: String,
+    pub timeout_minutes: u64,
+    // Lobbies which are not started and finished games are removed after this time without activity
+    pub session_ttl_minutes: u64,
+    // Lobby members who send nothing to the bot for this time are removed, 0 keeps them
+    pub lobby_idle_minutes: u64,
+    // Players /find_game puts together into a lobby, 0 turns the matchmaking off
+    pub match_players: usize,
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        Self {
+            nudge_secs: 120,
+            nudge_group: false,
+            typing: true,
+            timeout: "auto".to_string(),
+            timeout_minutes: 10,
+            session_ttl_minutes: 60,
+            lobby_idle_minutes: 20,
+            match_players: 5,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    // Public URL Telegram sends updates to
+    pub url: String,
+    // Local address the webhook server listens on
+    pub address: SocketAddr,
+    // Secret Telegram sends with every update. Generated on start if not set
+    pub secret_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    // Local address of the monitoring server with /metrics and /healthz
+    pub address: SocketAddr,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApiConfig {
+    // Local address of the REST and WebSocket API for clients other than Telegram
+    pub address: SocketAddr,
+}
+
+// The bot needs the Message Content intent. Games are kept in memory and are not saved
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    // Unique name of this bot instance
+    pub instance_id: String,
+    // URL of the http server of this instance, which other instances forward updates to
+    pub url: String,
+    // Instance which didn't renew the lease of the game in this time is considered stopped
+    #[serde(default = "default_lease_secs")]
+    pub lease_secs: u64,
+}
+
+fn default_lease_secs() -> u64 {
+    30
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: Option<String>,
+    pub admin_ids: Vec<i64>,
+    // Chat which gets /feedback of the players, the admins get it if it is not set
+    pub feedback_chat: Option<i64>,
+    pub storage: StorageBackend,
+    // Path to the SQLite database
+    pub db: String,
+    pub redis_url: String,
+    pub assets_dir: String,
+    pub log_level: String,
+    pub game: GameOptions,
+    // Long polling is used if webhook is not set
+    pub webhook: Option<WebhookConfig>,
+    // Monitoring server is not started if http is not set
+    pub http: Option<HttpConfig>,
+    // Crown holders choose the team with /suggest commands if webapp is not set
+    pub webapp: Option<WebAppConfig>,
+    // Game API is not started if api is not set
+    pub api: Option<ApiConfig>,
+    // Several instances run against the same storage if cluster is set
+    pub cluster: Option<ClusterConfig>,
+    // Discord frontend is not started if discord is not set
+    pub discord: Option<DiscordConfig>,
+    // Errors are only logged if reports is not set
+    pub reports: Option<ReportsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token: None,
+            admin_ids: Vec::new(),
+            feedback_chat: None,
+            storage: StorageBackend::Sqlite,
+            db: "avalon.db".to_string(),
+            redis_url: "redis://127.0.0.1/".to_string(),
+            assets_dir: "assets".to_string(),
+            log_level: "info".to_string(),
+            game: GameOptions::default(),
+            webhook: None,
+            http: None,
+            webapp: None,
+            api: None,
+            cluster: None,
+            discord: None,
+            reports: None,
+        }
+    }
+}
+
+impl Config {
+    // Command line flags override the values from the file
+    pub fn load(args: Args) -> Result<Self, Box<dyn Error>> {
+        let mut config = match &args.config {
+            Some(path) => Self::parse(&std::fs::read_to_string(path)?)?,
+            None => match std::fs::read_to_string(DEFAULT_CONFIG_PATH) {
+                Ok(content) => Self::parse(&content)?,
+                Err(_) => Self::default(),
+            },
+        };
+
+        if args.token.is_some() {
+            config.token = args.token;
+        }
+        if let Some(db) = args.db {
+            config.db = db;
+        }
+        if let Some(log_level) = args.log_level {
+            config.log_level = log_level;
+        }
+
+        if config.token.is_none() {
+            config.token = std::env::var("TELOXIDE_TOKEN").ok();
+        }
+
+        // Fail on start instead of the first game
+        config.check_minutes()?;
+        config.timeout()?;
+        config.check_features()?;
+        config.check_cluster()?;
+        if let Some(webapp) = &config.webapp {
+            webapp.team_url(&[], 1)?;
+        }
+        if let Some(reports) = &config.reports {
+            reports.check()?;
+        }
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    fn check_minutes(&self) -> Result<(), String> {
+        let minutes = [
+            ("timeout_minutes", self.game.timeout_minutes),
+            ("session_ttl_minutes", self.game.session_ttl_minutes),
+            ("lobby_idle_minutes", self.game.lobby_idle_minutes),
+        ];
+        match minutes.into_iter().find(|(_, minutes)| *minutes > MAX_MINUTES) {
+            Some((name, minutes)) => Err(format!("Invalid {} in the config: {}, at most {} minutes", name, minutes, MAX_MINUTES)),
+            None => Ok(()),
+        }
+    }
+
+    // Sections for the parts left out of the build are not ignored silently
+    fn check_features(&self) -> Result<(), String> {
+        if !cfg!(feature = "api") && (self.http.is_some() || self.api.is_some() || self.discord.is_some()) {
+            return Err("[http], [api] and [discord] need the bot built with the api feature".to_string());
+        }
+        if !cfg!(feature = "persistence") && self.storage != StorageBackend::Memory {
+            return Err("The bot is built without the persistence feature, set storage = \"memory\"".to_string());
+        }
+        Ok(())
+    }
+
+    // Instances receive updates from the same webhook and forward them to each other
+    // with the webhook secret through the http server
+    fn check_cluster(&self) -> Result<(), String> {
+        let Some(cluster) = &self.cluster else {
+            return Ok(());
+        };
+        if self.storage == StorageBackend::Memory {
+            return Err("Cluster needs a shared storage: sqlite or redis".to_string());
+        }
+        if self.webhook.as_ref().and_then(|webhook| webhook.secret_token.as_ref()).is_none() {
+            return Err("Cluster needs [webhook] with secret_token".to_string());
+        }
+        if self.http.is_none() {
+            return Err("Cluster needs [http] to receive updates from other instances".to_string());
+        }
+        if cluster.lease_secs == 0 {
+            return Err("Cluster lease_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn nudge(&self) -> NudgeConfig {
+        NudgeConfig {
+            idle: Duration::from_secs(self.game.nudge_secs),
+            notify_group: self.game.nudge_group,
+            typing: self.game.typing,
+        }
+    }
+
+    pub fn timeout(&self) -> Result<TimeoutSettings, String> {
+        let default = TimeoutSettings {
+            policy: crate::timeout::TimeoutPolicy::Auto,
+            duration: Duration::from_secs(self.game.timeout_minutes.saturating_mul(60)),
+        };
+        TimeoutSettings::parse(&format!("{} {}", self.game.timeout, self.game.timeout_minutes), default)
+            .map_err(|e| format!("Invalid timeout in the config: {}", e))
+    }
+
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.game.session_ttl_minutes.min(MAX_MINUTES) * 60)
+    }
+
+    pub fn lobby_idle(&self) -> Option<Duration> {
+        (self.game.lobby_idle_minutes > 0).then(|| Duration::from_secs(self.game.lobby_idle_minutes.min(MAX_MINUTES) * 60))
+    }
+
+    pub fn match_players(&self) -> Result<Option<usize>, String> {
+        match self.game.match_players {
+            0 => Ok(None),
+            players if (crate::game::MIN_PLAYERS..=crate::game::MAX_PLAYERS).contains(&players) => Ok(Some(players)),
+            players => Err(format!("Invalid match_players in the config: games are for {} to {} players, got {}",
+                                   crate::game::MIN_PLAYERS, crate::game::MAX_PLAYERS, players)),
+        }
+    }
+
+    pub fn media(&self) -> MediaConfig {
+        MediaConfig { assets_dir: PathBuf::from(&self.assets_dir) }
+    }
+
+    pub fn admin(&self) -> AdminConfig {
+        AdminConfig {
+            admin_ids: self.admin_ids.iter().cloned().map(ChatId).collect(),
+            log_filter: None,
+            feedback_chat: self.feedback_chat.map(ChatId),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config_has_defaults() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(r#"
+            token = "123:abc"
+            admin_ids = [42]
+            storage = "redis"
+            redis_url = "redis://redis:6379/"
+
+            [game]
+            timeout = "ai"
+            timeout_minutes = 3
+            match_players = 6
+
+            [webhook]
+            url = "https://example.com/avalon"
+            address = "0.0.0.0:8443"
+
+            [http]
+            address = "127.0.0.1:9090"
+
+            [api]
+            address = "0.0.0.0:8080"
+
+            [webapp]
+            url = "https://example.com/avalon/team.html"
+
+            [discord]
+            token = "discord-token"
+
+            [reports]
+            webhook_url = "https://example.com/avalon/errors"
+        "#).unwrap();
+
+        assert_eq!(config.token.as_deref(), Some("123:abc"));
+        assert_eq!(config.storage, StorageBackend::Redis);
+        assert_eq!(config.redis_url, "redis://redis:6379/");
+        assert!(config.admin().is_admin(ChatId(42)));
+        assert_eq!(config.game.nudge_secs, 120);
+        assert_eq!(config.timeout().unwrap().duration, Duration::from_secs(180));
+        assert_eq!(config.match_players(), Ok(Some(6)));
+        assert_eq!(config.webhook.unwrap().address.port(), 8443);
+        assert_eq!(config.http.unwrap().address.port(), 9090);
+        assert_eq!(config.api.unwrap().address.port(), 8080);
+        assert_eq!(config.webapp.unwrap().url, "https://example.com/avalon/team.html");
+        assert_eq!(config.discord.unwrap().token, "discord-token");
+        assert_eq!(config.reports.as_ref().map(|reports| reports.check()), Some(Ok(())));
+    }
+
+    #[test]
+    fn test_cluster_needs_webhook_and_http() {
+        let cluster = r#"
+            [cluster]
+            instance_id = "avalon-1"
+            url = "http://10.0.0.1:9090"
+        "#;
+        let config = Config::parse(cluster).unwrap();
+        assert_eq!(config.cluster.as_ref().unwrap().lease_secs, 30);
+        assert!(config.check_cluster().is_err());
+
+        let config = Config::parse(&format!(r#"
+            storage = "redis"
+            [webhook]
+            url = "https://example.com/avalon"
+            address = "0.0.0.0:8443"
+            secret_token = "secret"
+            [http]
+            address = "0.0.0.0:9090"
+            {}
+        "#, cluster)).unwrap();
+        assert_eq!(config.check_cluster(), Ok(()));
+    }
+
+    #[test]
+    fn test_sections_need_features() {
+        let config = Config::parse("storage = \"redis\"\n[api]\naddress = \"127.0.0.1:8080\"").unwrap();
+        assert_eq!(config.check_features().is_ok(), cfg!(all(feature = "api", feature = "persistence")));
+        assert_eq!(Config::parse("storage = \"memory\"").unwrap().check_features(), Ok(()));
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        let config = Config::parse(include_str!("../avalon.example.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_huge_minutes_are_error() {
+        let config = Config::parse("[game]\ntimeout_minutes = 999999999999999999").unwrap();
+        assert!(config.check_minutes().is_err());
+        assert!(config.timeout().is_err());
+        let config = Config::parse("[game]\nsession_ttl_minutes = 999999999999999999").unwrap();
+        assert!(config.check_minutes().is_err());
+        assert_eq!(Config::default().check_minutes(), Ok(()));
+    }
+
+    #[test]
+    fn test_unknown_option_is_error() {
+        assert!(Config::parse("tokne = \"123:abc\"").is_err());
+    }
+}
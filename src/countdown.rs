@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use tokio::sync::Mutex;
+
+use crate::session::SessionHandle;
+use crate::BotCtx;
+
+// Time to cancel the mistaken start, the players who join meanwhile get into the game
+pub const COUNTDOWN: Duration = Duration::from_secs(10);
+// Callback data of the cancel button
+pub const CANCEL: &str = "cancel_start";
+
+// Roles are dealt when the countdown ends, to the lobby members at that moment
+pub async fn begin(ctx: &mut BotCtx, shared: &Arc<Mutex<BotCtx>>, message: &Message, session: SessionHandle) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    if session.leader != chat_id {
+        ctx.bot.send_message(chat_id, "Only game leader can start the game").await?;
+        return respond(());
+    }
+    if ctx.countdowns.contains_key(&session.id) {
+        ctx.bot.send_message(chat_id, "The game is already starting").await?;
+        return respond(());
+    }
+
+    let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("❌ Cancel", CANCEL)]]);
+    let text = format!("The game starts in {} seconds", COUNTDOWN.as_secs());
+    for member in crate::lobby_members(ctx, session.id) {
+        ctx.bot.send_message(member, &text).reply_markup(keyboard.clone()).await?;
+    }
+
+    let (id, shared, cancelled) = (session.id, shared.clone(), session.token());
+    let countdown = tokio::spawn(async move {
+        // The game was stopped or removed by the cleanup before the start
+        tokio::select! {
+            _ = cancelled.cancelled() => return,
+            _ = tokio::time::sleep(COUNTDOWN) => {}
+        }
+        let ctx = &mut *shared.lock().await;
+        // Cancelled while the task was waiting for the lock
+        if ctx.countdowns.remove(&id).is_some() {
+            crate::start_lobby(ctx, &session);
+        }
+    });
+    ctx.countdowns.insert(id, countdown.abort_handle());
+    respond(())
+}
+
+// Any member of the lobby may cancel the start. Returns the text of the popup
+pub async fn cancel(ctx: &mut BotCtx, query: &CallbackQuery) -> ResponseResult<Option<&'static str>>
+{
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some(game_id) = ctx.user_games.get(&chat_id).cloned() else {
+        return Ok(Some("You are not in this game"));
+    };
+    let Some(countdown) = ctx.countdowns.remove(&game_id) else {
+        return Ok(Some("The game is already started"));
+    };
+    countdown.abort();
+
+    let text = format!("{} cancelled the start. The leader can use /start_game again", crate::get_display_name(ctx, chat_id));
+    for member in crate::lobby_members(ctx, game_id) {
+        ctx.bot.send_message(member, &text).await?;
+    }
+    Ok(None)
+}
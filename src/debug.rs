@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use crate::game::{self, ID};
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameSession};
+
+fn parse_players(args: &str) -> Result<usize, String> {
+    let usage = format!("Usage: /debug_game <players from {} to {}>", game::MIN_PLAYERS, game::MAX_PLAYERS);
+    match args.trim().parse::<usize>() {
+        Ok(players) if (game::MIN_PLAYERS..=game::MAX_PLAYERS).contains(&players) => Ok(players),
+        _ => Err(usage),
+    }
+}
+
+// Chats of the AI players don't exist, the negative ids are never taken by the private chats
+fn bots(players: usize) -> Vec<ChatId> {
+    (1..players as i64).map(|seat| ChatId(-seat)).collect()
+}
+
+// Only the admins can start it: the developer plays alone against the AI in every other seat
+// to click through every phase of the game
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    if !ctx.admin.is_admin(chat_id) {
+        ctx.bot.send_message(chat_id, "Unknown command").await?;
+        return respond(());
+    }
+    if crate::get_game_session(ctx, message).await.is_some() {
+        ctx.bot.send_message(chat_id, "You are already in the game. Use /exit to leave it").await?;
+        return respond(());
+    }
+    let players = match parse_players(args) {
+        Ok(players) => players,
+        Err(e) => {
+            ctx.bot.send_message(chat_id, e).await?;
+            return respond(());
+        }
+    };
+
+    let Some(game_id) = crate::create_session(ctx, message).await? else {
+        return respond(());
+    };
+    let name = crate::get_display_name(ctx, chat_id);
+    if let Some(session) = ctx.game_sessions.get(&game_id) {
+        session.send(SessionCommand::DebugStart { players, name });
+    }
+    respond(())
+}
+
+// Called by the session task. The leader takes the first seat, the crown is random as usual
+pub async fn start(session: &mut GameSession, players: usize, name: String) {
+    let bots = bots(players);
+    let mut user_names = bots.iter()
+        .enumerate()
+        .map(|(seat, bot)| (*bot, Arc::from(format!("🤖 AI {}", seat + 1))))
+        .collect::<HashMap<_, _>>();
+    user_names.insert(session.leader, Arc::from(name));
+    let players = std::iter::once(session.leader).chain(bots.iter().cloned()).collect();
+
+    session.bots = bots.into_iter().collect();
+    if let Err(e) = crate::start_game(session, players, user_names).await {
+        tracing::warn!("Failed to start debug game {}: {}", session.id, e);
+        return;
+    }
+    session.ai_seats = (1..session.bots.len() as ID + 1).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_game_size_is_checked() {
+        assert_eq!(parse_players(" 7 "), Ok(7));
+        assert!(parse_players("1").is_err());
+        assert!(parse_players("8").is_err());
+        assert!(parse_players("").is_err());
+        assert_eq!(bots(3), vec![ChatId(-1), ChatId(-2)]);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardMarkup, ParseMode};
+use teloxide::{ApiError, RequestError};
+
+use crate::{game_msg, Bot};
+
+const MAX_ATTEMPTS: u32 = 3;
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+// Failed messages in a row after which the player is considered unreachable
+const MAX_FAILURES: u32 = 3;
+
+// Delivery failures of the game players
+#[derive(Default, Debug)]
+pub struct DeliveryState {
+    failures: HashMap<ChatId, u32>,
+    unreachable: HashSet<ChatId>,
+}
+
+impl DeliveryState {
+    pub fn on_success(&mut self, chat_id: ChatId) {
+        self.failures.remove(&chat_id);
+        self.unreachable.remove(&chat_id);
+    }
+
+    // Returns true when the player has just become unreachable
+    pub fn on_failure(&mut self, chat_id: ChatId, permanent: bool) -> bool {
+        let failures = self.failures.entry(chat_id).or_default();
+        *failures += 1;
+
+        if (permanent || *failures >= MAX_FAILURES) && !self.unreachable.contains(&chat_id) {
+            self.unreachable.insert(chat_id);
+            return true;
+        }
+        false
+    }
+}
+
+// Errors which won't go away if the message is sent again
+pub fn is_permanent(err: &RequestError) -> bool {
+    matches!(err, RequestError::Api(ApiError::BotBlocked
+                                   | ApiError::BotKicked
+                                   | ApiError::UserDeactivated
+                                   | ApiError::ChatNotFound
+                                   | ApiError::CantInitiateConversation))
+}
+
+fn is_transient(err: &RequestError) -> bool {
+    matches!(err, RequestError::Network(_) | RequestError::Io(_))
+}
+
+// RetryAfter is already handled by the throttling adaptor, so only network errors are retried here.
+// The text is HTML of game_msg.rs, the secret one is hidden under a spoiler and can't be forwarded
+pub async fn send_with_retry(bot: &Bot, chat_id: ChatId, text: &str, keyboard: Option<&InlineKeyboardMarkup>, secret: bool)
+    -> Result<Message, RequestError>
+{
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempt = 1;
+    let text = if secret { game_msg::spoiler(text) } else { text.to_string() };
+    loop {
+        let mut request = bot.send_message(chat_id, &text)
+            .parse_mode(ParseMode::Html)
+            .protect_content(secret);
+        if let Some(keyboard) = keyboard {
+            request = request.reply_markup(keyboard.clone());
+        }
+        match request.await {
+            Err(e) if is_transient(&e) && attempt < MAX_ATTEMPTS => {
+                tracing::warn!("Failed to send message to {} (attempt {}): {}", chat_id, attempt, e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_becomes_unreachable_once() {
+        let mut state = DeliveryState::default();
+        let chat_id = ChatId(1);
+        assert!(!state.on_failure(chat_id, false));
+        assert!(!state.on_failure(chat_id, false));
+        assert!(state.on_failure(chat_id, false));
+        assert!(!state.on_failure(chat_id, false));
+    }
+
+    #[test]
+    fn test_success_resets_failures() {
+        let mut state = DeliveryState::default();
+        let chat_id = ChatId(1);
+        assert!(state.on_failure(chat_id, true));
+        state.on_success(chat_id);
+        assert!(!state.on_failure(chat_id, false));
+        assert!(state.on_failure(chat_id, true));
+    }
+
+    #[test]
+    fn test_blocked_bot_is_permanent() {
+        assert!(is_permanent(&RequestError::Api(ApiError::BotBlocked)));
+        assert!(!is_permanent(&RequestError::RetryAfter(Duration::from_secs(1))));
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serenity::async_trait;
+use serenity::client::{Client, Context, EventHandler};
+use serenity::http::Http;
+use serenity::model::channel::Message;
+use serenity::model::gateway::GatewayIntents;
+use serenity::model::id::{ChannelId, UserId};
+use teloxide::types::ChatId;
+use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
+use tracing::Instrument;
+
+use crate::commands::GameAction;
+use crate::config::DiscordConfig;
+use crate::game::{self, GameClient, GameEvent, TeamVote, ID};
+use crate::game_msg::{self, ComposedMessage, MessageRenderer};
+use crate::journal::Move;
+use crate::theme::Theme;
+use crate::{media, GameInfo};
+
+// Discord frontend: lobbies are created in server channels, the roles, the game messages
+// and the actions go through direct messages like in Telegram.
+// Discord clients take over messages starting with /, so the commands start with !
+const PREFIX: char = '!';
+
+struct DiscordRenderer;
+
+// Markdown text, the commands are typed since the direct messages have no buttons
+impl MessageRenderer for DiscordRenderer {
+    type Output = String;
+
+    fn render(&self, message: &ComposedMessage) -> String {
+        game_msg::to_markdown(&game_msg::render_html(message, PREFIX))
+    }
+}
+
+// Game messages are addressed by Telegram chat ids, Discord users get the same numbers
+fn chat_id(user: UserId) -> ChatId {
+    ChatId(user.0 as i64)
+}
+
+fn user_id(chat_id: ChatId) -> UserId {
+    UserId(chat_id.0 as u64)
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Channel(ChannelId, String),
+    Direct(UserId, String),
+}
+
+struct Running {
+    // Events of the previous games of the table are ignored after !restart
+    generation: u32,
+    info: Arc<GameInfo>,
+    engine: AbortHandle,
+    // Seats which acted since the last event. The engine counts every action it gets,
+    // so repeated ones are stopped here
+    acted: HashSet<ID>,
+    // Seats which tapped Reject on the last try once, the second tap is the vote
+    rejecting: HashSet<ID>,
+    // Team the crown holder is choosing with !suggest_N
+    suggestion: Vec<ID>,
+    finished: bool,
+}
+
+// Lobby of the channel and its game
+struct Table {
+    leader: UserId,
+    players: Vec<(UserId, String)>,
+    game: Option<Running>,
+}
+
+impl Table {
+    fn open(&self) -> bool {
+        self.game.as_ref().is_none_or(|game| !game.finished)
+    }
+}
+
+// Started game whose events are sent to the players by pump_events
+struct Started {
+    channel: ChannelId,
+    generation: u32,
+    cli: GameClient,
+}
+
+#[derive(Default)]
+struct Tables {
+    tables: HashMap<ChannelId, Table>,
+    // Channel of the table of each user
+    seats: HashMap<UserId, ChannelId>,
+    last_generation: u32,
+}
+
+impl Tables {
+    // User is in a lobby or a running game
+    fn busy(&self, user: UserId) -> bool {
+        self.seats.get(&user)
+            .and_then(|channel| self.tables.get(channel))
+            .is_some_and(Table::open)
+    }
+
+    fn new_game(&mut self, channel: ChannelId, in_server: bool, user: UserId, name: String) -> Vec<Reply> {
+        if !in_server {
+            return vec![Reply::Direct(user, "Create the game in a server channel, so others can join it".to_string())];
+        }
+        if self.busy(user) {
+            return vec![Reply::Channel(channel, format!("You are already in the game. Use {}exit to leave it", PREFIX))];
+        }
+        if self.tables.get(&channel).is_some_and(Table::open) {
+            return vec![Reply::Channel(channel, format!("There is a game in this channel already. Use {}join", PREFIX))];
+        }
+
+        if let Some(old) = self.tables.remove(&channel) {
+            for (player, _) in old.players {
+                if self.seats.get(&player) == Some(&channel) {
+                    self.seats.remove(&player);
+                }
+            }
+        }
+        let text = format!("{} created a game. Use {}join to join it, the leader starts it with {}start_game",
+                           name, PREFIX, PREFIX);
+        self.tables.insert(channel, Table { leader: user, players: vec![(user, name)], game: None });
+        self.seats.insert(user, channel);
+        vec![Reply::Channel(channel, text)]
+    }
+
+    fn join(&mut self, channel: ChannelId, user: UserId, name: String) -> Vec<Reply> {
+        if self.busy(user) {
+            return vec![Reply::Channel(channel, "You are already in the game".to_string())];
+        }
+        let Some(table) = self.tables.get_mut(&channel).filter(|table| table.game.is_none()) else {
+            return vec![Reply::Channel(channel, format!("There is no lobby in this channel. Use {}new_game", PREFIX))];
+        };
+        if table.players.len() == game::MAX_PLAYERS {
+            return vec![Reply::Channel(channel, "The lobby is full".to_string())];
+        }
+
+        let text = format!("{} joined the game, {} players", name, table.players.len() + 1);
+        table.players.push((user, name));
+        self.seats.insert(user, channel);
+        vec![Reply::Channel(channel, text)]
+    }
+
+    // Like in Telegram, the running game goes on without the player
+    fn exit(&mut self, user: UserId) -> Vec<Reply> {
+        let Some(channel) = self.seats.remove(&user) else {
+            return vec![Reply::Direct(user, "You are not in the game".to_string())];
+        };
+        let Some(table) = self.tables.get_mut(&channel) else {
+            return Vec::new();
+        };
+        if table.game.is_some() {
+            return vec![Reply::Direct(user, "You left the game".to_string())];
+        }
+
+        if table.leader == user {
+            if let Some(table) = self.tables.remove(&channel) {
+                for (player, _) in table.players {
+                    self.seats.remove(&player);
+                }
+            }
+            return vec![Reply::Channel(channel, "The leader left, the lobby is closed".to_string())];
+        }
+        table.players.retain(|(player, _)| *player != user);
+        vec![Reply::Channel(channel, format!("A player left the game, {} players", table.players.len()))]
+    }
+
+    // Starts the game of the leader's lobby, also used by !restart after the end of the game
+    async fn start(&mut self, user: UserId) -> Result<(Started, Vec<Reply>), Vec<Reply>> {
+        let fail = |text: &str| Err(vec![Reply::Direct(user, text.to_string())]);
+        let Some(channel) = self.seats.get(&user).cloned() else {
+            return fail("You are not in the game");
+        };
+        let generation = self.last_generation + 1;
+        let Some(table) = self.tables.get_mut(&channel) else {
+            return fail("You are not in the game");
+        };
+        if table.leader != user {
+            return fail("Only the game leader can start the game");
+        }
+        if table.game.as_ref().is_some_and(|game| !game.finished) {
+            return fail("The game is already running");
+        }
+        if table.players.len() < game::MIN_PLAYERS {
+            return fail("Not enough players to start the game");
+        }
+
+        let players = table.players.iter().map(|(user, _)| chat_id(*user)).collect::<Vec<_>>();
+        let user_names = table.players.iter().map(|(user, name)| (chat_id(*user), Arc::from(name.as_str()))).collect::<HashMap<_, _>>();
+        let (engine, cli) = game::Game::setup(players.len());
+        let info = GameInfo {
+            id: generation,
+            leader: chat_id(table.leader),
+            players,
+            user_names,
+            cli: cli.clone(),
+            delivery: Default::default(),
+            muted: Default::default(),
+            other_games: Default::default(),
+            theme: Default::default(),
+            narration: false,
+            bots: Default::default(),
+            topic: None,
+        };
+
+        let mut replies = vec![Reply::Channel(channel, format!("Game started with {} players! Check your direct messages",
+                                                               table.players.len()))];
+        let roles = cli.get_player_roles().await;
+        let crown = &table.players[cli.get_crown_id().await as usize].1;
+        let mermaid = &table.players[cli.get_mermaid_id().await as usize].1;
+        for ((player, _), role) in table.players.iter().zip(roles) {
+            let text = format!("Your role: {}. {}\n\n{} has the crown\n{} has the mermaid",
+                               role, media::role_description(&role, Theme::Classic), crown, mermaid);
+            replies.push(Reply::Direct(*player, text));
+        }
+
+        if let Some(previous) = table.game.take() {
+            previous.engine.abort();
+        }
+        table.game = Some(Running {
+            generation,
+            info: Arc::new(info),
+            engine: span(channel).in_scope(|| game::spawn_engine(engine)),
+            acted: HashSet::new(),
+            rejecting: HashSet::new(),
+            suggestion: Vec::new(),
+            finished: false,
+        });
+        self.last_generation = generation;
+        Ok((Started { channel, generation, cli }, replies))
+    }
+
+    fn running(&mut self, channel: ChannelId, generation: u32) -> Option<&mut Running> {
+        self.tables.get_mut(&channel)?.game.as_mut().filter(|game| game.generation == generation)
+    }
+
+    // Checks that the player is expected to act in the current phase like the Telegram bot does
+    async fn act(&mut self, user: UserId, action: GameAction) -> Vec<Reply> {
+        let reply = |text: String| vec![Reply::Direct(user, text)];
+        let Some(running) = self.seats.get(&user).cloned()
+            .and_then(|channel| self.tables.get_mut(&channel))
+            .and_then(|table| table.game.as_mut())
+            .filter(|game| !game.finished) else {
+            return reply("You are not in a running game".to_string());
+        };
+        let Some(seat) = running.info.players.iter().position(|id| *id == chat_id(user)) else {
+            return reply("You are not a player of this game".to_string());
+        };
+        let seat = seat as ID;
+        let mut cli = running.info.cli.clone();
+        if running.acted.contains(&seat)
+            || cli.get_phase().await != action.phase()
+            || !cli.get_waiting_for().await.contains(&seat) {
+            return reply("It's not your turn for that".to_string());
+        }
+
+        let accepted = match action {
+            GameAction::ToggleSuggestion(id) => {
+                if id as usize >= running.info.players.len() {
+                    return reply(format!("There is no player {}", id));
+                }
+                match running.suggestion.iter().position(|selected| *selected == id) {
+                    Some(pos) => {
+                        running.suggestion.remove(pos);
+                    }
+                    None => running.suggestion.push(id),
+                }
+                let team_size = cli.get_expected_team_size().await;
+                let history = cli.get_history().await;
+                let state = game_msg::suggestion_state(&running.info, &history, seat, team_size, &running.suggestion);
+                return reply(DiscordRenderer.render(&ComposedMessage::with_control(chat_id(user), "", state)));
+            }
+            GameAction::FinishSuggestion => Move::SuggestTeam(seat, running.suggestion.clone()),
+            GameAction::SuggestTeam(team) => Move::SuggestTeam(seat, team),
+            GameAction::TeamVote(TeamVote::Reject) if !running.rejecting.contains(&seat)
+                && game_msg::is_last_try(cli.get_try_count().await, cli.get_options().await.max_try_count) => {
+                running.rejecting.insert(seat);
+                return reply(game_msg::reject_confirmation(running.info.theme.table()));
+            }
+            GameAction::TeamVote(vote) => Move::TeamVote(seat, vote),
+            GameAction::MissionVote(vote) => Move::Mission(seat, vote),
+            GameAction::MermaidCheck(id) => Move::MermaidCheck(id),
+            GameAction::MermaidWord(word) => Move::MermaidWord(word),
+            GameAction::NameMerlin(id) => Move::NameMerlin(id),
+        };
+        let targets = match &accepted {
+            Move::SuggestTeam(_, team) => team.clone(),
+            Move::MermaidCheck(id) | Move::NameMerlin(id) => vec![*id],
+            _ => Vec::new(),
+        };
+        if let Some(id) = targets.into_iter().find(|id| *id as usize >= running.info.players.len()) {
+            return reply(format!("There is no player {}", id));
+        }
+
+        match accepted.apply(&mut cli).await {
+            Ok(()) => {
+                running.acted.insert(seat);
+                reply("Accepted".to_string())
+            }
+            Err(e) => reply(e),
+        }
+    }
+}
+
+async fn send(http: &Http, replies: Vec<Reply>) {
+    for reply in replies {
+        let result = match reply {
+            Reply::Channel(channel, text) => channel.say(http, text).await.map(|_| ()),
+            Reply::Direct(user, text) => match user.create_dm_channel(http).await {
+                Ok(dm) => dm.say(http, text).await.map(|_| ()),
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to send Discord message: {}", e);
+        }
+    }
+}
+
+// The channel is the game, there is one game per channel at a time
+fn span(channel: ChannelId) -> tracing::Span {
+    tracing::info_span!("discord", channel = %channel)
+}
+
+// Sends the messages of the engine events to the players until the end of the game
+async fn pump_events(tables: Arc<Mutex<Tables>>, http: Arc<Http>, started: Started) {
+    let Started { channel, generation, mut cli } = started;
+    loop {
+        let event = match cli.recv_event().await.map_err(|e| e.to_string()) {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("Discord game in {} stopped processing events: {}", channel, e);
+                break;
+            }
+        };
+        let finished = matches!(event, GameEvent::GameResult(_));
+
+        let info = {
+            let mut tables = tables.lock().await;
+            let Some(running) = tables.running(channel, generation) else {
+                break;
+            };
+            running.acted.clear();
+            running.rejecting.clear();
+            if let GameEvent::Turn(..) = event {
+                running.suggestion.clear();
+            }
+            running.finished = finished;
+            running.info.clone()
+        };
+
+        let context = game_msg::EventContext::fetch(&info.cli, &event).await;
+        let messages = game_msg::build_message_for_event(&info, &context, event);
+        let mut replies = game_msg::compose(&info.players, &[], messages).into_iter()
+            .map(|composed| Reply::Direct(user_id(composed.chat_id), DiscordRenderer.render(&composed)))
+            .collect::<Vec<_>>();
+        if finished {
+            replies.push(Reply::Channel(channel, "The game is over. Use !new_game to play again".to_string()));
+        }
+        send(&http, replies).await;
+
+        if finished {
+            break;
+        }
+    }
+}
+
+struct Handler {
+    tables: Arc<Mutex<Tables>>,
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, ctx: Context, msg: Message) {
+        if msg.author.bot {
+            return;
+        }
+        let Some(text) = msg.content.strip_prefix(PREFIX) else {
+            return;
+        };
+        let (user, name, channel) = (msg.author.id, msg.author.name.clone(), msg.channel_id);
+
+        let mut tables = self.tables.lock().await;
+        let replies = match text.split_whitespace().next().unwrap_or_default() {
+            "new_game" => tables.new_game(channel, msg.guild_id.is_some(), user, name),
+            "join" => tables.join(channel, user, name),
+            "exit" => tables.exit(user),
+            "start_game" | "restart" => match tables.start(user).await {
+                Ok((started, replies)) => {
+                    let span = span(started.channel);
+                    tokio::spawn(pump_events(self.tables.clone(), ctx.http.clone(), started).instrument(span));
+                    replies
+                }
+                Err(replies) => replies,
+            },
+            _ => match GameAction::parse(&format!("/{}", text)) {
+                Ok(action) => tables.act(user, action).await,
+                Err(e) => vec![Reply::Direct(user, e.to_string())],
+            },
+        };
+        drop(tables);
+        send(&ctx.http, replies).await;
+    }
+}
+
+pub fn spawn(config: DiscordConfig) {
+    tokio::spawn(async move {
+        let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+        let handler = Handler { tables: Arc::default() };
+        let mut client = match Client::builder(&config.token, intents).event_handler(handler).await {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!("Failed to create Discord client: {}", e);
+                return;
+            }
+        };
+        tracing::info!("Starting Discord frontend");
+        if let Err(e) = client.start().await {
+            tracing::error!("Discord client error: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::TeamVote;
+
+    #[tokio::test]
+    async fn test_lobby_and_actions() {
+        let mut tables = Tables::default();
+        let channel = ChannelId(100);
+        let (alice, bob, carol) = (UserId(1), UserId(2), UserId(3));
+
+        assert!(matches!(tables.new_game(channel, false, alice, "Alice".to_string())[0], Reply::Direct(..)));
+        tables.new_game(channel, true, alice, "Alice".to_string());
+        tables.join(channel, bob, "Bob".to_string());
+        tables.join(channel, carol, "Carol".to_string());
+        tables.exit(carol);
+        assert!(!tables.seats.contains_key(&carol));
+        assert!(tables.start(bob).await.is_err());
+
+        let (mut started, replies) = tables.start(alice).await.unwrap();
+        assert_eq!(replies.len(), 3);
+        assert_eq!(tables.join(channel, carol, "Carol".to_string()),
+                   vec![Reply::Channel(channel, "There is no lobby in this channel. Use !new_game".to_string())]);
+
+        let GameEvent::Turn(crown, team_size) = started.cli.recv_event().await.unwrap() else {
+            panic!("The game didn't start with a turn");
+        };
+        let [crown, other] = if crown == 0 { [alice, bob] } else { [bob, alice] };
+        let not_your_turn = vec![Reply::Direct(other, "It's not your turn for that".to_string())];
+        assert_eq!(tables.act(other, GameAction::FinishSuggestion).await, not_your_turn);
+
+        for id in 0..team_size as ID {
+            tables.act(crown, GameAction::ToggleSuggestion(id)).await;
+        }
+        assert_eq!(tables.act(crown, GameAction::FinishSuggestion).await,
+                   vec![Reply::Direct(crown, "Accepted".to_string())]);
+        assert_eq!(started.cli.recv_event().await.unwrap(),
+                   GameEvent::TeamSuggested((0..team_size as ID).collect()));
+
+        tables.running(channel, started.generation).unwrap().acted.clear();
+        tables.act(other, GameAction::TeamVote(TeamVote::Approve)).await;
+        assert_eq!(tables.act(other, GameAction::TeamVote(TeamVote::Reject)).await, not_your_turn);
+    }
+
+    #[test]
+    fn test_renderer_uses_markdown_and_prefix() {
+        let control = game_msg::ControlMessage {
+            dst: game_msg::Dst::User(ChatId(1)),
+            message: "Vote".to_string(),
+            commands: vec!["team_approve".to_string(), format!("merlin_0 {}", game_msg::bold("Al_x"))],
+        };
+        let composed = ComposedMessage::with_control(ChatId(1), &game_msg::italic("Turn"), control);
+        assert_eq!(DiscordRenderer.render(&composed), "*Turn*\n\nVote:\n!team\\_approve\n!merlin\\_0 **Al\\_x**");
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use teloxide::types::ChatId;
+use tokio::time::Instant;
+
+use crate::game::GameEvent;
+use crate::{game_msg, GameInfo, GameSession};
+
+// Players are warned when this much time of the discussion is left
+const LAST_MINUTE: Duration = Duration::from_secs(60);
+// Choices of the /settings menu, zero sends the vote controls at once
+pub const DURATIONS: [Duration; 5] = [
+    Duration::ZERO,
+    Duration::from_secs(60),
+    Duration::from_secs(2 * 60),
+    Duration::from_secs(3 * 60),
+    Duration::from_secs(5 * 60),
+];
+// Time of the bad team to agree on Merlin before the guess
+pub const GUESS_DURATION: Duration = Duration::from_secs(2 * 60);
+
+// Proposed team which the players talk about before the vote, or the guess of Merlin
+// which the bad team talks about in private
+pub struct Discussion {
+    // Event with the vote or guess controls, it is handled when the discussion ends
+    event: GameEvent,
+    ends_at: Instant,
+    warned: bool,
+    // Players who get the chat of the guess discussion, empty for the team discussion
+    bad_team: Vec<ChatId>,
+}
+
+impl Discussion {
+    // When the session task should call on_deadline
+    pub fn deadline(&self) -> Instant {
+        if self.warned { self.ends_at } else { self.ends_at - LAST_MINUTE }
+    }
+
+    // Players whose messages are relayed during the discussion, None if everybody talks
+    pub fn members(&self) -> Option<&[ChatId]> {
+        (!self.bad_team.is_empty()).then_some(&self.bad_team)
+    }
+}
+
+pub fn describe(duration: Duration) -> String {
+    match duration.as_secs() {
+        0 => "off".to_string(),
+        secs => format!("{} min", secs / 60),
+    }
+}
+
+pub fn next_duration(duration: Duration) -> Duration {
+    let pos = DURATIONS.iter().position(|choice| *choice == duration).unwrap_or_default();
+    DURATIONS[(pos + 1) % DURATIONS.len()]
+}
+
+// Returns true when the event is held back until the end of the discussion
+pub async fn start(session: &mut GameSession, info: &GameInfo, event: &GameEvent) -> bool {
+    match event {
+        GameEvent::TeamSuggested(team) => start_team(session, info, event, team).await,
+        GameEvent::BadLastChance(bad_team, guesser) => start_guess(session, info, event, bad_team, *guesser).await,
+        _ => false,
+    }
+}
+
+async fn start_team(session: &mut GameSession, info: &GameInfo, event: &GameEvent, team: &[u8]) -> bool {
+    let duration = session.discussion_time;
+    if duration.is_zero() {
+        return false;
+    }
+
+    session.discussion = Some(Discussion {
+        event: event.clone(),
+        ends_at: Instant::now() + duration,
+        warned: duration <= LAST_MINUTE,
+        bad_team: Vec::new(),
+    });
+    crate::send_everybody(&session.bot, info, &game_msg::discussion_started(info, team, &describe(duration))).await;
+    true
+}
+
+// The chat is open only for the bad team. The AI seats don't talk, so the guess comes at once
+// if the AI guesses or the guesser has nobody to talk to
+async fn start_guess(session: &mut GameSession, info: &GameInfo, event: &GameEvent, bad_team: &[u8], guesser: u8) -> bool {
+    let humans = bad_team.iter().filter(|id| !session.ai_seats.contains(id)).count();
+    if session.ai_seats.contains(&guesser) || humans < 2 {
+        return false;
+    }
+
+    let bad_team = bad_team.iter().map(|id| info.players[*id as usize]).collect::<Vec<_>>();
+    session.discussion = Some(Discussion {
+        event: event.clone(),
+        ends_at: Instant::now() + GUESS_DURATION,
+        warned: false,
+        bad_team: bad_team.clone(),
+    });
+    crate::send_everybody(&session.bot, info, &game_msg::guess_discussion_started(info, &describe(GUESS_DURATION))).await;
+    let text = format!("💬 Only {} gets your messages now", info.theme.table().bad_team);
+    for player in bad_team {
+        crate::deliver(&session.bot, info, player, &text).await;
+    }
+    true
+}
+
+// Called by the session task: warns about the last minute, then sends the vote controls
+pub async fn on_deadline(session: &mut GameSession) {
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    match session.discussion.as_mut() {
+        Some(discussion) if !discussion.warned => {
+            discussion.warned = true;
+            match discussion.members() {
+                Some(bad_team) => {
+                    let text = format!("60 seconds left to agree on {}", info.theme.table().role(&crate::game::Role::Merlin));
+                    for player in bad_team {
+                        crate::deliver(&session.bot, &info, *player, &text).await;
+                    }
+                }
+                None => {
+                    crate::send_everybody(&session.bot, &info, "60 seconds left to discuss the team").await;
+                }
+            }
+        }
+        Some(_) => {
+            if let Some(discussion) = session.discussion.take() {
+                crate::present_game_event(session, &discussion.event, &info).await;
+            }
+        }
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discussion_durations() {
+        assert_eq!(describe(Duration::ZERO), "off");
+        assert_eq!(describe(Duration::from_secs(180)), "3 min");
+        assert_eq!(next_duration(Duration::ZERO), Duration::from_secs(60));
+        assert_eq!(next_duration(Duration::from_secs(300)), Duration::ZERO);
+        // Unknown durations start the cycle again
+        assert_eq!(next_duration(Duration::from_secs(7)), Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use teloxide::prelude::*;
+use teloxide::types::InputFile;
+
+use crate::game::GameResult;
+use crate::stats::FinishedGame;
+use crate::BotCtx;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    // Parses "[csv|json]"
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        match arg.trim() {
+            "" | "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            other => Err(format!("Unknown export format '{}'. Use csv or json", other)),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CoPlayer {
+    name: String,
+    role: String,
+}
+
+#[derive(Serialize)]
+struct ExportedGame {
+    game_id: u32,
+    // UTC
+    finished_at: String,
+    seat: usize,
+    role: String,
+    team: &'static str,
+    won: bool,
+    co_players: Vec<CoPlayer>,
+}
+
+// "2023-11-14 22:13" in UTC, the days are converted to the date as in the proleptic Gregorian calendar
+fn format_time(secs: u64) -> String {
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rest / 3600, rest % 3600 / 60)
+}
+
+// The co-players are named "Player N" in the order they are met in the history,
+// so the same person has the same name in all the games of the file
+fn exported_games(chat_id: ChatId, games: &[FinishedGame]) -> Vec<ExportedGame> {
+    let mut pseudonyms = HashMap::new();
+    games.iter()
+        .filter_map(|game| {
+            let seat = game.players.iter().position(|player| *player == chat_id)?;
+            let role = game.roles.get(seat)?;
+            let co_players = game.players.iter().zip(&game.roles)
+                .filter(|(player, _)| **player != chat_id)
+                .map(|(player, role)| {
+                    let next = pseudonyms.len() + 1;
+                    let number = *pseudonyms.entry(*player).or_insert(next);
+                    CoPlayer { name: format!("Player {}", number), role: role.to_string() }
+                })
+                .collect();
+            Some(ExportedGame {
+                game_id: game.game_id,
+                finished_at: format_time(game.finished_at),
+                seat,
+                role: role.to_string(),
+                team: if role.is_good() { "good" } else { "evil" },
+                won: role.is_good() == (game.result == GameResult::GoodWins),
+                co_players,
+            })
+        })
+        .collect()
+}
+
+// Returns the file name and the content of the document
+pub fn build(chat_id: ChatId, games: &[FinishedGame], format: ExportFormat) -> Result<(String, String), serde_json::Error> {
+    let games = exported_games(chat_id, games);
+    let file_name = format!("avalon_history.{}", format.extension());
+    let content = match format {
+        ExportFormat::Csv => {
+            let mut lines = vec!["game_id,finished_at,seat,role,team,result,co_players".to_string()];
+            for game in &games {
+                let co_players = game.co_players.iter()
+                    .map(|player| format!("{} ({})", player.name, player.role))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                lines.push(format!("{},{},{},{},{},{},\"{}\"", game.game_id, game.finished_at, game.seat, game.role,
+                                   game.team, if game.won { "won" } else { "lost" }, co_players));
+            }
+            lines.join("\n") + "\n"
+        }
+        ExportFormat::Json => serde_json::to_string_pretty(&games)?,
+    };
+    Ok((file_name, content))
+}
+
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let format = match ExportFormat::parse(args) {
+        Ok(format) => format,
+        Err(e) => {
+            ctx.bot.send_message(chat_id, e).await?;
+            return respond(());
+        }
+    };
+
+    let games = match ctx.storage.load_history(chat_id) {
+        Ok(games) if games.is_empty() => {
+            ctx.bot.send_message(chat_id, "You have no finished games yet").await?;
+            return respond(());
+        }
+        Ok(games) => games,
+        Err(e) => {
+            tracing::warn!("Failed to load the history of {}: {}", chat_id, e);
+            ctx.bot.send_message(chat_id, "Your history is not available now").await?;
+            return respond(());
+        }
+    };
+    match build(chat_id, &games, format) {
+        Ok((file_name, content)) => {
+            let document = InputFile::memory(content.into_bytes()).file_name(file_name);
+            ctx.bot.send_document(chat_id, document).await?;
+        }
+        Err(e) => {
+            tracing::warn!("Failed to build the history of {}: {}", chat_id, e);
+            ctx.bot.send_message(chat_id, "Your history is not available now").await?;
+        }
+    }
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Role;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1_700_000_000), "2023-11-14 22:13");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00");
+    }
+
+    #[test]
+    fn test_co_players_are_anonymized() {
+        let game = |game_id, players: [i64; 3], roles: [Role; 3], result| FinishedGame {
+            game_id,
+            finished_at: 1_700_000_000,
+            players: players.into_iter().map(ChatId).collect(),
+            roles: roles.to_vec(),
+            result,
+        };
+        let games = [
+            game(1, [10, 20, 30], [Role::Merlin, Role::Assassin, Role::Good], GameResult::GoodWins),
+            game(2, [30, 40, 10], [Role::Assassin, Role::Merlin, Role::Good2], GameResult::GoodWins),
+        ];
+
+        let (file_name, csv) = build(ChatId(10), &games, ExportFormat::Csv).unwrap();
+        assert_eq!(file_name, "avalon_history.csv");
+        assert_eq!(csv, "game_id,finished_at,seat,role,team,result,co_players\n\
+                         1,2023-11-14 22:13,0,Merlin,good,won,\"Player 1 (Assassin); Player 2 (Good)\"\n\
+                         2,2023-11-14 22:13,2,Good,good,won,\"Player 2 (Assassin); Player 3 (Merlin)\"\n");
+
+        let (_, json) = build(ChatId(20), &games, ExportFormat::Json).unwrap();
+        let json = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["won"], false);
+        assert_eq!(json[0]["co_players"][0]["name"], "Player 1");
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use tokio::time::Instant;
+
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameSession};
+
+// One message a minute is enough to report a stuck game and keeps the admin chat readable
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+fn render(name: &str, chat_id: ChatId, game: &str, text: &str) -> String {
+    format!("📮 Feedback from {} (id {}), {}:\n{}", name, chat_id, game, text)
+}
+
+fn game_state(game_id: u32, phase: Option<crate::game::Phase>, stalled: bool) -> String {
+    let phase = match phase {
+        Some(_) if stalled => "stalled".to_string(),
+        Some(phase) => format!("{:?}", phase),
+        None => "lobby".to_string(),
+    };
+    format!("game #{}, phase {}", game_id, phase)
+}
+
+// The message goes to the admin chat from the config. The session task adds the phase of the
+// game the player is in
+pub async fn handle(ctx: &mut BotCtx, message: &Message, text: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let to = ctx.admin.feedback_chats();
+    if to.is_empty() {
+        ctx.bot.send_message(chat_id, "Feedback is not accepted by this bot").await?;
+        return respond(());
+    }
+    if text.is_empty() {
+        ctx.bot.send_message(chat_id, "Write the message after the command, e.g. /feedback The game is stuck on the vote").await?;
+        return respond(());
+    }
+    if ctx.feedback_sent.get(&chat_id).is_some_and(|sent| sent.elapsed() < COOLDOWN) {
+        ctx.bot.send_message(chat_id, "Please wait a minute before sending the next message").await?;
+        return respond(());
+    }
+    ctx.feedback_sent.retain(|_, sent| sent.elapsed() < COOLDOWN);
+    ctx.feedback_sent.insert(chat_id, Instant::now());
+
+    let name = crate::get_display_name(ctx, chat_id);
+    let session = ctx.user_games.get(&chat_id).and_then(|game_id| ctx.game_sessions.get(game_id));
+    match session {
+        Some(session) => session.send(SessionCommand::Feedback { chat_id, name, text: text.to_string(), to }),
+        None => {
+            let feedback = render(&name, chat_id, "not in a game", text);
+            for admin in to {
+                ctx.bot.send_message(admin, &feedback).await?;
+            }
+        }
+    }
+    ctx.bot.send_message(chat_id, "Thank you! The message is sent to the maintainer").await?;
+    respond(())
+}
+
+// Called by the session task which knows the phase of the game
+pub async fn forward(session: &GameSession, chat_id: ChatId, name: &str, text: &str, to: &[ChatId]) {
+    let phase = match session.info.as_ref() {
+        Some(info) => Some(info.cli.get_phase().await),
+        None => None,
+    };
+    let feedback = render(name, chat_id, &game_state(session.id, phase, session.stalled), text);
+    for admin in to {
+        if let Err(e) = session.bot.send_message(*admin, &feedback).await {
+            tracing::warn!("Failed to forward feedback to {}: {}", admin, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Phase;
+
+    #[test]
+    fn test_feedback_has_player_and_game() {
+        let game = game_state(12, Some(Phase::TeamVote), false);
+        assert_eq!(render("Bob", ChatId(42), &game, "Stuck"), "📮 Feedback from Bob (id 42), game #12, phase TeamVote:\nStuck");
+        assert_eq!(game_state(12, None, false), "game #12, phase lobby");
+        assert_eq!(game_state(12, Some(Phase::Mission), true), "game #12, phase stalled");
+    }
+}
@@ -0,0 +1,88 @@
+use teloxide::prelude::*;
+use teloxide::types::{Chat, ChatKind, ChatPublic, ParseMode, PublicChatKind};
+
+use crate::session::SessionCommand;
+use crate::{Bot, BotCtx};
+
+// Blue, one of the icon colors allowed by Telegram
+const ICON_COLOR: u32 = 0x6FB9F0;
+
+// Topic of the group with the public messages of one game. The players still get everything in private
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Topic {
+    pub chat_id: ChatId,
+    pub thread_id: i32,
+}
+
+fn is_forum(chat: &Chat) -> bool {
+    matches!(&chat.kind, ChatKind::Public(ChatPublic { kind: PublicChatKind::Supergroup(supergroup), .. }) if supergroup.is_forum)
+}
+
+async fn reply(ctx: &BotCtx, message: &Message, text: &str) -> ResponseResult<()> {
+    let mut request = ctx.bot.send_message(message.chat.id, text);
+    if let Some(thread_id) = message.thread_id {
+        request = request.message_thread_id(thread_id);
+    }
+    request.await?;
+    respond(())
+}
+
+// The leader sends /forum in the group to post the games of the lobby there, /forum off stops it
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let Some(user) = message.from() else {
+        return respond(());
+    };
+    let leader = ChatId(user.id.0 as i64);
+    let session = ctx.user_games.get(&leader).and_then(|game_id| ctx.game_sessions.get(game_id)).cloned();
+    let Some(session) = session else {
+        return reply(ctx, message, "Create a lobby with /new_game first").await;
+    };
+    if session.leader != leader {
+        return reply(ctx, message, "Only game leader can choose the group of the games").await;
+    }
+
+    let text = match (args.trim(), is_forum(&message.chat)) {
+        ("off", _) => {
+            session.send(SessionCommand::SetForum(None));
+            "The games of the lobby are not posted to the group anymore".to_string()
+        }
+        ("", true) => {
+            tracing::info!("Game {} is posted to the forum {}", session.id, message.chat.id);
+            session.send(SessionCommand::SetForum(Some(message.chat.id)));
+            format!("Every game of lobby #{} gets its own topic here", session.id)
+        }
+        ("", false) => "Send /forum in a group with topics, the bot must be its admin who can manage topics".to_string(),
+        _ => "Usage: /forum or /forum off".to_string(),
+    };
+    reply(ctx, message, &text).await
+}
+
+// A new topic for every game, so the games of the group are not mixed and the general topic stays clean
+pub async fn open(bot: &Bot, chat_id: ChatId, game_id: u32, players: usize) -> Option<Topic> {
+    let name = format!("Avalon #{}, {} players", game_id, players);
+    match bot.create_forum_topic(chat_id, name, ICON_COLOR, "").await {
+        Ok(topic) => Some(Topic { chat_id, thread_id: topic.message_thread_id }),
+        Err(e) => {
+            tracing::warn!("Failed to create the topic of game {} in {}: {}", game_id, chat_id, e);
+            None
+        }
+    }
+}
+
+pub async fn post(bot: &Bot, topic: Topic, text: &str) {
+    let sent = bot.send_message(topic.chat_id, text)
+        .message_thread_id(topic.thread_id)
+        .parse_mode(ParseMode::Html)
+        .await;
+    if let Err(e) = sent {
+        tracing::warn!("Failed to post to the topic {} of {}: {}", topic.thread_id, topic.chat_id, e);
+    }
+}
+
+// The finished game keeps its topic, nobody writes there anymore
+pub async fn close(bot: &Bot, topic: Topic) {
+    if let Err(e) = bot.close_forum_topic(topic.chat_id, topic.thread_id).await {
+        tracing::warn!("Failed to close the topic {} of {}: {}", topic.thread_id, topic.chat_id, e);
+    }
+}
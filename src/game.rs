@@ -4,7 +4,10 @@ use std::ops::DerefMut;
 use std::vec::Vec;
 use rand::Rng;
 use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, Mutex};
+use tokio::task::AbortHandle;
+use tracing::Instrument;
 
 /*
 Start:
@@ -30,7 +33,7 @@ Game:
   1. Repeat
 */
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum Team {
     Good,
     Bad
@@ -42,7 +45,7 @@ impl fmt::Display for Team {
     }
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum Role {
     Mordred,
@@ -86,7 +89,7 @@ impl Role {
 
 pub type ID=u8;
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum TeamVote {
     Approve,
     Reject
@@ -98,7 +101,7 @@ impl fmt::Display for TeamVote {
     }
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum MissionVote {
     Success,
     Fail
@@ -110,14 +113,102 @@ impl fmt::Display for MissionVote {
     }
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum GameResult {
     GoodWins,
     BadWins
 }
 
 pub const MAX_TRY_COUNT: u8 = 5;
+pub const MIN_PLAYERS_FOR_MERMAID: usize = 7;
+// Numbers of players the roles are defined for
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 7;
+
+// Roles which the leader may leave out of the game, see GameOptions
+pub const OPTIONAL_ROLES: [Role; 3] = [Role::Percival, Role::Morgen, Role::Oberon];
+
+// Rules chosen by the leader before the start
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct GameOptions {
+    // Optional roles in the game. The seats of the other ones get plain good or bad roles
+    pub roles: Vec<Role>,
+    pub mermaid: bool,
+    // Rejected teams in a row, after which the bad team wins
+    pub max_try_count: u8,
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        Self {
+            roles: OPTIONAL_ROLES.to_vec(),
+            mermaid: true,
+            max_try_count: MAX_TRY_COUNT,
+        }
+    }
+}
+
+impl GameOptions {
+    pub fn has_mermaid(&self, players: usize) -> bool {
+        self.mermaid && players >= MIN_PLAYERS_FOR_MERMAID
+    }
+}
+
+// What the game is waiting for at the moment
+#[derive(PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Phase {
+    #[default]
+    TeamSuggestion,
+    TeamVote,
+    Mission,
+    MermaidCheck,
+    MermaidWord,
+    MerlinGuess,
+    Finished,
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct TurnRecord {
+    pub mission: usize,
+    pub crown_id: ID,
+    pub team: Vec<ID>,
+    pub votes: Vec<TeamVote>,
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct MissionRecord {
+    pub team: Vec<ID>,
+    pub fails: usize,
+    pub result: MissionVote,
+}
 
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct MermaidRecord {
+    pub holder: ID,
+    pub checked: ID,
+    pub truth: Team,
+    pub claim: Team,
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct MerlinGuessRecord {
+    pub guesser: ID,
+    pub guess: ID,
+    pub merlin: ID,
+}
+
+// Everything which happened in the game, revealed to players after the end
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    pub turns: Vec<TurnRecord>,
+    pub missions: Vec<MissionRecord>,
+    pub mermaid: Vec<MermaidRecord>,
+    pub merlin_guess: Option<MerlinGuessRecord>,
+    pub result: Option<GameResult>,
+}
+
+// Serializable, so the bot can store a snapshot of the game and restore it after restart
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct GameInfo {
     players: Vec<Role>,
 
@@ -126,11 +217,31 @@ pub struct GameInfo {
 
     mermaid_id: ID,
     crown_id: ID,
+    try_count: u8,
+    #[serde(default)]
+    phase: Phase,
+    #[serde(default)]
+    mermaid_checked: Option<ID>, // player checked by the mermaid holder in the current round
+
+    missions: Vec<MissionVote>,
+    #[serde(default)]
+    history: History,
+    #[serde(default)]
+    options: GameOptions,
+}
 
-    missions: Vec<MissionVote>
+impl GameInfo {
+    // Roles of the seats, for the analysis of the stored games
+    pub fn roles(&self) -> &[Role] {
+        &self.players
+    }
+
+    pub fn options(&self) -> &GameOptions {
+        &self.options
+    }
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum GameEvent {
     Turn(ID, usize), // Crown ID, team size for the mission
     TeamSuggested(Vec<ID>),
@@ -149,20 +260,22 @@ pub enum GameEvent {
 
 #[derive(Clone)]
 pub struct GameClient {
+    // The only receiver is shared by the clones of the client
     rx_event:  Arc<Mutex<mpsc::UnboundedReceiver<GameEvent>>>,
 
+    // Senders are cloned with the client, so the moves are sent without locking.
     // Mermaid owner selected player
-    tx_mermaid_selection: Arc<Mutex<mpsc::UnboundedSender<ID>>>,
+    tx_mermaid_selection: mpsc::UnboundedSender<ID>,
     // Mermaid says who is player
-    tx_mermaid_word: Arc<Mutex<mpsc::UnboundedSender<Team>>>,
+    tx_mermaid_word: mpsc::UnboundedSender<Team>,
 
-    tx_team:    Arc<Mutex<mpsc::UnboundedSender<Vec<ID>>>>,
-    tx_vote:    Arc<Mutex<mpsc::UnboundedSender<Vec<TeamVote>>>>,
-    tx_mission: Arc<Mutex<mpsc::UnboundedSender<Vec<MissionVote>>>>,
-    tx_merlin:  Arc<Mutex<mpsc::UnboundedSender<ID>>>,
+    tx_team:    mpsc::UnboundedSender<Vec<ID>>,
+    tx_vote:    mpsc::UnboundedSender<Vec<TeamVote>>,
+    tx_mission: mpsc::UnboundedSender<Vec<MissionVote>>,
+    tx_merlin:  mpsc::UnboundedSender<ID>,
 
     votes: Arc<Mutex<Vec<Option<TeamVote>>>>,
-    mission_votes: Arc<Mutex<Vec<MissionVote>>>,
+    mission_votes: Arc<Mutex<Vec<(ID, MissionVote)>>>,
 
     info: Arc<Mutex<GameInfo>>,
 }
@@ -207,7 +320,73 @@ impl GameClient {
         info.missions.clone()
     }
 
-    pub async fn suggest_team(&mut self, from: ID, suggested_team: &Vec<ID>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    pub async fn get_history(&self) -> History {
+        let info = self.info.lock().await;
+        info.history.clone()
+    }
+
+    pub async fn get_try_count(&self) -> u8 {
+        let info = self.info.lock().await;
+        info.try_count
+    }
+
+    pub async fn get_options(&self) -> GameOptions {
+        let info = self.info.lock().await;
+        info.options.clone()
+    }
+
+    pub async fn get_current_team(&self) -> Vec<ID> {
+        let info = self.info.lock().await;
+        info.current_team.clone()
+    }
+
+    pub async fn get_expected_team_size(&self) -> usize {
+        let info = self.info.lock().await;
+        info.expected_team_size
+    }
+
+    pub async fn get_mermaid_checked(&self) -> Option<ID> {
+        let info = self.info.lock().await;
+        info.mermaid_checked
+    }
+
+    pub async fn get_phase(&self) -> Phase {
+        let info = self.info.lock().await;
+        info.phase
+    }
+
+    // Players whose action is required to move the game forward
+    pub async fn get_waiting_for(&self) -> Vec<ID> {
+        let info = self.info.lock().await;
+        match info.phase {
+            Phase::TeamSuggestion => vec![info.crown_id],
+            Phase::TeamVote => {
+                let votes = self.votes.lock().await;
+                votes.iter()
+                    .enumerate()
+                    .filter(|(_, vote)| vote.is_none())
+                    .map(|(id, _)| id as ID)
+                    .collect()
+            }
+            Phase::Mission => {
+                let mission_votes = self.mission_votes.lock().await;
+                info.current_team.iter()
+                    .filter(|id| !mission_votes.iter().any(|(voter, _)| voter == *id))
+                    .cloned()
+                    .collect()
+            }
+            Phase::MermaidCheck | Phase::MermaidWord => vec![info.mermaid_id],
+            Phase::MerlinGuess => vec![find_guesser(&info.players)],
+            Phase::Finished => Vec::new(),
+        }
+    }
+
+    pub async fn snapshot(&self) -> GameInfo {
+        let info = self.info.lock().await;
+        info.clone()
+    }
+
+    pub async fn suggest_team(&self, from: ID, suggested_team: &[ID]) -> Result<(), Box<dyn Error + Send + Sync>> {
         {
             let info = self.info.lock().await;
             if from != info.crown_id {
@@ -221,13 +400,12 @@ impl GameClient {
             }
         }
 
-        self.tx_team.lock().await.send(suggested_team.clone())?;
+        self.tx_team.send(suggested_team.to_vec())?;
         Ok(())
     }
 
-    pub async fn add_team_vote(&mut self, from: ID, vote: TeamVote) -> Result<(), Box<dyn Error>> {
+    pub async fn add_team_vote(&self, from: ID, vote: TeamVote) -> Result<(), Box<dyn Error>> {
         let mut votes_ref = self.votes.lock().await;
-        let votes_ref = votes_ref.deref_mut();
 
         votes_ref[from as usize] = Some(vote);
 
@@ -235,18 +413,18 @@ impl GameClient {
             let votes = votes_ref.iter()
                 .map(|x| x.clone().unwrap())
                 .collect();
-            for i in 0..votes_ref.len() {
-                votes_ref[i] = Option::None;
+            for vote in votes_ref.iter_mut() {
+                *vote = Option::None;
             }
             drop(votes_ref);
 
-            println!("send_team_votes");
-            self.tx_vote.lock().await.send(votes)?;
+            log::debug!("send_team_votes");
+            self.tx_vote.send(votes)?;
         }
         Ok(())
     }
 
-    pub async fn submit_for_mission(&mut self, from: ID, vote: MissionVote) -> Result<(), Box<dyn Error + Send + Sync>> {
+    pub async fn submit_for_mission(&self, from: ID, vote: MissionVote) -> Result<(), Box<dyn Error + Send + Sync>> {
         let enough_votes = {
             let info = self.info.lock().await;
 
@@ -261,17 +439,16 @@ impl GameClient {
             let mut votes_ref = self.mission_votes.lock().await;
             let votes_ref = votes_ref.deref_mut();
 
-            votes_ref.push(vote.clone());
+            votes_ref.push((from, vote.clone()));
             info.expected_team_size == votes_ref.len()
         };
 
         if enough_votes {
             let mut votes_ref = self.mission_votes.lock().await;
-            let votes_ref = votes_ref.deref_mut();
-            let votes = votes_ref.clone();
+            let votes = votes_ref.iter().map(|(_, vote)| vote.clone()).collect();
             votes_ref.clear();
             drop(votes_ref);
-            self.tx_mission.lock().await.send(votes)?;
+            self.tx_mission.send(votes)?;
         }
 
         Ok(())
@@ -283,24 +460,24 @@ impl GameClient {
         Ok(event)
     }
 
-    pub async fn send_mermaid_selection(&mut self, id: ID) -> Result<(), Box<dyn Error>> {
-        self.tx_mermaid_selection.lock().await.send(id)?;
+    pub async fn send_mermaid_selection(&self, id: ID) -> Result<(), Box<dyn Error>> {
+        self.tx_mermaid_selection.send(id)?;
         Ok(())
     }
 
-    pub async fn send_mermaid_word(&mut self, word: Team) -> Result<(), Box<dyn Error>> {
-        self.tx_mermaid_word.lock().await.send(word)?;
+    pub async fn send_mermaid_word(&self, word: Team) -> Result<(), Box<dyn Error>> {
+        self.tx_mermaid_word.send(word)?;
         Ok(())
     }
 
-    pub async fn send_merlin_check(&mut self, id: ID) -> Result<(), Box<dyn Error>> {
-        self.tx_merlin.lock().await.send(id)?;
+    pub async fn send_merlin_check(&self, id: ID) -> Result<(), Box<dyn Error>> {
+        self.tx_merlin.send(id)?;
         Ok(())
     }
 }
 
-fn is_mission_approved(votes: &Vec<TeamVote>) -> bool {
-    if votes.len() == 0 {
+fn is_mission_approved(votes: &[TeamVote]) -> bool {
+    if votes.is_empty() {
         return false
     }
 
@@ -308,21 +485,21 @@ fn is_mission_approved(votes: &Vec<TeamVote>) -> bool {
         .filter(|x| **x == TeamVote::Approve)
         .count();
 
-    return approve_cnt * 2 > votes.len();
+    approve_cnt * 2 > votes.len()
 }
 
-fn get_expected_team_size(mission: usize,
-                          players: usize) -> Option<usize> {
-    let mission = mission - 1;
-    if mission > 5 {
+// Mission is counted from one
+pub fn get_expected_team_size(mission: usize,
+                              players: usize) -> Option<usize> {
+    if !(1..=5).contains(&mission) {
         return None
     }
 
-    if players < 1 || players > 10 {
+    if !(2..=10).contains(&players) {
         return None;
     }
 
-    static TEAM_SIZE_TABLE: &'static [[usize; 9]; 5] = &[
+    static TEAM_SIZE_TABLE: &[[usize; 9]; 5] = &[
         [1, 2, 2, 2, 2, 2, 3, 3, 3],
         [2, 3, 3, 3, 3, 3, 4, 4, 4],
         [1, 2, 2, 2, 4, 3, 4, 4, 4],
@@ -330,30 +507,34 @@ fn get_expected_team_size(mission: usize,
         [2, 3, 3, 3, 4, 4, 5, 5, 5],
     ];
 
-    return Some(TEAM_SIZE_TABLE[mission][players - 2]);
+    Some(TEAM_SIZE_TABLE[mission - 1][players - 2])
+}
+
+// Fail votes which fail the mission, the fourth mission of the big games needs two of them
+pub fn fails_required(mission: usize, players: usize) -> usize {
+    if players > 7 && mission == 4 { 2 } else { 1 }
+}
+
+// Size of the good team, the optional roles don't change the teams
+pub fn good_players(players: usize) -> usize {
+    default_team(players).iter().filter(|role| role.is_good()).count()
 }
 
 fn calc_mission_result(mission: usize,
                        players: usize,
-                       mission_votes: &Vec<MissionVote>) -> MissionVote {
+                       mission_votes: &[MissionVote]) -> MissionVote {
     let fails_count = mission_votes.iter()
         .filter(|x| **x == MissionVote::Fail)
         .count();
 
-    let success = if players > 7 && mission == 4 {
-        fails_count < 2
-    } else {
-        fails_count == 0
-    };
-
-    if success {
+    if fails_count < fails_required(mission, players) {
         MissionVote::Success
     } else {
         MissionVote::Fail
     }
 }
 
-fn calc_winner(mission_votes: &Vec<MissionVote>) -> Option<GameResult> {
+fn calc_winner(mission_votes: &[MissionVote]) -> Option<GameResult> {
     let fails_count = mission_votes.iter()
         .filter(|x| **x == MissionVote::Fail)
         .count();
@@ -398,6 +579,16 @@ fn default_team(players: usize) -> Vec<Role> {
     }
 }
 
+fn team_with_options(players: usize, options: &GameOptions) -> Vec<Role> {
+    default_team(players).into_iter()
+        .map(|role| match role {
+            Role::Percival if !options.roles.contains(&role) => Role::Good,
+            Role::Morgen | Role::Oberon if !options.roles.contains(&role) => Role::Bad,
+            _ => role,
+        })
+        .collect()
+}
+
 fn find_role_safe(players: &[Role], search_for: Role) -> Option<ID> {
     for (id, role) in players.iter().enumerate() {
         if *role == search_for {
@@ -411,6 +602,16 @@ fn find_role(players: &[Role], search_for: Role) -> ID {
     find_role_safe(players, search_for).expect("Role not found")
 }
 
+fn find_guesser(players: &[Role]) -> ID {
+    // If there is Assassin, he should guess Merlin
+    // Otherwise it should be Mordred
+    if let Some(assassin_id) = find_role_safe(players, Role::Assassin) {
+        assassin_id
+    } else {
+        find_role(players, Role::Mordred)
+    }
+}
+
 fn calc_prev_id(id: ID, players: usize) -> ID {
     assert!(id < players as ID);
     let prev_id = id as i32 - 1;
@@ -423,34 +624,60 @@ fn calc_next_id(id: ID, players: usize) -> ID {
     prev_id.rem_euclid(players as i32) as ID
 }
 
+// Runs the game until its end or until the returned handle aborts it
+// The engine logs in the span of the caller, e.g. the session of the game
+pub fn spawn_engine(mut game: Game) -> AbortHandle {
+    tokio::spawn(async move {
+        if let Err(e) = game.start().await {
+            tracing::error!("Game error: {}", e);
+        }
+    }.in_current_span()).abort_handle()
+}
+
 impl Game {
     pub fn setup(number: usize) -> (Game, GameClient) {
-        let (tx_mermaid_selection, rx_mermaid_selection) = mpsc::unbounded_channel();
-        let (tx_mermaid_word, rx_mermaid_word) = mpsc::unbounded_channel();
-        let (tx_team, rx_team) = mpsc::unbounded_channel();
-        let (tx_vote, rx_vote) = mpsc::unbounded_channel();
-        let (tx_mission, rx_mission) = mpsc::unbounded_channel();
-        let (tx_event, rx_event) = mpsc::unbounded_channel();
-        let (tx_merlin, rx_merlin) = mpsc::unbounded_channel();
+        Self::setup_with(number, GameOptions::default())
+    }
 
+    pub fn setup_with(number: usize, options: GameOptions) -> (Game, GameClient) {
         let mut rng = rand::thread_rng();
         let crown_id = rng.gen_range(0..number) as ID;
 
         let mut raw_info = GameInfo {
-            players: default_team(number),
+            players: team_with_options(number, &options),
 
             missions: Vec::new(),
+            history: History::default(),
             current_team: Vec::new(),
 
             expected_team_size: 0,
             crown_id,
             mermaid_id: calc_prev_id(crown_id, number),
+            try_count: 1,
+            phase: Phase::TeamSuggestion,
+            mermaid_checked: None,
+            options,
         };
 
-        println!("Game init crown_id={} mermaid_id={}", raw_info.crown_id, raw_info.mermaid_id);
+        log::debug!("Game init crown_id={} mermaid_id={}", raw_info.crown_id, raw_info.mermaid_id);
 
         raw_info.players.shuffle(&mut rng);
 
+        Self::restore(raw_info)
+    }
+
+    // Game is resumed from the beginning of the current turn: the team suggestion,
+    // votes and mermaid check in progress at the moment of snapshot are lost
+    pub fn restore(raw_info: GameInfo) -> (Game, GameClient) {
+        let (tx_mermaid_selection, rx_mermaid_selection) = mpsc::unbounded_channel();
+        let (tx_mermaid_word, rx_mermaid_word) = mpsc::unbounded_channel();
+        let (tx_team, rx_team) = mpsc::unbounded_channel();
+        let (tx_vote, rx_vote) = mpsc::unbounded_channel();
+        let (tx_mission, rx_mission) = mpsc::unbounded_channel();
+        let (tx_event, rx_event) = mpsc::unbounded_channel();
+        let (tx_merlin, rx_merlin) = mpsc::unbounded_channel();
+
+        let number = raw_info.players.len();
         let info = Arc::new(Mutex::new(raw_info));
 
         let g = Game {
@@ -472,12 +699,12 @@ impl Game {
         let cli = GameClient {
             rx_event: Arc::new(Mutex::new(rx_event)),
 
-            tx_mermaid_selection: Arc::new(Mutex::new(tx_mermaid_selection)),
-            tx_mermaid_word: Arc::new(Mutex::new(tx_mermaid_word)),
-            tx_team: Arc::new(Mutex::new(tx_team)),
-            tx_vote: Arc::new(Mutex::new(tx_vote)),
-            tx_mission: Arc::new(Mutex::new(tx_mission)),
-            tx_merlin: Arc::new(Mutex::new(tx_merlin)),
+            tx_mermaid_selection,
+            tx_mermaid_word,
+            tx_team,
+            tx_vote,
+            tx_mission,
+            tx_merlin,
 
             mission_votes: Arc::new(Mutex::new(Vec::new())),
             votes: Arc::new(Mutex::new(votes)),
@@ -489,6 +716,7 @@ impl Game {
     }
 
     async fn get_mermaid_check(&mut self) -> Result<ID, Box<dyn Error>> {
+        self.set_phase(Phase::MermaidCheck).await;
         {
             let info = self.info.lock().await;
             self.tx_event.send(GameEvent::Mermaid(info.mermaid_id))?;
@@ -506,6 +734,7 @@ impl Game {
 
     async fn next_turn(&mut self) -> Result<(), Box<dyn Error>> {
         self.update_expected_team_size().await?;
+        self.set_phase(Phase::TeamSuggestion).await;
         self.send_turn_event().await?;
         Ok(())
     }
@@ -534,6 +763,21 @@ impl Game {
         info.missions.len() + 1
     }
 
+    async fn get_try_count(&self) -> u8 {
+        let info = self.info.lock().await;
+        info.try_count
+    }
+
+    async fn set_try_count(&mut self, try_count: u8) {
+        let mut info = self.info.lock().await;
+        info.try_count = try_count;
+    }
+
+    async fn get_options(&self) -> GameOptions {
+        let info = self.info.lock().await;
+        info.options.clone()
+    }
+
     async fn get_number_of_players(&self) -> usize {
         let info = self.info.lock().await;
         info.players.len()
@@ -547,19 +791,54 @@ impl Game {
         Ok(())
     }
 
-    async fn set_current_team(&mut self, team: &Vec<ID>) {
+    async fn set_current_team(&mut self, team: &[ID]) {
         let mut info = self.info.lock().await;
-        info.current_team = team.clone();
-        self.tx_event.send(GameEvent::TeamSuggested(team.clone())).unwrap();
+        info.current_team = team.to_vec();
+        info.phase = Phase::TeamVote;
+        self.tx_event.send(GameEvent::TeamSuggested(team.to_vec())).unwrap();
     }
 
-    async fn add_mission_result(&mut self, result: MissionVote) {
+    async fn add_mission_result(&mut self, result: MissionVote, mission_votes: &[MissionVote]) {
         let mut info = self.info.lock().await;
-        info.missions.push(result);
+        info.missions.push(result.clone());
+
+        let record = MissionRecord {
+            team: info.current_team.clone(),
+            fails: mission_votes.iter().filter(|vote| **vote == MissionVote::Fail).count(),
+            result,
+        };
+        info.history.missions.push(record);
+    }
+
+    async fn add_turn_record(&mut self, team: &[ID], votes: &[TeamVote]) {
+        let mut info = self.info.lock().await;
+        let record = TurnRecord {
+            mission: info.missions.len() + 1,
+            crown_id: info.crown_id,
+            team: team.to_vec(),
+            votes: votes.to_vec(),
+        };
+        info.history.turns.push(record);
     }
 
-    fn notify_mission_result(&mut self, mission_votes: &Vec<MissionVote>) -> Result<(), Box<dyn Error>> {
-        let mut mission_votes = mission_votes.clone();
+    async fn add_mermaid_record(&mut self, checked: ID, truth: Team, claim: Team) {
+        let mut info = self.info.lock().await;
+        let record = MermaidRecord {
+            holder: info.mermaid_id,
+            checked,
+            truth,
+            claim,
+        };
+        info.history.mermaid.push(record);
+    }
+
+    async fn set_merlin_guess_record(&mut self, guesser: ID, guess: ID, merlin: ID) {
+        let mut info = self.info.lock().await;
+        info.history.merlin_guess = Some(MerlinGuessRecord { guesser, guess, merlin });
+    }
+
+    fn notify_mission_result(&mut self, mission_votes: &[MissionVote]) -> Result<(), Box<dyn Error>> {
+        let mut mission_votes = mission_votes.to_vec();
         let mut rng = rand::thread_rng();
         mission_votes.shuffle(&mut rng);
         self.tx_event.send(GameEvent::MissionResult(mission_votes))?;
@@ -587,7 +866,9 @@ impl Game {
     }
 
     async fn send_mermaid_result(&mut self, checked_user: ID, team: Team) -> Result<(), Box<dyn Error>> {
-        let info = self.info.lock().await;
+        let mut info = self.info.lock().await;
+        info.phase = Phase::MermaidWord;
+        info.mermaid_checked = Some(checked_user);
         self.tx_event.send(GameEvent::MermaidResult(info.mermaid_id, checked_user, team))?;
         Ok(())
     }
@@ -599,6 +880,7 @@ impl Game {
     }
 
     async fn send_bad_last_chance(&mut self, bad_team: Vec<ID>, guesser: ID) -> Result<(), Box<dyn Error>> {
+        self.set_phase(Phase::MerlinGuess).await;
         self.tx_event.send(GameEvent::BadLastChance(bad_team, guesser))?;
         Ok(())
     }
@@ -610,7 +892,7 @@ impl Game {
     }
 
     async fn send_team_votes(&mut self, votes: &Vec<TeamVote>) -> Result<(), Box<dyn Error>> {
-        println!("Sending team votes: {:?}", votes);
+        log::debug!("Sending team votes: {:?}", votes);
         self.tx_event.send(GameEvent::TeamVote(votes.clone()))?;
         Ok(())
     }
@@ -626,13 +908,18 @@ impl Game {
     }
 
     async fn send_game_result(&mut self, result: GameResult) -> Result<(), Box<dyn Error>> {
+        {
+            let mut info = self.info.lock().await;
+            info.phase = Phase::Finished;
+            info.history.result = Some(result.clone());
+        }
         self.tx_event.send(GameEvent::GameResult(result))?;
         Ok(())
     }
 
     async fn move_mermaid(&mut self, mermaid_check: ID) -> Result<(), Box<dyn Error>> {
         let mut info = self.info.lock().await;
-        println!("Moving mermaid from {} to {}", info.mermaid_id, mermaid_check);
+        log::debug!("Moving mermaid from {} to {}", info.mermaid_id, mermaid_check);
         info.mermaid_id = mermaid_check;
         Ok(())
     }
@@ -647,87 +934,92 @@ impl Game {
     }
 
     async fn get_guesser(&self) -> ID {
-        // If there is Assassin, he should guess Merlin
-        // Otherwise it should be Mordred
         let info = self.info.lock().await;
-        if let Some(assassin_id) = find_role_safe(&info.players, Role::Assassin) {
-            assassin_id
-        } else {
-            find_role(&info.players, Role::Mordred)
-        }
+        find_guesser(&info.players)
+    }
+
+    async fn set_phase(&mut self, phase: Phase) {
+        let mut info = self.info.lock().await;
+        info.phase = phase;
     }
 
     pub async fn start(&mut self) -> Result<(), Box<dyn Error>> {
-        let current_mission = self.get_current_mission().await;
         let number_of_players = self.get_number_of_players().await;
+        let options = self.get_options().await;
 
-        while self.calc_winner().await == None {
-            let mut try_count = 1;
+        while self.calc_winner().await.is_none() {
+            let current_mission = self.get_current_mission().await;
+            let mut try_count = self.get_try_count().await;
 
             loop {
-                println!("New turn");
+                log::debug!("New turn");
                 self.next_turn().await?;
 
                 let team = self.get_suggested_team().await;
                 self.set_current_team(&team).await;
 
-                println!("Suggested team: {:?}", team);
+                log::debug!("Suggested team: {:?}", team);
 
                 let team_votes = self.get_team_votes().await;
+                self.add_turn_record(&team, &team_votes).await;
                 self.send_team_votes(&team_votes).await?;
 
-                println!("Votes for the team: {:?}", team_votes);
+                log::debug!("Votes for the team: {:?}", team_votes);
 
                 if is_mission_approved(&team_votes) {
-                    println!("Mission approved");
+                    log::debug!("Mission approved");
+                    self.set_phase(Phase::Mission).await;
                     self.send_team_vote_result(GameEvent::TeamApproved(team)).await?;
                     self.shift_crown().await;
+                    self.set_try_count(1).await;
                     break;
                 }
 
                 try_count += 1;
+                self.set_try_count(try_count).await;
                 self.send_team_vote_result(GameEvent::TeamRejected(try_count)).await?;
-                println!("Mission rejected. Try count: {}", try_count);
+                log::debug!("Mission rejected. Try count: {}", try_count);
 
-                if try_count >= MAX_TRY_COUNT {
+                if try_count >= options.max_try_count {
                     break;
                 }
 
                self.shift_crown().await;
             }
 
-            if try_count == MAX_TRY_COUNT {
-                println!("Too many tries. Bad wins");
+            if try_count >= options.max_try_count {
+                log::debug!("Too many tries. Bad wins");
                 self.send_game_result(GameResult::BadWins).await?;
                 return Ok(());
             }
 
             let mission_votes = self.rx_mission.recv().await.unwrap();
-            println!("Mission votes: {:?}", mission_votes);
+            log::debug!("Mission votes: {:?}", mission_votes);
 
             let result = calc_mission_result(current_mission,
                 number_of_players, &mission_votes);
-            println!("Mission result: {:?}", result);
+            log::debug!("Mission result: {:?}", result);
 
             let mission_idx = self.get_current_mission().await;
 
-            self.add_mission_result(result).await;
+            self.add_mission_result(result, &mission_votes).await;
 
             self.notify_mission_result(&mission_votes)?;
 
-            println!("Mission idx: {}", mission_idx);
-            let is_end_of_game = self.calc_winner().await != None;
-            let is_mermaid_in_game = number_of_players >= 7;
+            log::debug!("Mission idx: {}", mission_idx);
+            let is_end_of_game = self.calc_winner().await.is_some();
+            let is_mermaid_in_game = options.has_mermaid(number_of_players);
             let is_time_to_use_mermaid = 1 < mission_idx && mission_idx < 5;
 
             if is_mermaid_in_game && is_time_to_use_mermaid && !is_end_of_game {
-                println!("Waiting for mermaid selection");
+                log::debug!("Waiting for mermaid selection");
                 let mermaid_check = self.get_mermaid_check().await?;
                 let mermaid_result = self.get_player_team(mermaid_check).await;
-                println!("Mermaid sees that {} is {:?}", mermaid_check, mermaid_result);
-                self.send_mermaid_result(mermaid_check, mermaid_result).await?;
+                log::debug!("Mermaid sees that {} is {:?}", mermaid_check, mermaid_result);
+                self.send_mermaid_result(mermaid_check, mermaid_result.clone()).await?;
                 let mermaid_word = self.get_mermaid_word().await?;
-                println!("Mermaid says that player is {:?}", mermaid_word);
+                log::debug!("Mermaid says that player is {:?}", mermaid_word);
+                self.add_mermaid_record(mermaid_check, mermaid_result, mermaid_word.clone()).await;
                 self.send_mermaid_word(mermaid_check, mermaid_word).await?;
                 self.move_mermaid(mermaid_check).await?;
             }
@@ -746,6 +1038,7 @@ impl Game {
         // If good wins, bad have a chance to win by guessing Merlin
         let merlin_check = self.get_merlin_check().await?;
         let merlin = self.get_merlin().await;
+        self.set_merlin_guess_record(guesser, merlin_check, merlin).await;
 
         self.send_actual_merlin(merlin).await?;
 
@@ -815,7 +1108,41 @@ mod tests {
         assert_eq!(get_expected_team_size(5, 7), Some(4));
     }
 
-    async fn test_send_team_votes(cli: &mut GameClient, votes: &Vec<TeamVote>) -> Result<(), Box<dyn Error>> {
+    // Team sizes by the rules of the game for 5-10 players and by the house rules for the smaller games,
+    // written out by the player count apart from the engine table
+    fn rules_team_sizes(players: usize) -> [usize; 5] {
+        match players {
+            2 => [1, 2, 1, 2, 2],
+            3..=5 => [2, 3, 2, 3, 3],
+            6 => [2, 3, 4, 3, 4],
+            7 => [2, 3, 3, 4, 4],
+            8..=10 => [3, 4, 4, 5, 5],
+            _ => panic!("Not supported number of players"),
+        }
+    }
+
+    #[test]
+    fn test_team_size_for_every_game_size() {
+        for players in 2..=10 {
+            for (mission, size) in rules_team_sizes(players).into_iter().enumerate() {
+                assert_eq!(get_expected_team_size(mission + 1, players), Some(size), "mission {}, {} players", mission + 1, players);
+            }
+        }
+        assert_eq!(get_expected_team_size(0, 5), None);
+        assert_eq!(get_expected_team_size(6, 5), None);
+        assert_eq!(get_expected_team_size(1, 1), None);
+        assert_eq!(get_expected_team_size(1, 11), None);
+    }
+
+    #[test]
+    fn test_fourth_mission_of_big_game_needs_two_fails() {
+        let one_fail = [MissionVote::Success, MissionVote::Fail, MissionVote::Success, MissionVote::Success];
+        assert_eq!(calc_mission_result(4, 8, &one_fail), MissionVote::Success);
+        assert_eq!(calc_mission_result(3, 8, &one_fail), MissionVote::Fail);
+        assert_eq!(calc_mission_result(4, 7, &one_fail), MissionVote::Fail);
+    }
+
+    async fn test_send_team_votes(cli: &mut GameClient, votes: &[TeamVote]) -> Result<(), Box<dyn Error>> {
         for (i, vote) in votes.iter().enumerate() {
             cli.add_team_vote(i as ID, vote.clone()).await?;
         }
@@ -826,11 +1153,11 @@ mod tests {
         cli.recv_event().await.unwrap()
     }
 
-    fn mission_result_are_equal(a: &Vec<MissionVote>, b: &Vec<MissionVote>) -> bool {
+    fn mission_result_are_equal(a: &[MissionVote], b: &[MissionVote]) -> bool {
         assert_eq!(a.len(), b.len());
         let a_success_cnt = a.iter().filter(|x| **x == MissionVote::Success).count();
         let b_success_cnt = b.iter().filter(|x| **x == MissionVote::Success).count();
-        return a_success_cnt == b_success_cnt;
+        a_success_cnt == b_success_cnt
     }
 
     #[derive(Clone, Debug)]
@@ -851,6 +1178,7 @@ mod tests {
 
     struct ExpectedGame {
         num: usize,
+        options: GameOptions,
         players: Vec<Role>,
         start_crown_id: ID,
         turns: Vec<GameTurn>,
@@ -858,15 +1186,15 @@ mod tests {
         expected_game_result: GameResult,
     }
 
-    fn build_suggested_team(players: &Vec<Role>, roles: &Vec<Role>) -> Vec<ID> {
+    fn build_suggested_team(players: &[Role], roles: &[Role]) -> Vec<ID> {
         let mut team = Vec::new();
 
         for role in roles {
-            let mut id = find_role(&players, role.clone());
+            let mut id = find_role(players, role.clone());
             while team.contains(&id) {
                 // Find another player with the same role
                 // Pass slice of players starting after the previous id of the same role
-                id = find_role(&players[id as usize + 1..], role.clone());
+                id += 1 + find_role(&players[id as usize + 1..], role.clone());
             }
 
             team.push(id);
@@ -881,7 +1209,7 @@ mod tests {
     }
 
     async fn run_test_game(expected: ExpectedGame) {
-        let (mut g, mut cli) = Game::setup(expected.num);
+        let (mut g, mut cli) = Game::setup_with(expected.num, expected.options.clone());
 
         // During real game players and crown are assigned randomly.
         // But for testing purposes we will assign them manually.
@@ -928,13 +1256,13 @@ mod tests {
 
                 match recv_event(&mut cli).await {
                     GameEvent::TeamApproved(team) => {
-                        assert!(is_mission_approved(&expected_votes));
+                        assert!(is_mission_approved(expected_votes));
                         assert_eq!(team, suggested_team);
                     }
                     GameEvent::TeamRejected(try_cnt) => {
-                        assert!(!is_mission_approved(&expected_votes));
+                        assert!(!is_mission_approved(expected_votes));
                         assert_eq!(try_cnt, exp_turn.try_count);
-                        if try_cnt == MAX_TRY_COUNT {
+                        if try_cnt == expected.options.max_try_count {
                             break;
                         } else {
                             continue;
@@ -959,7 +1287,7 @@ mod tests {
 
                 if let Some(mermaid) = &exp_turn.mermaid_check {
                     println!("[TEST] mermaid: {:?}", mermaid);
-                    let holder_id = cli_find_role(&mut cli, mermaid.holder.clone()).await;
+                    let holder_id = cli_find_role(&cli, mermaid.holder.clone()).await;
                     match recv_event(&mut cli).await {
                         GameEvent::Mermaid(mermaid_id) => {
                             assert_eq!(mermaid_id, holder_id);
@@ -967,7 +1295,7 @@ mod tests {
                         event => panic!("Unexpected event: {:?}", event)
                     };
 
-                    let selection_id = cli_find_role(&mut cli, mermaid.selection.clone()).await;
+                    let selection_id = cli_find_role(&cli, mermaid.selection.clone()).await;
                     cli.send_mermaid_selection(selection_id).await.unwrap();
 
                     match recv_event(&mut cli).await {
@@ -1021,6 +1349,19 @@ mod tests {
                 event => panic!("Unexpected event: {:?}", event)
             };
 
+            let history = cli.get_history().await;
+            assert_eq!(history.turns.len(), expected.turns.len());
+            let approved = expected.turns.iter()
+                .filter(|turn| is_mission_approved(&turn.team_votes))
+                .count();
+            assert_eq!(history.missions.len(), approved);
+            let mermaid_checks = expected.turns.iter()
+                .filter(|turn| turn.mermaid_check.is_some())
+                .count();
+            assert_eq!(history.mermaid.len(), mermaid_checks);
+            assert_eq!(history.merlin_guess.map(|guess| guess.guess), expected.merlin_check);
+            assert_eq!(history.result, Some(expected.expected_game_result.clone()));
+
             // There should be end of the game with GoodWins result
             println!("End of test future");
         };
@@ -1033,6 +1374,7 @@ mod tests {
     async fn test_clear_good_game_merlin_is_not_guessed() {
         let expected = ExpectedGame {
             num: 7,
+            options: GameOptions::default(),
             players: default_team(7),
             start_crown_id: 0,
             turns: vec![
@@ -1071,6 +1413,7 @@ mod tests {
     async fn test_clear_good_game_but_merlin_is_guessed() {
         let expected = ExpectedGame {
             num: 7,
+            options: GameOptions::default(),
             players: default_team(7),
             start_crown_id: 0,
             turns: vec![
@@ -1109,6 +1452,7 @@ mod tests {
     async fn test_bad_wins_due_to_many_rejects() {
         let expected = ExpectedGame {
             num: 7,
+            options: GameOptions::default(),
             players: default_team(7),
             start_crown_id: 0,
             turns: vec![
@@ -1149,6 +1493,7 @@ mod tests {
     async fn test_game_with_fail_on_fourth_mission_and_one_reject() {
         let expected = ExpectedGame {
             num: 7,
+            options: GameOptions::default(),
             players: default_team(7),
             start_crown_id: 0,
             turns: vec![
@@ -1219,4 +1564,182 @@ mod tests {
 
         run_test_game(expected).await;
     }
-}
\ No newline at end of file
+
+    // Every subset of the optional roles, with and without the mermaid
+    fn all_options() -> Vec<GameOptions> {
+        (0..1 << OPTIONAL_ROLES.len()).flat_map(|mask| {
+            let roles = OPTIONAL_ROLES.iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, role)| role.clone())
+                .collect::<Vec<_>>();
+            [true, false].map(|mermaid| GameOptions { roles: roles.clone(), mermaid, max_try_count: MAX_TRY_COUNT })
+        }).collect()
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Scenario {
+        // Three successful missions, the bad team misses Merlin
+        GoodSweep,
+        // Missions alternate till the fifth one, then Merlin is guessed
+        CloseGame,
+        // Three failed missions
+        BadSweep,
+        // Every team of the first mission is rejected
+        Rejects,
+    }
+
+    // The same scenario is played by any number of players with any roles. The teams are taken from
+    // the line-up of the roles: the good ones are at the start of it and the bad ones at the end
+    fn scripted_game(num: usize, options: GameOptions, scenario: Scenario) -> ExpectedGame {
+        let players = team_with_options(num, &options);
+        let sizes = rules_team_sizes(num);
+        let has_mermaid = options.has_mermaid(num);
+        // The crown starts at the second seat, so the mermaid starts at Merlin in the first one.
+        // Only the roles which are never duplicated in the line-up are checked
+        let checks = [
+            (Role::Merlin, Role::Mordred, Team::Bad),
+            (Role::Mordred, Role::Good2, Team::Good),
+            (Role::Good2, Role::Merlin, Team::Good),
+        ];
+
+        let (results, merlin_check, expected_game_result) = match scenario {
+            Scenario::GoodSweep => (vec![MissionVote::Success; 3], Some(find_role(&players, Role::Mordred)), GameResult::GoodWins),
+            Scenario::CloseGame => {
+                let results = vec![MissionVote::Success, MissionVote::Fail, MissionVote::Success, MissionVote::Fail, MissionVote::Success];
+                (results, Some(find_role(&players, Role::Merlin)), GameResult::BadWins)
+            }
+            Scenario::BadSweep => (vec![MissionVote::Fail; 3], None, GameResult::BadWins),
+            Scenario::Rejects => (Vec::new(), None, GameResult::BadWins),
+        };
+
+        let mut turns = results.iter().enumerate().map(|(i, result)| {
+            let mission = i + 1;
+            let suggestion = match result {
+                MissionVote::Success => players.iter().take(sizes[i]).cloned().collect::<Vec<_>>(),
+                MissionVote::Fail => players.iter().rev().take(sizes[i]).cloned().collect(),
+            };
+            let mission_votes = suggestion.iter()
+                .map(|role| if *result == MissionVote::Fail && !role.is_good() { MissionVote::Fail } else { MissionVote::Success })
+                .collect();
+            let is_last = mission == results.len();
+            let mermaid_check = (has_mermaid && 1 < mission && mission < 5 && !is_last).then(|| {
+                let (holder, selection, word) = checks[mission - 2].clone();
+                MermaidCheck { holder, selection, word }
+            });
+            GameTurn {
+                suggestion,
+                team_votes: vec![TeamVote::Approve; num],
+                try_count: 1,
+                mission_votes,
+                mermaid_check,
+            }
+        }).collect::<Vec<_>>();
+        if let Scenario::Rejects = scenario {
+            turns = (2..=options.max_try_count).map(|try_count| GameTurn {
+                suggestion: players.iter().take(sizes[0]).cloned().collect(),
+                team_votes: vec![TeamVote::Reject; num],
+                try_count,
+                mission_votes: vec![],
+                mermaid_check: None,
+            }).collect();
+        }
+
+        ExpectedGame {
+            num,
+            options,
+            players,
+            start_crown_id: 1,
+            turns,
+            merlin_check,
+            expected_game_result,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scripted_games_for_every_size_and_roles() {
+        for num in MIN_PLAYERS..=MAX_PLAYERS {
+            for options in all_options() {
+                for scenario in [Scenario::GoodSweep, Scenario::CloseGame, Scenario::BadSweep, Scenario::Rejects] {
+                    println!("[TEST] {} players, {:?}, {:?}", num, options, scenario);
+                    run_test_game(scripted_game(num, options.clone(), scenario)).await;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waiting_for_follows_phases() {
+        let (mut g, mut cli) = Game::setup(5);
+        g.info.lock().await.players = default_team(5);
+        g.info.lock().await.crown_id = 0;
+
+        // The game is not played till the end in this test
+        let game_fut = g.start();
+
+        let test_fut = async {
+            assert!(matches!(recv_event(&mut cli).await, GameEvent::Turn(0, 2)));
+            assert_eq!(cli.get_phase().await, Phase::TeamSuggestion);
+            assert_eq!(cli.get_waiting_for().await, vec![0]);
+
+            cli.suggest_team(0, &[1, 2]).await.unwrap();
+            assert!(matches!(recv_event(&mut cli).await, GameEvent::TeamSuggested(_)));
+            assert_eq!(cli.get_phase().await, Phase::TeamVote);
+            assert_eq!(cli.get_waiting_for().await, vec![0, 1, 2, 3, 4]);
+
+            cli.add_team_vote(1, TeamVote::Approve).await.unwrap();
+            cli.add_team_vote(3, TeamVote::Approve).await.unwrap();
+            assert_eq!(cli.get_waiting_for().await, vec![0, 2, 4]);
+
+            for id in [0, 2, 4] {
+                cli.add_team_vote(id, TeamVote::Approve).await.unwrap();
+            }
+            assert!(matches!(recv_event(&mut cli).await, GameEvent::TeamVote(_)));
+            assert!(matches!(recv_event(&mut cli).await, GameEvent::TeamApproved(_)));
+            assert_eq!(cli.get_phase().await, Phase::Mission);
+            assert_eq!(cli.get_waiting_for().await, vec![1, 2]);
+
+            cli.submit_for_mission(2, MissionVote::Success).await.unwrap();
+            assert_eq!(cli.get_waiting_for().await, vec![1]);
+        };
+
+        tokio::select! {
+            _ = game_fut => panic!("Game should not be finished"),
+            _ = test_fut => {},
+        }
+    }
+
+    #[tokio::test]
+    async fn test_game_options() {
+        let options = GameOptions { roles: vec![Role::Oberon], mermaid: false, max_try_count: 3 };
+        assert!(!options.has_mermaid(7));
+        assert!(GameOptions::default().has_mermaid(7));
+
+        let (mut g, mut cli) = Game::setup_with(7, options);
+        let roles = cli.get_player_roles().await;
+        assert!(roles.contains(&Role::Oberon));
+        assert!(!roles.contains(&Role::Percival) && !roles.contains(&Role::Morgen));
+        assert_eq!(roles.iter().filter(|role| role.is_good()).count(), 4);
+
+        let game_fut = g.start();
+        let test_fut = async {
+            for try_count in 2..=3 {
+                let GameEvent::Turn(crown_id, team_size) = recv_event(&mut cli).await else {
+                    panic!("Turn is expected");
+                };
+                let team = (0..team_size as ID).collect::<Vec<_>>();
+                cli.suggest_team(crown_id, &team).await.unwrap();
+                assert!(matches!(recv_event(&mut cli).await, GameEvent::TeamSuggested(_)));
+                for id in 0..7 {
+                    cli.add_team_vote(id, TeamVote::Reject).await.unwrap();
+                }
+                assert!(matches!(recv_event(&mut cli).await, GameEvent::TeamVote(_)));
+                assert_eq!(recv_event(&mut cli).await, GameEvent::TeamRejected(try_count));
+            }
+            assert_eq!(recv_event(&mut cli).await, GameEvent::GameResult(GameResult::BadWins));
+        };
+
+        let (result, _) = tokio::join!(game_fut, test_fut);
+        result.unwrap();
+    }
+}
@@ -1,8 +1,10 @@
-use std::error::Error;
 
-use teloxide::types::ChatId;
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup};
 
-use crate::{game::{GameEvent, TeamVote, self, MissionVote, Team, GameResult}, GameInfo};
+use crate::claims::{Claim, Said};
+use crate::commands::GameAction;
+use crate::theme::{capitalize, ThemeTable};
+use crate::{game::{GameEvent, TeamVote, self, MissionVote, Team, GameResult, Phase, Role}, GameInfo};
 
 #[derive(PartialEq, Debug)]
 pub enum Dst {
@@ -16,7 +18,7 @@ pub struct Notification {
     pub message: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct ControlMessage {
     pub dst: Dst,
     pub message: String,
@@ -26,30 +28,266 @@ pub struct ControlMessage {
 #[derive(Debug)]
 pub enum GameMessage {
     Notification(Notification),
+    // Notification which tells nothing new about the game, so muted players don't get it
+    Flavor(Notification),
+    // What only the receiver knows. It is sent alone, so it can't be forwarded with the rest
+    Secret(Notification),
     ControlMessage(ControlMessage),
 }
 
+// All messages of one event for one chat, so they are sent at once
+#[derive(Debug, PartialEq)]
+pub struct ComposedMessage {
+    pub chat_id: ChatId,
+    pub notifications: Vec<String>,
+    pub controls: Vec<ControlMessage>,
+    // Secret message, the frontend hides it and forbids forwarding if it can
+    pub protected: bool,
+}
+
+impl ComposedMessage {
+    // Control message which replaces the previous one after the kept prefix
+    pub fn with_control(chat_id: ChatId, prefix: &str, control: ControlMessage) -> Self {
+        let notifications = if prefix.is_empty() { Vec::new() } else { vec![prefix.to_string()] };
+        Self { chat_id, notifications, controls: vec![control], protected: false }
+    }
+
+    // Text which is kept when the control part is replaced
+    pub fn prefix(&self) -> String {
+        self.notifications.join("\n\n")
+    }
+}
+
+pub fn join_parts(prefix: &str, text: &str) -> String {
+    match (prefix.is_empty(), text.is_empty()) {
+        (true, _) => text.to_string(),
+        (_, true) => prefix.to_string(),
+        _ => format!("{}\n\n{}", prefix, text),
+    }
+}
+
+// Messages are built as Telegram HTML. Names and other user text only get into them through
+// these helpers, so a player named `<b>` or `*_[]` can't break the rendering
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+pub fn bold(text: &str) -> String {
+    format!("<b>{}</b>", escape(text))
+}
+
+// Takes the formatted text, so the names in it stay bold
+pub fn italic(html: &str) -> String {
+    format!("<i>{}</i>", html)
+}
+
+pub fn monospace(text: &str) -> String {
+    format!("<pre>{}</pre>", escape(text))
+}
+
+pub fn spoiler(html: &str) -> String {
+    format!("<tg-spoiler>{}</tg-spoiler>", html)
+}
+
+// Rewrites the tags made by the helpers above and the text between them
+fn convert_html(html: &str, tag: impl Fn(&str) -> &'static str, text: impl Fn(&str) -> String) -> String {
+    let mut converted = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        converted.push_str(&text(&unescape(&rest[..start])));
+        let end = rest[start..].find('>').map_or(rest.len(), |end| start + end + 1);
+        converted.push_str(tag(rest[start..end].trim_matches(|c| c == '<' || c == '>' || c == '/')));
+        rest = &rest[end..];
+    }
+    converted.push_str(&text(&unescape(rest)));
+    converted
+}
+
+// For the files and the frontends without formatting
+pub fn to_plain(html: &str) -> String {
+    convert_html(html, |_| "", str::to_string)
+}
+
+// Only the Discord frontend of the api feature uses it
+#[cfg_attr(not(feature = "api"), allow(dead_code))]
+pub fn to_markdown(html: &str) -> String {
+    let tag = |name: &str| match name {
+        "b" => "**",
+        "i" => "*",
+        "pre" => "```",
+        "tg-spoiler" => "||",
+        _ => "",
+    };
+    let text = |text: &str| text.chars().fold(String::new(), |mut escaped, c| {
+        if "\\*_`~|".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    });
+    convert_html(html, tag, text)
+}
+
+// Turns the composed messages into what the frontend sends. The content is Telegram HTML
+// without commands syntax, so every transport renders it in its own way
+pub trait MessageRenderer {
+    type Output;
+    fn render(&self, message: &ComposedMessage) -> Self::Output;
+}
+
+// Command of the control message is followed by its label, like "suggest_0 <b>Al</b>"
+fn split_command(command: &str) -> (&str, &str) {
+    command.split_once(' ').unwrap_or((command, ""))
+}
+
+// HTML text of the message where the commands are shown with the prefix of the frontend
+pub fn render_html(message: &ComposedMessage, prefix: char) -> String {
+    let controls = message.controls.iter()
+        .map(|control| {
+            let commands = control.commands.iter()
+                .map(|command| format!("{}{}", prefix, command))
+                .collect::<Vec<_>>();
+            format!("{}:\n{}", control.message, commands.join("\n"))
+        })
+        .collect::<Vec<_>>();
+    join_parts(&message.prefix(), &controls.join("\n\n"))
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TelegramMessage {
+    pub text: String,
+    // Game actions of the control part, the commands in the text still work
+    pub keyboard: Option<InlineKeyboardMarkup>,
+}
+
+pub struct TelegramRenderer;
+
+impl MessageRenderer for TelegramRenderer {
+    type Output = TelegramMessage;
+
+    fn render(&self, message: &ComposedMessage) -> TelegramMessage {
+        // Bot commands like /new_game need the message of the user, so only the game actions get buttons
+        let buttons = message.controls.iter()
+            .flat_map(|control| &control.commands)
+            .filter_map(|command| {
+                let (name, label) = split_command(command);
+                let data = format!("/{}", name);
+                GameAction::parse(&data).ok()?;
+                let label = if label.is_empty() { name.replace('_', " ") } else { to_plain(label) };
+                Some(vec![InlineKeyboardButton::callback(label, data)])
+            })
+            .collect::<Vec<_>>();
+
+        TelegramMessage {
+            text: render_html(message, '/'),
+            keyboard: (!buttons.is_empty()).then(|| InlineKeyboardMarkup::new(buttons)),
+        }
+    }
+}
+
+// Control part always goes last, so the commands are at the bottom of the message
+pub fn compose(players: &[ChatId], muted: &[ChatId], messages: Vec<GameMessage>) -> Vec<ComposedMessage> {
+    enum Part {
+        Text(String),
+        Control(String, Vec<String>),
+    }
+
+    let mut composed: Vec<ComposedMessage> = Vec::new();
+    let mut add = |chat_id: ChatId, part: &Part, is_secret: bool| {
+        let index = match composed.iter().position(|msg| msg.chat_id == chat_id && !msg.protected && !is_secret) {
+            Some(index) => index,
+            None => {
+                composed.push(ComposedMessage { chat_id, notifications: Vec::new(), controls: Vec::new(), protected: is_secret });
+                composed.len() - 1
+            }
+        };
+
+        let msg = &mut composed[index];
+        match part {
+            Part::Text(text) => msg.notifications.push(text.clone()),
+            Part::Control(message, commands) => msg.controls.push(ControlMessage {
+                dst: Dst::User(chat_id),
+                message: message.clone(),
+                commands: commands.clone(),
+            }),
+        }
+    };
+
+    for message in messages {
+        let (dst, part, is_flavor, is_secret) = match message {
+            GameMessage::Notification(notification) => (notification.dst, Part::Text(notification.message), false, false),
+            GameMessage::Flavor(notification) => (notification.dst, Part::Text(italic(&notification.message)), true, false),
+            GameMessage::Secret(notification) => (notification.dst, Part::Text(notification.message), false, true),
+            GameMessage::ControlMessage(ControlMessage { dst, message, commands }) => {
+                (dst, Part::Control(message, commands), false, false)
+            }
+        };
+
+        let receives = |chat_id: &ChatId| !is_flavor || !muted.contains(chat_id);
+        match dst {
+            Dst::All => players.iter()
+                .filter(|chat_id| receives(chat_id))
+                .for_each(|chat_id| add(*chat_id, &part, is_secret)),
+            Dst::User(chat_id) if receives(&chat_id) => add(chat_id, &part, is_secret),
+            Dst::User(_) => {}
+        }
+    }
+
+    composed
+}
+
+// Messages for every player in one text for the chats outside of the game, the secrets and the controls stay private
+pub fn public_text(messages: &[GameMessage]) -> Option<String> {
+    let parts = messages.iter()
+        .filter_map(|message| match message {
+            GameMessage::Notification(notification) if notification.dst == Dst::All => Some(notification.message.clone()),
+            GameMessage::Flavor(notification) if notification.dst == Dst::All => Some(italic(&notification.message)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    (!parts.is_empty()).then(|| parts.join("\n\n"))
+}
+
 struct SuggestionUser {
     id: u8,
     name: String,
     selected: bool,
+    record: String,
+}
+
+// Recent behavior of the candidate shown on its button, like " · ⚪ · 2 missions, 1 failed":
+// the vote for the last team and the missions the player was sent to
+fn track_record(history: &game::History, id: u8) -> String {
+    let mut parts = Vec::new();
+    if let Some(vote) = history.turns.last().and_then(|turn| turn.votes.get(id as usize)) {
+        parts.push(if vote == &TeamVote::Approve { "⚪" } else { "⚫" }.to_string());
+    }
+    let missions = history.missions.iter().filter(|mission| mission.team.contains(&id)).collect::<Vec<_>>();
+    if !missions.is_empty() {
+        let failed = missions.iter().filter(|mission| mission.result == MissionVote::Fail).count();
+        let noun = if missions.len() == 1 { "mission" } else { "missions" };
+        parts.push(format!("{} {}, {} failed", missions.len(), noun, failed));
+    }
+    parts.iter().map(|part| format!(" · {}", part)).collect()
 }
 
 impl GameMessage {
-    fn turn(crown_name: &str, team_size: usize, results: &Vec<MissionVote>) -> Self {
+    fn turn(theme: &ThemeTable, crown_name: &str, team_size: usize, results: &[MissionVote]) -> Self {
         let mission_history = results.iter()
-            .map(|vote| {
-                if vote == &MissionVote::Success { "🏆" } else { "🗡️" }
-            })
+            .map(|vote| theme.mission(vote))
             .collect::<Vec<_>>()
             .join(" ");
 
         let mission_chose = format!("{} chooses a team of {} people", crown_name, team_size);
 
-        let history_str = if mission_history.len() != 0 {
+        let history_str = if !mission_history.is_empty() {
             format!("Missions: {}\n", mission_history)
         } else {
-            format!("")
+            String::new()
         };
 
         Self::Notification(Notification {
@@ -62,7 +300,7 @@ impl GameMessage {
         let mut users = users.iter()
             .map(|user| {
                 let icon = if user.selected { "☑️ " } else { "" };
-                format!("suggest_{} {}{}", user.id, icon, user.name)
+                format!("suggest_{} {}{}{}", user.id, icon, user.name, user.record)
             })
             .collect::<Vec<_>>();
 
@@ -79,7 +317,7 @@ impl GameMessage {
         Self::ControlMessage(Self::turn_ctrl_raw(crown_id, team_size, users))
     }
 
-    fn suggested_team(team_names: &[&str]) -> Self {
+    fn suggested_team(team_names: &[String]) -> Self {
         let message = format!("Suggested team: {}", team_names.join(", "));
 
         Self::Notification(Notification {
@@ -96,13 +334,16 @@ impl GameMessage {
         })
     }
 
+    // Table with the names aligned, the names are not formatted there
     fn team_votes(votes: &[(&str, TeamVote)]) -> Self {
-        let message = format!("Votes: \n{}", votes.iter()
+        let width = votes.iter().map(|(name, _)| name.chars().count()).max().unwrap_or_default();
+        let table = votes.iter()
             .map(|(name, vote)| {
-                format!("{} - {} {}", name, if vote == &TeamVote::Approve { "⚪" } else { "⚫" }, vote)
+                format!("{:<width$} {} {}", name, if vote == &TeamVote::Approve { "⚪" } else { "⚫" }, vote)
             })
             .collect::<Vec<_>>()
-            .join("\n"));
+            .join("\n");
+        let message = format!("Votes:\n{}", monospace(&table));
 
         Self::Notification(Notification {
             dst: Dst::All,
@@ -125,17 +366,24 @@ impl GameMessage {
         })
     }
 
-    fn team_rejected(try_count: u8) -> Self {
+    fn team_rejected(try_count: u8, max_try_count: u8) -> Self {
+        Self::Notification(Notification {
+            dst: Dst::All,
+            message: format!("Team rejected. Try count: {}/{}", try_count, max_try_count)
+        })
+    }
+
+    fn last_try_warning(theme: &ThemeTable) -> Self {
         Self::Notification(Notification {
             dst: Dst::All,
-            message: format!("Team rejected. Try count: {}/{}", try_count, game::MAX_TRY_COUNT)
+            message: bold(&format!("⚠️ Next rejection hands victory to {}!", theme.bad_team)),
         })
     }
 
-    fn mission_result(results: &[MissionVote]) -> Self {
-        let message = format!("Mission results: {}", results.iter().map(|result| {
-            format!("{} {}", if result == &MissionVote::Success { "🏆" } else { "🗡️" }, result)
-        }).collect::<Vec<_>>().join(", "));
+    fn mission_result(theme: &ThemeTable, results: &[MissionVote], board: &str) -> Self {
+        let message = format!("Mission results: {}\n{}", results.iter().map(|result| {
+            format!("{} {}", theme.mission(result), result)
+        }).collect::<Vec<_>>().join(", "), board);
 
         Self::Notification(Notification {
             dst: Dst::All,
@@ -143,14 +391,14 @@ impl GameMessage {
         })
     }
 
-    fn mermaid_turn(mermaid_name: &str) -> Self {
-        Self::Notification(Notification {
+    fn mermaid_turn(theme: &ThemeTable, mermaid_name: &str) -> Self {
+        Self::Flavor(Notification {
             dst: Dst::All,
-            message: format!("{} is going to use mermaid", mermaid_name),
+            message: format!("{} is going to use {}", mermaid_name, theme.mermaid),
         })
     }
 
-    fn mermaid_ctrl(mermaid_chat: ChatId, users: &[(u8, &str)]) -> Self {
+    fn mermaid_ctrl(theme: &ThemeTable, mermaid_chat: ChatId, users: &[(u8, String)]) -> Self {
         let users = users.iter()
             .map(|(id, name)| {
                 format!("mermaid_{} {}", id, name)
@@ -159,15 +407,15 @@ impl GameMessage {
 
         Self::ControlMessage(ControlMessage {
             dst: Dst::User(mermaid_chat),
-            message: "Use mermaid. Select user to check".to_string(),
+            message: format!("Use {}. Select user to check", theme.mermaid),
             commands: users,
         })
     }
 
-    fn mermaid_result(mermaid_id: ChatId, user: &str, team: Team) -> Self {
-        let message = format!("Mermaid sees that {} is {}", user, team);
+    fn mermaid_result(theme: &ThemeTable, mermaid_id: ChatId, user: &str, team: Team) -> Self {
+        let message = format!("{} sees that {} is {}", capitalize(theme.mermaid), user, theme.team(&team));
 
-        Self::Notification(Notification {
+        Self::Secret(Notification {
             dst: Dst::User(mermaid_id),
             message,
         })
@@ -181,8 +429,8 @@ impl GameMessage {
         })
     }
 
-    fn mermaid_word(mermaid_name: &str, user: &str, team: Team) -> Self {
-        let message = format!("🧜‍️{} says {} is {}", mermaid_name, user, team);
+    fn mermaid_word(theme: &ThemeTable, mermaid_name: &str, user: &str, team: Team) -> Self {
+        let message = format!("{} {} says {} is {}", theme.mermaid_icon, mermaid_name, user, theme.team(&team));
 
         Self::Notification(Notification {
             dst: Dst::All,
@@ -190,28 +438,28 @@ impl GameMessage {
         })
     }
 
-    fn intermediate_good_win() -> Self {
+    fn intermediate_good_win(theme: &ThemeTable) -> Self {
         Self::Notification(Notification {
             dst: Dst::All,
-            message: "Good team are winning, but bad team has one last chance".to_string()
+            message: format!("{} are winning, but {} has one last chance", capitalize(theme.good_team), theme.bad_team),
         })
     }
 
-    fn announce_bad_team(bad_team: &[&str]) -> Self {
+    fn announce_bad_team(theme: &ThemeTable, bad_team: &[String]) -> Self {
         Self::Notification(Notification {
             dst: Dst::All,
-            message: format!("Bad team: {}", bad_team.join(", ")),
+            message: format!("{}: {}", capitalize(theme.bad_team), bad_team.join(", ")),
         })
     }
 
-    fn announce_merlin_guesser(guesser: &str) -> Self {
-        Self::Notification(Notification {
+    fn announce_merlin_guesser(theme: &ThemeTable, guesser: &str) -> Self {
+        Self::Flavor(Notification {
             dst: Dst::All,
-            message: format!("{} is going to guess Merlin", guesser),
+            message: format!("{} is going to guess {}", guesser, theme.role(&Role::Merlin)),
         })
     }
 
-    fn last_chance_ctrl(guesser_id: ChatId, good_team: &[(u8, &str)]) -> Self {
+    fn last_chance_ctrl(guesser_id: ChatId, good_team: &[(u8, String)]) -> Self {
         let good_team = good_team.iter()
             .map(|(id, name)| {
                 format!("merlin_{} {}", id, name)
@@ -225,23 +473,61 @@ impl GameMessage {
         })
     }
 
-    fn announce_merlin(merlin_name: &str) -> Self {
+    fn announce_merlin(theme: &ThemeTable, merlin_name: &str) -> Self {
+        Self::Notification(Notification {
+            dst: Dst::All,
+            message: format!("{} is {}", theme.role(&Role::Merlin), merlin_name),
+        })
+    }
+
+    fn game_result(theme: &ThemeTable, result: GameResult) -> Self {
+        let winners = if result == GameResult::GoodWins { theme.good_team } else { theme.bad_team };
+
+        Self::Notification(Notification {
+            dst: Dst::All,
+            message: format!("{} won!", capitalize(winners)),
+        })
+    }
+
+    fn nudge(theme: &ThemeTable, chat_id: ChatId, phase: Phase) -> Self {
         Self::Notification(Notification {
+            dst: Dst::User(chat_id),
+            message: format!("The lobby is waiting for {}", awaited_action(theme, phase)),
+        })
+    }
+
+    fn waiting_for(names: &[String]) -> Self {
+        Self::Flavor(Notification {
             dst: Dst::All,
-            message: format!("Merlin is {}", merlin_name),
+            message: format!("Waiting for: {}", names.join(", ")),
         })
     }
 
-    fn game_result(result: GameResult) -> Self {
-        let message = if result == GameResult::GoodWins {
-            "Good team won!"
+    fn timeout(names: &[String], replaced: bool) -> Self {
+        let message = if replaced {
+            format!("⏰ {} did not act in time. Replaced by AI", names.join(", "))
         } else {
-            "Bad team won!"
+            format!("⏰ Time is up for {}. Default action is applied", names.join(", "))
         };
 
         Self::Notification(Notification {
             dst: Dst::All,
-            message: message.to_string(),
+            message,
+        })
+    }
+
+    // Story of the theme, it tells nothing about the game
+    fn narration(text: &str) -> Self {
+        Self::Flavor(Notification {
+            dst: Dst::All,
+            message: text.to_string(),
+        })
+    }
+
+    fn summary(message: String) -> Self {
+        Self::Notification(Notification {
+            dst: Dst::All,
+            message,
         })
     }
 
@@ -258,22 +544,75 @@ fn get_user_chat_id(info: &GameInfo, id: u8) -> ChatId {
     info.players[id as usize]
 }
 
-fn get_user_name(info: &GameInfo, id: u8) -> &str {
+// Bold and escaped, ready for the message
+// What the game waits for from the player in the phase, e.g. "your team vote"
+pub fn awaited_action(theme: &ThemeTable, phase: Phase) -> String {
+    match phase {
+        Phase::TeamSuggestion => "you to suggest a team".to_string(),
+        Phase::TeamVote => "your team vote".to_string(),
+        Phase::Mission => "your mission result".to_string(),
+        Phase::MermaidCheck => format!("you to use the {}", theme.mermaid),
+        Phase::MermaidWord => format!("you to announce what {} said", theme.mermaid),
+        Phase::MerlinGuess => format!("you to guess {}", theme.role(&Role::Merlin)),
+        Phase::Finished => "nothing".to_string(),
+    }
+}
+
+fn get_user_name(info: &GameInfo, id: u8) -> String {
     let chat_id = get_user_chat_id(info, id);
-    info.user_names.get(&chat_id).unwrap()
+    bold(info.user_names.get(&chat_id).unwrap())
 }
 
 fn get_user_name_by_chat<'a>(info: &'a GameInfo, chat_id: &ChatId) -> &'a str {
     info.user_names.get(chat_id).unwrap()
 }
 
-pub async fn build_message_for_event(info: &GameInfo, event: GameEvent) -> Result<Vec<GameMessage>, Box<dyn Error>>
+// Engine state which some messages show besides the event. It is read right after the event is received,
+// so building the messages doesn't depend on the engine
+#[derive(Default, Debug)]
+pub struct EventContext {
+    pub missions: Vec<MissionVote>,
+    pub try_count: u8,
+    pub max_try_count: u8,
+    pub roles: Vec<game::Role>,
+    pub history: game::History,
+    // Table talk of the players, the bot keeps it besides the engine
+    pub claims: Vec<Claim>,
+}
+
+impl EventContext {
+    // Only the state which the messages of the event need
+    pub async fn fetch(cli: &game::GameClient, event: &GameEvent) -> Self {
+        let mut context = Self::default();
+        match event {
+            GameEvent::Turn(..) => {
+                context.missions = cli.get_mission_results().await;
+                context.history = cli.get_history().await;
+            }
+            GameEvent::TeamApproved(_) => context.missions = cli.get_mission_results().await,
+            GameEvent::TeamRejected(_) => context.max_try_count = cli.get_options().await.max_try_count,
+            GameEvent::MissionResult(_) => {
+                context.missions = cli.get_mission_results().await;
+                context.try_count = cli.get_try_count().await;
+                context.max_try_count = cli.get_options().await.max_try_count;
+            }
+            GameEvent::GameResult(_) => {
+                context.roles = cli.get_player_roles().await;
+                context.history = cli.get_history().await;
+            }
+            _ => {}
+        }
+        context
+    }
+}
+
+pub fn build_message_for_event(info: &GameInfo, context: &EventContext, event: GameEvent) -> Vec<GameMessage>
 {
-    println!();
-    println!("Event: {:?}", event);
+    let theme = info.theme.table();
+    tracing::debug!("Event: {:?}", event);
     match event {
         GameEvent::Turn(crown_id, team_size) => {
-            println!("Turn: crown_id={} team_size={}", crown_id, team_size);
+            tracing::debug!("Turn: crown_id={} team_size={}", crown_id, team_size);
             let crown_chat_id = get_user_chat_id(info, crown_id);
             let crown_name = get_user_name(info, crown_id);
             let player_num = info.players.len() as u8;
@@ -282,28 +621,27 @@ pub async fn build_message_for_event(info: &GameInfo, event: GameEvent) -> Resul
                 .map(|id| {
                     SuggestionUser {
                         id,
-                        name: get_user_name(info, id).to_string(),
+                        name: get_user_name(info, id),
                         selected: false,
+                        record: track_record(&context.history, id),
                     }
                 })
                 .collect::<Vec<_>>();
 
-            let results = info.cli.get_mission_results().await;
-
-            Ok(vec![
-                GameMessage::turn(crown_name, team_size, &results),
+            vec![
+                GameMessage::turn(theme, &crown_name, team_size, &context.missions),
                 GameMessage::turn_ctrl(crown_chat_id, team_size, &users)
-            ])
+            ]
         },
         GameEvent::TeamSuggested(team) => {
             let team_names = team.iter().map(|id| {
                 get_user_name(info, *id)
             });
 
-            Ok(vec![
+            vec![
                 GameMessage::suggested_team(&team_names.collect::<Vec<_>>()),
                 GameMessage::team_vote_ctrl(),
-            ])
+            ]
         },
         GameEvent::TeamVote(votes) => {
             let player_votes = info.players.iter()
@@ -314,23 +652,40 @@ pub async fn build_message_for_event(info: &GameInfo, event: GameEvent) -> Resul
                 })
                 .collect::<Vec<_>>();
 
-            Ok(vec![GameMessage::team_votes(&player_votes)])
+            vec![GameMessage::team_votes(&player_votes)]
         },
         GameEvent::TeamApproved(team) => {
             let mut messages = vec![GameMessage::team_approved()];
+            if let Some(briefing) = theme.narration.briefings.get(context.missions.len()).filter(|_| info.narration) {
+                messages.push(GameMessage::narration(briefing));
+            }
 
             for player in &team {
                 let chat_id = get_user_chat_id(info, *player);
                 messages.push(GameMessage::on_mission_ctrl(chat_id));
             }
 
-            Ok(messages)
+            messages
         },
         GameEvent::TeamRejected(try_count) => {
-            Ok(vec![GameMessage::team_rejected(try_count)])
+            let mut messages = vec![GameMessage::team_rejected(try_count, context.max_try_count)];
+            if is_last_try(try_count, context.max_try_count) {
+                messages.push(GameMessage::last_try_warning(theme));
+            }
+            messages
         },
         GameEvent::MissionResult(results) => {
-            Ok(vec![GameMessage::mission_result(&results)])
+            let board = mission_board(theme, info.players.len(), &context.missions, context.try_count, context.max_try_count);
+            let mut messages = Vec::new();
+            if info.narration {
+                let reveal = match context.missions.last() {
+                    Some(MissionVote::Fail) => theme.narration.fail,
+                    _ => theme.narration.success,
+                };
+                messages.push(GameMessage::narration(reveal));
+            }
+            messages.push(GameMessage::mission_result(theme, &results, &board));
+            messages
         },
         GameEvent::Mermaid(mermaid_id) => {
             let mermaid_name = get_user_name(info, mermaid_id);
@@ -341,29 +696,29 @@ pub async fn build_message_for_event(info: &GameInfo, event: GameEvent) -> Resul
                 .filter(|id| *id != mermaid_id)
                 .map(|id| {
                     let username = get_user_name(info, id);
-                    (id as u8, username)
+                    (id, username)
                 })
                 .collect::<Vec<_>>();
 
-            Ok(vec![
-                GameMessage::mermaid_turn(mermaid_name),
-                GameMessage::mermaid_ctrl(mermaid_chat, &users),
-            ])
+            vec![
+                GameMessage::mermaid_turn(theme, &mermaid_name),
+                GameMessage::mermaid_ctrl(theme, mermaid_chat, &users),
+            ]
         },
         GameEvent::MermaidResult(mermaid_id, checked_user, team) => {
             let mermaid_chat_id = get_user_chat_id(info, mermaid_id);
 
             let checked_user_name = get_user_name(info, checked_user);
 
-            Ok(vec![
-                GameMessage::mermaid_result(mermaid_chat_id, checked_user_name, team),
+            vec![
+                GameMessage::mermaid_result(theme, mermaid_chat_id, &checked_user_name, team),
                 GameMessage::mermaid_word_ctrl(mermaid_chat_id),
-            ])
+            ]
         },
         GameEvent::MermaidSays(mermaid_id, checked_user, team) => {
             let checked_user_name = get_user_name(info, checked_user);
             let mermaid_user_name = get_user_name(info, mermaid_id);
-            Ok(vec![GameMessage::mermaid_word(mermaid_user_name, checked_user_name, team)])
+            vec![GameMessage::mermaid_word(theme, &mermaid_user_name, &checked_user_name, team)]
         },
         GameEvent::BadLastChance(bad_team, guesser) => {
             let bad_team_names = bad_team.iter().map(|id| {
@@ -375,31 +730,197 @@ pub async fn build_message_for_event(info: &GameInfo, event: GameEvent) -> Resul
             let player_num = info.players.len() as u8;
 
             let good_team = (0..player_num)
-                .filter(|id| { !bad_team.contains(&(*id as u8)) })
+                .filter(|id| { !bad_team.contains(&{ *id }) })
                 .map(|id| { (id, get_user_name(info, id)) })
                 .collect::<Vec<_>>();
 
-            Ok(vec![
-                GameMessage::intermediate_good_win(),
-                GameMessage::announce_bad_team(&bad_team_names),
-                GameMessage::announce_merlin_guesser(guesser_name),
+            vec![
+                GameMessage::intermediate_good_win(theme),
+                GameMessage::announce_bad_team(theme, &bad_team_names),
+                GameMessage::announce_merlin_guesser(theme, &guesser_name),
                 GameMessage::last_chance_ctrl(guesser_chat_id, &good_team),
-            ])
+            ]
         },
         GameEvent::Merlin(merlin_id) => {
             let merlin_name = get_user_name(info, merlin_id);
-            Ok(vec![GameMessage::announce_merlin(merlin_name)])
+            vec![GameMessage::announce_merlin(theme, &merlin_name)]
         },
         GameEvent::GameResult(result) => {
-            Ok(vec![
-                GameMessage::game_result(result),
+            vec![
+                GameMessage::game_result(theme, result),
+                GameMessage::summary(join_parts(&build_summary(info, &context.roles, &context.history),
+                                                &build_claims(info, &context.claims, &context.roles))),
                 GameMessage::restart(info.leader),
-            ])
+            ]
         },
     }
 }
 
-pub fn suggestion_state(info: &GameInfo, crown_id: u8, team_size: usize, selected_team: &[u8]) -> ControlMessage {
+fn names(info: &GameInfo, ids: &[u8]) -> String {
+    ids.iter()
+        .map(|id| get_user_name(info, *id))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Reveals roles, every vote and every claim after the end of the game
+pub fn build_summary(info: &GameInfo, roles: &[game::Role], history: &game::History) -> String {
+    let theme = info.theme.table();
+    let mut lines = vec!["📜 Game summary".to_string(), "Roles:".to_string()];
+    for (id, role) in roles.iter().enumerate() {
+        let mark = if role.is_good() { "😇" } else { "😈" };
+        lines.push(format!("{} {} - {}", mark, get_user_name(info, id as u8), theme.role(role)));
+    }
+
+    let missions = history.turns.iter()
+        .map(|turn| turn.mission)
+        .max()
+        .unwrap_or(0);
+    for mission in 1..=missions {
+        lines.push(String::new());
+        match history.missions.get(mission - 1) {
+            Some(record) => {
+                let mark = theme.mission(&record.result);
+                lines.push(format!("{} Mission {}: {} ({} fails)", mark, mission, record.result, record.fails));
+            }
+            None => lines.push(format!("Mission {}: not played", mission)),
+        }
+
+        for turn in history.turns.iter().filter(|turn| turn.mission == mission) {
+            let voters = |vote: TeamVote| {
+                let ids = turn.votes.iter()
+                    .enumerate()
+                    .filter(|(_, v)| **v == vote)
+                    .map(|(id, _)| id as u8)
+                    .collect::<Vec<_>>();
+                names(info, &ids)
+            };
+            lines.push(format!("{} {}: {}", theme.crown, get_user_name(info, turn.crown_id), names(info, &turn.team)));
+            lines.push(format!("    ✅ {}", voters(TeamVote::Approve)));
+            lines.push(format!("    ❌ {}", voters(TeamVote::Reject)));
+        }
+    }
+
+    if !history.mermaid.is_empty() {
+        lines.push(String::new());
+        for record in &history.mermaid {
+            let honesty = if record.truth == record.claim { "truth" } else { "lie" };
+            lines.push(format!("{} {} checked {}: saw {}, said {} ({})", theme.mermaid_icon,
+                               get_user_name(info, record.holder), get_user_name(info, record.checked),
+                               theme.team(&record.truth), theme.team(&record.claim), honesty));
+        }
+    }
+
+    if let Some(guess) = &history.merlin_guess {
+        lines.push(String::new());
+        let mark = if guess.guess == guess.merlin { "🎯" } else { "💨" };
+        let merlin = theme.role(&Role::Merlin);
+        lines.push(format!("{} {} named {} as {}. {} was {}", mark,
+                           get_user_name(info, guess.guesser), get_user_name(info, guess.guess),
+                           merlin, merlin, get_user_name(info, guess.merlin)));
+    }
+
+    lines.join("\n")
+}
+
+pub fn claim_line(info: &GameInfo, claim: &Claim) -> String {
+    let theme = info.theme.table();
+    let name = get_user_name(info, claim.seat);
+    match &claim.said {
+        Said::Role(role) => format!("📣 {} claims {}", name, theme.role(role)),
+        Said::Mermaid(checked, team) => {
+            format!("📣 {}: {} showed {} as {}", name, theme.mermaid_icon, get_user_name(info, *checked), theme.team(team))
+        }
+    }
+}
+
+// Claims in the order they were made, with the truth when the roles are revealed at the end
+pub fn build_claims(info: &GameInfo, claims: &[Claim], roles: &[game::Role]) -> String {
+    if claims.is_empty() {
+        return String::new();
+    }
+    let mut lines = vec!["Claims:".to_string()];
+    lines.extend(claims.iter().map(|claim| match roles.is_empty() {
+        true => claim_line(info, claim),
+        false => format!("{} {}", claim_line(info, claim), if claim.is_true(roles) { "✅" } else { "❌" }),
+    }));
+    lines.join("\n")
+}
+
+const MISSIONS: usize = 5;
+const MISSION_NUMBERS: [&str; MISSIONS] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣"];
+
+// Compact board of the missions: the team size and the result of every mission,
+// then the vote attempts of the current mission, e.g. ●●○○○ for the second one
+pub fn mission_board(theme: &ThemeTable, players: usize, results: &[MissionVote], try_count: u8, max_try_count: u8) -> String {
+    let mut lines = (0..MISSIONS)
+        .map(|i| {
+            let size = game::get_expected_team_size(i + 1, players).unwrap_or_default();
+            let result = results.get(i).map_or("⬜", |result| theme.mission(result));
+            let fails = game::fails_required(i + 1, players);
+            let marker = if fails > 1 { format!(" ⚠️ {} fails", fails) } else { String::new() };
+            format!("{} 👥{} {}{}", MISSION_NUMBERS[i], size, result, marker)
+        })
+        .collect::<Vec<_>>();
+    let attempts = (1..=max_try_count)
+        .map(|attempt| if attempt <= try_count { "●" } else { "○" })
+        .collect::<String>();
+    lines.push(format!("Vote attempts: {}", attempts));
+    lines.join("\n")
+}
+
+// Current state of the game which is kept in one pinned message
+pub async fn build_board(info: &GameInfo) -> String {
+    let theme = info.theme.table();
+    let cli = &info.cli;
+    let results = cli.get_mission_results().await;
+    let crown_name = get_user_name(info, cli.get_crown_id().await);
+    let options = cli.get_options().await;
+    let mut lines = vec![
+        "📋 Board".to_string(),
+        mission_board(theme, info.players.len(), &results, cli.get_try_count().await, options.max_try_count),
+        format!("{} {}", theme.crown, crown_name),
+    ];
+
+    if options.has_mermaid(info.players.len()) {
+        lines.push(format!("{} {}", theme.mermaid_icon, get_user_name(info, cli.get_mermaid_id().await)));
+    }
+
+    let phase = cli.get_phase().await;
+    if matches!(phase, Phase::TeamVote | Phase::Mission) {
+        let team = cli.get_current_team().await;
+        lines.push(format!("Team: {}", names(info, &team)));
+    }
+
+    let now = match phase {
+        Phase::TeamSuggestion => format!("{} chooses a team of {}", crown_name, cli.get_expected_team_size().await),
+        Phase::TeamVote => "Everybody votes for the team".to_string(),
+        Phase::Mission => "The team is on the mission".to_string(),
+        Phase::MermaidCheck | Phase::MermaidWord => {
+            format!("{} uses the {}", get_user_name(info, cli.get_mermaid_id().await), theme.mermaid)
+        }
+        Phase::MerlinGuess => format!("{} tries to guess {}", capitalize(theme.bad_team), theme.role(&Role::Merlin)),
+        Phase::Finished => match cli.get_history().await.result {
+            Some(GameResult::GoodWins) => format!("Game over. {} won", capitalize(theme.good_team)),
+            _ => format!("Game over. {} won", capitalize(theme.bad_team)),
+        },
+    };
+    lines.push(format!("Now: {}", now));
+
+    lines.join("\n")
+}
+
+// The bad team wins if the team of this try is rejected
+pub fn is_last_try(try_count: u8, max_try_count: u8) -> bool {
+    try_count + 1 == max_try_count
+}
+
+// Reject on the last try is counted only after the second tap, so a misclick doesn't lose the game
+pub fn reject_confirmation(theme: &ThemeTable) -> String {
+    format!("⚠️ Rejecting this team hands victory to {}. Tap Reject again to confirm", theme.bad_team)
+}
+
+pub fn suggestion_state(info: &GameInfo, history: &game::History, crown_id: u8, team_size: usize, selected_team: &[u8]) -> ControlMessage {
     let crown_chat_id = get_user_chat_id(info, crown_id);
     let player_num = info.players.len() as u8;
 
@@ -407,11 +928,388 @@ pub fn suggestion_state(info: &GameInfo, crown_id: u8, team_size: usize, selecte
         .map(|id| {
             SuggestionUser {
                 id,
-                name: get_user_name(info, id).to_string(),
+                name: get_user_name(info, id),
                 selected: selected_team.contains(&id),
+                record: track_record(history, id),
             }
         })
         .collect::<Vec<_>>();
 
     GameMessage::turn_ctrl_raw(crown_chat_id, team_size, &users)
 }
+
+pub fn build_nudge_messages(info: &GameInfo, phase: Phase, waiting: &[u8], notify_group: bool) -> Vec<GameMessage> {
+    let mut messages = waiting.iter()
+        .map(|id| GameMessage::nudge(info.theme.table(), get_user_chat_id(info, *id), phase))
+        .collect::<Vec<_>>();
+
+    if notify_group {
+        let names = waiting.iter()
+            .map(|id| get_user_name(info, *id))
+            .collect::<Vec<_>>();
+        messages.push(GameMessage::waiting_for(&names));
+    }
+
+    messages
+}
+
+// Vote controls are sent when the discussion ends
+pub fn discussion_started(info: &GameInfo, team: &[u8], duration: &str) -> String {
+    let team_names = team.iter().map(|id| get_user_name(info, *id)).collect::<Vec<_>>();
+    format!("Suggested team: {}. Discuss it, the vote opens in {}", team_names.join(", "), duration)
+}
+
+// The guess of Merlin is sent to the guesser when the bad team ends the discussion
+pub fn guess_discussion_started(info: &GameInfo, duration: &str) -> String {
+    let theme = info.theme.table();
+    format!("{} completed the missions. {} discusses who is {}, the guess comes in {}",
+            capitalize(theme.good_team), capitalize(theme.bad_team), theme.role(&Role::Merlin), duration)
+}
+
+// Shows who has already acted without revealing what they chose
+pub fn build_tracker_text(info: &GameInfo, phase: Phase, seats: &[u8], waiting: &[u8]) -> String {
+    let title = match phase {
+        Phase::Mission => "Mission",
+        _ => "Team vote",
+    };
+
+    let lines = seats.iter()
+        .map(|id| {
+            let mark = if waiting.contains(id) { "⏳" } else { "✅" };
+            format!("{} {}", mark, get_user_name(info, *id))
+        })
+        .collect::<Vec<_>>();
+
+    format!("{}:\n{}", title, lines.join("\n"))
+}
+
+pub fn build_timeout_messages(info: &GameInfo, waiting: &[u8], replaced: bool) -> Vec<GameMessage> {
+    let names = waiting.iter()
+        .map(|id| get_user_name(info, *id))
+        .collect::<Vec<_>>();
+
+    vec![GameMessage::timeout(&names, replaced)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use teloxide::types::InlineKeyboardButtonKind;
+    use crate::theme::Theme;
+
+    fn notification(dst: Dst, message: &str) -> GameMessage {
+        GameMessage::Notification(Notification { dst, message: message.to_string() })
+    }
+
+    #[test]
+    fn test_public_text() {
+        let messages = [
+            notification(Dst::All, "Al chooses a team"),
+            GameMessage::Secret(Notification { dst: Dst::User(ChatId(1)), message: "You see Bo".to_string() }),
+            notification(Dst::User(ChatId(2)), "Your turn"),
+            GameMessage::narration("The night falls"),
+        ];
+        assert_eq!(public_text(&messages).as_deref(), Some("Al chooses a team\n\n<i>The night falls</i>"));
+        assert_eq!(public_text(&messages[1..3]), None);
+    }
+
+    fn game_info(names: &[&str]) -> GameInfo {
+        let (_, cli) = game::Game::setup(names.len());
+        let players = (1..=names.len() as i64).map(ChatId).collect::<Vec<_>>();
+        GameInfo {
+            id: 1,
+            leader: players[0],
+            user_names: players.iter().cloned().zip(names.iter().map(|name| Arc::from(*name))).collect(),
+            players,
+            cli,
+            delivery: Default::default(),
+            muted: Default::default(),
+            other_games: Default::default(),
+            theme: Theme::Classic,
+            narration: false,
+            bots: Default::default(),
+            topic: None,
+        }
+    }
+
+    fn text(message: &ComposedMessage) -> String {
+        TelegramRenderer.render(message).text
+    }
+
+    #[test]
+    fn test_messages_are_composed_per_chat() {
+        let players = [ChatId(1), ChatId(2)];
+        let messages = vec![
+            notification(Dst::All, "Turn"),
+            GameMessage::ControlMessage(ControlMessage {
+                dst: Dst::User(ChatId(2)),
+                message: "Choose".to_string(),
+                commands: vec!["suggest_0".to_string()],
+            }),
+            notification(Dst::All, "Missions"),
+        ];
+
+        let composed = compose(&players, &[], messages);
+        assert_eq!(composed.len(), 2);
+        assert_eq!(text(&composed[0]), "Turn\n\nMissions");
+        assert_eq!(composed[1].prefix(), "Turn\n\nMissions");
+        assert_eq!(text(&composed[1]), "Turn\n\nMissions\n\nChoose:\n/suggest_0");
+    }
+
+    #[test]
+    fn test_muted_players_get_only_essential_messages() {
+        let players = [ChatId(1), ChatId(2)];
+        let messages = vec![
+            GameMessage::Flavor(Notification { dst: Dst::All, message: "Waiting".to_string() }),
+            notification(Dst::All, "Turn"),
+            GameMessage::Flavor(Notification { dst: Dst::User(ChatId(2)), message: "Hurry".to_string() }),
+            GameMessage::ControlMessage(ControlMessage {
+                dst: Dst::User(ChatId(2)),
+                message: "Choose".to_string(),
+                commands: vec!["suggest_0".to_string()],
+            }),
+        ];
+
+        let composed = compose(&players, &[ChatId(2)], messages);
+        assert_eq!(text(&composed[0]), "<i>Waiting</i>\n\nTurn");
+        assert_eq!(text(&composed[1]), "Turn\n\nChoose:\n/suggest_0");
+    }
+
+    #[test]
+    fn test_names_are_escaped() {
+        assert_eq!(bold("*_[]<b>&"), "<b>*_[]&lt;b&gt;&amp;</b>");
+        let html = format!("{} says {}", italic(&bold("<i>")), monospace("a & b"));
+        assert_eq!(to_plain(&html), "<i> says a & b");
+        assert_eq!(to_markdown(&html), "***<i>*** says ```a & b```");
+        assert_eq!(to_markdown(&bold("*_[]")), "**\\*\\_[]**");
+
+        let GameMessage::Notification(votes) = GameMessage::team_votes(&[("Al", TeamVote::Approve), ("<Bob>", TeamVote::Reject)]) else {
+            panic!("Votes are a notification");
+        };
+        assert_eq!(votes.message, "Votes:\n<pre>Al    ⚪ Approve\n&lt;Bob&gt; ⚫ Reject</pre>");
+    }
+
+    #[test]
+    fn test_narration_is_told_when_enabled() {
+        let mut info = game_info(&["Al", "Bob", "Cid", "Dan", "Eve"]);
+        let context = EventContext { missions: vec![MissionVote::Fail], ..Default::default() };
+        let flavor = |info: &GameInfo, event: GameEvent| build_message_for_event(info, &context, event).into_iter()
+            .filter_map(|message| match message {
+                GameMessage::Flavor(notification) => Some(notification.message),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(flavor(&info, GameEvent::TeamApproved(vec![0, 1])).is_empty());
+
+        info.narration = true;
+        let narration = &Theme::Classic.table().narration;
+        assert_eq!(flavor(&info, GameEvent::TeamApproved(vec![0, 1])), vec![narration.briefings[1]]);
+        assert_eq!(flavor(&info, GameEvent::MissionResult(vec![MissionVote::Fail, MissionVote::Success])), vec![narration.fail]);
+    }
+
+    #[test]
+    fn test_flavor_is_italic() {
+        let messages = vec![GameMessage::Flavor(Notification { dst: Dst::All, message: bold("Al") })];
+        let composed = compose(&[ChatId(1)], &[], messages);
+        assert_eq!(text(&composed[0]), "<i><b>Al</b></i>");
+    }
+
+    #[test]
+    fn test_secret_is_sent_alone() {
+        let players = [ChatId(1), ChatId(2)];
+        let messages = vec![
+            GameMessage::Secret(Notification { dst: Dst::User(ChatId(2)), message: "Mermaid sees".to_string() }),
+            notification(Dst::All, "Turn"),
+        ];
+
+        let composed = compose(&players, &[], messages);
+        assert_eq!(composed.len(), 3);
+        assert!(composed[0].protected);
+        assert_eq!(text(&composed[0]), "Mermaid sees");
+        assert!(composed.iter().skip(1).all(|msg| !msg.protected && text(msg) == "Turn"));
+    }
+
+    #[test]
+    fn test_game_actions_get_buttons() {
+        let control = ControlMessage {
+            dst: Dst::User(ChatId(1)),
+            message: "Choose".to_string(),
+            commands: vec![format!("suggest_0 ☑️ {}", bold("<Al>")), "suggest_finish".to_string(), "restart".to_string()],
+        };
+        let rendered = TelegramRenderer.render(&ComposedMessage::with_control(ChatId(1), "Turn", control));
+        assert_eq!(rendered.text, "Turn\n\nChoose:\n/suggest_0 ☑️ <b>&lt;Al&gt;</b>\n/suggest_finish\n/restart");
+
+        let buttons = rendered.keyboard.unwrap().inline_keyboard.concat();
+        let buttons = buttons.iter()
+            .map(|button| (button.text.as_str(), &button.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(buttons, vec![
+            ("☑️ <Al>", &InlineKeyboardButtonKind::CallbackData("/suggest_0".to_string())),
+            ("suggest finish", &InlineKeyboardButtonKind::CallbackData("/suggest_finish".to_string())),
+        ]);
+
+        let composed = compose(&[ChatId(1)], &[], vec![notification(Dst::All, "Turn")]);
+        assert_eq!(TelegramRenderer.render(&composed[0]).keyboard, None);
+    }
+
+    #[test]
+    fn test_messages_are_built_from_context() {
+        let info = game_info(&["Al", "Bob", "Cid", "Dan", "Eve"]);
+        let context = EventContext { missions: vec![MissionVote::Success], max_try_count: 5, ..Default::default() };
+
+        let messages = build_message_for_event(&info, &context, GameEvent::TeamRejected(2));
+        let composed = compose(&info.players, &[], messages);
+        assert_eq!(text(&composed[0]), "Team rejected. Try count: 2/5");
+        let messages = build_message_for_event(&info, &context, GameEvent::TeamRejected(4));
+        let composed = compose(&info.players, &[], messages);
+        assert_eq!(text(&composed[0]), "Team rejected. Try count: 4/5\n\n<b>⚠️ Next rejection hands victory to bad team!</b>");
+
+        let messages = build_message_for_event(&info, &context, GameEvent::Turn(1, 2));
+        let composed = compose(&info.players, &[], messages);
+        assert!(text(&composed[0]).contains("🏆"));
+        assert!(text(&composed[1]).contains("/suggest_finish"));
+    }
+
+    #[test]
+    fn test_candidates_show_track_record() {
+        let info = game_info(&["Al", "Bob", "Cid", "Dan", "Eve"]);
+        let mission = |team: Vec<u8>, result| game::MissionRecord { team, fails: 0, result };
+        let history = game::History {
+            turns: vec![game::TurnRecord {
+                mission: 2,
+                crown_id: 1,
+                team: vec![0, 2],
+                votes: vec![TeamVote::Approve, TeamVote::Reject, TeamVote::Approve, TeamVote::Approve, TeamVote::Reject],
+            }],
+            missions: vec![mission(vec![0, 1], MissionVote::Success), mission(vec![0, 2], MissionVote::Fail)],
+            ..Default::default()
+        };
+        assert_eq!(track_record(&history, 0), " · ⚪ · 2 missions, 1 failed");
+        assert_eq!(track_record(&history, 1), " · ⚫ · 1 mission, 0 failed");
+        assert_eq!(track_record(&history, 4), " · ⚫");
+        assert_eq!(track_record(&game::History::default(), 0), "");
+
+        let control = suggestion_state(&info, &history, 1, 2, &[2]);
+        assert_eq!(control.commands[2], format!("suggest_2 ☑️ {} · ⚪ · 1 mission, 1 failed", bold("Cid")));
+    }
+
+    #[test]
+    fn test_mission_board_marks_double_fail() {
+        let theme = crate::theme::Theme::Classic.table();
+        let board = mission_board(theme, 8, &[MissionVote::Success], 3, 5);
+        let lines = board.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "1️⃣ 👥3 🏆");
+        assert_eq!(lines[3], "4️⃣ 👥5 ⬜ ⚠️ 2 fails");
+        assert_eq!(lines[5], "Vote attempts: ●●●○○");
+        assert!(!mission_board(theme, 7, &[], 1, 5).contains("fails"));
+    }
+
+    #[test]
+    fn test_messages_use_theme_words() {
+        let mut info = game_info(&["Al", "Bob", "Cid", "Dan", "Eve"]);
+        info.theme = Theme::Pirates;
+        let messages = build_message_for_event(&info, &EventContext::default(), GameEvent::BadLastChance(vec![1, 3], 3));
+        let composed = compose(&info.players, &[], messages);
+        assert_eq!(text(&composed[0]), "The crew are winning, but the mutiny has one last chance\n\n\
+                                        The mutiny: <b>Bob</b>, <b>Dan</b>\n\n\
+                                        <i><b>Dan</b> is going to guess Navigator</i>");
+
+        let messages = build_message_for_event(&info, &EventContext::default(), GameEvent::MermaidSays(4, 1, Team::Bad));
+        assert_eq!(text(&compose(&info.players, &[], messages)[0]), "🧜 <b>Eve</b> says <b>Bob</b> is Mutineer");
+    }
+
+    // Golden files of every event, `UPDATE_SNAPSHOTS=1 cargo test` writes them after a wording change
+    fn snapshot_events() -> Vec<(&'static str, EventContext, GameEvent)> {
+        use game::{GameResult, History, MissionRecord, MermaidRecord, MerlinGuessRecord, Role, TurnRecord};
+        let votes = |pattern: &str| pattern.chars()
+            .map(|vote| if vote == '+' { TeamVote::Approve } else { TeamVote::Reject })
+            .collect::<Vec<_>>();
+        let history = History {
+            turns: vec![
+                TurnRecord { mission: 1, crown_id: 0, team: vec![0, 1], votes: votes("+--+-") },
+                TurnRecord { mission: 1, crown_id: 1, team: vec![1, 2], votes: votes("+++-+") },
+                TurnRecord { mission: 2, crown_id: 2, team: vec![2, 3, 4], votes: votes("+++++") },
+            ],
+            missions: vec![
+                MissionRecord { team: vec![1, 2], fails: 0, result: MissionVote::Success },
+                MissionRecord { team: vec![2, 3, 4], fails: 1, result: MissionVote::Fail },
+            ],
+            mermaid: vec![MermaidRecord { holder: 4, checked: 1, truth: Team::Bad, claim: Team::Good }],
+            merlin_guess: Some(MerlinGuessRecord { guesser: 3, guess: 2, merlin: 0 }),
+            result: Some(GameResult::GoodWins),
+        };
+        let finished = EventContext {
+            roles: vec![Role::Merlin, Role::Assassin, Role::Percival, Role::Mordred, Role::Good],
+            history,
+            ..Default::default()
+        };
+        let context = || EventContext {
+            missions: vec![MissionVote::Success, MissionVote::Fail],
+            try_count: 1,
+            max_try_count: 5,
+            ..Default::default()
+        };
+
+        vec![
+            ("turn", context(), GameEvent::Turn(0, 2)),
+            ("team_suggested", context(), GameEvent::TeamSuggested(vec![0, 2])),
+            ("team_vote", context(), GameEvent::TeamVote(votes("+-++-"))),
+            ("team_approved", context(), GameEvent::TeamApproved(vec![0, 2])),
+            ("team_rejected", context(), GameEvent::TeamRejected(3)),
+            ("mission_result", context(), GameEvent::MissionResult(vec![MissionVote::Success, MissionVote::Fail])),
+            ("mermaid", context(), GameEvent::Mermaid(4)),
+            ("mermaid_result", context(), GameEvent::MermaidResult(4, 1, Team::Bad)),
+            ("mermaid_says", context(), GameEvent::MermaidSays(4, 1, Team::Good)),
+            ("bad_last_chance", context(), GameEvent::BadLastChance(vec![1, 3], 3)),
+            ("merlin", context(), GameEvent::Merlin(0)),
+            ("game_result", finished, GameEvent::GameResult(GameResult::GoodWins)),
+        ]
+    }
+
+    // Every message as the player sees it in Telegram with the buttons under it.
+    // The same messages of several players are written once
+    fn render_snapshot(info: &GameInfo, composed: &[ComposedMessage]) -> String {
+        let mut blocks: Vec<(Vec<&str>, String)> = Vec::new();
+        for message in composed {
+            let rendered = TelegramRenderer.render(message);
+            let protected = if message.protected { "(protected)\n" } else { "" };
+            let mut block = format!("{}{}\n", protected, rendered.text);
+            for button in rendered.keyboard.iter().flat_map(|keyboard| keyboard.inline_keyboard.concat()) {
+                if let InlineKeyboardButtonKind::CallbackData(data) = &button.kind {
+                    block += &format!("[{}] {}\n", button.text, data);
+                }
+            }
+
+            let name = info.user_names[&message.chat_id].as_ref();
+            match blocks.iter_mut().find(|(_, text)| *text == block) {
+                Some((names, _)) => names.push(name),
+                None => blocks.push((vec![name], block)),
+            }
+        }
+        blocks.iter()
+            .map(|(names, block)| format!("=== {}\n{}", names.join(", "), block))
+            .collect()
+    }
+
+    #[test]
+    fn test_game_message_snapshots() {
+        let info = game_info(&["Al", "Bob", "<Cid>", "Dan & Co", "Eve"]);
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/snapshots/game_msg");
+        let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+        let mut mismatched = Vec::new();
+        for (name, context, event) in snapshot_events() {
+            let composed = compose(&info.players, &[], build_message_for_event(&info, &context, event));
+            let actual = render_snapshot(&info, &composed);
+            let path = dir.join(format!("{}.txt", name));
+            if update {
+                std::fs::create_dir_all(&dir).unwrap();
+                std::fs::write(&path, &actual).unwrap();
+            } else if std::fs::read_to_string(&path).ok().as_deref() != Some(actual.as_str()) {
+                println!("Snapshot {} differs:\n{}", path.display(), actual);
+                mismatched.push(name);
+            }
+        }
+        assert!(mismatched.is_empty(), "Changed messages: {:?}. Run with UPDATE_SNAPSHOTS=1 if it is expected", mismatched);
+    }
+}
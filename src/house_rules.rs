@@ -0,0 +1,77 @@
+use teloxide::prelude::*;
+
+use crate::game_msg;
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameSession};
+
+const MAX_RULES_LEN: usize = 500;
+
+// Rules of the table the engine doesn't know about, e.g. "no metagaming, no timer talk"
+fn parse(args: &str) -> Result<Option<String>, String> {
+    let rules = args.trim();
+    if rules.is_empty() {
+        return Ok(None);
+    }
+    if rules.chars().count() > MAX_RULES_LEN {
+        return Err(format!("House rules should be at most {} characters long", MAX_RULES_LEN));
+    }
+    Ok(Some(rules.to_string()))
+}
+
+pub fn describe(rules: &str) -> String {
+    format!("📜 House rules: {}", rules)
+}
+
+// The leader sets them before the start, they stay for the next games of the lobby
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some((session, _)) = crate::lobby_settings(ctx, chat_id) else {
+        ctx.bot.send_message(chat_id, "Only game leader can set the house rules before the start").await?;
+        return respond(());
+    };
+
+    let reply = match parse(args) {
+        Ok(Some(rules)) => {
+            session.send(SessionCommand::SetHouseRules(Some(rules.clone())));
+            format!("{}\nEverybody gets them at the start of the game", describe(&rules))
+        }
+        Ok(None) => {
+            session.send(SessionCommand::SetHouseRules(None));
+            "The house rules are cleared".to_string()
+        }
+        Err(e) => e,
+    };
+    ctx.bot.send_message(chat_id, reply).await?;
+
+    respond(())
+}
+
+// Called at the start of every game of the lobby
+pub async fn announce(session: &GameSession, players: &[ChatId]) {
+    let Some(rules) = session.house_rules.as_ref() else {
+        return;
+    };
+    for player in players {
+        if let Err(e) = session.bot.send_message(*player, describe(rules)).await {
+            tracing::warn!("Failed to send the house rules to {}: {}", player, e);
+        }
+    }
+}
+
+// Part of /status, the board is HTML
+pub fn status(session: &GameSession) -> String {
+    session.house_rules.as_deref().map(|rules| describe(&game_msg::escape(rules))).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_house_rules() {
+        assert_eq!(parse("  No timer talk "), Ok(Some("No timer talk".to_string())));
+        assert_eq!(parse(""), Ok(None));
+        assert!(parse(&"a".repeat(MAX_RULES_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use std::ops::ControlFlow;
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use teloxide::prelude::*;
+use teloxide::types::Me;
+use tokio::sync::Mutex;
+
+use crate::cluster::SECRET_HEADER;
+use crate::metrics::METRICS;
+use crate::BotCtx;
+
+// What the updates forwarded by other instances of the cluster are handled with
+#[derive(Clone)]
+pub struct Forwarding {
+    pub me: Me,
+    pub secret: String,
+}
+
+async fn metrics(State(ctx): State<Arc<Mutex<BotCtx>>>) -> impl IntoResponse {
+    let gauges = crate::game_gauges(&*ctx.lock().await);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], METRICS.render(&gauges))
+}
+
+// The bot is considered wedged if the state can't be locked or Telegram doesn't answer in this time
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Ids of the running games whose session task or engine has stopped
+fn dead_games(ctx: &BotCtx) -> Vec<u32> {
+    let mut dead = ctx.game_sessions.values()
+        .filter(|session| {
+            let status = session.status();
+            !status.finished && (!session.is_running() || status.stalled)
+        })
+        .map(|session| session.id)
+        .collect::<Vec<_>>();
+    dead.sort();
+    dead
+}
+
+async fn healthz(State(ctx): State<Arc<Mutex<BotCtx>>>) -> (StatusCode, String) {
+    let (bot, dead) = match tokio::time::timeout(HEALTH_TIMEOUT, async {
+        let ctx = ctx.lock().await;
+        (ctx.bot.clone(), dead_games(&ctx))
+    }).await {
+        Ok(state) => state,
+        Err(_) => return (StatusCode::SERVICE_UNAVAILABLE, "Bot state is locked".to_string()),
+    };
+
+    let mut problems = Vec::new();
+    match tokio::time::timeout(HEALTH_TIMEOUT, bot.get_me().send()).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => problems.push(format!("Telegram request failed: {}", e)),
+        Err(_) => problems.push("Telegram does not respond".to_string()),
+    }
+    if !dead.is_empty() {
+        let ids = dead.iter().map(|id| format!("#{}", id)).collect::<Vec<_>>();
+        problems.push(format!("Games are not processed: {}", ids.join(", ")));
+    }
+
+    if problems.is_empty() {
+        (StatusCode::OK, "ok".to_string())
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, problems.join("\n"))
+    }
+}
+
+// Update received by another instance for the game run by this one
+async fn internal_update(State(ctx): State<Arc<Mutex<BotCtx>>>, Extension(forwarding): Extension<Forwarding>,
+                         headers: HeaderMap, Json(update): Json<Update>) -> StatusCode {
+    if headers.get(SECRET_HEADER).map(|value| value.as_bytes()) != Some(forwarding.secret.as_bytes()) {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let bot = ctx.lock().await.bot.clone();
+    let deps = dptree::deps![update, bot, ctx, forwarding.me];
+    if let ControlFlow::Break(Err(e)) = crate::local_handler().dispatch(deps).await {
+        tracing::warn!("Failed to handle the forwarded update: {}", e);
+    }
+    StatusCode::OK
+}
+
+// Server for monitoring and the updates forwarded in the cluster,
+// separate from the webhook one which is exposed to Telegram
+pub fn spawn_server(address: SocketAddr, ctx: Arc<Mutex<BotCtx>>, forwarding: Option<Forwarding>) {
+    let mut app = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz));
+    if let Some(forwarding) = forwarding {
+        app = app.route("/internal/update", post(internal_update).layer(Extension(forwarding)));
+    }
+    let app = app.with_state(ctx);
+
+    tokio::spawn(async move {
+        tracing::info!("Serving metrics and health checks on {}", address);
+        if let Err(e) = axum::Server::bind(&address).serve(app.into_make_service()).await {
+            tracing::error!("HTTP server error: {}", e);
+        }
+    });
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use reqwest::Url;
+use teloxide::prelude::*;
+use teloxide::types::{
+    InlineKeyboardButton, InlineKeyboardMarkup, InlineQueryResult, InlineQueryResultArticle, InputMessageContent,
+    InputMessageContentText,
+};
+use tokio::sync::Mutex;
+
+use crate::session::SessionCommand;
+use crate::BotCtx;
+
+const MAX_DESCRIPTION_LEN: usize = 200;
+
+pub fn invite_url(bot_username: &str, game_id: u32) -> String {
+    format!("https://t.me/{}?start={}", bot_username, game_id)
+}
+
+// Description of /set_invite, the quotes around it are optional and the empty one clears it
+fn parse_description(args: &str) -> Result<Option<String>, String> {
+    let args = args.trim();
+    let description = args.strip_prefix('"').and_then(|args| args.strip_suffix('"')).unwrap_or(args).trim();
+    if description.is_empty() {
+        return Ok(None);
+    }
+
+    if description.chars().count() > MAX_DESCRIPTION_LEN {
+        return Err(format!("Description should be at most {} characters long", MAX_DESCRIPTION_LEN));
+    }
+
+    Ok(Some(description.to_string()))
+}
+
+// The leader gets the invite with the description, ready to be forwarded
+pub async fn set_description(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some((session, _)) = crate::lobby_settings(ctx, chat_id) else {
+        ctx.bot.send_message(chat_id, "Only game leader can describe the lobby before the start").await?;
+        return respond(());
+    };
+
+    match parse_description(args) {
+        Ok(description) => {
+            session.send(SessionCommand::SetInvite(description.clone()));
+            let url = invite_url(&ctx.bot_username, session.id);
+            let reply = match description {
+                Some(description) => format!("{}\nJoin the game: {}", description, url),
+                None => format!("The description is cleared. Join the game: {}", url),
+            };
+            ctx.bot.send_message(chat_id, reply).await?;
+        }
+        Err(e) => {
+            ctx.bot.send_message(chat_id, e).await?;
+        }
+    }
+
+    respond(())
+}
+
+// Lobby of the leader which is shared by `@bot <game id>`, the empty query means the own lobby.
+// Games which are started or belong to somebody else get no card
+fn shared_game(ctx: &BotCtx, chat_id: ChatId, query: &str) -> Option<u32> {
+    let game_id = match query.trim() {
+        "" => *ctx.user_games.get(&chat_id)?,
+        token => token.parse().ok()?,
+    };
+    let session = ctx.game_sessions.get(&game_id)?;
+    let status = session.status();
+    (session.leader == chat_id && (!status.started || status.finished)).then_some(game_id)
+}
+
+// Inline mode, so the leader can post the invite card with the join button into any chat
+#[tracing::instrument(skip_all, err, fields(chat = %query.from.id))]
+pub async fn handle_inline_query(query: InlineQuery, ctx: Arc<Mutex<BotCtx>>) -> ResponseResult<()>
+{
+    let ctx = &mut *ctx.lock().await;
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let mut results = Vec::new();
+    if let Some(game_id) = shared_game(ctx, chat_id, &query.query) {
+        match Url::parse(&invite_url(&ctx.bot_username, game_id)) {
+            Ok(url) => {
+                let name = crate::get_display_name(ctx, chat_id);
+                let joined = crate::lobby_members(ctx, game_id).len();
+                let mut text = format!("{} invites you to play The Resistance Avalon", name);
+                if let Some(invite) = ctx.game_sessions.get(&game_id).and_then(|session| session.status().invite.clone()) {
+                    text = format!("{}\n📝 {}", text, invite);
+                }
+                let card = InlineQueryResultArticle::new(
+                    game_id.to_string(),
+                    "Join my Avalon game",
+                    InputMessageContent::Text(InputMessageContentText::new(text)),
+                )
+                    .description(format!("Game {}, players joined: {}", game_id, joined))
+                    .reply_markup(InlineKeyboardMarkup::new([[InlineKeyboardButton::url("Join the game", url)]]));
+                results.push(InlineQueryResult::Article(card));
+            }
+            Err(e) => tracing::warn!("Invalid invite link of game {}: {}", game_id, e),
+        }
+    }
+
+    // Lobbies change, so the answer is not cached
+    ctx.bot.answer_inline_query(query.id, results).cache_time(0).is_personal(true).await?;
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_description() {
+        assert_eq!(parse_description("\"Friday Avalon, 21:00\""), Ok(Some("Friday Avalon, 21:00".to_string())));
+        assert_eq!(parse_description("  Friday Avalon "), Ok(Some("Friday Avalon".to_string())));
+        assert_eq!(parse_description(""), Ok(None));
+        assert_eq!(parse_description("\"\""), Ok(None));
+        assert!(parse_description(&"a".repeat(MAX_DESCRIPTION_LEN + 1)).is_err());
+    }
+}
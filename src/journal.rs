@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
+
+use crate::ai;
+use crate::game::{self, GameClient, GameEvent, MissionVote, Phase, Team, TeamVote, ID};
+
+// How long the replay waits for the engine to produce the next logged event
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Player action accepted by the engine
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum Move {
+    SuggestTeam(ID, Vec<ID>),
+    TeamVote(ID, TeamVote),
+    Mission(ID, MissionVote),
+    MermaidCheck(ID),
+    MermaidWord(Team),
+    NameMerlin(ID),
+}
+
+impl Move {
+    pub async fn apply(&self, cli: &mut GameClient) -> Result<(), String> {
+        match self.clone() {
+            Move::SuggestTeam(from, team) => cli.suggest_team(from, &team).await.map_err(|e| e.to_string()),
+            Move::TeamVote(from, vote) => cli.add_team_vote(from, vote).await.map_err(|e| e.to_string()),
+            Move::Mission(from, vote) => cli.submit_for_mission(from, vote).await.map_err(|e| e.to_string()),
+            Move::MermaidCheck(id) => cli.send_mermaid_selection(id).await.map_err(|e| e.to_string()),
+            Move::MermaidWord(word) => cli.send_mermaid_word(word).await.map_err(|e| e.to_string()),
+            Move::NameMerlin(id) => cli.send_merlin_check(id).await.map_err(|e| e.to_string()),
+        }
+    }
+
+    // Phase of the game in which the move is accepted
+    pub fn phase(&self) -> Phase {
+        match self {
+            Move::SuggestTeam(..) => Phase::TeamSuggestion,
+            Move::TeamVote(..) => Phase::TeamVote,
+            Move::Mission(..) => Phase::Mission,
+            Move::MermaidCheck(_) => Phase::MermaidCheck,
+            Move::MermaidWord(_) => Phase::MermaidWord,
+            Move::NameMerlin(_) => Phase::MerlinGuess,
+        }
+    }
+
+    // Seat of the player who voted for the team or the mission
+    fn voter(&self) -> Option<ID> {
+        match self {
+            Move::TeamVote(from, _) | Move::Mission(from, _) => Some(*from),
+            _ => None,
+        }
+    }
+}
+
+// Append-only log of the game. Each /restart of the session begins a new game with Started
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum LogEntry {
+    // Engine state right after the setup, with the roles and the first crown holder
+    Started(game::GameInfo),
+    Event(GameEvent),
+    Move(Move),
+}
+
+pub struct Recovered {
+    pub engine: AbortHandle,
+    pub cli: GameClient,
+    // Last event which asked players to act, it is shown to them again
+    pub prompt: Option<GameEvent>,
+    // Players who already voted after the prompt
+    pub voted: Vec<ID>,
+}
+
+// Runs the engine from the start of the last game in the log and applies the logged moves.
+// Logged events are the checkpoints: every move is applied only when the engine has reached
+// the state it was accepted in, and the engine must produce exactly the same events as before
+pub async fn recover(log: &[LogEntry]) -> Result<Recovered, String> {
+    let start = log.iter()
+        .rposition(|entry| matches!(entry, LogEntry::Started(_)))
+        .ok_or("The log has no start of the game")?;
+    let LogEntry::Started(initial) = &log[start] else {
+        unreachable!();
+    };
+
+    let (game, mut cli) = game::Game::restore(initial.clone());
+    let engine = game::spawn_engine(game);
+    let players = cli.get_player_roles().await.len();
+    let mut prompt = None;
+    let mut voted = Vec::new();
+
+    for entry in &log[start + 1..] {
+        let result = match entry {
+            LogEntry::Event(expected) => match tokio::time::timeout(REPLAY_TIMEOUT, cli.recv_event()).await {
+                Ok(Ok(event)) if is_replayed(&event, expected) => {
+                    if ai::prompted_seats(&event, players).is_some() {
+                        prompt = Some(event);
+                        voted.clear();
+                    }
+                    Ok(())
+                }
+                Ok(Ok(event)) => Err(format!("Expected {:?}, but the engine sent {:?}", expected, event)),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err(format!("The engine did not send {:?}", expected)),
+            },
+            LogEntry::Move(logged) => {
+                voted.extend(logged.voter());
+                logged.apply(&mut cli).await
+            }
+            LogEntry::Started(_) => Ok(()),
+        };
+        if let Err(e) = result {
+            engine.abort();
+            return Err(e);
+        }
+    }
+
+    Ok(Recovered { engine, cli, prompt, voted })
+}
+
+// The engine shuffles the mission votes, so only the numbers of the votes have to match
+fn is_replayed(event: &GameEvent, expected: &GameEvent) -> bool {
+    match (event, expected) {
+        (GameEvent::MissionResult(votes), GameEvent::MissionResult(expected)) => {
+            let fails = |votes: &[MissionVote]| votes.iter().filter(|vote| **vote == MissionVote::Fail).count();
+            votes.len() == expected.len() && fails(votes) == fails(expected)
+        }
+        _ => event == expected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_votes_in_progress_are_recovered() {
+        let (mut game, mut cli) = game::Game::setup(5);
+        let mut log = vec![LogEntry::Started(cli.snapshot().await)];
+        tokio::spawn(async move { game.start().await.map_err(|e| e.to_string()) });
+
+        let turn = cli.recv_event().await.unwrap();
+        let GameEvent::Turn(crown_id, team_size) = turn else {
+            panic!("Unexpected event {:?}", turn);
+        };
+        log.push(LogEntry::Event(turn));
+        let team = (0..team_size as ID).collect::<Vec<_>>();
+        let suggestion = Move::SuggestTeam(crown_id, team.clone());
+        suggestion.apply(&mut cli).await.unwrap();
+        log.push(LogEntry::Move(suggestion));
+        let suggested = cli.recv_event().await.unwrap();
+        assert_eq!(suggested, GameEvent::TeamSuggested(team.clone()));
+        log.push(LogEntry::Event(suggested.clone()));
+
+        // Two of five players voted before the crash
+        for logged in [Move::TeamVote(0, TeamVote::Approve), Move::TeamVote(3, TeamVote::Reject)] {
+            logged.apply(&mut cli).await.unwrap();
+            log.push(LogEntry::Move(logged));
+        }
+
+        let mut recovered = recover(&log).await.unwrap();
+        assert_eq!(recovered.prompt, Some(suggested));
+        assert_eq!(recovered.voted, vec![0, 3]);
+        assert_eq!(recovered.cli.get_current_team().await, team);
+        assert_eq!(recovered.cli.get_waiting_for().await, vec![1, 2, 4]);
+
+        for id in [1, 2, 4] {
+            recovered.cli.add_team_vote(id, TeamVote::Approve).await.unwrap();
+        }
+        let votes = recovered.cli.recv_event().await.unwrap();
+        assert_eq!(votes, GameEvent::TeamVote(vec![
+            TeamVote::Approve, TeamVote::Approve, TeamVote::Approve, TeamVote::Reject, TeamVote::Approve,
+        ]));
+        recovered.engine.abort();
+    }
+
+    #[tokio::test]
+    async fn test_mixed_mission_is_replayed() {
+        let (mut game, mut cli) = game::Game::setup(5);
+        let mut log = vec![LogEntry::Started(cli.snapshot().await)];
+        tokio::spawn(async move { game.start().await.map_err(|e| e.to_string()) });
+
+        let roles = cli.get_player_roles().await;
+        let evil = roles.iter().position(|role| !role.is_good()).unwrap() as ID;
+        let good = roles.iter().position(|role| role.is_good()).unwrap() as ID;
+        let turn = cli.recv_event().await.unwrap();
+        let GameEvent::Turn(crown_id, 2) = turn else {
+            panic!("Unexpected event {:?}", turn);
+        };
+        log.push(LogEntry::Event(turn));
+
+        let mut moves = vec![Move::SuggestTeam(crown_id, vec![evil, good])];
+        moves.extend((0..5).map(|id| Move::TeamVote(id, TeamVote::Approve)));
+        moves.extend([Move::Mission(evil, MissionVote::Fail), Move::Mission(good, MissionVote::Success)]);
+        for logged in moves {
+            logged.apply(&mut cli).await.unwrap();
+            log.push(LogEntry::Move(logged));
+            if let Ok(Ok(event)) = tokio::time::timeout(Duration::from_millis(100), cli.recv_event()).await {
+                log.push(LogEntry::Event(event));
+            }
+        }
+        let Some(LogEntry::Event(GameEvent::MissionResult(votes))) = log.pop() else {
+            panic!("The mission has no result");
+        };
+
+        // The replay shuffles the votes again, both orders are the same result
+        for order in [votes.clone(), votes.into_iter().rev().collect()] {
+            let mut log = log.clone();
+            log.push(LogEntry::Event(GameEvent::MissionResult(order)));
+            recover(&log).await.unwrap().engine.abort();
+        }
+        let mixed = |votes: &[MissionVote]| GameEvent::MissionResult(votes.to_vec());
+        assert!(is_replayed(&mixed(&[MissionVote::Fail, MissionVote::Success]),
+                            &mixed(&[MissionVote::Success, MissionVote::Fail])));
+        assert!(!is_replayed(&mixed(&[MissionVote::Fail, MissionVote::Fail]),
+                             &mixed(&[MissionVote::Success, MissionVote::Fail])));
+    }
+
+    #[tokio::test]
+    async fn test_diverged_log_is_rejected() {
+        let (_game, cli) = game::Game::setup(5);
+        let log = vec![
+            LogEntry::Started(cli.snapshot().await),
+            LogEntry::Event(GameEvent::GameResult(game::GameResult::GoodWins)),
+        ];
+        assert!(recover(&log).await.is_err());
+        assert!(recover(&log[1..]).await.is_err());
+    }
+}
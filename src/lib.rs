@@ -0,0 +1,7 @@
+// Game engine shared by the bot and the avalon-cli terminal client
+pub mod ai;
+pub mod commands;
+pub mod game;
+pub mod journal;
+pub mod script;
+pub mod simulation;
@@ -1,70 +1,517 @@
-mod game;
+mod about;
+mod admin;
+mod analytics;
+#[cfg(feature = "api")]
+mod api;
+mod audit;
+mod ban;
+mod claims;
+mod cleanup;
+mod cluster;
+mod commentary;
+mod config;
+mod countdown;
+mod debug;
+mod delivery;
+mod discussion;
+#[cfg(feature = "api")]
+mod discord;
+mod export;
+mod feedback;
+mod forum;
 mod game_msg;
-
-use std::{sync::Arc, ops::DerefMut, collections::HashMap, error::Error};
+#[cfg(feature = "api")]
+mod http;
+mod house_rules;
+mod invite;
+mod matchmaking;
+mod media;
+mod metrics;
+#[cfg(test)]
+mod mock_telegram;
+mod notes;
+mod nudge;
+mod outbox;
+mod qr;
+mod rating;
+mod relay;
+mod replay;
+mod reports;
+mod reveal;
+mod seating;
+mod session;
+mod settings;
+mod stats;
+mod storage;
+mod switcher;
+mod theme;
+mod timeout;
+mod tournament;
+mod transcript;
+mod typing;
+mod unreachable;
+mod users;
+mod vote_poll;
+mod webapp;
+mod whoami;
+
+use avalon_tg_bot::{ai, commands, game, journal};
+
+use std::{sync::Arc, ops::DerefMut, collections::{HashMap, HashSet}, error::Error};
 
 use game::GameEvent;
-use game_msg::GameMessage;
+use game_msg::{GameMessage, MessageRenderer, TelegramMessage};
+use teloxide::adaptors::throttle::{Limits, Throttle};
+use teloxide::dispatching::UpdateHandler;
+use teloxide::error_handlers::IgnoringErrorHandler;
 use teloxide::prelude::*;
-use teloxide::types::MessageId;
+use teloxide::types::{BotCommand, InputFile, MessageId, MessageKind, ParseMode, WebAppData};
+use teloxide::update_listeners::webhooks;
+use teloxide::utils::command::BotCommands;
 use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
+use tracing_subscriber::prelude::*;
+use admin::AdminConfig;
+use clap::Parser;
+use commands::{GameAction, ParseError};
+use config::Config;
+use journal::{LogEntry, Move};
+use media::MediaConfig;
+use metrics::METRICS;
+use nudge::NudgeConfig;
+use outbox::Outbox;
+use relay::RelayMode;
+use session::{Restored, SessionCommand, SessionHandle};
+use settings::{LobbySettings, Setting};
+use storage::Storage;
+use theme::Theme;
+use timeout::TimeoutSettings;
+use users::{MutedChats, UserProfile};
+use webapp::WebAppConfig;
 use crate::game::{MissionVote, Team, TeamVote};
 
-const BOT_TG_ADDR: &str = "the_resistance_avalon_bot";
+// All requests go through one queue, which keeps per-chat and overall send rates
+// within Telegram limits and retries requests rejected with RetryAfter
+pub type Bot = Throttle<teloxide::Bot>;
+
+
+// Game action commands (/suggest_N, /team_approve, ...) are generated dynamically
+// in control messages, so they are matched by prefix and not listed here
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "snake_case", description = "The Resistance Avalon Bot commands:")]
+enum Command {
+    #[command(description = "show welcome message")]
+    Start(String),
+    #[command(description = "create a new game session")]
+    NewGame,
+    #[command(description = "wait in the queue until enough players are found for a game")]
+    FindGame,
+    #[command(description = "start the game when everybody is joined")]
+    StartGame,
+    #[command(description = "restart the game with the same group")]
+    Restart,
+    #[command(description = "leave the current game")]
+    Exit,
+    #[command(description = "show your games and choose the active one: [game id]")]
+    Switch(String),
+    #[command(description = "set your name for the next games")]
+    Nickname(String),
+    #[command(description = "set what to do with players who do not act in time: off, auto or ai [minutes]")]
+    Timeout(String),
+    #[command(description = "show the settings menu, or mute or unmute the notifications which don't need your action")]
+    Settings(String),
+    #[command(description = "remove a player from your lobby, they can't join it again")]
+    Ban,
+    #[command(description = "show the seats around the table, the leader can reorder them before the start")]
+    Seats,
+    #[command(description = "configure the game in one go: classic7, beginner5 or chaos")]
+    Preset(String),
+    #[command(description = "describe your lobby for the invite, e.g. \"Friday Avalon, 21:00\", empty to clear it")]
+    SetInvite(String),
+    #[command(description = "set the rules of your table which everybody gets at the start and in /status, empty to clear them")]
+    HouseRules(String),
+    #[command(description = "send it in a group with topics to post every game of your lobby in its own topic, off to stop")]
+    Forum(String),
+    #[command(description = "send it in the chat of the stream to comment the games of your lobby there, off to stop")]
+    Commentator(String),
+    #[command(description = "play a series of games with the same lobby and count the points: <games> or off")]
+    Tournament(String),
+    #[command(description = "show the timed order of every move after the end of the game")]
+    Audit,
+    #[command(description = "show the board of the missions")]
+    Status,
+    #[command(description = "remind your seat, role and what the game is waiting for from you")]
+    Whoami,
+    #[command(description = "message the rest of the bad team anonymously, if the lobby allows it")]
+    Evil(String),
+    #[command(description = "tell everybody your role or what the mermaid showed: <role> or mermaid <name> good|bad")]
+    Claim(String),
+    #[command(description = "keep a private note during the game, it is wiped at the end")]
+    Note(String),
+    #[command(description = "show your notes of the game")]
+    Notes,
+    #[command(description = "show your statistics")]
+    Stats,
+    #[command(description = "show top players: wins or rating [page] [season:N]")]
+    Leaderboard(String),
+    #[command(description = "get the log of the finished game as a file: text or json")]
+    Transcript(String),
+    #[command(description = "step through the events of the finished game")]
+    Replay,
+    #[command(description = "get all your finished games as a file: csv or json")]
+    Export(String),
+    #[command(description = "add a comment to your rating of the finished game")]
+    Comment(String),
+    #[command(description = "send a message to the maintainer, e.g. when the game is stuck")]
+    Feedback(String),
+    #[command(description = "show the version of the bot, its features and uptime")]
+    About,
+    #[command(description = "show the list of commands")]
+    Help,
+    #[command(description = "off")]
+    SuggestFinish,
+    #[command(description = "off")]
+    Admin(String),
+    #[command(description = "off")]
+    Analytics(String),
+    #[command(description = "off")]
+    DebugGame(String),
+}
+
+const COMMAND_DESCRIPTIONS_RU: &[(&str, &str)] = &[
+    ("start", "показать приветствие"),
+    ("new_game", "создать новую игру"),
+    ("find_game", "встать в очередь, пока не найдётся достаточно игроков"),
+    ("start_game", "начать игру, когда все присоединились"),
+    ("restart", "начать заново с той же группой"),
+    ("exit", "покинуть текущую игру"),
+    ("switch", "показать ваши игры и выбрать активную: [номер игры]"),
+    ("nickname", "задать своё имя для следующих игр"),
+    ("timeout", "что делать с игроками, которые не успели сходить: off, auto или ai [минуты]"),
+    ("settings", "меню настроек, или mute или unmute для уведомлений, не требующих вашего хода"),
+    ("ban", "убрать игрока из вашего лобби без возможности вернуться"),
+    ("seats", "показать места за столом, ведущий может поменять их до начала игры"),
+    ("preset", "настроить игру одной командой: classic7, beginner5 или chaos"),
+    ("set_invite", "описать ваше лобби для приглашения, например \"Пятничный Авалон, 21:00\", пустое убирает его"),
+    ("house_rules", "задать правила вашего стола, все получат их в начале игры и в /status, пустые убирают их"),
+    ("forum", "отправьте в группу с темами, чтобы каждая игра вашего лобби шла в своей теме, off отключает"),
+    ("commentator", "отправьте в чат трансляции, чтобы там комментировались игры вашего лобби, off отключает"),
+    ("tournament", "сыграть серию игр тем же лобби с подсчётом очков: <игры> или off"),
+    ("audit", "показать порядок и время всех ходов после конца игры"),
+    ("status", "показать табло миссий"),
+    ("whoami", "напомнить ваше место, роль и чего игра ждёт от вас"),
+    ("evil", "анонимно написать остальной плохой команде, если это разрешено в лобби"),
+    ("claim", "объявить всем свою роль или что показала русалка: <роль> или mermaid <имя> good|bad"),
+    ("note", "сохранить личную заметку во время игры, она удаляется в конце"),
+    ("notes", "показать ваши заметки в этой игре"),
+    ("stats", "показать вашу статистику"),
+    ("leaderboard", "лучшие игроки: wins или rating [страница] [season:N]"),
+    ("transcript", "получить запись законченной игры файлом: text или json"),
+    ("replay", "пошагово просмотреть события законченной игры"),
+    ("export", "получить все ваши законченные игры файлом: csv или json"),
+    ("comment", "добавить комментарий к вашей оценке законченной игры"),
+    ("feedback", "написать разработчику, например если игра зависла"),
+    ("about", "показать версию бота, его возможности и время работы"),
+    ("help", "показать список команд"),
+];
+
+fn bot_commands(language: Option<&str>) -> Vec<BotCommand> {
+    Command::bot_commands().into_iter()
+        .map(|cmd| {
+            let name = cmd.command.trim_start_matches('/').to_string();
+            let description = match language {
+                Some("ru") => COMMAND_DESCRIPTIONS_RU.iter()
+                    .find(|(command, _)| *command == name)
+                    .map(|(_, description)| description.to_string())
+                    .unwrap_or(cmd.description),
+                _ => cmd.description,
+            };
+            BotCommand::new(name, description)
+        })
+        .collect()
+}
+
+async fn register_commands(bot: &Bot) -> ResponseResult<()> {
+    bot.set_my_commands(bot_commands(None)).await?;
+    bot.set_my_commands(bot_commands(Some("ru"))).language_code("ru").await?;
+    respond(())
+}
 
 struct BotCtx {
     bot: Bot,
-    last_game_id: u32,
-    user_names: HashMap<ChatId, String>,
+    // Username of the bot, used in invite links and to parse commands with @mention
+    bot_username: String,
+    storage: Storage,
+    nudge: NudgeConfig,
+    timeout: TimeoutSettings,
+    media: MediaConfig,
+    webapp: Option<WebAppConfig>,
+    admin: AdminConfig,
+    // Other instances run games against the same storage
+    cluster: Option<cluster::Cluster>,
+    users: HashMap<ChatId, UserProfile>,
+    muted: MutedChats,
     user_games: HashMap<ChatId, u32>,
-    game_sessions: HashMap<u32, Arc<Mutex<GameSession>>>,
+    // Lobbies and games of the users besides the active one above, see switcher.rs
+    other_games: switcher::OtherGames,
+    // Open team vote polls of the group games, see vote_poll.rs
+    polls: vote_poll::VotePolls,
+    game_sessions: HashMap<u32, SessionHandle>,
+    // Games which start when the countdown ends, see countdown.rs
+    countdowns: HashMap<u32, AbortHandle>,
+    // Idle lobby members are removed, see cleanup.rs
+    lobby_activity: HashMap<ChatId, cleanup::Activity>,
+    // Strangers waiting for a game with /find_game
+    matchmaking: matchmaking::Matchmaking,
+    // Last /feedback of the users, see feedback.rs
+    feedback_sent: HashMap<ChatId, tokio::time::Instant>,
+    // Start of the bot for the uptime in /about
+    started_at: tokio::time::Instant,
 }
 
-struct SuggestionInfo {
+// Control message and the notifications sent together with it
+#[derive(Clone)]
+struct SentControl {
     msg_id: MessageId,
+    prefix: String,
+}
+
+struct SuggestionInfo {
+    control: SentControl,
     crown_id: u8,
     team_size: usize,
     users: Vec<u8>,
 }
 
+// Message which shows the progress of the team vote or the mission
+struct Tracker {
+    phase: game::Phase,
+    seats: Vec<game::ID>,
+    acted: Vec<game::ID>,
+    messages: Vec<(ChatId, MessageId)>,
+    text: String,
+}
+
+// State of the game owned by its session task, see session.rs
 struct GameSession {
     id: u32,
     leader: ChatId,
-    info: Option<GameInfo>,
+    created_at: tokio::time::Instant,
+    bot: Bot,
+    storage: Storage,
+    nudge: NudgeConfig,
+    media: MediaConfig,
+    webapp: Option<WebAppConfig>,
+    muted: MutedChats,
+    other_games: switcher::OtherGames,
+    polls: vote_poll::VotePolls,
+    // Rules of the next game chosen by the leader
+    options: game::GameOptions,
+    // Free text of the players is relayed to the others, see relay.rs
+    relay: RelayMode,
+    // Names of the players in the anonymous chat
+    pseudonyms: HashMap<ChatId, String>,
+    // The bad team talks in private with /evil, their names there are not the ones of the anonymous chat
+    evil_chat: bool,
+    evil_pseudonyms: HashMap<ChatId, String>,
+    // Roles and mermaid results the players claimed with /claim, see claims.rs
+    claims: Vec<claims::Claim>,
+    // Private notes of the players made with /note, see notes.rs
+    notes: notes::Notes,
+    // Time to talk about each proposed team before the vote, see discussion.rs
+    discussion_time: std::time::Duration,
+    discussion: Option<discussion::Discussion>,
+    // Cards of the mission are shown one by one before the result, see reveal.rs
+    card_reveal: bool,
+    reveal: Option<reveal::Reveal>,
+    // Ratings of the last finished game, see rating.rs
+    rating: Option<rating::RatingPrompt>,
+    // Stickers of the big moments, see media.rs
+    stickers: bool,
+    theme: Theme,
+    // Story of the theme between the game messages, chosen in /settings
+    narration: bool,
+    // Description of the lobby in the invite and the join message, see invite.rs
+    invite: Option<String>,
+    // Rules of the table set with /house_rules, see house_rules.rs
+    house_rules: Option<String>,
+    // Group with topics where each game gets a topic for the public messages, see forum.rs
+    forum: Option<ChatId>,
+    // Chat of the stream which gets the public events with the odds and the warnings, see commentary.rs
+    commentator: Option<ChatId>,
+    commentary: commentary::Board,
+    // Points of the series of games started with /tournament
+    tournament: Option<tournament::Tournament>,
+    // Order of the lobby members around the table chosen by the leader, see seating.rs
+    seating: Vec<ChatId>,
+    // Users the leader removed from the lobby, they can't join it again
+    banned: HashSet<ChatId>,
+    // AI players of the next game started by /debug_game, see debug.rs
+    bots: HashSet<ChatId>,
+    // Accepted moves of the game with their time, see audit.rs
+    audit: audit::AuditLog,
+    audit_access: audit::AuditAccess,
+    // Engine task of the running game
+    engine: Option<AbortHandle>,
+    info: Option<Arc<GameInfo>>,
     suggestion: Option<SuggestionInfo>,
     finished: bool,
+    // Engine stopped sending events before the end of the game
+    stalled: bool,
+    // The game can't go on after the failure of the engine, see session::crash
+    crashed: bool,
+    // Times the engine of the game was started again after a failure, see session::recover
+    recoveries: u32,
+    // Last time the game moved forward or players were reminded
+    idle_since: tokio::time::Instant,
+    // Last time the game moved forward or timeout action was applied
+    waiting_since: tokio::time::Instant,
+    timeout: TimeoutSettings,
+    // Seats of players replaced by AI
+    ai_seats: Vec<game::ID>,
+    // Control message waiting for the player's action
+    control_messages: HashMap<ChatId, SentControl>,
+    tracker: Option<Tracker>,
+    // Poll of the team vote in the topic of the game, see vote_poll.rs
+    poll: Option<vote_poll::VotePoll>,
+    // Players who already voted for the current team or mission. The engine counts
+    // every vote it gets, so repeated ones are stopped here
+    voted: HashSet<ChatId>,
+    // Players who tapped Reject on the last try once, the second tap is the vote
+    rejecting: HashSet<ChatId>,
+    // Players shown the typing indicator, see typing.rs
+    typing: typing::Pending,
+    // Player who tries to guess Merlin at the end of the game
+    guesser: Option<game::ID>,
+    // Pinned message with the state of the game
+    board_messages: HashMap<ChatId, MessageId>,
+    board_text: String,
+}
+
+impl GameSession {
+    // Applies the move to the engine and writes it to the game log if it is accepted
+    async fn perform(&mut self, accepted: Move) -> Result<(), String> {
+        self.perform_as(accepted, false).await
+    }
+
+    async fn perform_as(&mut self, accepted: Move, auto: bool) -> Result<(), String> {
+        let Some(info) = self.info.as_ref() else {
+            return Err("The game is not started".to_string());
+        };
+        accepted.apply(&mut info.cli.clone()).await?;
+        self.storage.append_log(self.id, &LogEntry::Move(accepted.clone()));
+        self.audit.record(accepted, auto);
+        Ok(())
+    }
+
+    // Acts instead of the player who is replaced by AI or did not act in time
+    async fn play_for(&mut self, id: game::ID, phase: game::Phase, strategy: ai::Strategy) -> Result<(), String> {
+        let Some(info) = self.info.as_ref() else {
+            return Ok(());
+        };
+        match ai::choose(&info.cli, id, phase, strategy).await? {
+            Some(chosen) => self.perform_as(chosen, true).await,
+            None => Ok(()),
+        }
+    }
+
+    fn new(ctx: &BotCtx, id: u32, leader: ChatId) -> Self {
+        Self {
+            id,
+            leader,
+            created_at: tokio::time::Instant::now(),
+            bot: ctx.bot.clone(),
+            storage: ctx.storage.clone(),
+            nudge: ctx.nudge,
+            media: ctx.media.clone(),
+            webapp: ctx.webapp.clone(),
+            muted: ctx.muted.clone(),
+            other_games: ctx.other_games.clone(),
+            polls: ctx.polls.clone(),
+            options: game::GameOptions::default(),
+            relay: RelayMode::Names,
+            pseudonyms: HashMap::new(),
+            evil_chat: false,
+            evil_pseudonyms: HashMap::new(),
+            claims: Vec::new(),
+            notes: notes::Notes::new(),
+            discussion_time: std::time::Duration::ZERO,
+            discussion: None,
+            card_reveal: true,
+            reveal: None,
+            rating: None,
+            stickers: true,
+            theme: Theme::Classic,
+            narration: false,
+            invite: None,
+            house_rules: None,
+            forum: None,
+            commentator: None,
+            commentary: Default::default(),
+            tournament: None,
+            seating: Vec::new(),
+            banned: HashSet::new(),
+            bots: HashSet::new(),
+            audit: audit::AuditLog::new(),
+            audit_access: audit::AuditAccess::Leader,
+            engine: None,
+            info: None,
+            suggestion: None,
+            finished: false,
+            stalled: false,
+            crashed: false,
+            recoveries: 0,
+            idle_since: tokio::time::Instant::now(),
+            waiting_since: tokio::time::Instant::now(),
+            timeout: ctx.timeout,
+            ai_seats: Vec::new(),
+            control_messages: HashMap::new(),
+            tracker: None,
+            poll: None,
+            voted: HashSet::new(),
+            rejecting: HashSet::new(),
+            typing: Default::default(),
+            guesser: None,
+            board_messages: HashMap::new(),
+            board_text: String::new(),
+        }
+    }
 }
 
 // TODO: Move out to separate file
 #[derive(Clone)]
 pub struct GameInfo {
+    id: u32,
     leader: ChatId,
     players: Vec<ChatId>,
-    user_names: HashMap<ChatId, String>,
+    // Shared by every message of the game, so they are not copied for each player
+    user_names: HashMap<ChatId, Arc<str>>,
     cli: game::GameClient,
+    delivery: Arc<std::sync::Mutex<delivery::DeliveryState>>,
+    muted: MutedChats,
+    // Messages of the game are tagged for the players who are in other games too
+    other_games: switcher::OtherGames,
+    // Words and icons of the game messages, see theme.rs
+    theme: Theme,
+    narration: bool,
+    // Seats of /debug_game played by the AI, they have no chat
+    bots: HashSet<ChatId>,
+    // Topic of the game in the group chosen with /forum
+    topic: Option<forum::Topic>,
 }
 
-async fn get_game_session(ctx: &mut BotCtx, message: &Message) -> Option<Arc<Mutex<GameSession>>> {
-    if let Some(game_id) = ctx.user_games.get(&message.chat.id) {
-        if let Some(session) = ctx.game_sessions.get(game_id).cloned() {
-            let session_id = session.lock().await.id;
-            let finished = session.lock().await.finished;
-            if finished {
-                drop(session);
-                ctx.game_sessions.remove(&session_id);
-                None
-            } else {
-                Some(session)
-            }
-        } else {
-            None
-        }
-    } else {
+async fn get_game_session(ctx: &mut BotCtx, message: &Message) -> Option<SessionHandle> {
+    let game_id = ctx.user_games.get(&message.chat.id)?;
+    let session = ctx.game_sessions.get(game_id)?.clone();
+    if session.status().finished {
+        ctx.game_sessions.remove(&session.id);
         None
+    } else {
+        Some(session)
     }
 }
 
-fn get_game_session_without_cleanup(ctx: &mut BotCtx, message: &Message) -> Option<Arc<Mutex<GameSession>>>
+fn get_game_session_without_cleanup(ctx: &mut BotCtx, message: &Message) -> Option<SessionHandle>
 {
     if let Some(game_id) = ctx.user_games.get(&message.chat.id) {
         ctx.game_sessions.get(game_id).cloned()
@@ -73,33 +520,48 @@ fn get_game_session_without_cleanup(ctx: &mut BotCtx, message: &Message) -> Opti
     }
 }
 
-async fn handle_start_bot<'a, I>(ctx: &mut BotCtx, message: &Message, mut cmd: I) -> ResponseResult<()>
-    where I: Iterator<Item = &'a str>
+async fn handle_start_bot(ctx: &mut BotCtx, message: &Message, param: &str) -> ResponseResult<()>
 {
-    if let Some(_) = get_game_session(ctx, message).await {
+    // Another game is joined with its link, the current one waits in /switch
+    let current = get_game_session(ctx, message).await.map(|session| session.id);
+    let joined = param.parse::<u32>().ok();
+    if current.is_some() && (joined.is_none() || joined == current) {
         ctx.bot.send_message(message.chat.id, "You are already in the game").await?;
         ctx.bot.send_message(message.chat.id, "If you want to leave it, use /exit command, than join the link again").await?;
+    } else if let Some(game_id) = joined.filter(|game_id| ctx.other_games.get(message.chat.id).contains(game_id)) {
+        switcher::join(ctx, message.chat.id, game_id);
+        ctx.bot.send_message(message.chat.id, format!("Game #{} is active now", game_id)).await?;
     } else {
-        if let Some(param) = cmd.next() {
+        if !param.is_empty() {
             if let Ok(game_id) = param.parse::<u32>() {
-                println!("Game ID: {}", game_id);
-                println!("Game sessions: {}",
-                         ctx.game_sessions.iter()
-                             .map(|(k, _v)| { format!("{}", *k) })
+                tracing::debug!("Game ID: {}", game_id);
+                tracing::debug!("Game sessions: {}",
+                         ctx.game_sessions.keys().map(|k| { format!("{}", *k) })
                              .collect::<Vec<_>>()
                              .join(","));
-                if let Some(session) = ctx.game_sessions.get(&game_id) {
-                    let session = session.lock().await;
+                if ctx.game_sessions.get(&game_id).is_some_and(|session| session.status().banned.contains(&message.chat.id)) {
+                    ctx.bot.send_message(message.chat.id, "The leader of this game doesn't let you join it").await?;
+                } else if let Some(session) = ctx.game_sessions.get(&game_id) {
+                    session.send(SessionCommand::Joined);
+                    let leader = session.leader;
+                    let status = session.status();
                     ctx.bot.send_message(message.chat.id, "You are joined the game. Wait for the game to start").await?;
-                    let name = if let Some(user) = &message.from() {
-                        user.first_name.clone()
-                    } else {
-                        message.chat.id.to_string()
-                    };
-
-                    ctx.bot.send_message(session.leader, format!("{} joined the game", name)).await?;
-                    ctx.user_games.insert(message.chat.id, game_id);
-                    ctx.user_names.insert(message.chat.id, name);
+                    if let Some(invite) = &status.invite {
+                        ctx.bot.send_message(message.chat.id, format!("📝 {}", invite)).await?;
+                    }
+                    if let Some(rules) = &status.house_rules {
+                        ctx.bot.send_message(message.chat.id, house_rules::describe(rules)).await?;
+                    }
+                    let name = remember_user(ctx, message);
+
+                    if !ctx.muted.contains(leader) {
+                        ctx.bot.send_message(leader, format!("{} joined the game", name)).await?;
+                    }
+                    switcher::join(ctx, message.chat.id, game_id);
+                    if let Some(current) = current {
+                        let notice = format!("You are still in game #{}, use /switch to choose the active game", current);
+                        ctx.bot.send_message(message.chat.id, notice).await?;
+                    }
                 } else {
                     ctx.bot.send_message(message.chat.id, "Invalid game id!").await?;
                 }
@@ -116,523 +578,1821 @@ async fn handle_start_bot<'a, I>(ctx: &mut BotCtx, message: &Message, mut cmd: I
     respond(())
 }
 
-async fn handle_exit(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
-{
-    if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let session = session.lock().await;
-        ctx.bot.send_message(message.chat.id, "You left the game").await?;
-        let username = ctx.user_names.get(&message.chat.id).unwrap();
-        ctx.bot.send_message(session.leader, format!("{} left the game", username)).await?;
-        ctx.user_games.remove(&message.chat.id);
-    } else {
-        ctx.bot.send_message(message.chat.id, "You are not in the game").await?;
+// Saves fresh Telegram data of the user and returns the name shown to other players
+fn remember_user(ctx: &mut BotCtx, message: &Message) -> String {
+    let fresh = UserProfile::from_message(message);
+    // Other instances may have changed the profile, e.g. the nickname, since it was cached
+    if ctx.cluster.is_some() {
+        match ctx.storage.load_user(message.chat.id) {
+            Ok(Some(stored)) => {
+                ctx.muted.set(message.chat.id, stored.muted);
+                ctx.users.insert(message.chat.id, stored);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Failed to load user {}: {}", message.chat.id, e),
+        }
     }
+    let user = ctx.users.entry(message.chat.id)
+        .and_modify(|user| user.update(fresh.clone()))
+        .or_insert(fresh);
+    ctx.storage.save_user(message.chat.id, user);
+    user.display_name().to_string()
+}
 
-    respond(())
+fn get_display_name(ctx: &BotCtx, chat_id: ChatId) -> String {
+    ctx.users.get(&chat_id)
+        .map(|user| user.display_name().to_string())
+        .unwrap_or_else(|| chat_id.to_string())
 }
 
-async fn handle_new_game(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+async fn handle_nickname(ctx: &mut BotCtx, message: &Message, nickname: &str) -> ResponseResult<()>
 {
-    if let Some(_) = get_game_session(ctx, message).await {
-        ctx.bot.send_message(message.chat.id, "You are already in the game").await?;
-        ctx.bot.send_message(message.chat.id, "If you want to leave it, use /exit command, than join the link again").await?;
-    } else {
-        let game_id = ctx.last_game_id + 1;
-        let session = GameSession {
-            id: game_id,
-            leader: message.chat.id,
-            info: None,
-            suggestion: None,
-            finished: false,
-        };
-
-        ctx.game_sessions.insert(session.id, Arc::new(Mutex::new(session)));
-        ctx.user_games.insert(message.chat.id, game_id);
-        ctx.last_game_id += 1;
-
-        let name = if let Some(user) = &message.from() {
-            user.first_name.clone()
-        } else {
-            message.chat.id.to_string()
-        };
-
-        ctx.user_names.insert(message.chat.id, name);
-
-        let id = message.chat.id;
-        ctx.bot.send_message(id, "Starting a new game...").await?;
-        ctx.bot.send_message(id, "Send the following invite link to your team").await?;
-        let url = format!("https://t.me/{}?start={}", BOT_TG_ADDR, game_id);
-        ctx.bot.send_message(id, url).await?;
-        ctx.bot.send_message(id, "When everybody is joined use /start_game").await?;
+    match users::validate_nickname(nickname) {
+        Ok(nickname) => {
+            remember_user(ctx, message);
+            let user = ctx.users.entry(message.chat.id).or_insert_with(|| UserProfile::from_message(message));
+            user.nickname = nickname;
+            ctx.storage.save_user(message.chat.id, user);
+            let reply = format!("Your name is {}. It will be used in the next games", user.display_name());
+            ctx.bot.send_message(message.chat.id, reply).await?;
+        }
+        Err(e) => {
+            ctx.bot.send_message(message.chat.id, e).await?;
+        }
     }
 
     respond(())
 }
 
-async fn handle_restart(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
-{
-    println!(">handle_restart");
-    if let Some(session_arc) = get_game_session_without_cleanup(ctx, message) {
-        session_arc.lock().await.finished = false;
-        handle_start_game(ctx, &message).await?
-    } else {
-        send_not_in_game(&ctx.bot, message).await?
+// Settings the leader can change before the game starts, also between the games
+fn lobby_settings(ctx: &BotCtx, chat_id: ChatId) -> Option<(SessionHandle, LobbySettings)> {
+    let session = ctx.user_games.get(&chat_id).and_then(|game_id| ctx.game_sessions.get(game_id))?;
+    let status = session.status();
+    if session.leader != chat_id || (status.started && !status.finished) {
+        return None;
     }
+    Some((session.clone(), LobbySettings {
+        options: status.options,
+        timeout: status.timeout,
+        relay: status.relay,
+        evil_chat: status.evil_chat,
+        discussion: status.discussion,
+        theme: status.theme,
+        narration: status.narration,
+        card_reveal: status.card_reveal,
+        stickers: status.stickers,
+        audit: status.audit,
+    }))
+}
 
-    println!("<handle_restart");
-    respond(())
+fn set_muted(ctx: &mut BotCtx, chat_id: ChatId, fresh: UserProfile, muted: bool) {
+    let user = ctx.users.entry(chat_id).or_insert(fresh);
+    user.muted = muted;
+    ctx.storage.save_user(chat_id, user);
+    ctx.muted.set(chat_id, muted);
 }
 
-async fn send_everybody(bot: &Bot, info: &GameInfo, msg: &str) {
-    for player in &info.players {
-        println!("Message '{}' to {}", msg, *player);
-        let _ = bot.send_message(*player, msg).await;
+async fn handle_settings(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    remember_user(ctx, message);
+    match args.trim() {
+        "" => {
+            let lobby = lobby_settings(ctx, chat_id).map(|(_, lobby)| lobby);
+            let muted = ctx.muted.contains(chat_id);
+            ctx.bot.send_message(chat_id, settings::notifications_text(muted))
+                .reply_markup(settings::keyboard(muted, lobby.as_ref()))
+                .await?;
+        }
+        args @ ("mute" | "unmute") => {
+            let muted = args == "mute";
+            set_muted(ctx, chat_id, UserProfile::from_message(message), muted);
+            ctx.bot.send_message(chat_id, settings::notifications_text(muted)).await?;
+        }
+        _ => {
+            ctx.bot.send_message(chat_id, "Use /settings, /settings mute or /settings unmute").await?;
+        }
     }
-}
 
-async fn send_not_in_game(bot: &Bot, message: &Message) -> ResponseResult<()> {
-    bot.send_message(message.chat.id, "You are not in a game. Join or create new one").await?;
     respond(())
 }
 
-fn control_message_to_string(control: &game_msg::ControlMessage) -> String {
-    let commands = control.commands
-        .iter()
-        .map(|c| format!("/{}", c))
-        .collect::<Vec<_>>();
+async fn handle_preset(ctx: &mut BotCtx, message: &Message, name: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some((session, mut lobby)) = lobby_settings(ctx, chat_id) else {
+        ctx.bot.send_message(chat_id, "Only game leader can choose a preset before the start").await?;
+        return respond(());
+    };
+
+    match lobby.apply_preset(name.trim()) {
+        Ok(description) => {
+            session.send(SessionCommand::SetOptions(lobby.options.clone()));
+            session.send(SessionCommand::SetTimeout(lobby.timeout));
+            let muted = ctx.muted.contains(chat_id);
+            ctx.bot.send_message(chat_id, format!("Preset {}: {}", name.trim(), description))
+                .reply_markup(settings::keyboard(muted, Some(&lobby)))
+                .await?;
+        }
+        Err(e) => {
+            ctx.bot.send_message(chat_id, e).await?;
+        }
+    }
 
-    format!("{}:\n{}", control.message, commands.join("\n"))
+    respond(())
 }
 
-async fn process_game_event(session: &mut GameSession, event: &GameEvent, bot: &Bot, info: &GameInfo) -> Result<(), Box<dyn Error>>
+// Button of the /settings menu was pressed, the menu is edited with the new values.
+// Returns the text of the popup
+async fn handle_setting(ctx: &mut BotCtx, query: &CallbackQuery, setting: Setting) -> ResponseResult<Option<&'static str>>
 {
-    println!(">process_game_event");
-    let messages = game_msg::build_message_for_event(info, event.clone()).await?;
-    println!("messages: {:?}", messages);
-
-    // TODO: Extract to function returning message id of control message (if any)
-    for msg in messages {
-        match msg {
-            GameMessage::Notification(notification) => {
-                match notification.dst {
-                    game_msg::Dst::All => {
-                        send_everybody(bot, info, &notification.message).await;
-                    }
-                    game_msg::Dst::User(id) => {
-                        println!("Message '{}' to {}", notification.message, id);
-                        bot.send_message(id, &notification.message).await?;
-                    }
-                }
-            }
-            GameMessage::ControlMessage(control) => {
-                let message = control_message_to_string(&control);
-                match control.dst {
-                    game_msg::Dst::All => {
-                        send_everybody(bot, info, message.as_str()).await;
-                    }
-                    game_msg::Dst::User(id) => {
-                        println!("Message '{}' to {}", message, id);
-                        let res = bot.send_message(id, message).await?;
-                        if let GameEvent::Turn(crown_id, team_size) = event {
-                            session.suggestion = Some(SuggestionInfo {
-                                msg_id: res.id,
-                                crown_id: *crown_id,
-                                team_size: *team_size,
-                                users: Vec::new(),
-                            });
-                        }
-                    }
-                }
-            }
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let mut lobby = lobby_settings(ctx, chat_id);
+    let mut muted = ctx.muted.contains(chat_id);
+    match (&setting, lobby.as_mut()) {
+        (Setting::Notifications, _) => {
+            muted = !muted;
+            set_muted(ctx, chat_id, UserProfile::from_user(&query.from), muted);
         }
+        (_, Some((session, lobby))) => {
+            lobby.change(&setting);
+            session.send(SessionCommand::SetOptions(lobby.options.clone()));
+            session.send(SessionCommand::SetTimeout(lobby.timeout));
+            session.send(SessionCommand::SetRelay(lobby.relay));
+            session.send(SessionCommand::SetEvilChat(lobby.evil_chat));
+            session.send(SessionCommand::SetDiscussion(lobby.discussion));
+            session.send(SessionCommand::SetTheme(lobby.theme));
+            session.send(SessionCommand::SetNarration(lobby.narration));
+            session.send(SessionCommand::SetCardReveal(lobby.card_reveal));
+            session.send(SessionCommand::SetStickers(lobby.stickers));
+            session.send(SessionCommand::SetAudit(lobby.audit));
+        }
+        (_, None) => return Ok(Some("Only game leader can change the game settings before the start")),
     }
 
-    if let GameEvent::GameResult(_) = event {
-        session.finished = true;
+    if let Some(message) = &query.message {
+        let lobby = lobby.map(|(_, lobby)| lobby);
+        ctx.bot.edit_message_text(message.chat.id, message.id, settings::notifications_text(muted))
+            .reply_markup(settings::keyboard(muted, lobby.as_ref()))
+            .await?;
     }
+    Ok(None)
+}
 
-    println!("<process_game_event");
-    Ok(())
+async fn handle_stats(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let reply = match ctx.storage.load_stats(message.chat.id) {
+        Ok(stats) => stats.render(&get_display_name(ctx, message.chat.id)),
+        Err(e) => {
+            tracing::warn!("Failed to load stats: {}", e);
+            "Statistics are not available now".to_string()
+        }
+    };
+    ctx.bot.send_message(message.chat.id, reply).await?;
+
+    respond(())
 }
 
-async fn handle_start_game(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+async fn handle_leaderboard(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
 {
-    println!(">handle_start_game");
-    if let Some(session_arc) = get_game_session(ctx, message).await {
-        let mut session = session_arc.lock().await;
-        if session.leader == message.chat.id {
-            let players = ctx.user_games.iter()
-                .filter(|entry| { *entry.1 == session.id })
-                .map(|entry| { entry.0.clone() })
+    let query = match stats::LeaderboardQuery::parse(args) {
+        Ok(query) => query,
+        Err(e) => {
+            ctx.bot.send_message(message.chat.id, e).await?;
+            return respond(());
+        }
+    };
+
+    let leaderboard = ctx.storage.current_season()
+        .and_then(|current| Ok((current, ctx.storage.load_leaderboard(&query)?)));
+    let reply = match leaderboard {
+        Ok((current, (rows, total))) => {
+            let rows = rows.into_iter()
+                .map(|(chat_id, stats)| (get_display_name(ctx, chat_id), stats))
                 .collect::<Vec<_>>();
+            stats::render_leaderboard(&query, current, total, &rows)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to load leaderboard: {}", e);
+            "Leaderboard is not available now".to_string()
+        }
+    };
+    ctx.bot.send_message(message.chat.id, reply).await?;
 
-            let start_msg = format!("Game started with {} players!", players.len());
-            for player in &players {
-                ctx.bot.send_message(*player, &start_msg).await?;
-            }
+    respond(())
+}
 
-            let (mut game, cli) = game::Game::setup(players.len());
+async fn handle_transcript(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let format = match transcript::TranscriptFormat::parse(args) {
+        Ok(format) => format,
+        Err(e) => {
+            ctx.bot.send_message(message.chat.id, e).await?;
+            return respond(());
+        }
+    };
 
-            let roles = cli.get_player_roles().await;
-            for (player, role) in players.iter().zip(roles) {
-                ctx.bot.send_message(*player, format!("Your role is {}", role.to_string())).await?;
-            }
+    let Some(session) = get_game_session_without_cleanup(ctx, message) else {
+        return send_not_in_game(&ctx.bot, message.chat.id).await;
+    };
+    session.send(SessionCommand::Transcript { chat_id: message.chat.id, format });
 
-            let crown_id = cli.get_crown_id().await;
-            println!("Start game crown_id: {}", crown_id);
-            let crown_chat_id = players[crown_id as usize];
-            let crown_name = ctx.user_names.get(&crown_chat_id).unwrap();
+    respond(())
+}
 
-            let mermaid_id = cli.get_mermaid_id().await;
-            println!("Start game mermaid_id: {}", crown_id);
-            let mermaid_chat_id = players[mermaid_id as usize];
-            let mermaid_name = ctx.user_names.get(&mermaid_chat_id).unwrap();
+async fn handle_audit(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let Some(session) = get_game_session_without_cleanup(ctx, message) else {
+        return send_not_in_game(&ctx.bot, message.chat.id).await;
+    };
+    session.send(SessionCommand::Audit { chat_id: message.chat.id });
 
-            for player in &players {
-                let crown_name = if *player == crown_chat_id { "You" } else { crown_name };
-                let mermaid_name = if *player == mermaid_chat_id { "You" } else { mermaid_name };
+    respond(())
+}
 
-                ctx.bot.send_message(*player, format!("{} has the crown", crown_name)).await?;
-                // TODO: Print only if number of players is enough
-                ctx.bot.send_message(*player, format!("{} has the mermaid", mermaid_name)).await?;
-            }
+async fn handle_status(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let Some(session) = get_game_session_without_cleanup(ctx, message) else {
+        return send_not_in_game(&ctx.bot, message.chat.id).await;
+    };
+    session.send(SessionCommand::Status { chat_id: message.chat.id });
 
-            let user_names = {
-                let mut user_names = HashMap::new();
-                for player in &players {
-                    let name = ctx.user_names.get(player).unwrap();
-                    user_names.insert(*player, name.clone());
-                }
-                user_names
-            };
+    respond(())
+}
 
-            let info = GameInfo {
-                leader: session.leader,
-                players,
-                cli: cli.clone(),
-                user_names,
-            };
+async fn handle_whoami(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let Some(session) = get_game_session_without_cleanup(ctx, message) else {
+        return send_not_in_game(&ctx.bot, message.chat.id).await;
+    };
+    session.send(SessionCommand::WhoAmI { chat_id: message.chat.id });
+
+    respond(())
+}
 
-            session.info = Some(info.clone());
-            drop(session);
+async fn handle_admin(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    // Admin commands are not visible to other users
+    if !ctx.admin.is_admin(message.chat.id) {
+        ctx.bot.send_message(message.chat.id, "Unknown command").await?;
+        return respond(());
+    }
 
-            tokio::spawn(async move {
-                if let Err(e) = game.start().await {
-                    println!("Game error: {}", e);
+    let command = match admin::AdminCommand::parse(args) {
+        Ok(command) => command,
+        Err(e) => {
+            ctx.bot.send_message(message.chat.id, e).await?;
+            return respond(());
+        }
+    };
+
+    let reply = match command {
+        admin::AdminCommand::Games => {
+            let mut sessions = Vec::new();
+            for session in ctx.game_sessions.values() {
+                let status = session.status();
+                if status.finished {
+                    continue;
                 }
-            });
 
-            let bot = ctx.bot.clone();
-            tokio::spawn(async move {
-                let info = info.clone();
-                let session = session_arc.clone();
-                while !session.lock().await.finished {
-                    println!("Event processing iteration");
-                    let event = info.cli.clone().recv_event().await.unwrap();
-                    let mut session = session.lock().await;
-                    if let Err(e) = process_game_event(session.deref_mut(), &event, &bot, &info).await {
-                        println!("Event processing error: {}", e);
-                        break;
-                    }
+                let players = match status.started {
+                    true => status.players.len(),
+                    false => ctx.user_games.values().filter(|id| **id == session.id).count(),
+                };
+                sessions.push(admin::SessionSummary {
+                    id: session.id,
+                    age: session.created_at.elapsed(),
+                    players,
+                    started: status.started,
+                });
+            }
+            sessions.sort_by_key(|session| session.id);
+            admin::render_games(&sessions)
+        }
+        admin::AdminCommand::Stop(game_id) => {
+            if stop_game(ctx, game_id, "The game was stopped by the administrator").await {
+                format!("Game #{} is stopped", game_id)
+            } else {
+                format!("There is no game #{}", game_id)
+            }
+        }
+        admin::AdminCommand::Broadcast(text) => {
+            let mut delivered = 0;
+            for chat_id in active_players(ctx) {
+                if ctx.bot.send_message(chat_id, format!("📢 {}", text)).await.is_ok() {
+                    delivered += 1;
                 }
-            });
-        } else {
-            ctx.bot.send_message(message.chat.id, "Only game leader can start the game").await?;
+            }
+            format!("Message is delivered to {} players", delivered)
         }
-    } else {
-        send_not_in_game(&ctx.bot, message).await?;
-    }
+        admin::AdminCommand::LogLevel(level) => match &ctx.admin.log_filter {
+            Some(filter) => match filter.reload(tracing_subscriber::EnvFilter::new(&level)) {
+                Ok(()) => {
+                    tracing::info!("Log level is changed to {} by {}", level, message.chat.id);
+                    format!("Log level is {} until the restart", level)
+                }
+                Err(e) => format!("Failed to change the log level: {}", e),
+            },
+            None => "Log level can't be changed on this instance".to_string(),
+        },
+        admin::AdminCommand::CloseSeason => match ctx.storage.close_season() {
+            Ok(season) => {
+                tracing::info!("Season {} is closed by {}", season, message.chat.id);
+                format!("Season {} is closed, season {} starts now. Its results: /leaderboard season:{}", season, season + 1, season)
+            }
+            Err(e) => format!("Failed to close the season: {}", e),
+        },
+        admin::AdminCommand::Ratings => match ctx.storage.load_ratings() {
+            Ok(ratings) => rating::render(&ratings),
+            Err(e) => format!("Failed to load the ratings: {}", e),
+        },
+    };
+    ctx.bot.send_message(message.chat.id, reply).await?;
 
-    println!("<handle_start_game");
     respond(())
 }
 
-fn get_user_id(info: &GameInfo, chat_id: ChatId) -> game::ID {
-    info.players.iter()
-        .position(|&id| { id == chat_id })
-        .map(|id| { id as u8 })
-        .unwrap()
+fn active_players(ctx: &BotCtx) -> Vec<ChatId> {
+    ctx.user_games.iter()
+        .filter(|(_, game_id)| {
+            ctx.game_sessions.get(game_id).is_some_and(|session| !session.status().finished)
+        })
+        .map(|(chat_id, _)| *chat_id)
+        .collect()
 }
 
-async fn handle_finish_suggestion(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
-{
-    println!(">handle_finish_suggestion");
-    if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let mut session = session.lock().await;
-        if let Some(suggestion) = session.suggestion.take() {
-            let info = session.info.as_mut().unwrap();
-            let mut cli = info.cli.clone();
+// Force-ends the game, so its players are free to join other games. They get the notice why
+async fn stop_game(ctx: &mut BotCtx, game_id: u32, notice: &str) -> bool {
+    let Some(session) = ctx.game_sessions.remove(&game_id) else {
+        return false;
+    };
 
-            let user_id = get_user_id(info, message.chat.id);
-            if let Err(e) = cli.suggest_team(user_id, &suggestion.users).await {
-                ctx.bot.send_message(message.chat.id, e.to_string()).await?;
-                // In case of error, restore the suggestion
-                session.suggestion = Some(suggestion);
-            } else {
-                ctx.bot.send_message(message.chat.id, "Suggestion sent").await?;
-            }
-        } else {
-            ctx.bot.send_message(message.chat.id, "No suggestion in progress").await?;
+    session.cancel();
+    if let Some(countdown) = ctx.countdowns.remove(&game_id) {
+        countdown.abort();
+    }
+    ctx.storage.save_session(session.id, session.leader, true);
+    cluster::release(ctx, game_id);
+
+    for chat_id in lobby_members(ctx, game_id) {
+        if !ctx.other_games.remove(chat_id, game_id) {
+            switcher::leave(ctx, chat_id);
         }
-    } else {
-        send_not_in_game(&ctx.bot, message).await?;
+        let _ = ctx.bot.send_message(chat_id, notice).await;
     }
 
-    println!("<handle_finish_suggestion");
-    respond(())
+    true
 }
 
-async fn handle_team_suggestion(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()> {
-    println!(">handle_team_suggestion");
+async fn handle_exit(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
     if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let mut session = session.lock().await;
-        let info = session.info.as_ref().unwrap().clone();
-
-        if let Some(suggestions) = session.suggestion.as_mut() {
-            let suggest_cmd = message.text().unwrap().split("_").collect::<Vec<_>>();
-            if let Some(suggest_id) = suggest_cmd.get(1) {
-                if let Some(suggest_id) = suggest_id.parse::<u8>().ok() {
-                    if let Some(pos) = suggestions.users.iter().position(|&id| { id == suggest_id }) {
-                        suggestions.users.remove(pos);
-                    } else {
-                        suggestions.users.push(suggest_id);
-                    }
-                    let ctrl_msg = game_msg::suggestion_state(
-                        &info, suggestions.crown_id,
-                        suggestions.team_size, &suggestions.users);
-
-                    assert_ne!(ctrl_msg.dst, game_msg::Dst::All);
-                    let text_msg = control_message_to_string(&ctrl_msg);
-                    println!("Suggestion state: {}", text_msg);
-                    ctx.bot.edit_message_text(message.chat.id, suggestions.msg_id, text_msg).await?;
-                } else {
-                    ctx.bot.send_message(message.chat.id, "Invalid suggestion command").await?;
-                }
-            } else {
-                ctx.bot.send_message(message.chat.id, "Invalid suggestion command").await?;
-            }
-        } else {
-            ctx.bot.send_message(message.chat.id, "No suggestion in progress").await?;
+        let leader = session.leader;
+        ctx.bot.send_message(message.chat.id, "You left the game").await?;
+        if !ctx.muted.contains(leader) {
+            let username = get_display_name(ctx, message.chat.id);
+            ctx.bot.send_message(leader, format!("{} left the game", username)).await?;
         }
+        if let Some(next) = switcher::leave(ctx, message.chat.id) {
+            ctx.bot.send_message(message.chat.id, format!("Game #{} is active now", next)).await?;
+        }
+    } else if ctx.matchmaking.leave(message.chat.id) {
+        ctx.bot.send_message(message.chat.id, "You left the queue").await?;
     } else {
-        send_not_in_game(&ctx.bot, message).await?;
+        ctx.bot.send_message(message.chat.id, "You are not in the game").await?;
     }
 
-    println!("<handle_team_suggestion");
     respond(())
 }
 
-async fn handle_team_vote(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()> {
-    if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let mut session = session.lock().await;
-        let info = session.info.as_mut().unwrap();
-        let mut cli = info.cli.clone();
-        let user_id = info.players.iter().position(|&id| { id == message.chat.id }).unwrap() as u8;
-        let vote_cmd = message.text().unwrap().split("_").collect::<Vec<_>>();
-        if let Some(vote) = vote_cmd.get(1) {
-            match *vote {
-                "approve" => {
-                    cli.add_team_vote(user_id, TeamVote::Approve).await.unwrap();
-                },
-                "reject" => {
-                    cli.add_team_vote(user_id, TeamVote::Reject).await.unwrap();
-                },
-                _ => {
-                    ctx.bot.send_message(message.chat.id, "Invalid vote command").await?;
+async fn handle_timeout(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    if let Some(session) = get_game_session(ctx, message).await {
+        let reply = if session.leader != message.chat.id {
+            "Only game leader can change the timeout".to_string()
+        } else {
+            match TimeoutSettings::parse(args, session.status().timeout) {
+                Ok(settings) => {
+                    session.send(SessionCommand::SetTimeout(settings));
+                    format!("Timeout: {}", settings)
                 }
+                Err(e) => e,
             }
-        } else {
-            ctx.bot.send_message(message.chat.id, "Invalid vote command").await?;
-        }
+        };
+        ctx.bot.send_message(message.chat.id, reply).await?;
     } else {
-        send_not_in_game(&ctx.bot, message).await?;
+        send_not_in_game(&ctx.bot, message.chat.id).await?;
     }
 
     respond(())
 }
 
-async fn handle_mission_result(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()> {
-    if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let mut session = session.lock().await;
-        let info = session.info.as_mut().unwrap();
-        let mut cli = info.cli.clone();
-        let user_id = info.players.iter().position(|&id| { id == message.chat.id }).unwrap() as u8;
-        let result_cmd = message.text().unwrap().split("_").collect::<Vec<_>>();
-        if let Some(vote) = result_cmd.get(1) {
-            let result = match *vote {
-                "success" => {
-                    cli.submit_for_mission(user_id, MissionVote::Success).await
-                },
-                "fail" => {
-                    cli.submit_for_mission(user_id, MissionVote::Fail).await
-                },
-                _ => {
-                    Err("Invalid result command".into())
-                }
-            };
-            if let Err(err) = result {
-                ctx.bot.send_message(message.chat.id, format!("{}", err)).await?;
-            }
-        } else {
-            ctx.bot.send_message(message.chat.id, "Invalid result command").await?;
-        }
+async fn handle_new_game(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    if get_game_session(ctx, message).await.is_some() {
+        ctx.bot.send_message(message.chat.id, "You are already in the game").await?;
+        ctx.bot.send_message(message.chat.id, "If you want to leave it, use /exit command, than join the link again").await?;
     } else {
-        send_not_in_game(&ctx.bot, message).await?;
+        let Some(game_id) = create_session(ctx, message).await? else {
+            return respond(());
+        };
+
+        let id = message.chat.id;
+        ctx.bot.send_message(id, "Starting a new game...").await?;
+        ctx.bot.send_message(id, "Send the following invite link to your team").await?;
+        let url = invite::invite_url(&ctx.bot_username, game_id);
+        ctx.bot.send_message(id, &url).await?;
+        send_invite_qr(&ctx.bot, id, &url).await;
+        ctx.bot.send_message(id, format!("Or type @{} in any chat to post the invite card there", ctx.bot_username)).await?;
+        ctx.bot.send_message(id, "When everybody is joined use /start_game").await?;
     }
 
     respond(())
 }
 
-async fn handle_mermaid(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()> {
-    if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let mut session = session.lock().await;
-        let info = session.info.as_mut().unwrap();
-        let mut cli = info.cli.clone();
-        let mermaid_cmd = message.text().unwrap().split("_").collect::<Vec<_>>();
-        if let Some(check_id) = mermaid_cmd.get(1) {
-            if let Some(check_id) = check_id.parse::<u8>().ok() {
-                cli.send_mermaid_selection(check_id).await.unwrap();
-            } else {
-                ctx.bot.send_message(message.chat.id, "Invalid mermaid command").await?;
-            }
-        } else {
-            ctx.bot.send_message(message.chat.id, "Invalid mermaid command").await?;
+// New lobby led by the author of the message. The author is told if it can't be created
+async fn create_session(ctx: &mut BotCtx, message: &Message) -> ResponseResult<Option<u32>>
+{
+    let Some(game_id) = open_lobby(ctx, message.chat.id) else {
+        ctx.bot.send_message(message.chat.id, "Failed to create a game, try again later").await?;
+        return Ok(None);
+    };
+
+    remember_user(ctx, message);
+    Ok(Some(game_id))
+}
+
+// Spawns the session of the new lobby with the leader in it, also for the matched players of matchmaking.rs
+fn open_lobby(ctx: &mut BotCtx, leader: ChatId) -> Option<u32> {
+    let game_id = match ctx.storage.create_session(leader) {
+        Ok(game_id) => game_id,
+        Err(e) => {
+            tracing::warn!("Failed to create a game: {}", e);
+            return None;
+        }
+    };
+    if let Some(cluster) = &ctx.cluster {
+        if let Err(e) = cluster.claim(ctx, game_id) {
+            tracing::warn!("Failed to claim game {}: {}", game_id, e);
         }
-    } else {
-        send_not_in_game(&ctx.bot, message).await?;
     }
+    let session = GameSession::new(ctx, game_id, leader);
+    ctx.game_sessions.insert(session.id, session::spawn(session, None));
+    ctx.storage.save_user_game(leader, game_id);
+    ctx.user_games.insert(leader, game_id);
+    Some(game_id)
+}
 
-    respond(())
+// Players sitting together can scan the link from the leader's screen.
+// The text link is already sent, so failures are only logged
+async fn send_invite_qr(bot: &Bot, chat_id: ChatId, url: &str) {
+    let image = match qr::render_png(url) {
+        Ok(image) => image,
+        Err(e) => {
+            tracing::warn!("Failed to render QR code of {}: {}", url, e);
+            return;
+        }
+    };
+    let photo = InputFile::memory(image).file_name("invite.png");
+    if let Err(e) = bot.send_photo(chat_id, photo).caption("Scan to join the game").await {
+        tracing::warn!("Failed to send QR code: {}", e);
+    }
 }
 
-async fn handle_mermaid_word(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()> {
+async fn handle_restart(ctx: &mut BotCtx, shared: &Arc<Mutex<BotCtx>>, message: &Message) -> ResponseResult<()>
+{
+    tracing::debug!(">handle_restart");
     if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let mut session = session.lock().await;
-        let info = session.info.as_mut().unwrap();
-        let mut cli = info.cli.clone();
-        let mermaid_word = message.text().unwrap().split("_").collect::<Vec<_>>();
-        if let Some(word) = mermaid_word.get(1) {
-            match *word {
-                "good" => {
-                    cli.send_mermaid_word(Team::Good).await.unwrap();
-                },
-                "bad" => {
-                    cli.send_mermaid_word(Team::Bad).await.unwrap();
-                },
-                _ => {
-                    ctx.bot.send_message(message.chat.id, "Invalid mermaid word").await?;
-                }
-            }
-        } else {
-            ctx.bot.send_message(message.chat.id, "Invalid mermaid word").await?;
-        }
+        countdown::begin(ctx, shared, message, session).await?
     } else {
-        send_not_in_game(&ctx.bot, message).await?;
+        send_not_in_game(&ctx.bot, message.chat.id).await?
     }
 
+    tracing::debug!("<handle_restart");
     respond(())
 }
 
-async fn handle_last_chance(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()> {
-    if let Some(session) = get_game_session_without_cleanup(ctx, message) {
-        let mut session = session.lock().await;
-        let info = session.info.as_mut().unwrap();
-        let mut cli = info.cli.clone();
-        let merlin_cmd = message.text().unwrap().split("_").collect::<Vec<_>>();
-        if let Some(merlin_id) = merlin_cmd.get(1) {
-            if let Some(merlin_id) = merlin_id.parse::<u8>().ok() {
-                cli.send_merlin_check(merlin_id).await.unwrap();
-            } else {
-                ctx.bot.send_message(message.chat.id, "Invalid last chance command").await?;
+// Sends the message to the game player and tells the leader if the player can't be reached anymore
+async fn deliver(bot: &Bot, info: &GameInfo, chat_id: ChatId, msg: &str) -> Option<MessageId> {
+    deliver_with(bot, info, chat_id, &TelegramMessage { text: msg.to_string(), keyboard: None }, false).await
+}
+
+async fn deliver_with(bot: &Bot, info: &GameInfo, chat_id: ChatId, msg: &TelegramMessage, secret: bool) -> Option<MessageId> {
+    if info.bots.contains(&chat_id) {
+        return None;
+    }
+    tracing::debug!("Message '{}' to {}", msg.text, chat_id);
+    let text = switcher::tag(&info.other_games, chat_id, info.id, &msg.text);
+    match delivery::send_with_retry(bot, chat_id, &text, msg.keyboard.as_ref(), secret).await {
+        Ok(res) => {
+            info.delivery.lock().unwrap().on_success(chat_id);
+            Some(res.id)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to deliver message to {}: {}", chat_id, e);
+            METRICS.send_failed();
+            let permanent = delivery::is_permanent(&e);
+            let unreachable = info.delivery.lock().unwrap().on_failure(chat_id, permanent);
+            if unreachable {
+                notify_unreachable(bot, info, chat_id, permanent).await;
             }
+            None
+        }
+    }
+}
+
+// The leader chooses to replace the player with AI or to abort the game, the other players
+// learn why the game waits. Sent directly, so the failures here don't start another notice
+async fn notify_unreachable(bot: &Bot, info: &GameInfo, chat_id: ChatId, permanent: bool) {
+    let name = info.user_names.get(&chat_id).cloned().unwrap_or_else(|| chat_id.to_string().into());
+    let notice = unreachable::notice(&name, permanent);
+    for player in info.players.iter().filter(|player| **player != chat_id) {
+        let result = if *player == info.leader {
+            bot.send_message(*player, &notice).reply_markup(unreachable::keyboard(chat_id)).await
         } else {
-            ctx.bot.send_message(message.chat.id, "Invalid last chance command").await?;
+            bot.send_message(*player, format!("{}. The leader can replace them with AI or abort the game", notice)).await
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to tell {} about unreachable {}: {}", player, chat_id, e);
         }
-    } else {
-        send_not_in_game(&ctx.bot, message).await?;
     }
+}
 
-    respond(())
+// Returns ids of the delivered messages
+async fn send_everybody(bot: &Bot, info: &GameInfo, msg: &str) -> Vec<(ChatId, MessageId)> {
+    let mut sent = Vec::new();
+    for player in &info.players {
+        if let Some(msg_id) = deliver(bot, info, *player, msg).await {
+            sent.push((*player, msg_id));
+        }
+    }
+    sent
 }
 
-async fn handle_tg_message(bot: Bot, message: Message, ctx: Arc<Mutex<BotCtx>>) -> ResponseResult<()>
-{
-    if let Some(text) = message.text() {
-        let mut ctx = ctx.lock().await;
-        let mut input = text.split_whitespace();
-        let cmd = input.next().unwrap();
-        let args = input;
-        match cmd {
-            "/start" => {
-                handle_start_bot(ctx.deref_mut(), &message, args).await
-            }
-            "/new_game" => {
-                handle_new_game(ctx.deref_mut(), &message).await
-            }
-            "/restart" => {
-                handle_restart(ctx.deref_mut(), &message).await
-            }
-            "/start_game" => {
-                handle_start_game(ctx.deref_mut(), &message).await
-            }
-            "/exit" => {
-                handle_exit(ctx.deref_mut(), &message).await
-            }
+// Progress messages which muted players don't need
+async fn send_unmuted(bot: &Bot, info: &GameInfo, msg: &str) -> Vec<(ChatId, MessageId)> {
+    let mut sent = Vec::new();
+    for player in info.players.iter().filter(|player| !info.muted.contains(**player)) {
+        if let Some(msg_id) = deliver(bot, info, *player, msg).await {
+            sent.push((*player, msg_id));
+        }
+    }
+    sent
+}
 
-            "/suggest_finish" => {
-                handle_finish_suggestion(ctx.deref_mut(), &message).await
-            }
+// Replaces the control message with the result of the action, so its commands can't be reused
+fn close_control_message(session: &mut GameSession, outbox: &mut Outbox, chat_id: ChatId, text: &str) {
+    if let Some(control) = session.control_messages.remove(&chat_id) {
+        outbox.edit(chat_id, control.msg_id, game_msg::join_parts(&control.prefix, text));
+    }
+}
 
-            cmd if cmd.starts_with("/suggest") => {
-                handle_team_suggestion(ctx.deref_mut(), &message).await
-            }
+fn edit_tracker(tracker: &mut Tracker, info: &GameInfo, outbox: &mut Outbox) {
+    let waiting = tracker.seats.iter()
+        .filter(|id| !tracker.acted.contains(id))
+        .cloned()
+        .collect::<Vec<_>>();
+    let text = game_msg::build_tracker_text(info, tracker.phase, &tracker.seats, &waiting);
+    if text == tracker.text {
+        return;
+    }
 
-            cmd if cmd.starts_with("/team") => {
-                handle_team_vote(ctx.deref_mut(), &message).await
-            }
+    for (chat_id, msg_id) in &tracker.messages {
+        outbox.edit(*chat_id, *msg_id, &text);
+    }
+    tracker.text = text;
+}
 
-            cmd if cmd.starts_with("/mission") => {
-                handle_mission_result(ctx.deref_mut(), &message).await
+// Marks players who have acted since the last update
+async fn update_tracker(session: &mut GameSession, outbox: &mut Outbox) {
+    let (Some(tracker), Some(info)) = (session.tracker.as_mut(), session.info.as_ref()) else {
+        return;
+    };
+
+    // Votes are reset in the engine as soon as the last one arrives,
+    // so the players who have acted are only accumulated here
+    if info.cli.get_phase().await == tracker.phase {
+        let waiting = info.cli.get_waiting_for().await;
+        for id in &tracker.seats {
+            if !waiting.contains(id) && !tracker.acted.contains(id) {
+                tracker.acted.push(*id);
             }
+        }
+    }
 
-            cmd if cmd.starts_with("/mermaid") => {
-                handle_mermaid(ctx.deref_mut(), &message).await
-            }
+    edit_tracker(tracker, info, outbox);
+}
 
-            cmd if cmd.starts_with("/say") => {
-                handle_mermaid_word(ctx.deref_mut(), &message).await
-            }
+// Shows everybody as done when the phase is over
+fn finish_tracker(session: &mut GameSession, info: &GameInfo, outbox: &mut Outbox) {
+    if let Some(mut tracker) = session.tracker.take() {
+        tracker.acted = tracker.seats.clone();
+        edit_tracker(&mut tracker, info, outbox);
+    }
+}
+
+// Edits the existing boards and returns the chats where the board has to be sent
+fn update_board(session: &mut GameSession, info: &GameInfo, text: String, outbox: &mut Outbox) -> Vec<ChatId> {
+    if text == session.board_text {
+        return Vec::new();
+    }
 
-            cmd if cmd.starts_with("/merlin") => {
-                handle_last_chance(ctx.deref_mut(), &message).await
+    let mut missing = Vec::new();
+    for chat_id in info.players.iter().filter(|player| !info.bots.contains(player)) {
+        match session.board_messages.get(chat_id) {
+            Some(msg_id) => outbox.edit(*chat_id, *msg_id, &text),
+            None => missing.push(*chat_id),
+        }
+    }
+    session.board_text = text;
+    missing
+}
+
+async fn send_boards(bot: &Bot, chat_ids: Vec<ChatId>, text: &str) -> Vec<(ChatId, MessageId)> {
+    let mut sent = Vec::new();
+    for chat_id in chat_ids {
+        match bot.send_message(chat_id, text).parse_mode(ParseMode::Html).await {
+            Ok(msg) => {
+                sent.push((chat_id, msg.id));
+                if let Err(e) = bot.pin_chat_message(chat_id, msg.id).disable_notification(true).await {
+                    tracing::warn!("Failed to pin board: {}", e);
+                }
             }
+            Err(e) => tracing::warn!("Failed to send board: {}", e),
+        }
+    }
+    sent
+}
 
-            _ => {
-                bot.send_message(message.chat.id, "Unknown command").await?;
-                respond(())
+fn player_name(info: &GameInfo, id: game::ID) -> String {
+    info.players.get(id as usize)
+        .and_then(|chat_id| info.user_names.get(chat_id))
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| id.to_string())
+}
+
+async fn send_not_in_game(bot: &Bot, chat_id: ChatId) -> ResponseResult<()> {
+    bot.send_message(chat_id, "You are not in a game. Join or create new one").await?;
+    respond(())
+}
+
+// Returns the sent control messages
+async fn send_game_messages(bot: &Bot, info: &GameInfo, messages: Vec<GameMessage>) -> Result<Vec<(ChatId, SentControl)>, Box<dyn Error>>
+{
+    if let Some((topic, text)) = info.topic.zip(game_msg::public_text(&messages)) {
+        forum::post(bot, topic, &text).await;
+    }
+    let mut control_messages = Vec::new();
+    let muted = info.players.iter()
+        .filter(|player| info.muted.contains(**player))
+        .cloned()
+        .collect::<Vec<_>>();
+    for composed in game_msg::compose(&info.players, &muted, messages) {
+        let rendered = game_msg::TelegramRenderer.render(&composed);
+        let msg_id = deliver_with(bot, info, composed.chat_id, &rendered, composed.protected).await;
+        if let (Some(msg_id), false) = (msg_id, composed.controls.is_empty()) {
+            control_messages.push((composed.chat_id, SentControl { msg_id, prefix: composed.prefix() }));
+        }
+    }
+
+    Ok(control_messages)
+}
+
+async fn process_game_event(session: &mut GameSession, event: &GameEvent, info: &GameInfo) -> Result<(), Box<dyn Error>>
+{
+    tracing::debug!(">process_game_event");
+    let bot = session.bot.clone();
+    let mut context = game_msg::EventContext::fetch(&info.cli, event).await;
+    if let GameEvent::GameResult(_) = event {
+        context.claims = session.claims.clone();
+    }
+    let messages = game_msg::build_message_for_event(info, &context, event.clone());
+    tracing::debug!("messages: {:?}", messages);
+
+    let mut outbox = Outbox::default();
+    finish_tracker(session, info, &mut outbox);
+    vote_poll::close(session).await;
+    commentary::on_event(session, event, &messages).await;
+    let control_messages = send_game_messages(&bot, info, messages).await?;
+    session.control_messages.extend(control_messages.iter().cloned());
+
+    if let Some((phase @ (game::Phase::TeamVote | game::Phase::Mission), seats)) = ai::prompted_seats(event, info.players.len()) {
+        let text = game_msg::build_tracker_text(info, phase, &seats, &seats);
+        let messages = send_unmuted(&bot, info, &text).await;
+        session.tracker = Some(Tracker { phase, seats, acted: Vec::new(), messages, text });
+        session.voted.clear();
+        session.rejecting.clear();
+    }
+    if let GameEvent::TeamSuggested(team) = event {
+        vote_poll::open(session, info, team).await;
+    }
+
+    if let GameEvent::Turn(crown_id, team_size) = event {
+        let crown_chat_id = info.players[*crown_id as usize];
+        if let Some((_, control)) = control_messages.iter().find(|(chat_id, _)| *chat_id == crown_chat_id) {
+            session.suggestion = Some(SuggestionInfo {
+                control: control.clone(),
+                crown_id: *crown_id,
+                team_size: *team_size,
+                users: Vec::new(),
+            });
+        }
+        if let Some(webapp) = &session.webapp {
+            send_team_keyboard(&bot, webapp, info, crown_chat_id, *team_size).await;
+        }
+    }
+
+    if let GameEvent::BadLastChance(_, guesser) = event {
+        session.guesser = Some(*guesser);
+    }
+
+    let board_text = game_msg::build_board(info).await;
+    let missing_boards = update_board(session, info, board_text.clone(), &mut outbox);
+    outbox.flush(&bot).await;
+    let board_messages = send_boards(&bot, missing_boards, &board_text).await;
+    session.board_messages.extend(board_messages);
+
+    if let GameEvent::GameResult(_) = event {
+        session.finished = true;
+    }
+
+    session.idle_since = tokio::time::Instant::now();
+    session.waiting_since = tokio::time::Instant::now();
+
+    tracing::debug!("<process_game_event");
+    Ok(())
+}
+
+async fn handle_start_game(ctx: &mut BotCtx, shared: &Arc<Mutex<BotCtx>>, message: &Message) -> ResponseResult<()>
+{
+    tracing::debug!(">handle_start_game");
+    if let Some(session) = get_game_session(ctx, message).await {
+        countdown::begin(ctx, shared, message, session).await?;
+    } else {
+        send_not_in_game(&ctx.bot, message.chat.id).await?;
+    }
+
+    tracing::debug!("<handle_start_game");
+    respond(())
+}
+
+// Also the members who are in the lobby while another game is active for them
+fn lobby_members(ctx: &BotCtx, game_id: u32) -> Vec<ChatId> {
+    let mut members = ctx.user_games.iter()
+        .filter(|(_, id)| **id == game_id)
+        .map(|(chat_id, _)| *chat_id)
+        .collect::<Vec<_>>();
+    members.extend(ctx.other_games.members(game_id));
+    members
+}
+
+// Starts the game with everybody who joined the lobby, seated in the order chosen by the leader
+fn start_lobby(ctx: &mut BotCtx, session: &SessionHandle) {
+    let players = seating::arrange(&lobby_members(ctx, session.id), &session.status().seating);
+    let user_names = users::disambiguate(&players, &ctx.users);
+    session.send(SessionCommand::Start { players, user_names });
+}
+
+async fn start_game(session: &mut GameSession, players: Vec<ChatId>, user_names: HashMap<ChatId, Arc<str>>) -> ResponseResult<()>
+{
+    // Previous game of the group might still be running after /restart
+    if let Some(engine) = session.engine.take() {
+        engine.abort();
+    }
+    session.finished = false;
+    session.stalled = false;
+    session.voted.clear();
+    session.rejecting.clear();
+    session.pseudonyms.clear();
+    session.evil_pseudonyms.clear();
+    session.claims.clear();
+    session.notes.clear();
+    session.discussion = None;
+    session.reveal = None;
+    session.rating = None;
+    session.guesser = None;
+    session.audit = audit::AuditLog::new();
+    session.recoveries = 0;
+    let bot = session.bot.clone();
+
+    let mut start_msg = format!("Game started with {} players!", players.len());
+    if session.evil_chat {
+        start_msg.push_str("\n😈 The bad team can message each other anonymously with /evil in this game");
+    }
+    let humans = players.iter().filter(|player| !session.bots.contains(player)).cloned().collect::<Vec<_>>();
+    for player in &humans {
+        bot.send_message(*player, &start_msg).await?;
+    }
+    house_rules::announce(session, &humans).await;
+    let topic = match session.forum {
+        Some(forum) => forum::open(&bot, forum, session.id, players.len()).await,
+        None => None,
+    };
+    match topic {
+        Some(topic) => forum::post(&bot, topic, &start_msg).await,
+        None if session.forum.is_some() => {
+            let notice = "Failed to create the topic of the game, the bot must be an admin of the group who can manage topics";
+            bot.send_message(session.leader, notice).await?;
+        }
+        None => {}
+    }
+    session.commentary = commentary::Board::new(players.len(), session.options.max_try_count);
+    commentary::send(session, &start_msg).await;
+
+    let (game, cli) = game::Game::setup_with(players.len(), session.options.clone());
+
+    let roles = cli.get_player_roles().await;
+    for (player, role) in players.iter().zip(roles).filter(|(player, _)| !session.bots.contains(player)) {
+        media::send_role_card(&bot, &session.media, *player, &role, session.theme).await?;
+    }
+
+    let crown_id = cli.get_crown_id().await;
+    tracing::debug!("Start game crown_id: {}", crown_id);
+    let crown_chat_id = players[crown_id as usize];
+    let crown_name = user_names.get(&crown_chat_id).cloned().unwrap_or_else(|| crown_chat_id.to_string().into());
+
+    let mermaid_id = cli.get_mermaid_id().await;
+    tracing::debug!("Start game mermaid_id: {}", crown_id);
+    let mermaid_chat_id = players[mermaid_id as usize];
+    let mermaid_name = user_names.get(&mermaid_chat_id).cloned().unwrap_or_else(|| mermaid_chat_id.to_string().into());
+
+    for player in &humans {
+        if session.narration && !session.muted.contains(*player) {
+            bot.send_message(*player, session.theme.table().narration.intro).await?;
+        }
+        let crown_name = if *player == crown_chat_id { "You" } else { &crown_name };
+        let mermaid_name = if *player == mermaid_chat_id { "You" } else { &mermaid_name };
+
+        bot.send_message(*player, format!("{} has the crown", crown_name)).await?;
+        if session.options.has_mermaid(players.len()) {
+            bot.send_message(*player, format!("{} has the {}", mermaid_name, session.theme.table().mermaid)).await?;
+        }
+    }
+
+    let info = GameInfo {
+        id: session.id,
+        leader: session.leader,
+        players,
+        cli: cli.clone(),
+        user_names,
+        delivery: Default::default(),
+        muted: session.muted.clone(),
+        other_games: session.other_games.clone(),
+        theme: session.theme,
+        narration: session.narration,
+        bots: session.bots.clone(),
+        topic,
+    };
+
+    let initial = info.cli.snapshot().await;
+    if info.bots.is_empty() {
+        session.storage.save_session(session.id, session.leader, false);
+        session.storage.save_game(session.id, &info.players, &initial, info.theme, info.narration);
+    } else {
+        // The AI seats of debug games are not stored, so the game would stall after a restart
+        session.storage.save_session(session.id, session.leader, true);
+    }
+    session.storage.append_log(session.id, &LogEntry::Started(initial));
+    session.info = Some(Arc::new(info));
+    session.engine = Some(game::spawn_engine(game));
+
+    respond(())
+}
+
+async fn save_stats(storage: &Storage, info: &GameInfo, result: &game::GameResult, guesser: Option<game::ID>) {
+    let roles = info.cli.get_player_roles().await;
+    for (id, (chat_id, role)) in info.players.iter().zip(roles.iter()).enumerate() {
+        let mut stats = match storage.load_stats(*chat_id) {
+            Ok(stats) => stats,
+            Err(e) => {
+                tracing::warn!("Failed to load stats of {}: {}", chat_id, e);
+                continue;
             }
+        };
+
+        let guessed = (guesser == Some(id as game::ID)).then_some(*result == game::GameResult::BadWins);
+        stats.add_game(role, result, guessed);
+        storage.save_stats(*chat_id, &stats);
+    }
+}
+
+// Stickers are flavor, so muted players don't get them. The moment has no sticker if its file is missing
+async fn send_sticker(session: &GameSession, event: &GameEvent, info: &GameInfo) {
+    if !session.stickers {
+        return;
+    }
+    let moment = match event {
+        GameEvent::MissionResult(_) if info.cli.get_mission_results().await.last() == Some(&MissionVote::Fail) => {
+            media::Moment::MissionFailed
+        }
+        // Merlin was guessed after the good team won the missions
+        GameEvent::GameResult(game::GameResult::BadWins) if session.guesser.is_some() => media::Moment::MerlinAssassinated,
+        GameEvent::GameResult(game::GameResult::GoodWins) => media::Moment::GoodWins,
+        _ => return,
+    };
+    let Some(sticker) = session.media.sticker(info.theme, moment) else {
+        return;
+    };
+    for player in info.players.iter().filter(|player| !info.muted.contains(**player) && !info.bots.contains(player)) {
+        if let Err(e) = session.bot.send_sticker(*player, InputFile::file(&sticker)).await {
+            tracing::warn!("Failed to send the sticker {} to {}: {}", sticker.display(), player, e);
+        }
+    }
+}
+
+// The tournament is over with its last game
+async fn send_standings(session: &mut GameSession, info: &GameInfo, result: &game::GameResult) {
+    let Some(tournament) = session.tournament.as_mut() else {
+        return;
+    };
+    let roles = info.cli.get_player_roles().await;
+    tournament.add_game(&info.players, &roles, result, &info.user_names);
+    let standings = tournament.standings();
+    if tournament.is_over() {
+        session.tournament = None;
+    }
+    for player in &info.players {
+        deliver(&session.bot, info, *player, &standings).await;
+    }
+}
+
+async fn save_history(storage: &Storage, game_id: u32, info: &GameInfo, result: &game::GameResult) {
+    let finished_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    storage.save_history(&stats::FinishedGame {
+        game_id,
+        finished_at: finished_at.as_secs(),
+        players: info.players.clone(),
+        roles: info.cli.get_player_roles().await,
+        result: result.clone(),
+    });
+}
+
+async fn on_game_event(session: &mut GameSession, event: &GameEvent)
+{
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    session.storage.append_log(session.id, &LogEntry::Event(event.clone()));
+    if !discussion::start(session, &info, event).await && !reveal::start(session, event).await {
+        present_game_event(session, event, &info).await;
+    }
+}
+
+// Sends the messages of the event and lets the AI seats act on it
+async fn present_game_event(session: &mut GameSession, event: &GameEvent, info: &GameInfo)
+{
+    // The error is not Send, so it is not kept over the await
+    let result = process_game_event(session, event, info).await.map_err(|e| e.to_string());
+    if let Err(e) = result {
+        session::recover(session, &format!("Event processing error: {}", e)).await;
+        return;
+    }
+    METRICS.event_processed();
+    send_sticker(session, event, info).await;
+
+    if let Some((phase, seats)) = ai::prompted_seats(event, info.players.len()) {
+        let ai_seats = seats.into_iter().filter(|id| session.ai_seats.contains(id)).collect::<Vec<_>>();
+        for id in ai_seats {
+            if let Err(e) = session.play_for(id, phase, ai::Strategy::Ai).await {
+                tracing::warn!("AI seat {} failed to act: {}", id, e);
+            }
+        }
+    }
+
+    let mut outbox = Outbox::default();
+    update_tracker(session, &mut outbox).await;
+    outbox.flush(&session.bot).await;
+
+    // Debug games are not counted
+    if let (GameEvent::GameResult(result), true) = (event, info.bots.is_empty()) {
+        save_stats(&session.storage, info, result, session.guesser).await;
+        save_history(&session.storage, session.id, info, result).await;
+        rating::ask(session, info).await;
+    }
+    if let GameEvent::GameResult(result) = event {
+        session.notes.clear();
+        send_standings(session, info, result).await;
+        if let Some(topic) = info.topic {
+            forum::close(&session.bot, topic).await;
+        }
+    }
+    if info.bots.is_empty() {
+        session.storage.save_game(session.id, &info.players, &info.cli.snapshot().await, info.theme, info.narration);
+    }
+    if session.finished {
+        session.storage.save_session(session.id, session.leader, true);
+    }
+}
+
+// Situations in which an action can't be handled. They are replied to the sender, so a late or
+// mistyped command never panics the session task
+enum ActionError {
+    NotAvailable,
+    NoSuchPlayer(game::ID),
+    Request(teloxide::RequestError),
+}
+
+impl From<teloxide::RequestError> for ActionError {
+    fn from(e: teloxide::RequestError) -> Self {
+        ActionError::Request(e)
+    }
+}
+
+type ActionResult = Result<(), ActionError>;
+
+// Game state and the seat of the sender
+fn player_state(session: &GameSession, chat_id: ChatId) -> Result<(Arc<GameInfo>, game::ID), ActionError> {
+    let info = session.info.clone().ok_or(ActionError::NotAvailable)?;
+    let user_id = info.players.iter()
+        .position(|&id| id == chat_id)
+        .ok_or(ActionError::NotAvailable)?;
+    Ok((info, user_id as game::ID))
+}
+
+// Engine indexes the players by the chosen id, so it must be checked before
+fn check_target(info: &GameInfo, id: game::ID) -> Result<game::ID, ActionError> {
+    match (id as usize) < info.players.len() {
+        true => Ok(id),
+        false => Err(ActionError::NoSuchPlayer(id)),
+    }
+}
+
+// Web app button is sent in addition to the /suggest commands, which keep working
+async fn send_team_keyboard(bot: &Bot, webapp: &WebAppConfig, info: &GameInfo, chat_id: ChatId, team_size: usize) {
+    let names = (0..info.players.len())
+        .map(|id| player_name(info, id as game::ID))
+        .collect::<Vec<_>>();
+    let keyboard = match webapp.team_keyboard(&names, team_size) {
+        Ok(keyboard) => keyboard,
+        Err(e) => {
+            tracing::warn!("Failed to build the team keyboard: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = bot.send_message(chat_id, "Or choose the team in the app").reply_markup(keyboard).await {
+        tracing::warn!("Failed to send the team keyboard: {}", e);
+    }
+}
+
+fn confirm_suggestion(session: &mut GameSession, outbox: &mut Outbox, chat_id: ChatId, info: &GameInfo, team: &[game::ID]) {
+    let team = team.iter()
+        .map(|id| game_msg::bold(&player_name(info, *id)))
+        .collect::<Vec<_>>();
+    let text = format!("✅ You suggested: {}", team.join(", "));
+    close_control_message(session, outbox, chat_id, &text);
+    match session.webapp {
+        Some(_) => outbox.send_removing_keyboard(chat_id, "Suggestion sent"),
+        None => outbox.send(chat_id, "Suggestion sent"),
+    }
+}
+
+async fn handle_finish_suggestion(session: &mut GameSession, chat_id: ChatId) -> ActionResult
+{
+    tracing::debug!(">handle_finish_suggestion");
+    let mut outbox = Outbox::default();
+    let (info, user_id) = player_state(session, chat_id)?;
+    if let Some(suggestion) = session.suggestion.take() {
+        if let Err(e) = session.perform(Move::SuggestTeam(user_id, suggestion.users.clone())).await {
+            outbox.send(chat_id, e);
+            // In case of error, restore the suggestion
+            session.suggestion = Some(suggestion);
+        } else {
+            confirm_suggestion(session, &mut outbox, chat_id, &info, &suggestion.users);
         }
     } else {
-        respond(())
+        outbox.send(chat_id, "No suggestion in progress");
     }
+    outbox.flush(&session.bot).await;
+
+    tracing::debug!("<handle_finish_suggestion");
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let bot = Bot::from_env();
-    let ctx = Arc::new(Mutex::new(BotCtx {
+async fn handle_team_suggestion(session: &mut GameSession, chat_id: ChatId, suggest_id: u8) -> ActionResult {
+    tracing::debug!(">handle_team_suggestion");
+    let mut outbox = Outbox::default();
+    let (info, _) = player_state(session, chat_id)?;
+    let suggest_id = check_target(&info, suggest_id)?;
+
+    if let Some(suggestions) = session.suggestion.as_mut() {
+        if let Some(pos) = suggestions.users.iter().position(|&id| { id == suggest_id }) {
+            suggestions.users.remove(pos);
+        } else {
+            suggestions.users.push(suggest_id);
+        }
+        let history = info.cli.get_history().await;
+        let ctrl_msg = game_msg::suggestion_state(
+            &info, &history, suggestions.crown_id,
+            suggestions.team_size, &suggestions.users);
+
+        assert_ne!(ctrl_msg.dst, game_msg::Dst::All);
+        let composed = game_msg::ComposedMessage::with_control(chat_id, &suggestions.control.prefix, ctrl_msg);
+        let rendered = game_msg::TelegramRenderer.render(&composed);
+        tracing::debug!("Suggestion state: {}", rendered.text);
+        outbox.edit_message(chat_id, suggestions.control.msg_id, rendered);
+    } else {
+        outbox.send(chat_id, "No suggestion in progress");
+    }
+    outbox.flush(&session.bot).await;
+
+    tracing::debug!("<handle_team_suggestion");
+    Ok(())
+}
+
+async fn handle_team_selection(session: &mut GameSession, chat_id: ChatId, team: Vec<u8>) -> ActionResult {
+    let mut outbox = Outbox::default();
+    let (info, user_id) = player_state(session, chat_id)?;
+    let team = team.into_iter()
+        .map(|id| check_target(&info, id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match session.perform(Move::SuggestTeam(user_id, team.clone())).await {
+        Ok(()) => {
+            session.suggestion = None;
+            confirm_suggestion(session, &mut outbox, chat_id, &info, &team);
+        }
+        Err(e) => outbox.send(chat_id, e),
+    }
+    outbox.flush(&session.bot).await;
+    Ok(())
+}
+
+async fn handle_team_vote(session: &mut GameSession, chat_id: ChatId, vote: TeamVote) -> ActionResult {
+    let mut outbox = Outbox::default();
+    let (info, user_id) = player_state(session, chat_id)?;
+    if vote == TeamVote::Reject && !session.rejecting.contains(&chat_id)
+        && game_msg::is_last_try(info.cli.get_try_count().await, info.cli.get_options().await.max_try_count) {
+        session.rejecting.insert(chat_id);
+        outbox.send(chat_id, game_msg::reject_confirmation(info.theme.table()));
+    } else if let Err(e) = session.perform(Move::TeamVote(user_id, vote.clone())).await {
+        outbox.send(chat_id, e);
+    } else {
+        session.voted.insert(chat_id);
+        let text = format!("✅ You voted {}", vote);
+        close_control_message(session, &mut outbox, chat_id, &text);
+        update_tracker(session, &mut outbox).await;
+    }
+    outbox.flush(&session.bot).await;
+
+    Ok(())
+}
+
+async fn handle_mission_result(session: &mut GameSession, chat_id: ChatId, vote: MissionVote) -> ActionResult {
+    let mut outbox = Outbox::default();
+    let (_, user_id) = player_state(session, chat_id)?;
+    if let Err(err) = session.perform(Move::Mission(user_id, vote.clone())).await {
+        outbox.send(chat_id, err);
+    } else {
+        session.voted.insert(chat_id);
+        let text = format!("✅ You submitted {}", vote);
+        close_control_message(session, &mut outbox, chat_id, &text);
+        update_tracker(session, &mut outbox).await;
+    }
+    outbox.flush(&session.bot).await;
+
+    Ok(())
+}
+
+async fn handle_mermaid(session: &mut GameSession, chat_id: ChatId, check_id: u8) -> ActionResult {
+    let mut outbox = Outbox::default();
+    let (info, _) = player_state(session, chat_id)?;
+    let check_id = check_target(&info, check_id)?;
+    if let Err(e) = session.perform(Move::MermaidCheck(check_id)).await {
+        outbox.send(chat_id, e);
+    } else {
+        let text = format!("✅ You checked {}", game_msg::bold(&player_name(&info, check_id)));
+        close_control_message(session, &mut outbox, chat_id, &text);
+    }
+    outbox.flush(&session.bot).await;
+
+    Ok(())
+}
+
+async fn handle_mermaid_word(session: &mut GameSession, chat_id: ChatId, word: Team) -> ActionResult {
+    let mut outbox = Outbox::default();
+    player_state(session, chat_id)?;
+    if let Err(e) = session.perform(Move::MermaidWord(word.clone())).await {
+        outbox.send(chat_id, e);
+    } else {
+        let word = session.info.as_ref().map_or(Theme::Classic, |info| info.theme).table().team(&word);
+        let text = format!("✅ You announced {}", word);
+        close_control_message(session, &mut outbox, chat_id, &text);
+    }
+    outbox.flush(&session.bot).await;
+
+    Ok(())
+}
+
+async fn handle_last_chance(session: &mut GameSession, chat_id: ChatId, merlin_id: u8) -> ActionResult {
+    let mut outbox = Outbox::default();
+    let (info, _) = player_state(session, chat_id)?;
+    let merlin_id = check_target(&info, merlin_id)?;
+    if let Err(e) = session.perform(Move::NameMerlin(merlin_id)).await {
+        outbox.send(chat_id, e);
+    } else {
+        let text = format!("✅ You named {} as {}", game_msg::bold(&player_name(&info, merlin_id)),
+                           info.theme.table().role(&game::Role::Merlin));
+        close_control_message(session, &mut outbox, chat_id, &text);
+    }
+    outbox.flush(&session.bot).await;
+
+    Ok(())
+}
+
+// Game actions are handled by the session task of the player's game, see switcher.rs for the players of several games
+// Commands and buttons of the control messages come from the private chat of the player
+async fn route_game_action(ctx: &mut BotCtx, chat_id: ChatId, action: GameAction) -> ResponseResult<()>
+{
+    let Some(session) = switcher::action_game(ctx, chat_id) else {
+        return send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    if session.status().ai_players.contains(&chat_id) {
+        ctx.bot.send_message(chat_id, "You were replaced by AI because you did not act in time").await?;
+        return respond(());
+    }
+
+    session.send(SessionCommand::Action { chat_id, action });
+    respond(())
+}
+
+async fn is_allowed(session: &GameSession, chat_id: ChatId, action: &GameAction) -> bool {
+    let Some(info) = session.info.as_ref() else {
+        return false;
+    };
+    let Some(user_id) = info.players.iter().position(|&id| id == chat_id) else {
+        return false;
+    };
+    commands::is_allowed(&info.cli, user_id as game::ID, action).await
+}
+
+async fn handle_game_action(session: &mut GameSession, chat_id: ChatId, action: GameAction) -> ResponseResult<()>
+{
+    let is_player = session.info.as_ref().map(|info| info.players.contains(&chat_id));
+    match is_player {
+        None => {
+            session.bot.send_message(chat_id, "The game is not started yet").await?;
+            return respond(());
+        }
+        Some(false) => {
+            session.bot.send_message(chat_id, "You are not a player of this game").await?;
+            return respond(());
+        }
+        Some(true) => {}
+    }
+
+    if session.discussion.is_some() && matches!(action, GameAction::TeamVote(_)) {
+        session.bot.send_message(chat_id, "The vote opens after the discussion").await?;
+        return respond(());
+    }
+    if session.discussion.is_some() && matches!(action, GameAction::NameMerlin(_)) {
+        session.bot.send_message(chat_id, "The guess opens after the discussion").await?;
+        return respond(());
+    }
+
+    if !is_allowed(session, chat_id, &action).await {
+        session.bot.send_message(chat_id, "It's not your turn for that").await?;
+        return respond(());
+    }
+
+    if session.voted.contains(&chat_id) {
+        let text = match action {
+            GameAction::TeamVote(_) => Some("You have already voted for this team"),
+            GameAction::MissionVote(_) => Some("You have already submitted your mission result"),
+            _ => None,
+        };
+        if let Some(text) = text {
+            session.bot.send_message(chat_id, text).await?;
+            return respond(());
+        }
+    }
+
+    let result = match action {
+        GameAction::FinishSuggestion => handle_finish_suggestion(session, chat_id).await,
+        GameAction::ToggleSuggestion(id) => handle_team_suggestion(session, chat_id, id).await,
+        GameAction::SuggestTeam(team) => handle_team_selection(session, chat_id, team).await,
+        GameAction::TeamVote(vote) => handle_team_vote(session, chat_id, vote).await,
+        GameAction::MissionVote(vote) => handle_mission_result(session, chat_id, vote).await,
+        GameAction::MermaidCheck(id) => handle_mermaid(session, chat_id, id).await,
+        GameAction::MermaidWord(word) => handle_mermaid_word(session, chat_id, word).await,
+        GameAction::NameMerlin(id) => handle_last_chance(session, chat_id, id).await,
+    };
+    match result {
+        Ok(()) => respond(()),
+        Err(ActionError::NotAvailable) => {
+            session.bot.send_message(chat_id, "You can't do that right now").await?;
+            respond(())
+        }
+        Err(ActionError::NoSuchPlayer(id)) => {
+            session.bot.send_message(chat_id, format!("There is no player {}", id)).await?;
+            respond(())
+        }
+        Err(ActionError::Request(e)) => Err(e),
+    }
+}
+
+// Metric label of the message. Only known commands are used to keep the number of series small
+fn command_label(text: &str) -> String {
+    const PREFIXES: &[&str] = &["/suggest_finish", "/suggest", "/team", "/mission", "/mermaid", "/say", "/merlin",
+                                "/leaderboard", "/admin"];
+    let word = text.split(|c: char| c.is_whitespace() || c == '@').next().unwrap_or_default();
+    if Command::bot_commands().iter().any(|cmd| cmd.command == word) {
+        return word.to_string();
+    }
+    PREFIXES.iter()
+        .find(|prefix| word.starts_with(*prefix))
+        .map(|prefix| prefix.to_string())
+        .unwrap_or_else(|| "other".to_string())
+}
+
+// Games in progress and players waiting in lobbies
+#[cfg(feature = "api")]
+fn game_gauges(ctx: &BotCtx) -> metrics::Gauges {
+    let mut active_games = 0;
+    let mut lobbies = Vec::new();
+    for (id, session) in &ctx.game_sessions {
+        let status = session.status();
+        if status.finished {
+            continue;
+        }
+        match status.started {
+            true => active_games += 1,
+            false => lobbies.push(*id),
+        }
+    }
+    let lobby_players = ctx.user_games.values().filter(|id| lobbies.contains(id)).count();
+    metrics::Gauges { active_games, lobby_players }
+}
+
+// Game and seat of the user are added to the span of the handler, so the logs of the update
+// are found by the game like the logs of the session task
+fn record_player(ctx: &BotCtx, chat_id: ChatId) {
+    let Some(session) = ctx.user_games.get(&chat_id).and_then(|game_id| ctx.game_sessions.get(game_id)) else {
+        return;
+    };
+    let span = tracing::Span::current();
+    span.record("game", session.id);
+    if let Some(seat) = session.status().players.iter().position(|player| *player == chat_id) {
+        span.record("seat", seat);
+    }
+}
+
+// Commands of the Command enum, parsed by the dispatcher
+#[tracing::instrument(skip_all, err, fields(chat = %message.chat.id, game = tracing::field::Empty, seat = tracing::field::Empty))]
+async fn handle_command(message: Message, command: Command, ctx: Arc<Mutex<BotCtx>>) -> ResponseResult<()>
+{
+    let started = std::time::Instant::now();
+    let result = {
+        let mut guard = ctx.lock().await;
+        record_player(&guard, message.chat.id);
+        guard.lobby_activity.insert(message.chat.id, cleanup::Activity::now());
+        run_command(guard.deref_mut(), &ctx, &message, command).await
+    };
+    METRICS.command_handled(&command_label(message.text().unwrap_or_default()), started.elapsed());
+    result
+}
+
+// The shared context is for the tasks which outlive the command, like the start countdown
+async fn run_command(ctx: &mut BotCtx, shared: &Arc<Mutex<BotCtx>>, message: &Message, command: Command) -> ResponseResult<()>
+{
+    match command {
+        Command::Start(param) => {
+            handle_start_bot(ctx, message, param.trim()).await
+        }
+        Command::NewGame => {
+            handle_new_game(ctx, message).await
+        }
+        Command::FindGame => {
+            matchmaking::find_game(ctx, message).await
+        }
+        Command::Restart => {
+            handle_restart(ctx, shared, message).await
+        }
+        Command::StartGame => {
+            handle_start_game(ctx, shared, message).await
+        }
+        Command::Exit => {
+            handle_exit(ctx, message).await
+        }
+        Command::Switch(args) => {
+            switcher::handle(ctx, message, &args).await
+        }
+        Command::Nickname(nickname) => {
+            handle_nickname(ctx, message, &nickname).await
+        }
+        Command::Timeout(args) => {
+            handle_timeout(ctx, message, &args).await
+        }
+        Command::Settings(args) => {
+            handle_settings(ctx, message, &args).await
+        }
+        Command::Ban => {
+            ban::show(ctx, message).await
+        }
+        Command::Seats => {
+            seating::show(ctx, message).await
+        }
+        Command::Preset(name) => {
+            handle_preset(ctx, message, &name).await
+        }
+        Command::SetInvite(description) => {
+            invite::set_description(ctx, message, &description).await
+        }
+        Command::HouseRules(rules) => {
+            house_rules::handle(ctx, message, &rules).await
+        }
+        Command::Forum(args) => {
+            forum::handle(ctx, message, &args).await
+        }
+        Command::Commentator(args) => {
+            commentary::handle(ctx, message, &args).await
+        }
+        Command::Tournament(args) => {
+            tournament::handle(ctx, message, &args).await
+        }
+        Command::Audit => {
+            handle_audit(ctx, message).await
+        }
+        Command::Status => {
+            handle_status(ctx, message).await
+        }
+        Command::Whoami => {
+            handle_whoami(ctx, message).await
+        }
+        Command::Evil(text) => {
+            relay::handle_evil(ctx, message, &text).await
+        }
+        Command::Claim(text) => {
+            claims::handle(ctx, message, &text).await
+        }
+        Command::Note(text) => {
+            notes::handle_note(ctx, message, &text).await
+        }
+        Command::Notes => {
+            notes::handle_notes(ctx, message).await
+        }
+        Command::Feedback(text) => {
+            feedback::handle(ctx, message, text.trim()).await
+        }
+        Command::Stats => {
+            handle_stats(ctx, message).await
+        }
+        Command::Leaderboard(args) => {
+            handle_leaderboard(ctx, message, &args).await
+        }
+        Command::Transcript(args) => {
+            handle_transcript(ctx, message, &args).await
+        }
+        Command::Replay => {
+            replay::handle(ctx, message).await
+        }
+        Command::Analytics(args) => {
+            analytics::handle(ctx, message, &args).await
+        }
+        Command::Export(args) => {
+            export::handle(ctx, message, &args).await
+        }
+        Command::Comment(text) => {
+            rating::comment(ctx, message, &text).await
+        }
+        Command::About => {
+            about::handle(ctx, message).await
+        }
+        Command::Help => {
+            ctx.bot.send_message(message.chat.id, Command::descriptions().to_string()).await?;
+            respond(())
+        }
+        Command::SuggestFinish => {
+            route_game_action(ctx, message.chat.id, GameAction::FinishSuggestion).await
+        }
+        Command::Admin(args) => {
+            handle_admin(ctx, message, &args).await
+        }
+        Command::DebugGame(args) => {
+            debug::handle(ctx, message, &args).await
+        }
+    }
+}
+
+// Text messages which are not commands of the Command enum: game actions with arguments
+// in the command name like /team_approve or /suggest_2, and leaderboard page links
+#[tracing::instrument(skip_all, err, fields(chat = %message.chat.id, game = tracing::field::Empty, seat = tracing::field::Empty))]
+async fn handle_text(message: Message, ctx: Arc<Mutex<BotCtx>>) -> ResponseResult<()>
+{
+    let Some(text) = message.text() else {
+        return respond(());
+    };
+    let started = std::time::Instant::now();
+    let ctx = &mut *ctx.lock().await;
+    record_player(ctx, message.chat.id);
+    ctx.lobby_activity.insert(message.chat.id, cleanup::Activity::now());
+    // Page links of the leaderboard can't contain spaces
+    let result = match text.strip_prefix("/leaderboard_") {
+        Some(args) => handle_leaderboard(ctx, &message, &args.replace('_', " ")).await,
+        None => match GameAction::parse(text) {
+            Ok(action) => route_game_action(ctx, message.chat.id, action).await,
+            Err(ParseError::UnknownCommand) if !text.starts_with('/') => relay::handle_chat(ctx, &message, text).await,
+            Err(e) => {
+                ctx.bot.send_message(message.chat.id, e.to_string()).await?;
+                respond(())
+            }
+        },
+    };
+    METRICS.command_handled(&command_label(text), started.elapsed());
+    result
+}
+
+// Team chosen by the crown holder in the web app
+#[tracing::instrument(skip_all, err, fields(chat = %message.chat.id, game = tracing::field::Empty, seat = tracing::field::Empty))]
+async fn handle_web_app_data(message: Message, data: WebAppData, ctx: Arc<Mutex<BotCtx>>) -> ResponseResult<()>
+{
+    let ctx = &mut *ctx.lock().await;
+    record_player(ctx, message.chat.id);
+    match webapp::parse_selection(&data.data) {
+        Ok(action) => route_game_action(ctx, message.chat.id, action).await,
+        Err(e) => {
+            ctx.bot.send_message(message.chat.id, e.to_string()).await?;
+            respond(())
+        }
+    }
+}
+
+// Buttons of the /settings, /seats and /ban menus and of the game control messages. The other ones are only acknowledged
+// to stop the loading indicator in the client
+#[tracing::instrument(skip_all, err, fields(chat = %query.from.id, game = tracing::field::Empty, seat = tracing::field::Empty))]
+async fn handle_callback_query(query: CallbackQuery, ctx: Arc<Mutex<BotCtx>>) -> ResponseResult<()>
+{
+    let ctx = &mut *ctx.lock().await;
+    record_player(ctx, ChatId(query.from.id.0 as i64));
+    ctx.lobby_activity.insert(ChatId(query.from.id.0 as i64), cleanup::Activity::now());
+    let popup = match query.data.as_deref() {
+        Some(countdown::CANCEL) => countdown::cancel(ctx, &query).await?,
+        Some(cleanup::READY) => Some("You stay in the lobby"),
+        Some(matchmaking::READY) => matchmaking::ready(ctx, &query).await?,
+        Some(data) if data.starts_with('/') => match GameAction::parse(data) {
+            Ok(action) => {
+                route_game_action(ctx, ChatId(query.from.id.0 as i64), action).await?;
+                None
+            }
+            Err(e) => {
+                tracing::warn!("Unexpected game button from {}: {}", query.from.id, e);
+                None
+            }
+        },
+        Some(data) if data.starts_with(seating::PREFIX) => seating::change(ctx, &query, data).await?,
+        Some(data) if data.starts_with(ban::PREFIX) => ban::ban(ctx, &query, data).await?,
+        Some(data) if data.starts_with(unreachable::PREFIX) => unreachable::choose(ctx, &query, data).await?,
+        Some(data) if data.starts_with(replay::PREFIX) => replay::turn(ctx, &query, data).await?,
+        Some(data) if data.starts_with(rating::PREFIX) => rating::choose(ctx, &query, data).await?,
+        Some(data) if data.starts_with(switcher::PREFIX) => switcher::choose(ctx, &query, data).await?,
+        data => match data.and_then(Setting::parse) {
+            Some(setting) => handle_setting(ctx, &query, setting).await?,
+            None => {
+                tracing::warn!("Unexpected callback query from {}: {:?}", query.from.id, query.data);
+                None
+            }
+        },
+    };
+    let mut answer = ctx.bot.answer_callback_query(query.id);
+    if let Some(popup) = popup {
+        answer = answer.text(popup);
+    }
+    answer.await?;
+    respond(())
+}
+
+// Handles the update on this instance, also used for the updates forwarded by other instances
+fn local_handler() -> UpdateHandler<teloxide::RequestError> {
+    let messages = Update::filter_message()
+        .branch(dptree::entry().filter_command::<Command>().endpoint(handle_command))
+        .branch(dptree::filter_map(|message: Message| match message.kind {
+            MessageKind::WebAppData(data) => Some(data.web_app_data),
+            _ => None,
+        }).endpoint(handle_web_app_data))
+        .branch(dptree::endpoint(handle_text));
+
+    dptree::entry()
+        .branch(messages)
+        .branch(Update::filter_callback_query().endpoint(handle_callback_query))
+        .branch(Update::filter_inline_query().endpoint(invite::handle_inline_query))
+        .branch(Update::filter_poll_answer().endpoint(vote_poll::handle_answer))
+}
+
+fn update_handler() -> UpdateHandler<teloxide::RequestError> {
+    dptree::entry()
+        .branch(dptree::filter_map_async(cluster::remote_owner).endpoint(cluster::forward))
+        .branch(local_handler())
+}
+
+async fn restore_session(ctx: &mut BotCtx, stored: storage::StoredSession) {
+    let mut session = GameSession::new(ctx, stored.id, stored.leader);
+    let mut restored = None;
+
+    if let Some(stored_game) = stored.game {
+        let user_names = users::disambiguate(&stored_game.players, &ctx.users);
+
+        // Snapshot only has the beginning of the turn, the log also has the votes made after it
+        let log = ctx.storage.load_log(stored.id).unwrap_or_else(|e| {
+            tracing::warn!("Failed to load the log of game {}: {}", stored.id, e);
+            Vec::new()
+        });
+        let (cli, engine) = match journal::recover(&log).await {
+            Ok(recovered) => (recovered.cli.clone(), Restored::Log(recovered)),
+            Err(e) => {
+                tracing::warn!("Failed to replay the log of game {}: {}. Restoring the snapshot", stored.id, e);
+                let (game, cli) = game::Game::restore(stored_game.snapshot);
+                (cli, Restored::Snapshot(game))
+            }
+        };
+        // The next games of the lobby keep the look too
+        session.theme = stored_game.theme;
+        session.narration = stored_game.narration;
+        session.info = Some(Arc::new(GameInfo {
+            id: stored.id,
+            leader: stored.leader,
+            players: stored_game.players,
+            cli,
+            user_names,
+            delivery: Default::default(),
+            muted: ctx.muted.clone(),
+            other_games: ctx.other_games.clone(),
+            theme: stored_game.theme,
+            narration: stored_game.narration,
+            bots: HashSet::new(),
+            topic: None,
+        }));
+        restored = Some(engine);
+    }
+
+    ctx.game_sessions.insert(stored.id, session::spawn(session, restored));
+}
+
+async fn restore_sessions(bot: &Bot, bot_username: String, storage: &Storage, config: &Config) -> Result<BotCtx, Box<dyn std::error::Error>> {
+    let state = storage.load().map_err(|e| e.to_string())?;
+    let cluster = config.cluster.as_ref().map(|cluster| {
+        let secret = config.webhook.as_ref().and_then(|webhook| webhook.secret_token.clone()).unwrap_or_default();
+        cluster::Cluster::new(cluster, secret)
+    });
+    let mut ctx = BotCtx {
         bot: bot.clone(),
-        last_game_id: 0,
-        user_games: HashMap::new(),
+        bot_username,
+        storage: storage.clone(),
+        nudge: config.nudge(),
+        timeout: config.timeout()?,
+        media: config.media(),
+        webapp: config.webapp.clone(),
+        admin: config.admin(),
+        cluster,
+        user_games: state.user_games,
+        other_games: Default::default(),
+        polls: Default::default(),
         game_sessions: HashMap::new(),
-        user_names: HashMap::new(),
-    }));
+        countdowns: HashMap::new(),
+        lobby_activity: HashMap::new(),
+        matchmaking: matchmaking::Matchmaking::new(config.match_players()?),
+        feedback_sent: HashMap::new(),
+        started_at: tokio::time::Instant::now(),
+        muted: MutedChats::from_users(&state.users),
+        users: state.users,
+    };
+
+    for stored in state.sessions {
+        // Games run by other instances are left to them
+        if let Some(cluster) = &ctx.cluster {
+            match cluster.claim(&ctx, stored.id) {
+                Ok(None) => {}
+                Ok(Some(_)) => continue,
+                Err(e) => {
+                    tracing::warn!("Failed to claim game {}: {}", stored.id, e);
+                    continue;
+                }
+            }
+        }
+        restore_session(&mut ctx, stored).await;
+    }
+
+    if ctx.cluster.is_some() {
+        let local = ctx.game_sessions.keys().cloned().collect::<HashSet<_>>();
+        ctx.user_games.retain(|_, game_id| local.contains(game_id));
+    }
+
+    Ok(ctx)
+}
+
+// Stops the running games and saves them, so they are restored on the next start
+async fn shutdown(ctx: &mut BotCtx) {
+    for session in ctx.game_sessions.values() {
+        session.shutdown().await;
+        cluster::release(ctx, session.id);
+    }
+    tracing::info!("Games are saved, shutting down");
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen to SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
 
-    teloxide::repl(bot, move |bot: Bot, message: Message| {
-        let ctx = ctx.clone();
-        async move { handle_tg_message(bot, message, ctx).await }
-    }).await;
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
 
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load(config::Args::parse())?;
+    // Lines of the handlers and the session tasks show the game and the seat, e.g.
+    // "session{game=12}:player{chat=42 seat=3}: ...", so one game can be filtered out with grep
+    let (filter, log_filter) = tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new(&config.log_level));
+    let reports = match &config.reports {
+        Some(reports) => {
+            reports::capture_panics();
+            Some(reports::spawn(reports.clone())?)
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(reports)
+        .init();
+
+    let token = config.token.clone().ok_or("Bot token is not set. Use the config file, --token or TELOXIDE_TOKEN")?;
+    let bot = teloxide::Bot::new(token).throttle(Limits::default());
+    let storage = storage::open(config.storage, &config.db, &config.redis_url).map_err(|e| e.to_string())?;
+    let me = bot.get_me().await?;
+    let bot_username = me.username().to_string();
+    tracing::info!("Running as @{}", bot_username);
+
+    let mut ctx = restore_sessions(&bot, bot_username, &storage, &config).await?;
+    ctx.admin.log_filter = Some(log_filter);
+    let ctx = Arc::new(Mutex::new(ctx));
+
+    let cluster = ctx.lock().await.cluster.clone();
+    cleanup::spawn_cleanup(ctx.clone(), config.session_ttl(), config.lobby_idle());
+    matchmaking::spawn_expiry(ctx.clone());
+    if let Some(cluster) = &cluster {
+        cluster::spawn_lease_renewal(ctx.clone(), cluster.clone());
+    }
+    #[cfg(feature = "api")]
+    if let Some(http) = &config.http {
+        let forwarding = cluster.as_ref().map(|cluster| http::Forwarding { me: me.clone(), secret: cluster.secret.clone() });
+        http::spawn_server(http.address, ctx.clone(), forwarding);
+    }
+    #[cfg(feature = "api")]
+    if let Some(api) = &config.api {
+        api::spawn_server(api.address);
+    }
+    #[cfg(feature = "api")]
+    if let Some(discord) = &config.discord {
+        discord::spawn(discord.clone());
+    }
+
+    if let Err(e) = register_commands(&bot).await {
+        tracing::warn!("Failed to register bot commands: {}", e);
+    }
+
+    // The handlers log their errors themselves in their span with the game, see #[instrument(err)]
+    let mut dispatcher = Dispatcher::builder(bot.clone(), update_handler())
+        .dependencies(dptree::deps![ctx.clone(), me])
+        .error_handler(IgnoringErrorHandler::new())
+        .build();
+
+    // Ctrl+C handler of the dispatcher is not enabled, shutdown_signal also covers SIGTERM sent by docker or systemd
+    let shutdown_token = dispatcher.shutdown_token();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        match shutdown_token.shutdown() {
+            Ok(stopped) => stopped.await,
+            Err(e) => tracing::warn!("Failed to stop the dispatcher: {}", e),
+        }
+    });
+
+    match &config.webhook {
+        Some(webhook) => {
+            let mut options = webhooks::Options::new(webhook.address, webhook.url.parse()?);
+            if let Some(secret) = &webhook.secret_token {
+                options = options.secret_token(secret.clone());
+            }
+            let error_handler = LoggingErrorHandler::with_custom_text("An error from the update listener");
+            match &cluster {
+                Some(cluster) => {
+                    let listener = cluster::webhook_listener(&bot, options, cluster.secret.clone()).await?;
+                    dispatcher.dispatch_with_listener(listener, error_handler).await;
+                }
+                None => {
+                    let listener = webhooks::axum(bot.clone(), options).await?;
+                    dispatcher.dispatch_with_listener(listener, error_handler).await;
+                }
+            }
+        }
+        None => dispatcher.dispatch().await,
+    }
+    shutdown(ctx.lock().await.deref_mut()).await;
 
     Ok(())
-}
\ No newline at end of file
+}
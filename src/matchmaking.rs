@@ -0,0 +1,238 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::BotCtx;
+
+// Time the matched players have to confirm the game
+pub const READY_TIMEOUT: Duration = Duration::from_secs(60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// Callback data of the ready check button
+pub const READY: &str = "match_ready";
+
+// Strangers waiting in /find_game and the lobbies made of them, which start when everybody is ready
+pub struct Matchmaking {
+    // Players of the lobby, None when the matchmaking is off
+    players: Option<usize>,
+    queue: VecDeque<ChatId>,
+    checks: HashMap<u32, ReadyCheck>,
+}
+
+struct ReadyCheck {
+    // Matched players in the queue order, the first one leads the lobby
+    players: Vec<ChatId>,
+    waiting: HashSet<ChatId>,
+    since: Instant,
+}
+
+impl Matchmaking {
+    pub fn new(players: Option<usize>) -> Self {
+        Self { players, queue: VecDeque::new(), checks: HashMap::new() }
+    }
+
+    // Returns false if the user was not in the queue
+    pub fn leave(&mut self, chat_id: ChatId) -> bool {
+        let queued = self.queue.len();
+        self.queue.retain(|queued| *queued != chat_id);
+        self.queue.len() != queued
+    }
+
+    // Players for the next lobby in the queue order. Those who got into a game
+    // some other way since they queued are dropped
+    fn take_match(&mut self, in_game: impl Fn(ChatId) -> bool) -> Option<Vec<ChatId>> {
+        let players = self.players?;
+        self.queue.retain(|chat_id| !in_game(*chat_id));
+        (self.queue.len() >= players).then(|| self.queue.drain(..players).collect())
+    }
+
+    // The ready players of the failed match keep their place ahead of everybody else
+    fn requeue(&mut self, players: &[ChatId]) {
+        for chat_id in players.iter().rev() {
+            self.queue.push_front(*chat_id);
+        }
+    }
+}
+
+// Closes the lobbies which were not confirmed in time
+pub fn spawn_expiry(ctx_arc: Arc<Mutex<BotCtx>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let ctx = &mut *ctx_arc.lock().await;
+            if let Err(e) = expire_checks(ctx).await {
+                tracing::warn!("Failed to close the unconfirmed games: {}", e);
+            }
+        }
+    })
+}
+
+pub async fn find_game(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some(players) = ctx.matchmaking.players else {
+        ctx.bot.send_message(chat_id, "Matchmaking is off, use /new_game and invite your friends").await?;
+        return respond(());
+    };
+    if ctx.user_games.contains_key(&chat_id) {
+        ctx.bot.send_message(chat_id, "You are already in the game").await?;
+        return respond(());
+    }
+
+    crate::remember_user(ctx, message);
+    if ctx.matchmaking.queue.contains(&chat_id) {
+        ctx.bot.send_message(chat_id, "You are already in the queue. Use /exit to leave it").await?;
+        return respond(());
+    }
+    ctx.matchmaking.queue.push_back(chat_id);
+    let text = format!("You are in the queue for a game of {} players. The lobby is created when enough players are found, \
+                        use /exit to leave the queue", players);
+    ctx.bot.send_message(chat_id, text).await?;
+    match_queued(ctx).await
+}
+
+// Creates the lobby of the first queued players and asks them to confirm the game
+async fn match_queued(ctx: &mut BotCtx) -> ResponseResult<()>
+{
+    let user_games = &ctx.user_games;
+    let Some(players) = ctx.matchmaking.take_match(|chat_id| user_games.contains_key(&chat_id)) else {
+        return respond(());
+    };
+    let Some(game_id) = crate::open_lobby(ctx, players[0]) else {
+        ctx.matchmaking.requeue(&players);
+        return respond(());
+    };
+    for chat_id in &players[1..] {
+        ctx.storage.save_user_game(*chat_id, game_id);
+        ctx.user_games.insert(*chat_id, game_id);
+    }
+
+    let names = players.iter().map(|chat_id| crate::get_display_name(ctx, *chat_id)).collect::<Vec<_>>();
+    let text = format!("The game is found: {}. Press the button in {} seconds, the game starts when everybody is ready",
+                       names.join(", "), READY_TIMEOUT.as_secs());
+    let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("✅ Ready", READY)]]);
+    // The check is there before the first request, so a player who blocked the bot
+    // is not confirmed in time and the others go back to the queue
+    let waiting = players.iter().cloned().collect();
+    ctx.matchmaking.checks.insert(game_id, ReadyCheck { players: players.clone(), waiting, since: Instant::now() });
+    for chat_id in &players {
+        if let Err(e) = ctx.bot.send_message(*chat_id, &text).reply_markup(keyboard.clone()).await {
+            tracing::warn!("Failed to send the ready check to {}: {}", chat_id, e);
+        }
+    }
+    respond(())
+}
+
+// The game starts with the last confirmation. Returns the text of the popup
+pub async fn ready(ctx: &mut BotCtx, query: &CallbackQuery) -> ResponseResult<Option<&'static str>>
+{
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some(game_id) = ctx.user_games.get(&chat_id).cloned() else {
+        return Ok(Some("The game is not waiting for you anymore"));
+    };
+    let Some(check) = ctx.matchmaking.checks.get_mut(&game_id) else {
+        return Ok(Some("The game is not waiting for you anymore"));
+    };
+    if !check.waiting.remove(&chat_id) {
+        return Ok(Some("You are already ready"));
+    }
+    if !check.waiting.is_empty() {
+        return Ok(Some("Waiting for the others"));
+    }
+
+    ctx.matchmaking.checks.remove(&game_id);
+    if let Some(session) = ctx.game_sessions.get(&game_id).cloned() {
+        crate::start_lobby(ctx, &session);
+    }
+    Ok(None)
+}
+
+// Not everybody confirmed in time, so the lobbies are closed. The ready players return to the queue
+pub async fn expire_checks(ctx: &mut BotCtx) -> ResponseResult<()>
+{
+    let expired = ctx.matchmaking.checks.iter()
+        .filter(|(_, check)| check.since.elapsed() >= READY_TIMEOUT)
+        .map(|(game_id, _)| *game_id)
+        .collect::<Vec<_>>();
+    for game_id in expired {
+        if let Some(check) = ctx.matchmaking.checks.remove(&game_id) {
+            expire(ctx, game_id, check).await?;
+        }
+    }
+    match_queued(ctx).await
+}
+
+async fn expire(ctx: &mut BotCtx, game_id: u32, check: ReadyCheck) -> ResponseResult<()>
+{
+    // The leader started the game without waiting for the others
+    if ctx.game_sessions.get(&game_id).is_some_and(|session| session.status().started) {
+        return respond(());
+    }
+    if let Some(session) = ctx.game_sessions.remove(&game_id) {
+        session.cancel();
+        ctx.countdowns.remove(&game_id);
+        ctx.storage.save_session(game_id, session.leader, true);
+        crate::cluster::release(ctx, game_id);
+    }
+
+    // Those who left the lobby with /exit are not ready either
+    let ready = check.players.iter()
+        .filter(|chat_id| !check.waiting.contains(chat_id) && ctx.user_games.get(chat_id) == Some(&game_id))
+        .cloned()
+        .collect::<Vec<_>>();
+    for chat_id in &check.players {
+        if ctx.user_games.get(chat_id) == Some(&game_id) {
+            ctx.user_games.remove(chat_id);
+            ctx.storage.remove_user_game(*chat_id);
+        }
+    }
+    ctx.matchmaking.requeue(&ready);
+    for chat_id in &check.waiting {
+        let text = "You didn't confirm the game in time. Use /find_game to queue again";
+        if let Err(e) = ctx.bot.send_message(*chat_id, text).await {
+            tracing::warn!("Failed to send the expired ready check to {}: {}", chat_id, e);
+        }
+    }
+    for chat_id in &ready {
+        let text = "Not everybody confirmed the game, you are back at the front of the queue";
+        if let Err(e) = ctx.bot.send_message(*chat_id, text).await {
+            tracing::warn!("Failed to send the expired ready check to {}: {}", chat_id, e);
+        }
+    }
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_takes_queue_order_and_requeue_keeps_it() {
+        let mut matchmaking = Matchmaking::new(Some(3));
+        matchmaking.queue.extend((1..=5).map(ChatId));
+        assert!(matchmaking.leave(ChatId(2)));
+        assert!(!matchmaking.leave(ChatId(2)));
+
+        // The fourth one joined a friend's lobby meanwhile
+        let players = matchmaking.take_match(|chat_id| chat_id == ChatId(4));
+        assert_eq!(players, Some(vec![ChatId(1), ChatId(3), ChatId(5)]));
+        assert_eq!(matchmaking.take_match(|_| false), None);
+
+        matchmaking.queue.push_back(ChatId(6));
+        matchmaking.requeue(&[ChatId(1), ChatId(5)]);
+        assert_eq!(matchmaking.take_match(|_| false), Some(vec![ChatId(1), ChatId(5), ChatId(6)]));
+    }
+
+    #[test]
+    fn test_off_matchmaking_never_matches() {
+        let mut matchmaking = Matchmaking::new(None);
+        matchmaking.queue.extend((1..=7).map(ChatId));
+        assert_eq!(matchmaking.take_match(|_| false), None);
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+
+use teloxide::prelude::*;
+use teloxide::types::{InputFile, MessageEntity};
+
+use crate::game::Role;
+use crate::theme::Theme;
+use crate::Bot;
+
+#[derive(Clone)]
+pub struct MediaConfig {
+    // Role cards are looked up in <assets_dir>/roles/<role>.png and the stickers in
+    // <assets_dir>/stickers, so they can be replaced without rebuilding the bot
+    pub assets_dir: PathBuf,
+}
+
+// Big moments of the game which get a sticker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Moment {
+    MissionFailed,
+    MerlinAssassinated,
+    GoodWins,
+}
+
+impl Moment {
+    fn file_name(self) -> &'static str {
+        match self {
+            Moment::MissionFailed => "mission_failed.webp",
+            Moment::MerlinAssassinated => "merlin_assassinated.webp",
+            Moment::GoodWins => "good_wins.webp",
+        }
+    }
+}
+
+impl MediaConfig {
+    fn role_card(&self, role: &Role) -> PathBuf {
+        let name = match role {
+            Role::Good2 => Role::Good.to_string(),
+            _ => role.to_string(),
+        };
+        self.assets_dir.join("roles").join(format!("{}.png", name.to_lowercase()))
+    }
+
+    // The sticker of the theme is preferred, e.g. stickers/pirates/good_wins.webp over stickers/good_wins.webp
+    pub fn sticker(&self, theme: Theme, moment: Moment) -> Option<PathBuf> {
+        let stickers = self.assets_dir.join("stickers");
+        [stickers.join(theme.table().key), stickers]
+            .into_iter()
+            .map(|dir| dir.join(moment.file_name()))
+            .find(|sticker| sticker.is_file())
+    }
+}
+
+// Other roles are named in the words of the theme
+pub fn role_description(role: &Role, theme: Theme) -> String {
+    let theme = theme.table();
+    let name = |role: Role| theme.role(&role);
+    match role {
+        Role::Mordred => format!("Evil. {} does not know that you are evil", name(Role::Merlin)),
+        Role::Morgen => format!("Evil. {} sees you as {} and can't tell you apart", name(Role::Percival), name(Role::Merlin)),
+        Role::Oberon => "Evil, but you don't know other evil players and they don't know you".to_string(),
+        Role::Assassin => format!("Evil. If {} wins the missions, you try to guess {}", theme.good_team, name(Role::Merlin)),
+        Role::Bad => "Evil. Fail the missions without being caught".to_string(),
+        Role::Merlin => format!("Good. You know evil players except {}, but don't let them find you", name(Role::Mordred)),
+        Role::Percival => format!("Good. You see {} and {}, but don't know who is who", name(Role::Merlin), name(Role::Morgen)),
+        Role::Good | Role::Good2 => "Good. Make the missions succeed and find evil players".to_string(),
+    }
+}
+
+const ROLE_PREFIX: &str = "Your role is ";
+
+pub fn role_caption(role: &Role, theme: Theme) -> String {
+    format!("{}{}\n{}", ROLE_PREFIX, theme.table().role(role), role_description(role, theme))
+}
+
+// Hides the text after the first `visible` characters. Telegram counts the offsets in UTF-16 units
+pub fn spoiler(text: &str, visible: usize) -> Vec<MessageEntity> {
+    let offset = text.chars().take(visible).map(char::len_utf16).sum::<usize>();
+    let length = text.encode_utf16().count() - offset;
+    vec![MessageEntity::spoiler(offset, length)]
+}
+
+// The role can't be forwarded as a proof and is hidden until tapped, so nobody sees it over the shoulder.
+// Falls back to the plain text if there is no card for the role
+pub async fn send_role_card(bot: &Bot, config: &MediaConfig, chat_id: ChatId, role: &Role, theme: Theme) -> ResponseResult<()> {
+    let caption = role_caption(role, theme);
+    let entities = spoiler(&caption, ROLE_PREFIX.chars().count());
+    let card = config.role_card(role);
+    if card.is_file() {
+        let photo = bot.send_photo(chat_id, InputFile::file(&card))
+            .caption(caption.clone())
+            .caption_entities(entities.clone())
+            .has_spoiler(true)
+            .protect_content(true);
+        match photo.await {
+            Ok(_) => return respond(()),
+            Err(e) => tracing::warn!("Failed to send role card {}: {}", card.display(), e),
+        }
+    }
+
+    bot.send_message(chat_id, caption).entities(entities).protect_content(true).await?;
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_card_path() {
+        let config = MediaConfig { assets_dir: PathBuf::from("cards") };
+        assert_eq!(config.role_card(&Role::Merlin), PathBuf::from("cards/roles/merlin.png"));
+        assert_eq!(config.role_card(&Role::Good2), PathBuf::from("cards/roles/good.png"));
+    }
+
+    #[test]
+    fn test_themed_sticker_is_preferred() {
+        let assets_dir = std::env::temp_dir().join(format!("avalon_stickers_{}", std::process::id()));
+        let stickers = assets_dir.join("stickers");
+        std::fs::create_dir_all(stickers.join("pirates")).unwrap();
+        std::fs::write(stickers.join("good_wins.webp"), b"").unwrap();
+        std::fs::write(stickers.join("pirates").join("good_wins.webp"), b"").unwrap();
+
+        let config = MediaConfig { assets_dir: assets_dir.clone() };
+        assert_eq!(config.sticker(Theme::Pirates, Moment::GoodWins), Some(stickers.join("pirates").join("good_wins.webp")));
+        assert_eq!(config.sticker(Theme::Classic, Moment::GoodWins), Some(stickers.join("good_wins.webp")));
+        assert_eq!(config.sticker(Theme::Classic, Moment::MissionFailed), None);
+        std::fs::remove_dir_all(assets_dir).unwrap();
+    }
+
+    #[test]
+    fn test_role_is_under_spoiler() {
+        let caption = role_caption(&Role::Merlin, Theme::Classic);
+        let entity = &spoiler(&caption, ROLE_PREFIX.chars().count())[0];
+        assert_eq!(entity.offset, ROLE_PREFIX.len());
+        assert_eq!(entity.offset + entity.length, caption.encode_utf16().count());
+        // Emoji take two UTF-16 units
+        assert_eq!(spoiler("🦊 fox", 2)[0].offset, 3);
+    }
+
+    #[test]
+    fn test_role_caption_is_themed() {
+        assert_eq!(role_caption(&Role::Percival, Theme::Classic),
+                   "Your role is Percival\nGood. You see Merlin and Morgen, but don't know who is who");
+        assert_eq!(role_caption(&Role::Assassin, Theme::Pirates),
+                   "Your role is Cutthroat\nEvil. If the crew wins the missions, you try to guess Navigator");
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Counters are global, so they can be updated from the game tasks without passing anything around
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+struct Latency {
+    count: u64,
+    total: f64,
+}
+
+pub struct Metrics {
+    events_processed: AtomicU64,
+    send_failures: AtomicU64,
+    commands: Mutex<BTreeMap<String, Latency>>,
+}
+
+// Values which are taken from the sessions on every request
+#[cfg_attr(not(feature = "api"), allow(dead_code))]
+pub struct Gauges {
+    pub active_games: usize,
+    pub lobby_players: usize,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            events_processed: AtomicU64::new(0),
+            send_failures: AtomicU64::new(0),
+            commands: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn event_processed(&self) {
+        self.events_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn send_failed(&self) {
+        self.send_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn command_handled(&self, command: &str, duration: Duration) {
+        let mut commands = self.commands.lock().unwrap();
+        let latency = commands.entry(command.to_string()).or_default();
+        latency.count += 1;
+        latency.total += duration.as_secs_f64();
+    }
+
+    // Prometheus text exposition format, served by the http server of the api feature
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        };
+        metric("avalon_active_games", "gauge", "Games in progress", gauges.active_games.to_string());
+        metric("avalon_lobby_players", "gauge", "Players waiting in lobbies for the game start",
+               gauges.lobby_players.to_string());
+        metric("avalon_events_processed_total", "counter", "Game events sent to the players",
+               self.events_processed.load(Ordering::Relaxed).to_string());
+        metric("avalon_send_failures_total", "counter", "Messages which could not be delivered to Telegram",
+               self.send_failures.load(Ordering::Relaxed).to_string());
+
+        let _ = writeln!(out, "# HELP avalon_command_duration_seconds Time spent handling the command");
+        let _ = writeln!(out, "# TYPE avalon_command_duration_seconds summary");
+        for (command, latency) in self.commands.lock().unwrap().iter() {
+            let _ = writeln!(out, "avalon_command_duration_seconds_sum{{command=\"{}\"}} {}", command, latency.total);
+            let _ = writeln!(out, "avalon_command_duration_seconds_count{{command=\"{}\"}} {}", command, latency.count);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::new();
+        metrics.event_processed();
+        metrics.event_processed();
+        metrics.send_failed();
+        metrics.command_handled("/new_game", Duration::from_millis(500));
+        metrics.command_handled("/new_game", Duration::from_millis(250));
+
+        let text = metrics.render(&Gauges { active_games: 1, lobby_players: 3 });
+        assert!(text.contains("# TYPE avalon_active_games gauge\navalon_active_games 1\n"));
+        assert!(text.contains("\navalon_lobby_players 3\n"));
+        assert!(text.contains("\navalon_events_processed_total 2\n"));
+        assert!(text.contains("\navalon_send_failures_total 1\n"));
+        assert!(text.contains("\navalon_command_duration_seconds_sum{command=\"/new_game\"} 0.75\n"));
+        assert!(text.contains("\navalon_command_duration_seconds_count{command=\"/new_game\"} 2\n"));
+    }
+}
@@ -0,0 +1,1251 @@
+// Integration test harness: the updates are fed into the same handler stack as in production,
+// and the bot talks to a fake Bot API server which records every request and answers it
+// with made up messages, so whole games are played without a bot token
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use teloxide::adaptors::throttle::Limits;
+use teloxide::dispatching::UpdateHandler;
+use teloxide::prelude::*;
+use teloxide::types::Me;
+use tokio::sync::Mutex;
+
+use crate::config::Config;
+use crate::storage::{self, StorageBackend};
+use crate::{Bot, BotCtx};
+
+const BOT_ID: i64 = 1000;
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Request of the bot to the Bot API
+#[derive(Clone, Debug)]
+pub struct Call {
+    pub method: String,
+    pub chat_id: Option<i64>,
+    // Text or caption of the sent message
+    pub text: Option<String>,
+}
+
+#[derive(Default)]
+struct Server {
+    calls: StdMutex<Vec<Call>>,
+    last_message_id: StdMutex<i32>,
+    // Users who blocked the bot, the messages to them fail
+    blocked: StdMutex<HashSet<i64>>,
+    // Id of the last sent poll, the tests answer it
+    last_poll: StdMutex<Option<String>>,
+}
+
+fn poll(id: &str, question: &str, is_closed: bool) -> Value {
+    json!({
+        "id": id,
+        "question": question,
+        "options": [{ "text": "Approve", "voter_count": 0 }, { "text": "Reject", "voter_count": 0 }],
+        "is_closed": is_closed,
+        "total_voter_count": 0,
+        "is_anonymous": false,
+        "type": "regular",
+        "allows_multiple_answers": false,
+    })
+}
+
+fn user(id: i64) -> Value {
+    json!({ "id": id, "is_bot": false, "first_name": format!("Player{}", id) })
+}
+
+// Files are sent as multipart forms, the other requests as JSON
+fn multipart_field(body: &str, name: &str) -> Option<String> {
+    let start = body.find(&format!("name=\"{}\"", name))?;
+    let (_, value) = body[start..].split_once("\r\n\r\n")?;
+    value.split_once("\r\n").map(|(value, _)| value.to_string())
+}
+
+fn parse_call(method: &str, headers: &HeaderMap, body: &[u8]) -> Call {
+    // teloxide sends GetMe, the Bot API documents getMe
+    let mut chars = method.chars();
+    let method = chars.next().map(|first| first.to_lowercase().chain(chars).collect()).unwrap_or_default();
+    let is_json = headers.get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    if is_json {
+        let params = serde_json::from_slice::<Value>(body).unwrap_or_default();
+        // Inline query answers are checked by the title of the first result, polls by the question
+        let text = params.get("text").or_else(|| params.get("caption")).or_else(|| params.pointer("/results/0/title"))
+            .or_else(|| params.get("question"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        return Call { method, chat_id: params.get("chat_id").and_then(Value::as_i64), text };
+    }
+
+    let body = String::from_utf8_lossy(body);
+    Call {
+        method,
+        chat_id: multipart_field(&body, "chat_id").and_then(|chat_id| chat_id.parse().ok()),
+        text: multipart_field(&body, "caption"),
+    }
+}
+
+async fn bot_api(State(server): State<Arc<Server>>, Path((_, method)): Path<(String, String)>,
+                 headers: HeaderMap, body: axum::body::Bytes) -> Json<Value> {
+    let call = parse_call(&method, &headers, &body);
+    server.calls.lock().unwrap().push(call.clone());
+    if call.chat_id.is_some_and(|chat_id| server.blocked.lock().unwrap().contains(&chat_id)) {
+        return Json(json!({ "ok": false, "error_code": 403, "description": "Forbidden: bot was blocked by the user" }));
+    }
+
+    let result = if call.method == "getMe" {
+        json!({
+            "id": BOT_ID,
+            "is_bot": true,
+            "first_name": "Avalon",
+            "username": "avalon_test_bot",
+            "can_join_groups": false,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        })
+    } else if call.method == "sendChatAction" {
+        json!(true)
+    } else if call.method == "createForumTopic" {
+        json!({ "message_thread_id": 2, "name": call.text.unwrap_or_default(), "icon_color": 0x6FB9F0 })
+    } else if call.method == "stopPoll" {
+        poll("0", "", true)
+    } else if call.method.starts_with("send") || call.method.starts_with("edit") {
+        let message_id = {
+            let mut last = server.last_message_id.lock().unwrap();
+            *last += 1;
+            *last
+        };
+        let chat_id = call.chat_id.unwrap_or_default();
+        let mut message = json!({
+            "message_id": message_id,
+            "date": 0,
+            "chat": { "id": chat_id, "type": "private", "first_name": format!("Player{}", chat_id) },
+            "from": { "id": BOT_ID, "is_bot": true, "first_name": "Avalon" },
+        });
+        if call.method == "sendPoll" {
+            let poll_id = message_id.to_string();
+            message["poll"] = poll(&poll_id, &call.text.unwrap_or_default(), false);
+            *server.last_poll.lock().unwrap() = Some(poll_id);
+        } else {
+            message["text"] = json!(call.text.unwrap_or_default());
+        }
+        message
+    } else {
+        json!(true)
+    };
+    Json(json!({ "ok": true, "result": result }))
+}
+
+pub struct Harness {
+    bot: Bot,
+    me: Me,
+    ctx: Arc<Mutex<BotCtx>>,
+    server: Arc<Server>,
+    handler: UpdateHandler<teloxide::RequestError>,
+    last_update_id: StdMutex<i32>,
+}
+
+impl Harness {
+    // Starts the fake Bot API and the bot with the default config and the memory storage
+    pub async fn start() -> Self {
+        let server = Arc::new(Server::default());
+        let app = Router::new()
+            .route("/:token/:method", post(bot_api))
+            .with_state(server.clone());
+        let api = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+        let url = format!("http://{}", api.local_addr()).parse().unwrap();
+        tokio::spawn(api);
+
+        // Telegram limits would only slow the tests down
+        let limits = Limits {
+            messages_per_sec_chat: 1000,
+            messages_per_min_chat: 60000,
+            messages_per_min_channel: 60000,
+            messages_per_sec_overall: 1000,
+        };
+        let bot = teloxide::Bot::new("1000:test").set_api_url(url).throttle(limits);
+        let me = bot.get_me().await.unwrap();
+        let storage = storage::open(StorageBackend::Memory, "", "").unwrap();
+        let ctx = crate::restore_sessions(&bot, me.username().to_string(), &storage, &Config::default()).await.unwrap();
+
+        Self {
+            bot,
+            me,
+            ctx: Arc::new(Mutex::new(ctx)),
+            server,
+            handler: crate::update_handler(),
+            last_update_id: StdMutex::new(0),
+        }
+    }
+
+    fn next_update_id(&self) -> i32 {
+        let mut last = self.last_update_id.lock().unwrap();
+        *last += 1;
+        *last
+    }
+
+    async fn dispatch(&self, update: Value) {
+        let update = serde_json::from_str::<Update>(&update.to_string()).unwrap();
+        let deps = dptree::deps![update, self.bot.clone(), self.ctx.clone(), self.me.clone()];
+        match self.handler.dispatch(deps).await {
+            ControlFlow::Break(result) => result.unwrap(),
+            ControlFlow::Continue(_) => panic!("The update was not handled"),
+        }
+    }
+
+    // Private message of the user, the chat id is the same as the user id
+    pub async fn message(&self, from: i64, text: &str) {
+        let id = self.next_update_id();
+        let mut message = json!({
+            "message_id": id,
+            "date": 0,
+            "chat": { "id": from, "type": "private", "first_name": format!("Player{}", from) },
+            "from": user(from),
+            "text": text,
+        });
+        if text.starts_with('/') {
+            let command = text.split_whitespace().next().unwrap_or_default();
+            message["entities"] = json!([{ "type": "bot_command", "offset": 0, "length": command.encode_utf16().count() }]);
+        }
+        self.dispatch(json!({ "update_id": id, "message": message })).await;
+    }
+
+    // Message of the user in the supergroup with topics
+    pub async fn forum_message(&self, chat_id: i64, from: i64, text: &str) {
+        let id = self.next_update_id();
+        let command = text.split_whitespace().next().unwrap_or_default();
+        self.dispatch(json!({
+            "update_id": id,
+            "message": {
+                "message_id": id,
+                "date": 0,
+                "chat": { "id": chat_id, "type": "supergroup", "title": "Avalon club", "is_forum": true },
+                "from": user(from),
+                "text": text,
+                "entities": [{ "type": "bot_command", "offset": 0, "length": command.encode_utf16().count() }],
+            },
+        })).await;
+    }
+
+    // Press of an inline button
+    pub async fn callback_query(&self, from: i64, data: &str) {
+        let id = self.next_update_id();
+        self.dispatch(json!({
+            "update_id": id,
+            "callback_query": { "id": id.to_string(), "from": user(from), "chat_instance": "1", "data": data },
+        })).await;
+    }
+
+    // Leader's /start_game, the roles are dealt after the countdown
+    pub async fn start_game(&self, leader: i64) {
+        let after = self.calls().len();
+        self.message(leader, "/start_game").await;
+        self.wait_for_text(after, leader, "The game starts in").await;
+        tokio::time::sleep(crate::countdown::COUNTDOWN).await;
+    }
+
+    // Answer to the poll, no options is the retracted vote
+    pub async fn poll_answer(&self, from: i64, poll_id: &str, option_ids: &[i32]) {
+        let id = self.next_update_id();
+        self.dispatch(json!({
+            "update_id": id,
+            "poll_answer": { "poll_id": poll_id, "user": user(from), "option_ids": option_ids },
+        })).await;
+    }
+
+    pub fn last_poll(&self) -> Option<String> {
+        self.server.last_poll.lock().unwrap().clone()
+    }
+
+    pub async fn inline_query(&self, from: i64, query: &str) {
+        let id = self.next_update_id();
+        self.dispatch(json!({
+            "update_id": id,
+            "inline_query": { "id": id.to_string(), "from": user(from), "query": query, "offset": "" },
+        })).await;
+    }
+
+    pub fn block(&self, chat_id: i64) {
+        self.server.blocked.lock().unwrap().insert(chat_id);
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.server.calls.lock().unwrap().clone()
+    }
+
+    // Waits for the first request after the given number of requests which matches the predicate,
+    // game events are handled by the session tasks in the background
+    pub async fn wait_for(&self, after: usize, predicate: impl Fn(&Call) -> bool) -> (usize, Call) {
+        let waiting = async {
+            loop {
+                let found = self.calls().into_iter().enumerate().skip(after).find(|(_, call)| predicate(call));
+                if let Some(found) = found {
+                    return found;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(WAIT_TIMEOUT, waiting).await.expect("The bot didn't send the expected request")
+    }
+
+    // Waits for a message to the chat which contains the text
+    pub async fn wait_for_text(&self, after: usize, chat_id: i64, text: &str) -> (usize, Call) {
+        self.wait_for(after, |call| {
+            call.chat_id == Some(chat_id) && call.text.as_deref().is_some_and(|sent| sent.contains(text))
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::Cluster;
+    use crate::config::ClusterConfig;
+    use crate::game::Role;
+    use crate::theme::Theme;
+
+    fn command_with_prefix<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+        text.split_whitespace().find(|word| word.starts_with(prefix))
+    }
+
+    #[tokio::test]
+    async fn test_callback_query_is_answered() {
+        let harness = Harness::start().await;
+        harness.callback_query(1, "unknown").await;
+        assert!(harness.calls().iter().any(|call| call.method == "answerCallbackQuery"));
+    }
+
+    // Uploads are not finished in time when the clock is paused
+    #[tokio::test]
+    async fn test_invite_has_qr_code() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, qr) = harness.wait_for_text(0, 1, "Scan to join the game").await;
+        assert_eq!(qr.method, "sendPhoto");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leader_shares_invite_card() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+
+        let answers = |harness: &Harness| harness.calls().into_iter()
+            .filter(|call| call.method == "answerInlineQuery")
+            .map(|call| call.text)
+            .collect::<Vec<_>>();
+        harness.inline_query(1, &game_id).await;
+        harness.inline_query(1, "").await;
+        harness.inline_query(2, &game_id).await;
+        harness.inline_query(1, "999").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let card = Some("Join my Avalon game".to_string());
+        assert_eq!(answers(&harness), [card.clone(), card, None, None]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_muted_leader_gets_no_lobby_news() {
+        let harness = Harness::start().await;
+        harness.message(1, "/settings mute").await;
+        harness.wait_for_text(0, 1, "Notifications are muted").await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+
+        harness.message(2, &format!("/start {}", game_id)).await;
+        harness.message(2, "/exit").await;
+        harness.wait_for_text(0, 2, "You left the game").await;
+        assert!(!harness.calls().iter().any(|call| call.chat_id == Some(1)
+            && call.text.as_deref().is_some_and(|text| text.contains("the game") && text.starts_with("Player2"))));
+
+        harness.message(1, "/settings unmute").await;
+        harness.message(2, &format!("/start {}", game_id)).await;
+        harness.wait_for_text(0, 1, "Player2 joined the game").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leader_changes_settings_with_buttons() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        harness.message(1, "/settings").await;
+        harness.wait_for_text(0, 1, "You get all notifications").await;
+
+        harness.callback_query(1, "settings mermaid").await;
+        harness.callback_query(1, "settings role_oberon").await;
+        harness.callback_query(2, "settings mermaid").await;
+        harness.wait_for(0, |call| call.method == "answerCallbackQuery"
+            && call.text.as_deref().is_some_and(|text| text.starts_with("Only game leader"))).await;
+
+        // Session task applies the options in the background
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let session = harness.ctx.lock().await.game_sessions.values().next().unwrap().clone();
+        let options = session.status().options;
+        assert!(!options.mermaid);
+        assert!(!options.roles.contains(&crate::game::Role::Oberon));
+        assert!(options.roles.contains(&crate::game::Role::Percival));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leader_reorders_seats() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in [3, 2] {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+
+        harness.message(1, "/seats").await;
+        harness.wait_for_text(0, 1, "1. Player1\n2. Player2\n3. Player3").await;
+        harness.callback_query(1, "seats down 0").await;
+        harness.callback_query(2, "seats shuffle").await;
+        harness.wait_for(0, |call| call.method == "answerCallbackQuery"
+            && call.text.as_deref().is_some_and(|text| text.starts_with("Only game leader"))).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        harness.message(3, "/seats").await;
+        harness.wait_for_text(0, 3, "1. Player2\n2. Player1\n3. Player3").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_whoami_tells_seat_and_turn() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(1, "/whoami").await;
+        harness.wait_for_text(0, 1, "The game is not started yet").await;
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+        harness.start_game(1).await;
+        harness.wait_for_text(0, 5, "has the crown").await;
+
+        let after = harness.calls().len();
+        for player in 1..=5 {
+            harness.message(player, "/whoami").await;
+            harness.wait_for_text(after, player, &format!("seat {} of 5", player)).await;
+        }
+        let answers = harness.calls().into_iter()
+            .skip(after)
+            .filter_map(|call| call.text.filter(|text| text.contains(" of 5\n")))
+            .collect::<Vec<_>>();
+        let crowns = answers.iter().filter(|text| text.contains("You hold the crown")).collect::<Vec<_>>();
+        assert_eq!(crowns.len(), 1);
+        assert!(crowns[0].ends_with("The game is waiting for you to suggest a team"));
+        assert!(answers.iter().all(|text| text.contains("Your role is ")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leader_replaces_player_who_blocked_bot() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+        harness.start_game(1).await;
+        let (_, turn) = harness.wait_for(0, |call| call.text.as_deref().is_some_and(|text| text.contains("You chooses a team of"))).await;
+        let crown = turn.chat_id.unwrap();
+        let blocked = if crown == 3 { 4 } else { 3 };
+        harness.block(blocked);
+
+        // The suggested team is shown to everybody, so the blocked player is found
+        for id in 0..2 {
+            harness.message(crown, &format!("/suggest_{}", id)).await;
+        }
+        harness.message(crown, "/suggest_finish").await;
+        let (_, notice) = harness.wait_for_text(0, 1, &format!("Player{} has blocked the bot", blocked)).await;
+        assert_eq!(notice.method, "sendMessage");
+        let other = if blocked == 2 { 5 } else { 2 };
+        harness.wait_for_text(0, other, "The leader can replace them with AI").await;
+
+        harness.callback_query(other, &format!("unreachable ai {}", blocked)).await;
+        harness.wait_for(0, |call| call.method == "answerCallbackQuery"
+            && call.text.as_deref() == Some("Only game leader can decide")).await;
+        harness.callback_query(1, &format!("unreachable ai {}", blocked)).await;
+        harness.wait_for_text(0, other, &format!("AI plays for Player{} from now on", blocked)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_banned_user_cant_rejoin() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+
+        harness.message(2, "/ban").await;
+        harness.wait_for_text(0, 2, "Only game leader can ban").await;
+        harness.message(1, "/ban").await;
+        harness.wait_for_text(0, 1, "Tap the name").await;
+        harness.callback_query(1, "ban 2").await;
+        harness.wait_for_text(0, 2, "The leader removed you from the game").await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let after = harness.calls().len();
+        harness.message(2, &format!("/start {}", game_id)).await;
+        harness.wait_for_text(after, 2, "doesn't let you join").await;
+        assert!(!harness.ctx.lock().await.user_games.contains_key(&ChatId(2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_member_is_removed_from_lobby() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in [2, 3] {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+
+        let idle = Duration::from_secs(20 * 60);
+        tokio::time::advance(idle / 2).await;
+        harness.message(3, "/whoami").await;
+        crate::cleanup::remove_idle_members(&mut *harness.ctx.lock().await, idle).await;
+        harness.wait_for_text(0, 2, "Are you still here?").await;
+
+        tokio::time::advance(idle / 2).await;
+        crate::cleanup::remove_idle_members(&mut *harness.ctx.lock().await, idle).await;
+        harness.wait_for_text(0, 2, "You were removed from the lobby for inactivity").await;
+        harness.wait_for_text(0, 1, "Player2 was removed from the lobby for inactivity").await;
+        let ctx = harness.ctx.lock().await;
+        assert!(!ctx.user_games.contains_key(&ChatId(2)));
+        assert!(ctx.user_games.contains_key(&ChatId(1)) && ctx.user_games.contains_key(&ChatId(3)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_debug_game_fills_other_seats_with_ai() {
+        let harness = Harness::start().await;
+        harness.message(1, "/debug_game 5").await;
+        harness.wait_for_text(0, 1, "Unknown command").await;
+
+        harness.ctx.lock().await.admin.admin_ids = vec![ChatId(1)];
+        let after = harness.calls().len();
+        harness.message(1, "/debug_game 5").await;
+        harness.wait_for_text(after, 1, "Game started with 5 players!").await;
+        // The AI either suggests the team or waits for the suggestion of the developer
+        harness.wait_for(after, |call| call.chat_id == Some(1) && call.text.as_deref().is_some_and(|text| text.contains("🤖 AI"))).await;
+        assert!(harness.calls().iter().all(|call| call.chat_id.is_none_or(|chat_id| chat_id > 0)));
+
+        // The AI seats are not stored, so the game is not restored after a restart
+        harness.wait_for_text(after, 1, "📋 Board").await;
+        let ctx = harness.ctx.lock().await;
+        assert!(ctx.storage.load().unwrap().sessions.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_admin_closes_season() {
+        let harness = Harness::start().await;
+        harness.ctx.lock().await.admin.admin_ids = vec![ChatId(100)];
+        let stats = crate::stats::PlayerStats { games: 2, good_wins: 1, ..Default::default() };
+        harness.ctx.lock().await.storage.save_stats(ChatId(1), &stats);
+
+        harness.message(1, "/admin close_season").await;
+        harness.wait_for_text(0, 1, "Unknown command").await;
+        harness.message(100, "/admin close_season").await;
+        harness.wait_for_text(0, 100, "Season 1 is closed, season 2 starts now").await;
+
+        harness.message(2, "/leaderboard").await;
+        harness.wait_for_text(0, 2, "Season 2, top players by wins:\nNobody is here yet").await;
+        harness.message(2, "/leaderboard season:1").await;
+        harness.wait_for_text(0, 2, "1. 1 - 1 wins of 2 games (50%)").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_feedback_goes_to_admin_with_game_phase() {
+        let harness = Harness::start().await;
+        harness.ctx.lock().await.admin.admin_ids = vec![ChatId(100)];
+        harness.message(1, "/new_game").await;
+        harness.message(1, "/feedback The lobby is stuck").await;
+        let (_, feedback) = harness.wait_for_text(0, 100, "Feedback from").await;
+        let text = feedback.text.unwrap();
+        assert!(text.contains("(id 1), game #") && text.contains("phase lobby:\nThe lobby is stuck"), "{}", text);
+        harness.wait_for_text(0, 1, "The message is sent to the maintainer").await;
+
+        let after = harness.calls().len();
+        harness.message(1, "/feedback Again").await;
+        harness.wait_for_text(after, 1, "Please wait a minute").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_game_goes_on_after_engine_failure() {
+        let harness = Harness::start().await;
+        let players = (1..=5).map(ChatId).collect::<Vec<_>>();
+        let user_names = players.iter().map(|player| (*player, Arc::from(format!("Player{}", player)))).collect();
+        let mut session = crate::GameSession::new(&*harness.ctx.lock().await, 77, ChatId(1));
+        crate::start_game(&mut session, players, user_names).await.unwrap();
+        let event = session.info.as_ref().unwrap().cli.clone().recv_event().await.unwrap();
+        crate::on_game_event(&mut session, &event).await;
+        let crate::game::GameEvent::Turn(crown, size) = event else {
+            panic!("Unexpected first event {:?}", event);
+        };
+
+        let after = harness.calls().len();
+        session.engine.take().unwrap().abort();
+        crate::session::recover(&mut session, "Test failure").await;
+        harness.wait_for_text(after, 2, "It continues from where it stopped").await;
+
+        // The new engine takes the moves
+        let team = (0..size as u8).collect::<Vec<_>>();
+        session.perform(crate::Move::SuggestTeam(crown, team.clone())).await.unwrap();
+        let event = session.info.as_ref().unwrap().cli.clone().recv_event().await.unwrap();
+        assert_eq!(event, crate::game::GameEvent::TeamSuggested(team));
+
+        for _ in 1..crate::session::MAX_RECOVERIES {
+            crate::session::recover(&mut session, "Test failure").await;
+            assert!(!session.crashed);
+        }
+        let after = harness.calls().len();
+        crate::session::recover(&mut session, "Test failure").await;
+        assert!(session.crashed && session.finished);
+        harness.wait_for_text(after, 2, "The game crashed").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_players_of_dead_session_are_freed() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+
+        // The task of the session ends before the cleanup sees it
+        let session = harness.ctx.lock().await.game_sessions.values().next().unwrap().clone();
+        session.cancel();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!session.is_running());
+
+        let ctx = &mut *harness.ctx.lock().await;
+        crate::cleanup::remove_stale_sessions(ctx, Duration::from_secs(3600)).await;
+        assert!(ctx.game_sessions.is_empty());
+        assert!(ctx.user_games.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chat_is_relayed_to_other_players() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+
+        harness.message(2, "hello").await;
+        harness.wait_for_text(0, 1, "💬 Player2: hello").await;
+        harness.wait_for_text(0, 5, "💬 Player2: hello").await;
+
+        harness.callback_query(1, "settings relay").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        harness.message(2, "silence").await;
+        let (_, anonymous) = harness.wait_for_text(0, 1, "says: silence").await;
+        assert!(!anonymous.text.unwrap().contains("Player2"));
+        harness.message(2, "I am player2").await;
+        harness.wait_for_text(0, 2, "the message with your name is not relayed").await;
+
+        harness.callback_query(1, "settings relay").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        harness.message(2, "anybody?").await;
+        harness.wait_for_text(0, 2, "Chat is turned off").await;
+
+        harness.callback_query(1, "settings relay").await;
+        harness.start_game(1).await;
+        let (seen, _) = harness.wait_for_text(0, 5, "Your role is").await;
+        harness.message(3, "I am good").await;
+        harness.wait_for_text(seen, 4, "💬 Player3: I am good").await;
+        assert!(!harness.calls().iter().any(|call| call.chat_id == Some(3)
+            && call.text.as_deref().is_some_and(|text| text.starts_with("💬 Player3"))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bad_team_talks_in_private() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+        harness.message(2, "/evil Hi").await;
+        harness.wait_for_text(0, 2, "The bad team chat is off in this game").await;
+        harness.callback_query(1, "settings evil_chat").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        harness.start_game(1).await;
+        harness.wait_for_text(0, 3, "The bad team can message each other anonymously with /evil").await;
+
+        let roles = [Role::Merlin, Role::Percival, Role::Good, Role::Good2, Role::Mordred, Role::Morgen, Role::Assassin, Role::Oberon, Role::Bad];
+        let (mut good, mut bad) = (Vec::new(), Vec::new());
+        for player in 1..=5 {
+            let (_, card) = harness.wait_for_text(0, player, "Your role is").await;
+            let card = card.text.unwrap();
+            let role = roles.iter().find(|role| card == crate::media::role_caption(role, Theme::Classic)).unwrap();
+            if role.is_good() { good.push(player) } else { bad.push(player) }
+        }
+        harness.message(good[0], "/evil Hi").await;
+        harness.wait_for_text(0, good[0], "Only bad team can use /evil").await;
+
+        let seen = harness.calls().len();
+        harness.message(bad[0], "/evil Who is Merlin?").await;
+        let (_, relayed) = harness.wait_for_text(seen, bad[1], "Who is Merlin?").await;
+        assert!(relayed.text.unwrap().starts_with("😈 "));
+        assert!(!harness.calls().iter().skip(seen).any(|call| call.chat_id.is_some_and(|chat_id| good.contains(&chat_id))
+            && call.text.as_deref().is_some_and(|text| text.contains("Who is Merlin?"))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_vote_opens_after_discussion() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+        harness.callback_query(1, "settings discussion").await;
+        harness.callback_query(1, "settings discussion").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        harness.start_game(1).await;
+
+        let (seen, turn) = harness.wait_for(0, |call| call.text.as_deref().is_some_and(|text| text.contains("You chooses a team of "))).await;
+        let (crown, text) = (turn.chat_id.unwrap(), turn.text.unwrap());
+        let size = text.split_once("You chooses a team of ").unwrap().1.split_whitespace().next().unwrap();
+        for id in 0..size.parse::<usize>().unwrap() {
+            harness.message(crown, &format!("/suggest_{}", id)).await;
+        }
+        harness.message(crown, "/suggest_finish").await;
+        let (seen, _) = harness.wait_for_text(seen, 1, "the vote opens in 2 min").await;
+        harness.message(2, "/team_approve").await;
+        harness.wait_for_text(seen, 2, "The vote opens after the discussion").await;
+
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        let (seen, _) = harness.wait_for_text(seen, 3, "60 seconds left").await;
+        assert!(!harness.calls().iter().skip(seen).any(|call| call.text.as_deref().is_some_and(|text| text.contains("/team_approve"))));
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        harness.wait_for_text(seen, 3, "/team_approve").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crown_sees_typing_until_team_is_suggested() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+        harness.start_game(1).await;
+
+        let (seen, turn) = harness.wait_for(0, |call| call.text.as_deref().is_some_and(|text| text.contains("You chooses a team of "))).await;
+        let crown = turn.chat_id.unwrap();
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        let (_, typing) = harness.wait_for(seen, |call| call.method == "sendChatAction").await;
+        assert_eq!(typing.chat_id, Some(crown));
+
+        harness.message(crown, "/suggest_0").await;
+        harness.message(crown, "/suggest_finish").await;
+        let (seen, _) = harness.wait_for_text(seen, crown, "/team_approve").await;
+        tokio::time::sleep(crate::typing::INTERVAL * 2).await;
+        let typing = harness.calls().into_iter().skip(seen).filter(|call| call.method == "sendChatAction").count();
+        assert_eq!(typing, 0, "The voters are shown typing only after the delay");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_team_is_chosen_with_buttons() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+        harness.start_game(1).await;
+
+        let (seen, turn) = harness.wait_for(0, |call| call.text.as_deref().is_some_and(|text| text.contains("You chooses a team of "))).await;
+        let (crown, text) = (turn.chat_id.unwrap(), turn.text.unwrap());
+        let size = text.split_once("You chooses a team of ").unwrap().1.split_whitespace().next().unwrap();
+        for id in 0..size.parse::<usize>().unwrap() {
+            harness.callback_query(crown, &format!("/suggest_{}", id)).await;
+        }
+        let (seen, _) = harness.wait_for(seen, |call| call.method == "editMessageText"
+            && call.text.as_deref().is_some_and(|text| text.contains("☑️"))).await;
+        harness.callback_query(crown, "/suggest_finish").await;
+        harness.wait_for_text(seen, 1, "/team_approve").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reject_on_last_try_is_confirmed() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+        harness.start_game(1).await;
+
+        // Every team is rejected, so the bad team wins on the last try
+        let (mut seen, mut last_try) = (0, false);
+        loop {
+            let (index, call) = harness.wait_for(seen, |call| call.method == "sendMessage").await;
+            seen = index + 1;
+            let (Some(chat_id), Some(text)) = (call.chat_id, call.text) else {
+                continue;
+            };
+            if text.contains("Bad team won!") {
+                break;
+            }
+
+            last_try |= text.contains("⚠️ Next rejection hands victory to bad team!");
+            if let Some(size) = text.split_once("You chooses a team of ").and_then(|(_, rest)| rest.split_whitespace().next()) {
+                for id in 0..size.parse::<usize>().unwrap() {
+                    harness.message(chat_id, &format!("/suggest_{}", id)).await;
+                }
+                harness.message(chat_id, "/suggest_finish").await;
+            } else if text.contains("/team_reject") {
+                harness.message(chat_id, "/team_reject").await;
+                if last_try {
+                    harness.wait_for_text(seen, chat_id, "Tap Reject again to confirm").await;
+                    harness.message(chat_id, "/team_reject").await;
+                }
+            }
+        }
+        assert!(last_try);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_start_countdown_is_cancelled() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+
+        harness.message(1, "/start_game").await;
+        harness.wait_for_text(0, 2, "The game starts in 10 seconds").await;
+        harness.message(1, "/start_game").await;
+        harness.wait_for_text(0, 1, "The game is already starting").await;
+        harness.callback_query(2, "cancel_start").await;
+        harness.wait_for_text(0, 1, "Player2 cancelled the start").await;
+        tokio::time::sleep(crate::countdown::COUNTDOWN).await;
+        assert!(!harness.calls().iter().any(|call| call.text.as_deref().is_some_and(|text| text.starts_with("Game started"))));
+
+        // Who joins during the countdown plays too
+        harness.message(1, "/start_game").await;
+        harness.message(3, &format!("/start {}", game_id)).await;
+        harness.wait_for_text(0, 3, "You are joined the game").await;
+        tokio::time::sleep(crate::countdown::COUNTDOWN).await;
+        harness.wait_for_text(0, 3, "Game started with 3 players").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_joined_player_sees_lobby_description() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+        let (seen, _) = harness.wait_for_text(0, 2, "You are joined the game").await;
+        harness.message(2, "/set_invite \"Mine now\"").await;
+        harness.wait_for_text(seen, 2, "Only game leader can describe the lobby").await;
+
+        harness.message(1, "/set_invite \"Friday Avalon, 21:00\"").await;
+        harness.wait_for_text(0, 1, "Friday Avalon, 21:00\nJoin the game: ").await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        harness.message(3, &format!("/start {}", game_id)).await;
+        harness.wait_for_text(0, 3, "📝 Friday Avalon, 21:00").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_house_rules_are_shown_at_start() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+        let (seen, _) = harness.wait_for_text(0, 2, "You are joined the game").await;
+        harness.message(2, "/house_rules No timer talk").await;
+        harness.wait_for_text(seen, 2, "Only game leader can set the house rules").await;
+
+        harness.message(1, "/house_rules No timer talk & no <screenshots>").await;
+        harness.wait_for_text(0, 1, "Everybody gets them at the start of the game").await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        for player in 3..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+        }
+        harness.wait_for_text(0, 5, "📜 House rules: No timer talk & no <screenshots>").await;
+        let seen = harness.calls().len();
+        harness.start_game(1).await;
+        for player in 1..=5 {
+            harness.wait_for_text(seen, player, "📜 House rules: No timer talk & no <screenshots>").await;
+        }
+        harness.message(2, "/status").await;
+        let (_, status) = harness.wait_for_text(seen, 2, "📋 Board").await;
+        assert!(status.text.unwrap().ends_with("\n\n📜 House rules: No timer talk &amp; no &lt;screenshots&gt;"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_matched_strangers_start_when_everybody_is_ready() {
+        let harness = Harness::start().await;
+        for player in 1..=5 {
+            harness.message(player, "/find_game").await;
+        }
+        for player in 1..=5 {
+            harness.wait_for_text(0, player, "The game is found: Player1, Player2").await;
+        }
+        harness.message(1, "/find_game").await;
+        harness.wait_for_text(0, 1, "You are already in the game").await;
+
+        // The fifth one never answers, so the others are queued again ahead of the newcomer
+        for player in 1..=4 {
+            harness.callback_query(player, crate::matchmaking::READY).await;
+        }
+        harness.message(6, "/find_game").await;
+        tokio::time::sleep(crate::matchmaking::READY_TIMEOUT).await;
+        let seen = harness.calls().len();
+        crate::matchmaking::expire_checks(&mut *harness.ctx.lock().await).await.unwrap();
+        harness.wait_for_text(seen, 5, "You didn't confirm the game in time").await;
+        harness.wait_for_text(seen, 1, "you are back at the front of the queue").await;
+        harness.wait_for_text(seen, 6, "The game is found: Player1, Player2, Player3, Player4, Player6").await;
+        assert!(!harness.ctx.lock().await.user_games.contains_key(&ChatId(5)));
+
+        for player in [1, 2, 3, 4, 6] {
+            harness.callback_query(player, crate::matchmaking::READY).await;
+        }
+        harness.wait_for_text(seen, 6, "Game started with 5 players").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_matched_player_who_blocked_bot_does_not_stall_others() {
+        let harness = Harness::start().await;
+        for player in 1..=4 {
+            harness.message(player, "/find_game").await;
+        }
+        harness.block(3);
+        harness.message(5, "/find_game").await;
+        for player in [1, 2, 4, 5] {
+            harness.wait_for_text(0, player, "The game is found: Player1, Player2, Player3").await;
+        }
+
+        for player in [1, 2, 4, 5] {
+            harness.callback_query(player, crate::matchmaking::READY).await;
+        }
+        tokio::time::sleep(crate::matchmaking::READY_TIMEOUT).await;
+        let seen = harness.calls().len();
+        crate::matchmaking::expire_checks(&mut *harness.ctx.lock().await).await.unwrap();
+        for player in [1, 2, 4, 5] {
+            harness.wait_for_text(seen, player, "you are back at the front of the queue").await;
+        }
+        assert!(!harness.ctx.lock().await.user_games.contains_key(&ChatId(3)));
+        harness.message(6, "/find_game").await;
+        harness.wait_for_text(seen, 6, "The game is found: Player1, Player2, Player4, Player5, Player6").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_session_stops_countdown() {
+        let harness = Harness::start().await;
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(2, &format!("/start {}", game_id)).await;
+        harness.message(1, "/start_game").await;
+        harness.wait_for_text(0, 2, "The game starts in 10 seconds").await;
+
+        let session = harness.ctx.lock().await.game_sessions.values().next().unwrap().clone();
+        session.cancel();
+        tokio::time::sleep(crate::countdown::COUNTDOWN * 2).await;
+        assert!(!session.is_running());
+        assert!(!harness.calls().iter().any(|call| call.text.as_deref().is_some_and(|text| text.starts_with("Game started"))));
+        assert!(harness.ctx.lock().await.countdowns.contains_key(&session.id));
+
+        let ctx = &mut *harness.ctx.lock().await;
+        crate::cleanup::remove_stale_sessions(ctx, Duration::from_secs(3600)).await;
+        assert!(ctx.countdowns.is_empty());
+    }
+
+    // The throttling adaptor waits a quarter of a second after each request,
+    // the paused clock skips these waits
+    #[tokio::test(start_paused = true)]
+    async fn test_game_is_posted_to_forum_topic() {
+        let (harness, forum) = (Harness::start().await, -1001);
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+            harness.wait_for_text(0, player, "You are joined the game").await;
+        }
+
+        harness.message(1, "/forum").await;
+        harness.wait_for_text(0, 1, "Send /forum in a group with topics").await;
+        harness.forum_message(forum, 2, "/forum").await;
+        harness.wait_for_text(0, forum, "Only game leader can choose the group").await;
+        harness.forum_message(forum, 1, "/forum").await;
+        harness.wait_for_text(0, forum, &format!("Every game of lobby #{} gets its own topic here", game_id)).await;
+
+        harness.start_game(1).await;
+        harness.wait_for(0, |call| call.method == "createForumTopic" && call.chat_id == Some(forum)).await;
+        harness.wait_for_text(0, forum, "Game started with 5 players!").await;
+        harness.wait_for_text(0, forum, "chooses a team of 2 people").await;
+        // The controls and the roles stay in the private chats
+        let posts = harness.calls().into_iter().filter(|call| call.chat_id == Some(forum)).filter_map(|call| call.text);
+        assert!(posts.into_iter().all(|text| !text.contains("/suggest_") && !text.contains("Your role is")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_team_is_voted_with_poll_in_forum_topic() {
+        let (harness, forum) = (Harness::start().await, -1001);
+        harness.message(1, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, 1, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", game_id)).await;
+            harness.wait_for_text(0, player, "You are joined the game").await;
+        }
+        harness.forum_message(forum, 1, "/forum").await;
+        harness.wait_for_text(0, forum, "gets its own topic here").await;
+        harness.start_game(1).await;
+
+        let (seen, turn) = harness.wait_for(0, |call| call.text.as_deref().is_some_and(|text| text.contains("You chooses a team of "))).await;
+        let (crown, text) = (turn.chat_id.unwrap(), turn.text.unwrap());
+        let size = text.split_once("You chooses a team of ").unwrap().1.split_whitespace().next().unwrap();
+        for id in 0..size.parse::<usize>().unwrap() {
+            harness.message(crown, &format!("/suggest_{}", id)).await;
+        }
+        harness.message(crown, "/suggest_finish").await;
+        let (seen, _) = harness.wait_for(seen, |call| call.method == "sendPoll" && call.chat_id == Some(forum)
+            && call.text.as_deref().is_some_and(|text| text.starts_with("Vote for the team: Player1"))).await;
+        let poll_id = harness.last_poll().unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // The poll and the buttons are the same vote, the second one is not counted
+        harness.poll_answer(1, &poll_id, &[0]).await;
+        harness.wait_for_text(seen, 1, "✅ You voted Approve").await;
+        harness.poll_answer(1, &poll_id, &[]).await;
+        harness.poll_answer(1, &poll_id, &[1]).await;
+        harness.wait_for_text(seen, 1, "You have already voted for this team").await;
+        harness.wait_for_text(seen, 1, "Your answer to the poll is not counted").await;
+        harness.message(2, "/team_reject").await;
+        harness.wait_for_text(seen, 2, "✅ You voted Reject").await;
+        harness.poll_answer(2, &poll_id, &[0]).await;
+        harness.wait_for_text(seen, 2, "You have already voted for this team").await;
+        harness.wait_for_text(seen, 2, "Your answer to the poll is not counted").await;
+        for player in 3..=5 {
+            harness.poll_answer(player, &poll_id, &[1]).await;
+        }
+        let (closed, _) = harness.wait_for(seen, |call| call.method == "stopPoll" && call.chat_id == Some(forum)).await;
+        harness.wait_for_text(seen, forum, "Votes:").await;
+        assert!(!harness.calls().into_iter().skip(seen).any(|call| (3..=5).contains(&call.chat_id.unwrap_or_default())
+            && call.text.is_some_and(|text| text.contains("not counted"))));
+
+        // The answers to the closed poll are not counted for the next team
+        harness.poll_answer(3, &poll_id, &[0]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!harness.calls().into_iter().skip(closed).any(|call| call.chat_id == Some(3)
+            && call.text.is_some_and(|text| text.contains("You voted"))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_buttons_and_poll_answers_are_forwarded_to_game_owner() {
+        let harness = Harness::start().await;
+        // The instance which runs the game only records the forwarded updates
+        let forwarded = Arc::new(StdMutex::new(Vec::new()));
+        let app = Router::new()
+            .route("/internal/update", post(|State(forwarded): State<Arc<StdMutex<Vec<Value>>>>, Json(update): Json<Value>| async move {
+                forwarded.lock().unwrap().push(update);
+            }))
+            .with_state(forwarded.clone());
+        let owner = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+        let owner_url = format!("http://{}", owner.local_addr());
+        tokio::spawn(owner);
+        {
+            let ctx = &mut *harness.ctx.lock().await;
+            let config = ClusterConfig { instance_id: "this".to_string(), url: "http://this".to_string(), lease_secs: 30 };
+            ctx.cluster = Some(Cluster::new(&config, "secret".to_string()));
+            assert_eq!(ctx.storage.claim_game(7, "owner", &owner_url, Duration::from_secs(30)).unwrap(), None);
+            ctx.user_games.insert(ChatId(1), 7);
+        }
+
+        harness.callback_query(1, "/team_approve").await;
+        harness.poll_answer(1, "3", &[0]).await;
+        let forwarded = forwarded.lock().unwrap().clone();
+        assert_eq!(forwarded.len(), 2);
+        assert_eq!(forwarded[0]["callback_query"]["data"], "/team_approve");
+        assert_eq!(forwarded[1]["poll_answer"]["poll_id"], "3");
+        assert!(harness.calls().into_iter().all(|call| call.chat_id != Some(1) && call.method != "answerCallbackQuery"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_player_waits_in_lobby_while_playing_another_game() {
+        let harness = Harness::start().await;
+        let game_id = |call: Call| call.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        harness.message(1, "/new_game").await;
+        let playing = game_id(harness.wait_for_text(0, 1, "?start=").await.1);
+        for player in 2..=5 {
+            harness.message(player, &format!("/start {}", playing)).await;
+            harness.wait_for_text(0, player, "You are joined the game").await;
+        }
+        harness.message(6, "/new_game").await;
+        let waiting = game_id(harness.wait_for_text(0, 6, "?start=").await.1);
+        harness.message(2, &format!("/start {}", waiting)).await;
+        harness.wait_for_text(0, 2, &format!("You are still in game #{}, use /switch", playing)).await;
+
+        // The player of both games is seated in the started one, its messages are tagged
+        harness.start_game(1).await;
+        harness.wait_for_text(0, 2, &format!("🎲 #{}\n", playing)).await;
+        let crown = harness.wait_for(0, |call| call.text.as_deref().is_some_and(|text| text.contains("You chooses a team of"))
+            && call.chat_id.is_some_and(|chat_id| chat_id <= 5)).await.1;
+        let text = crown.text.unwrap();
+        let size = text.split_once("You chooses a team of ").unwrap().1.split_whitespace().next().unwrap();
+        for id in 0..size.parse().unwrap() {
+            harness.message(crown.chat_id.unwrap(), &format!("/suggest_{}", id)).await;
+        }
+        harness.message(crown.chat_id.unwrap(), "/suggest_finish").await;
+        harness.wait_for_text(0, 2, "/team_approve").await;
+        harness.message(2, "/team_approve").await;
+        harness.wait_for_text(0, 2, "✅ You voted").await;
+
+        let seen = harness.calls().len();
+        harness.message(2, "/switch").await;
+        harness.wait_for_text(seen, 2, &format!("Active game: #{} lobby", waiting)).await;
+        harness.callback_query(2, &format!("switch {}", playing)).await;
+        assert_eq!(harness.ctx.lock().await.user_games.get(&ChatId(2)), Some(&playing.parse().unwrap()));
+        harness.message(2, "/exit").await;
+        harness.wait_for_text(seen, 2, &format!("Game #{} is active now", waiting)).await;
+        assert!(harness.ctx.lock().await.other_games.get(ChatId(2)).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_game_from_lobby_to_result() {
+        let harness = Harness::start().await;
+        let (leader, players) = (1, [1, 2, 3, 4, 5]);
+
+        harness.message(leader, "/new_game").await;
+        let (_, invite) = harness.wait_for_text(0, leader, "?start=").await;
+        let game_id = invite.text.unwrap().split_once("?start=").unwrap().1.to_string();
+        for player in &players[1..] {
+            harness.message(*player, &format!("/start {}", game_id)).await;
+            harness.wait_for_text(0, *player, "You are joined the game").await;
+        }
+        harness.message(2, "/start_game").await;
+        harness.wait_for_text(0, 2, "Only game leader can start the game").await;
+        harness.message(leader, "/tournament 2").await;
+        harness.wait_for_text(0, leader, "The next 2 games are a tournament").await;
+        let stream = -1002;
+        harness.forum_message(stream, leader, "/commentator").await;
+        harness.wait_for_text(0, stream, "are commented here").await;
+        harness.start_game(leader).await;
+        harness.wait_for_text(0, 3, "Game started with 5 players!").await;
+        harness.message(3, "/claim merlin").await;
+        harness.wait_for_text(0, 4, "📣 <b>Player3</b> claims Merlin").await;
+        harness.message(4, "/status").await;
+        // The board is sent on every event too, only the one of /status has the claims
+        let (_, status) = harness.wait_for_text(0, 4, "\n\nClaims:\n").await;
+        assert!(status.text.unwrap().ends_with("\n\nClaims:\n📣 <b>Player3</b> claims Merlin"));
+        harness.message(4, "/note Player3 is too loud").await;
+        harness.wait_for_text(0, 4, "📝 Noted, you have 1 notes").await;
+        harness.message(4, "/notes").await;
+        harness.wait_for_text(0, 4, "📝 Your notes:\n1. Mission 1: Player3 is too loud").await;
+
+        // Every player approves the teams and supports the missions, so the good team wins
+        // the missions and the game ends with the guess of Merlin
+        let (mut seen, mut discussed) = (0, false);
+        loop {
+            let (index, call) = harness.wait_for(seen, |call| call.method == "sendMessage").await;
+            seen = index + 1;
+            let (Some(chat_id), Some(text)) = (call.chat_id, call.text) else {
+                continue;
+            };
+            if text.contains("Good team won!") || text.contains("Bad team won!") {
+                break;
+            }
+
+            if let Some(size) = text.split_once("You chooses a team of ").and_then(|(_, rest)| rest.split_whitespace().next()) {
+                for id in 0..size.parse::<usize>().unwrap() {
+                    harness.message(chat_id, &format!("/suggest_{}", id)).await;
+                }
+                harness.message(chat_id, "/suggest_finish").await;
+            } else if text.contains("/team_approve") {
+                harness.message(chat_id, "/team_approve").await;
+            } else if text.contains("/mission_success") {
+                harness.message(chat_id, "/mission_success").await;
+            } else if let Some(command) = command_with_prefix(&text, "/merlin_") {
+                harness.message(chat_id, command).await;
+            } else if text.contains("discusses who is Merlin, the guess comes in 2 min") && !discussed {
+                // Only the bad team talks before the guess
+                discussed = true;
+                let only = |call: &Call| call.text.as_deref().is_some_and(|text| text.contains("Only bad team gets your messages now"));
+                let (index, first) = harness.wait_for(seen, only).await;
+                let (_, second) = harness.wait_for(index + 1, only).await;
+                let bad_team = [first.chat_id.unwrap(), second.chat_id.unwrap()];
+                let good = *players.iter().find(|player| !bad_team.contains(player)).unwrap();
+                harness.message(good, "It is me").await;
+                harness.wait_for_text(seen, good, "Only bad team talks before the guess").await;
+                harness.message(bad_team[0], "Who is Merlin?").await;
+                harness.wait_for_text(seen, bad_team[1], "Who is Merlin?").await;
+                tokio::time::sleep(crate::discussion::GUESS_DURATION).await;
+            }
+        }
+        assert!(discussed);
+
+        let (_, summary) = harness.wait_for_text(0, leader, "📜 Game summary").await;
+        let seen = harness.calls().len();
+        harness.message(4, "/notes").await;
+        harness.wait_for_text(seen, 4, "You have no notes in this game").await;
+        assert!(summary.text.unwrap().contains("Claims:\n📣 <b>Player3</b> claims Merlin "));
+        let restart = harness.wait_for_text(0, leader, "/restart").await;
+        assert_eq!(restart.1.method, "sendMessage");
+        // The cards of every mission are shown one by one before its result
+        let (revealed, _) = harness.wait_for(0, |call| call.method == "editMessageText" && call.chat_id == Some(leader)
+            && call.text.as_deref().is_some_and(|text| text.ends_with("Revealing card 2... 🏆"))).await;
+        harness.wait_for_text(revealed, leader, "Mission results:").await;
+        harness.wait_for_text(0, stream, "🎙 🔥 2 missions in a row succeeded").await;
+        harness.wait_for_text(0, stream, "📊 Good team wins with").await;
+        let (_, standings) = harness.wait_for_text(0, 2, "Standings after game 1 of 2").await;
+        assert_eq!(standings.text.unwrap().lines().count(), players.len() + 2);
+
+        harness.message(3, "/replay").await;
+        let (seen, replay) = harness.wait_for_text(0, 3, &format!("Replay of game #{}, step 1 of", game_id)).await;
+        assert!(replay.text.unwrap().contains("chooses a team of"));
+        harness.callback_query(3, &format!("replay {} 1", game_id)).await;
+        harness.wait_for_text(seen, 3, "step 2 of").await;
+
+        harness.wait_for_text(0, 4, "How did you like the game?").await;
+        harness.message(4, "/comment Fun").await;
+        harness.wait_for_text(0, 4, "Rate the game with the buttons first").await;
+        harness.callback_query(4, &format!("rate {} 5", game_id)).await;
+        harness.wait_for_text(0, 4, "You rated the game ⭐⭐⭐⭐⭐").await;
+        harness.message(4, "/comment Fun").await;
+        harness.wait_for_text(0, 4, "Thanks for the comment!").await;
+
+        harness.message(2, "/audit").await;
+        harness.wait_for_text(0, 2, "Only game leader can see the audit").await;
+        harness.message(leader, "/audit").await;
+        let (_, audit) = harness.wait_for_text(0, leader, "Audit of game").await;
+        assert!(audit.text.unwrap().contains("Player2 voted ✅ for the team"));
+        harness.wait_for_text(0, 5, "Your role is").await;
+
+        harness.ctx.lock().await.admin.admin_ids = vec![ChatId(100)];
+        harness.message(100, "/analytics").await;
+        let (_, analytics) = harness.wait_for_text(0, 100, "Analytics of 1 finished games").await;
+        assert!(analytics.text.unwrap().contains("Good team win rate by players:\n5 players - "));
+        harness.message(100, "/admin ratings").await;
+        let (_, ratings) = harness.wait_for_text(0, 100, "⭐ 1 ratings of 1 feature sets:").await;
+        assert!(ratings.text.unwrap().contains("classic Avalon, card reveal, stickers\n  💬 Fun"));
+
+        let seen = harness.calls().len();
+        harness.message(6, "/export").await;
+        harness.wait_for_text(seen, 6, "You have no finished games yet").await;
+        harness.message(2, "/export json").await;
+        harness.wait_for(seen, |call| call.method == "sendDocument" && call.chat_id == Some(2)).await;
+    }
+}
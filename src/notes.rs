@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use teloxide::prelude::*;
+
+use crate::game::Phase;
+use crate::outbox::Outbox;
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameSession};
+
+const MAX_NOTES: usize = 30;
+const MAX_NOTE_LEN: usize = 300;
+
+// Private scratchpads of the players, only in memory and wiped at the end of the game
+pub type Notes = HashMap<ChatId, Vec<String>>;
+
+pub async fn handle_note(ctx: &mut BotCtx, message: &Message, text: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let text = text.trim();
+    if text.is_empty() {
+        ctx.bot.send_message(chat_id, "Use /note <text> to keep a private note, /notes shows them").await?;
+        return respond(());
+    }
+    let Some(session) = crate::get_game_session_without_cleanup(ctx, message) else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    session.send(SessionCommand::Note { chat_id, text: text.chars().take(MAX_NOTE_LEN).collect() });
+
+    respond(())
+}
+
+pub async fn handle_notes(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some(session) = crate::get_game_session_without_cleanup(ctx, message) else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    session.send(SessionCommand::Notes { chat_id });
+
+    respond(())
+}
+
+fn render(notes: &[String]) -> String {
+    if notes.is_empty() {
+        return "You have no notes in this game. Add one with /note <text>".to_string();
+    }
+    let mut lines = vec!["📝 Your notes:".to_string()];
+    lines.extend(notes.iter().enumerate().map(|(index, note)| format!("{}. {}", index + 1, note)));
+    lines.join("\n")
+}
+
+// Called by the session task. The note starts with the mission it was made in, or the guess of Merlin
+pub async fn add(session: &mut GameSession, chat_id: ChatId, text: String) {
+    let mut outbox = Outbox::default();
+    let info = session.info.clone().filter(|info| !session.finished && info.players.contains(&chat_id));
+    match info {
+        None => outbox.send(chat_id, "Notes are kept by the players during the game"),
+        Some(info) => {
+            let notes = session.notes.entry(chat_id).or_default();
+            if notes.len() >= MAX_NOTES {
+                outbox.send(chat_id, format!("You can keep up to {} notes in a game", MAX_NOTES));
+            } else {
+                let when = match info.cli.get_phase().await {
+                    Phase::MerlinGuess => "Guess".to_string(),
+                    _ => format!("Mission {}", info.cli.get_mission_results().await.len() + 1),
+                };
+                notes.push(format!("{}: {}", when, text));
+                outbox.send(chat_id, format!("📝 Noted, you have {} notes. See them with /notes", notes.len()));
+            }
+        }
+    }
+    outbox.flush(&session.bot).await;
+}
+
+// Called by the session task
+pub async fn send(session: &GameSession, chat_id: ChatId) {
+    let mut outbox = Outbox::default();
+    outbox.send(chat_id, render(session.notes.get(&chat_id).map(Vec::as_slice).unwrap_or_default()));
+    outbox.flush(&session.bot).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes_are_numbered() {
+        assert!(render(&[]).starts_with("You have no notes"));
+        let notes = ["Mission 1: Al approved everything".to_string(), "Mission 2: Bob lies".to_string()];
+        assert_eq!(render(&notes), "📝 Your notes:\n1. Mission 1: Al approved everything\n2. Mission 2: Bob lies");
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::{game_msg, GameSession};
+
+#[derive(Clone, Copy)]
+pub struct NudgeConfig {
+    // How long the game may wait for a player before the reminder
+    pub idle: Duration,
+    // Also tell everybody who is holding the game up
+    pub notify_group: bool,
+    // Chat action for the players the game waits for, see typing.rs
+    pub typing: bool,
+}
+
+// Called periodically by the session task
+pub async fn nudge_idle_players(session: &mut GameSession) {
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    if session.finished || session.idle_since.elapsed() < session.nudge.idle {
+        return;
+    }
+
+    // Next reminder only after another idle period
+    session.idle_since = Instant::now();
+
+    let phase = info.cli.get_phase().await;
+    let waiting = info.cli.get_waiting_for().await;
+    if waiting.is_empty() {
+        return;
+    }
+
+    tracing::info!("Nudging players {:?} in phase {:?}", waiting, phase);
+    let messages = game_msg::build_nudge_messages(&info, phase, &waiting, session.nudge.notify_group);
+    if let Err(e) = crate::send_game_messages(&session.bot, &info, messages).await {
+        tracing::warn!("Nudge error: {}", e);
+    }
+}
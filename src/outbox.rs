@@ -0,0 +1,60 @@
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardMarkup, KeyboardRemove, MessageId, ParseMode};
+
+use crate::game_msg::TelegramMessage;
+use crate::Bot;
+
+enum Outgoing {
+    Send(ChatId, String),
+    // Game messages, their text is HTML of game_msg.rs. The buttons are removed unless they are given again
+    Edit(ChatId, MessageId, String, Option<InlineKeyboardMarkup>),
+    // Also hides the reply keyboard, like the web app button of the crown holder
+    SendRemovingKeyboard(ChatId, String),
+}
+
+// Telegram requests collected while the session task handles a command and sent after it has finished
+// handling it, so a failed request can't leave the game state half updated
+#[derive(Default)]
+pub struct Outbox {
+    requests: Vec<Outgoing>,
+}
+
+impl Outbox {
+    pub fn send(&mut self, chat_id: ChatId, text: impl Into<String>) {
+        self.requests.push(Outgoing::Send(chat_id, text.into()));
+    }
+
+    pub fn send_removing_keyboard(&mut self, chat_id: ChatId, text: impl Into<String>) {
+        self.requests.push(Outgoing::SendRemovingKeyboard(chat_id, text.into()));
+    }
+
+    pub fn edit(&mut self, chat_id: ChatId, msg_id: MessageId, text: impl Into<String>) {
+        self.requests.push(Outgoing::Edit(chat_id, msg_id, text.into(), None));
+    }
+
+    pub fn edit_message(&mut self, chat_id: ChatId, msg_id: MessageId, message: TelegramMessage) {
+        self.requests.push(Outgoing::Edit(chat_id, msg_id, message.text, message.keyboard));
+    }
+
+    // Failures are only logged, the game state is already updated at this point
+    pub async fn flush(self, bot: &Bot) {
+        for request in self.requests {
+            let result = match request {
+                Outgoing::Send(chat_id, text) => bot.send_message(chat_id, text).await.map(|_| ()),
+                Outgoing::Edit(chat_id, msg_id, text, keyboard) => {
+                    let mut request = bot.edit_message_text(chat_id, msg_id, text).parse_mode(ParseMode::Html);
+                    if let Some(keyboard) = keyboard {
+                        request = request.reply_markup(keyboard);
+                    }
+                    request.await.map(|_| ())
+                }
+                Outgoing::SendRemovingKeyboard(chat_id, text) => bot.send_message(chat_id, text)
+                    .reply_markup(KeyboardRemove::new())
+                    .await.map(|_| ()),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to send queued message: {}", e);
+            }
+        }
+    }
+}
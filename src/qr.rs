@@ -0,0 +1,49 @@
+use qrcode::{Color, QrCode};
+
+// Pixels per module of the code and the width of the white border in modules,
+// scanners need the border to find the code on a phone screen
+const SCALE: usize = 8;
+const QUIET_ZONE: usize = 4;
+
+// Grayscale PNG image of the QR code
+pub fn render_png(data: &str) -> Result<Vec<u8>, String> {
+    let code = QrCode::new(data).map_err(|e| e.to_string())?;
+    let colors = code.to_colors();
+    let modules = code.width();
+    let size = (modules + 2 * QUIET_ZONE) * SCALE;
+
+    let pixels = (0..size * size)
+        .map(|pixel| {
+            let (x, y) = (pixel % size / SCALE, pixel / size / SCALE);
+            let dark = (QUIET_ZONE..QUIET_ZONE + modules).contains(&x)
+                && (QUIET_ZONE..QUIET_ZONE + modules).contains(&y)
+                && colors[(y - QUIET_ZONE) * modules + x - QUIET_ZONE] == Color::Dark;
+            if dark { 0 } else { 255 }
+        })
+        .collect::<Vec<u8>>();
+
+    let mut image = Vec::new();
+    let mut encoder = png::Encoder::new(&mut image, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&pixels).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qr_code_is_png() {
+        let image = render_png("https://t.me/avalon_bot?start=42").unwrap();
+        let decoder = png::Decoder::new(image.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.width, info.height);
+        assert_eq!(info.width as usize % SCALE, 0);
+        assert!(info.width as usize > 2 * QUIET_ZONE * SCALE);
+    }
+}
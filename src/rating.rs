@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashMap};
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId};
+
+use crate::outbox::Outbox;
+use crate::session::SessionCommand;
+use crate::stats::GameRating;
+use crate::{BotCtx, GameInfo, GameSession};
+
+// Callback data of the rating buttons starts with it, e.g. "rate 42 5"
+pub const PREFIX: &str = "rate ";
+const MAX_STARS: u8 = 5;
+const MAX_COMMENT_LEN: usize = 200;
+// The admin sees the best rated feature sets and the latest comments of each of them
+const MAX_FEATURE_SETS: usize = 15;
+const MAX_COMMENTS: usize = 3;
+
+// Ratings of the last game of the lobby, they are saved again when the player changes them
+pub struct RatingPrompt {
+    finished_at: u64,
+    features: String,
+    ratings: HashMap<ChatId, GameRating>,
+}
+
+fn data(game_id: u32, stars: u8) -> String {
+    format!("{}{} {}", PREFIX, game_id, stars)
+}
+
+fn parse(data: &str) -> Option<(u32, u8)> {
+    let (game_id, stars) = data.strip_prefix(PREFIX)?.split_once(' ')?;
+    let stars = stars.parse().ok().filter(|stars| (1..=MAX_STARS).contains(stars))?;
+    Some((game_id.parse().ok()?, stars))
+}
+
+fn keyboard(game_id: u32) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([(1..=MAX_STARS)
+        .map(|stars| InlineKeyboardButton::callback(format!("{}⭐", stars), data(game_id, stars)))
+        .collect::<Vec<_>>()])
+}
+
+// Rules and settings of the game which may make it more or less fun
+async fn features(session: &GameSession, info: &GameInfo) -> String {
+    let options = info.cli.get_options().await;
+    let mut parts = vec![crate::analytics::variant(&options, info.players.len()), info.theme.to_string()];
+    let flags = [
+        (info.narration, "narration"),
+        (!session.discussion_time.is_zero(), "discussion"),
+        (session.card_reveal, "card reveal"),
+        (session.stickers, "stickers"),
+        (session.evil_chat, "bad team chat"),
+    ];
+    parts.extend(flags.iter().filter(|(enabled, _)| *enabled).map(|(_, flag)| flag.to_string()));
+    parts.join(", ")
+}
+
+// Called at the end of every game which is not a debug one
+pub async fn ask(session: &mut GameSession, info: &GameInfo) {
+    let finished_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+    session.rating = Some(RatingPrompt { finished_at, features: features(session, info).await, ratings: HashMap::new() });
+    let keyboard = keyboard(session.id);
+    for player in &info.players {
+        let sent = session.bot.send_message(*player, "How did you like the game? Rate it from 1 to 5")
+            .reply_markup(keyboard.clone())
+            .await;
+        if let Err(e) = sent {
+            tracing::warn!("Failed to ask {} to rate the game: {}", player, e);
+        }
+    }
+}
+
+// A rating button was pressed. The session replies, so there is no popup
+pub async fn choose(ctx: &mut BotCtx, query: &CallbackQuery, data: &str) -> ResponseResult<Option<&'static str>>
+{
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some((game_id, stars)) = parse(data) else {
+        tracing::warn!("Unexpected rating button from {}: {}", chat_id, data);
+        return Ok(None);
+    };
+    let Some(session) = ctx.game_sessions.get(&game_id) else {
+        return Ok(Some("The game is closed, the rating is not saved"));
+    };
+    let message = query.message.as_ref().map(|message| message.id);
+    session.send(SessionCommand::Rate { chat_id, stars, message });
+    Ok(None)
+}
+
+pub async fn comment(ctx: &mut BotCtx, message: &Message, text: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let text = text.trim();
+    if text.is_empty() {
+        ctx.bot.send_message(chat_id, "Use /comment <text> after you rate the game").await?;
+        return respond(());
+    }
+    let Some(session) = crate::get_game_session_without_cleanup(ctx, message) else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    session.send(SessionCommand::RatingComment { chat_id, text: text.chars().take(MAX_COMMENT_LEN).collect() });
+
+    respond(())
+}
+
+async fn reply(session: &GameSession, chat_id: ChatId, text: &str) {
+    let mut outbox = Outbox::default();
+    outbox.send(chat_id, text);
+    outbox.flush(&session.bot).await;
+}
+
+// Called by the session task. The buttons are replaced with the thanks
+pub async fn rate(session: &mut GameSession, chat_id: ChatId, stars: u8, message: Option<MessageId>) {
+    let is_player = session.info.as_ref().is_some_and(|info| info.players.contains(&chat_id));
+    let Some(prompt) = session.rating.as_mut().filter(|_| is_player) else {
+        return reply(session, chat_id, "The game is not waiting for your rating anymore").await;
+    };
+    let rating = prompt.ratings.entry(chat_id).or_insert_with(|| GameRating {
+        game_id: session.id,
+        finished_at: prompt.finished_at,
+        chat_id,
+        features: prompt.features.clone(),
+        stars,
+        comment: None,
+    });
+    rating.stars = stars;
+    session.storage.save_rating(rating);
+
+    let text = format!("Thanks! You rated the game {}. Add a comment with /comment <text> if you like", "⭐".repeat(stars as usize));
+    let sent = match message {
+        Some(message) => session.bot.edit_message_text(chat_id, message, text).await.map(|_| ()),
+        None => session.bot.send_message(chat_id, text).await.map(|_| ()),
+    };
+    if let Err(e) = sent {
+        tracing::warn!("Failed to thank {} for the rating: {}", chat_id, e);
+    }
+}
+
+// Called by the session task
+pub async fn add_comment(session: &mut GameSession, chat_id: ChatId, text: String) {
+    let rating = session.rating.as_mut().and_then(|prompt| prompt.ratings.get_mut(&chat_id));
+    let Some(rating) = rating else {
+        return reply(session, chat_id, "Rate the game with the buttons first").await;
+    };
+    rating.comment = Some(text);
+    session.storage.save_rating(rating);
+    reply(session, chat_id, "Thanks for the comment!").await;
+}
+
+// Average of each feature set, the best rated first
+pub fn render(ratings: &[GameRating]) -> String {
+    if ratings.is_empty() {
+        return "No ratings yet".to_string();
+    }
+
+    let mut sets = BTreeMap::<&str, Vec<&GameRating>>::new();
+    for rating in ratings {
+        sets.entry(&rating.features).or_default().push(rating);
+    }
+    let average = |ratings: &[&GameRating]| ratings.iter().map(|rating| rating.stars as f64).sum::<f64>() / ratings.len() as f64;
+    let mut sets = sets.into_iter().collect::<Vec<_>>();
+    sets.sort_by(|(_, a), (_, b)| average(b).total_cmp(&average(a)).then(b.len().cmp(&a.len())));
+
+    let mut lines = vec![format!("⭐ {} ratings of {} feature sets:", ratings.len(), sets.len())];
+    for (features, mut ratings) in sets.into_iter().take(MAX_FEATURE_SETS) {
+        lines.push(format!("{:.1} from {} - {}", average(&ratings), ratings.len(), features));
+        ratings.sort_by_key(|rating| std::cmp::Reverse(rating.finished_at));
+        let comments = ratings.iter().filter_map(|rating| rating.comment.as_ref()).take(MAX_COMMENTS);
+        lines.extend(comments.map(|comment| format!("  💬 {}", comment)));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_button_data() {
+        assert_eq!(parse(&data(42, 5)), Some((42, 5)));
+        assert_eq!(parse("rate 42 6"), None);
+        assert_eq!(parse("rate 42 0"), None);
+        assert_eq!(parse("rate 42"), None);
+    }
+
+    #[test]
+    fn test_ratings_by_feature_set() {
+        let rating = |finished_at, features: &str, stars, comment: Option<&str>| GameRating {
+            game_id: 1,
+            finished_at,
+            chat_id: ChatId(1),
+            features: features.to_string(),
+            stars,
+            comment: comment.map(str::to_string),
+        };
+        let ratings = [
+            rating(1, "classic", 3, Some("Too long")),
+            rating(2, "classic", 4, None),
+            rating(3, "classic, narration", 5, Some("Fun story")),
+            rating(4, "classic", 5, Some("Better now")),
+        ];
+        assert_eq!(render(&ratings), "⭐ 4 ratings of 2 feature sets:\n\
+                                      5.0 from 1 - classic, narration\n  💬 Fun story\n\
+                                      4.0 from 3 - classic\n  💬 Better now\n  💬 Too long");
+        assert_eq!(render(&[]), "No ratings yet");
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use rand::seq::IteratorRandom;
+use teloxide::prelude::*;
+
+use crate::game::Phase;
+use crate::outbox::Outbox;
+use crate::{BotCtx, GameSession};
+
+// Pseudonyms of the anonymous chat, there are more of them than seats in the game
+const ANIMALS: &[&str] = &["🦊", "🐺", "🦉", "🐻", "🐱", "🐰", "🦁", "🐸", "🐢", "🦄", "🐙", "🐝"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RelayMode {
+    Off,
+    Names,
+    // Only the pseudonym of the player is shown, for the "silent Avalon" games
+    Anonymous,
+}
+
+impl fmt::Display for RelayMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelayMode::Off => write!(f, "off"),
+            RelayMode::Names => write!(f, "with names"),
+            RelayMode::Anonymous => write!(f, "anonymous"),
+        }
+    }
+}
+
+// Players talk to the bot in private chats, so their free text is relayed to the other players
+// of the game. It is allowed in the lobby and while the team is discussed, not during the missions.
+// Before the guess of Merlin only the bad team talks, see discussion.rs
+pub fn is_open(phase: Phase) -> bool {
+    matches!(phase, Phase::TeamSuggestion | Phase::TeamVote | Phase::MerlinGuess)
+}
+
+// Whole word match, so "Al" is not found in "Always"
+fn mentions(text: &str, name: &str) -> bool {
+    let (text, name) = (text.to_lowercase(), name.to_lowercase());
+    !name.is_empty() && text.match_indices(&name).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + name.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+// Stable during the game, a new player of the lobby gets a free random animal
+fn pseudonym(pseudonyms: &mut HashMap<ChatId, String>, chat_id: ChatId) -> String {
+    if let Some(animal) = pseudonyms.get(&chat_id) {
+        return animal.clone();
+    }
+    let animal = ANIMALS.iter()
+        .filter(|animal| !pseudonyms.values().any(|taken| taken == *animal))
+        .choose(&mut rand::thread_rng())
+        .map(|animal| animal.to_string())
+        .unwrap_or_else(|| format!("#{}", pseudonyms.len() + 1));
+    pseudonyms.insert(chat_id, animal.clone());
+    animal
+}
+
+// Free text of the player, relayed by the session task which knows the current phase
+pub async fn handle_chat(ctx: &mut BotCtx, message: &Message, text: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some(session) = crate::get_game_session_without_cleanup(ctx, message) else {
+        ctx.bot.send_message(chat_id, "Unknown command").await?;
+        return respond(());
+    };
+
+    let user = ctx.users.get(&chat_id);
+    let name = crate::get_display_name(ctx, chat_id);
+    let reveals_name = mentions(text, &name)
+        || user.and_then(|user| user.username.as_ref()).is_some_and(|username| mentions(text, username));
+    match session.status().relay {
+        RelayMode::Off => {
+            ctx.bot.send_message(chat_id, "Chat is turned off in this game").await?;
+        }
+        RelayMode::Anonymous if reveals_name => {
+            ctx.bot.send_message(chat_id, "The chat is anonymous, the message with your name is not relayed").await?;
+        }
+        _ => {
+            let lobby = crate::lobby_members(ctx, session.id);
+            session.send(crate::SessionCommand::Chat { chat_id, name, text: text.to_string(), lobby });
+        }
+    }
+    respond(())
+}
+
+// Called by the session task. Lobby members get the messages until the game is started
+pub async fn relay_chat(session: &mut GameSession, chat_id: ChatId, name: String, text: &str, lobby: Vec<ChatId>) {
+    let mut outbox = Outbox::default();
+    let (members, name) = match session.info.as_ref().filter(|_| !session.finished) {
+        Some(info) if !is_open(info.cli.get_phase().await) => {
+            outbox.send(chat_id, "Chat is closed until the mission is over");
+            outbox.flush(&session.bot).await;
+            return;
+        }
+        Some(info) => {
+            let members = match session.discussion.as_ref().and_then(|discussion| discussion.members()) {
+                Some(bad_team) if !bad_team.contains(&chat_id) => {
+                    outbox.send(chat_id, format!("Only {} talks before the guess", info.theme.table().bad_team));
+                    outbox.flush(&session.bot).await;
+                    return;
+                }
+                Some(bad_team) => bad_team.to_vec(),
+                None => info.players.clone(),
+            };
+            (members, info.user_names.get(&chat_id).map(|name| name.to_string()).unwrap_or(name))
+        }
+        None => (lobby, name),
+    };
+
+    let line = match session.relay {
+        RelayMode::Anonymous => format!("💬 Player {} says: {}", pseudonym(&mut session.pseudonyms, chat_id), text),
+        _ => format!("💬 {}: {}", name, text),
+    };
+    for member in members.iter().filter(|member| **member != chat_id) {
+        outbox.send(*member, line.clone());
+    }
+    outbox.flush(&session.bot).await;
+}
+
+// /evil of the bad team player in the game which allows it, the session knows the roles
+pub async fn handle_evil(ctx: &mut BotCtx, message: &Message, text: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let text = text.trim();
+    if text.is_empty() {
+        ctx.bot.send_message(chat_id, "Use /evil <text> to message the rest of the bad team").await?;
+        return respond(());
+    }
+    let Some(session) = crate::get_game_session_without_cleanup(ctx, message) else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    if !session.status().evil_chat {
+        ctx.bot.send_message(chat_id, "The bad team chat is off in this game").await?;
+        return respond(());
+    }
+    session.send(crate::SessionCommand::EvilChat { chat_id, text: text.to_string() });
+    respond(())
+}
+
+// Called by the session task. Only the pseudonym of the sender is shown, the same for the whole
+// game, so the chat doesn't reveal Oberon to the rest of the team
+pub async fn relay_evil(session: &mut GameSession, chat_id: ChatId, text: &str) {
+    let mut outbox = Outbox::default();
+    match session.info.clone().filter(|_| !session.finished) {
+        None => outbox.send(chat_id, "The bad team chat is open only during the game"),
+        Some(info) => {
+            let roles = info.cli.get_player_roles().await;
+            let bad_team = info.players.iter()
+                .zip(&roles)
+                .filter(|(_, role)| !role.is_good())
+                .map(|(player, _)| *player)
+                .collect::<Vec<_>>();
+            if bad_team.contains(&chat_id) {
+                let line = format!("😈 {}: {}", pseudonym(&mut session.evil_pseudonyms, chat_id), text);
+                for member in bad_team.iter().filter(|member| **member != chat_id && !info.bots.contains(member)) {
+                    outbox.send(*member, line.clone());
+                }
+            } else {
+                outbox.send(chat_id, format!("Only {} can use /evil", info.theme.table().bad_team));
+            }
+        }
+    }
+    outbox.flush(&session.bot).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_is_open_in_discussion() {
+        assert!(is_open(Phase::TeamSuggestion));
+        assert!(is_open(Phase::TeamVote));
+        assert!(!is_open(Phase::Mission));
+        assert!(!is_open(Phase::MermaidWord));
+    }
+
+    #[test]
+    fn test_pseudonyms_are_stable_and_unique() {
+        let mut pseudonyms = HashMap::new();
+        let fox = pseudonym(&mut pseudonyms, ChatId(1));
+        assert_eq!(pseudonym(&mut pseudonyms, ChatId(1)), fox);
+        for id in 2..=ANIMALS.len() as i64 + 1 {
+            assert_ne!(pseudonym(&mut pseudonyms, ChatId(id)), fox);
+        }
+        let mut taken = pseudonyms.values().collect::<Vec<_>>();
+        taken.sort();
+        taken.dedup();
+        assert_eq!(taken.len(), ANIMALS.len() + 1);
+    }
+
+    #[test]
+    fn test_name_is_found_as_word() {
+        assert!(mentions("I am Alex, trust me", "alex"));
+        assert!(mentions("Merlin fan is good", "Merlin fan"));
+        assert!(!mentions("Always approve", "Al"));
+        assert!(!mentions("hello", ""));
+    }
+}
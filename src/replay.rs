@@ -0,0 +1,224 @@
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode};
+
+use crate::game::{self, GameEvent, History, MissionVote};
+use crate::game_msg::{self, EventContext, GameMessage};
+use crate::journal::LogEntry;
+use crate::outbox::Outbox;
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameInfo, GameSession};
+
+// Callback data of the Prev and Next buttons starts with it, e.g. "replay 42 3" for the step 3 of game 42
+pub const PREFIX: &str = "replay ";
+
+fn data(game_id: u32, step: usize) -> String {
+    format!("{}{} {}", PREFIX, game_id, step)
+}
+
+fn parse(data: &str) -> Option<(u32, usize)> {
+    let (game_id, step) = data.strip_prefix(PREFIX)?.split_once(' ')?;
+    Some((game_id.parse().ok()?, step.parse().ok()?))
+}
+
+// Event of the game with the state of the board right after it
+struct Step {
+    event: GameEvent,
+    context: EventContext,
+}
+
+// Events of the last finished game in the log. The missions and the vote attempts are counted
+// the same way the engine does, so the board of every step is as the players saw it
+fn steps(log: &[LogEntry], history: &History) -> Vec<Step> {
+    let mut finished = Vec::new();
+    let mut steps = Vec::new();
+    let (mut roles, mut max_try_count) = (Vec::new(), game::MAX_TRY_COUNT);
+    let (mut missions, mut try_count) = (Vec::<MissionVote>::new(), 1);
+    for entry in log {
+        let event = match entry {
+            LogEntry::Started(info) => {
+                // The engine state is logged again after a failure, the game goes on
+                if steps.is_empty() {
+                    missions.clear();
+                    try_count = 1;
+                }
+                roles = info.roles().to_vec();
+                max_try_count = info.options().max_try_count;
+                continue;
+            }
+            LogEntry::Event(event) => event,
+            LogEntry::Move(_) => continue,
+        };
+
+        let mut context = EventContext { max_try_count, ..Default::default() };
+        match event {
+            GameEvent::TeamApproved(_) => try_count = 1,
+            GameEvent::TeamRejected(count) => try_count = *count,
+            GameEvent::MissionResult(votes) => {
+                let fails = votes.iter().filter(|vote| **vote == MissionVote::Fail).count();
+                let players = roles.len();
+                missions.push(match fails < game::fails_required(missions.len() + 1, players) {
+                    true => MissionVote::Success,
+                    false => MissionVote::Fail,
+                });
+            }
+            GameEvent::GameResult(_) => {
+                context.roles = roles.clone();
+                context.history = history.clone();
+            }
+            _ => {}
+        }
+        context.missions = missions.clone();
+        context.try_count = try_count;
+        steps.push(Step { event: event.clone(), context });
+
+        if matches!(event, GameEvent::GameResult(_)) {
+            finished = std::mem::take(&mut steps);
+        }
+    }
+    finished
+}
+
+// The messages the players got at the step without the buttons. Secrets like what the Mermaid
+// holder saw are shown too, the game is over
+fn render(game_id: u32, info: &GameInfo, steps: &[Step], index: usize) -> String {
+    let step = &steps[index];
+    let mut parts = vec![format!("🎞 Replay of game #{}, step {} of {}", game_id, index + 1, steps.len())];
+    // The mission result has the board in it
+    if !matches!(step.event, GameEvent::MissionResult(_)) {
+        let context = &step.context;
+        let theme = info.theme.table();
+        parts.push(game_msg::mission_board(theme, info.players.len(), &context.missions, context.try_count, context.max_try_count));
+    }
+    parts.extend(game_msg::build_message_for_event(info, &step.context, step.event.clone())
+        .into_iter()
+        .filter_map(|message| match message {
+            GameMessage::Notification(notification)
+            | GameMessage::Flavor(notification)
+            | GameMessage::Secret(notification) => Some(notification.message),
+            GameMessage::ControlMessage(_) => None,
+        }));
+    parts.join("\n\n")
+}
+
+fn keyboard(game_id: u32, steps: usize, index: usize) -> InlineKeyboardMarkup {
+    let mut buttons = Vec::new();
+    if index > 0 {
+        buttons.push(InlineKeyboardButton::callback("◀️ Prev", data(game_id, index - 1)));
+    }
+    if index + 1 < steps {
+        buttons.push(InlineKeyboardButton::callback("Next ▶️", data(game_id, index + 1)));
+    }
+    InlineKeyboardMarkup::new([buttons])
+}
+
+pub async fn handle(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let Some(session) = crate::get_game_session_without_cleanup(ctx, message) else {
+        return crate::send_not_in_game(&ctx.bot, message.chat.id).await;
+    };
+    session.send(SessionCommand::Replay { chat_id: message.chat.id, step: 0, message: None });
+
+    respond(())
+}
+
+// Prev or Next was pressed. Returns the text of the popup
+pub async fn turn(ctx: &mut BotCtx, query: &CallbackQuery, data: &str) -> ResponseResult<Option<&'static str>>
+{
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some((game_id, step)) = parse(data) else {
+        tracing::warn!("Unexpected replay button from {}: {}", chat_id, data);
+        return Ok(None);
+    };
+    let session = ctx.user_games.get(&chat_id)
+        .filter(|user_game| **user_game == game_id)
+        .and_then(|game_id| ctx.game_sessions.get(game_id));
+    let Some(session) = session else {
+        return Ok(Some("The replay is not available anymore"));
+    };
+    let message = query.message.as_ref().map(|message| message.id);
+    session.send(SessionCommand::Replay { chat_id, step, message });
+    Ok(None)
+}
+
+async fn notice(session: &GameSession, chat_id: ChatId, text: &str) {
+    let mut outbox = Outbox::default();
+    outbox.send(chat_id, text);
+    outbox.flush(&session.bot).await;
+}
+
+// Called by the session task. The step replaces the previous one in the message, if there is one
+pub async fn show(session: &GameSession, chat_id: ChatId, step: usize, message: Option<MessageId>) {
+    let Some(info) = session.info.as_ref().filter(|_| session.finished) else {
+        return notice(session, chat_id, "Replay is available after the end of the game").await;
+    };
+    let log = match session.storage.load_log(session.id) {
+        Ok(log) => log,
+        Err(e) => {
+            tracing::warn!("Failed to load the log of game {}: {}", session.id, e);
+            return notice(session, chat_id, "Replay is not available now").await;
+        }
+    };
+    let steps = steps(&log, &info.cli.get_history().await);
+    if steps.is_empty() {
+        return notice(session, chat_id, "Replay is not available for this game").await;
+    }
+
+    let step = step.min(steps.len() - 1);
+    let text = render(session.id, info, &steps, step);
+    let keyboard = keyboard(session.id, steps.len(), step);
+    let sent = match message {
+        Some(message) => session.bot.edit_message_text(chat_id, message, text)
+            .parse_mode(ParseMode::Html).reply_markup(keyboard).await.map(|_| ()),
+        None => session.bot.send_message(chat_id, text)
+            .parse_mode(ParseMode::Html).reply_markup(keyboard).await.map(|_| ()),
+    };
+    if let Err(e) = sent {
+        tracing::warn!("Failed to send the replay of game {} to {}: {}", session.id, chat_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameResult, TeamVote};
+
+    #[tokio::test]
+    async fn test_steps_of_last_finished_game() {
+        let (_, cli) = game::Game::setup(5);
+        let started = LogEntry::Started(cli.snapshot().await);
+        let event = LogEntry::Event;
+        let log = vec![
+            started.clone(),
+            event(GameEvent::Turn(0, 2)),
+            event(GameEvent::GameResult(GameResult::BadWins)),
+            started.clone(),
+            event(GameEvent::Turn(0, 2)),
+            event(GameEvent::TeamSuggested(vec![0, 1])),
+            LogEntry::Move(crate::journal::Move::TeamVote(0, TeamVote::Reject)),
+            event(GameEvent::TeamRejected(2)),
+            // The engine failed and continued from the snapshot
+            started.clone(),
+            event(GameEvent::Turn(1, 2)),
+            event(GameEvent::TeamApproved(vec![0, 1])),
+            event(GameEvent::MissionResult(vec![MissionVote::Success, MissionVote::Fail])),
+            event(GameEvent::GameResult(GameResult::GoodWins)),
+            started.clone(),
+            event(GameEvent::Turn(0, 2)),
+        ];
+
+        let steps = steps(&log, &History::default());
+        assert_eq!(steps.len(), 7);
+        assert_eq!(steps[3].context.try_count, 2);
+        assert_eq!(steps[5].context.missions, vec![MissionVote::Fail]);
+        assert_eq!(steps[5].context.try_count, 1);
+        assert_eq!(steps[6].event, GameEvent::GameResult(GameResult::GoodWins));
+        assert_eq!(steps[6].context.roles.len(), 5);
+    }
+
+    #[test]
+    fn test_button_data() {
+        assert_eq!(parse(&data(42, 3)), Some((42, 3)));
+        assert_eq!(parse("replay 42"), None);
+        assert_eq!(parse("replay x 1"), None);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+// Reports over the limit are only logged, so a broken Telegram connection doesn't flood the tracker
+const MAX_REPORTS_PER_MINUTE: u32 = 30;
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReportsConfig {
+    // DSN of the Sentry project, e.g. "https://KEY@o0.ingest.sentry.io/42"
+    pub sentry_dsn: Option<String>,
+    // Any endpoint which takes the report as JSON
+    pub webhook_url: Option<String>,
+}
+
+impl ReportsConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(dsn) = &self.sentry_dsn {
+            SentryDsn::parse(dsn)?;
+        }
+        if self.sentry_dsn.is_none() && self.webhook_url.is_none() {
+            return Err("Set sentry_dsn or webhook_url in [reports]".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Error logged by the bot with the fields of the spans it happened in, e.g. the game and the seat
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Report {
+    pub message: String,
+    pub target: String,
+    pub context: BTreeMap<String, String>,
+}
+
+impl Report {
+    fn sentry_event(&self) -> serde_json::Value {
+        json!({
+            "message": self.message,
+            "level": "error",
+            "logger": self.target,
+            "platform": "other",
+            "tags": self.context,
+        })
+    }
+}
+
+struct SentryDsn {
+    key: String,
+    store_url: String,
+}
+
+impl SentryDsn {
+    // The DSN is "<scheme>://<key>@<host>[:<port>][/<path>]/<project>"
+    fn parse(dsn: &str) -> Result<Self, String> {
+        let url = reqwest::Url::parse(dsn).map_err(|e| format!("Invalid sentry_dsn: {}", e))?;
+        let host = url.host_str().ok_or("sentry_dsn has no host")?;
+        let (path, project) = url.path().rsplit_once('/').unwrap_or_default();
+        if url.username().is_empty() || project.is_empty() {
+            return Err("sentry_dsn must have the key and the project".to_string());
+        }
+        let port = url.port().map(|port| format!(":{}", port)).unwrap_or_default();
+        Ok(Self {
+            key: url.username().to_string(),
+            store_url: format!("{}://{}{}{}/api/{}/store/", url.scheme(), host, port, path, project),
+        })
+    }
+
+    fn auth(&self) -> String {
+        format!("Sentry sentry_version=7, sentry_client=avalon_tg_bot/{}, sentry_key={}", env!("CARGO_PKG_VERSION"), self.key)
+    }
+}
+
+// Values of the span or event fields by their names
+#[derive(Default)]
+struct Fields(BTreeMap<String, String>);
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+// Sends the events of the error level to the reporting task, the rest of the log is not touched
+pub struct ReportLayer {
+    reports: mpsc::UnboundedSender<Report>,
+}
+
+impl<S> Layer<S> for ReportLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    // The game and the seat of the handler span are recorded after it is created
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        if *metadata.level() != Level::ERROR {
+            return;
+        }
+
+        let mut context = BTreeMap::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(fields) = span.extensions().get::<Fields>() {
+                    context.extend(fields.0.clone());
+                }
+            }
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        // Errors of the handlers are logged by #[instrument(err)] without the message
+        let message = fields.0.remove("message")
+            .or_else(|| fields.0.get("error").cloned())
+            .unwrap_or_default();
+        context.extend(fields.0.into_iter().filter(|(name, _)| !name.starts_with("log.")));
+
+        let _ = self.reports.send(Report { message, target: metadata.target().to_string(), context });
+    }
+}
+
+// Starts the task which sends the reports one by one, so logging an error never waits for the network
+pub fn spawn(config: ReportsConfig) -> Result<ReportLayer, String> {
+    let sentry = config.sentry_dsn.as_deref().map(SentryDsn::parse).transpose()?;
+    let (reports, mut received) = mpsc::unbounded_channel::<Report>();
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut window = (Instant::now(), 0);
+        while let Some(report) = received.recv().await {
+            if window.0.elapsed() >= Duration::from_secs(60) {
+                window = (Instant::now(), 0);
+            }
+            window.1 += 1;
+            if window.1 > MAX_REPORTS_PER_MINUTE {
+                continue;
+            }
+
+            // Failures are logged as warnings, so they are not reported again
+            if let Some(sentry) = &sentry {
+                let result = client.post(&sentry.store_url)
+                    .header("X-Sentry-Auth", sentry.auth())
+                    .json(&report.sentry_event())
+                    .send().await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    tracing::warn!("Failed to report the error to Sentry: {}", e);
+                }
+            }
+            if let Some(url) = &config.webhook_url {
+                let result = client.post(url)
+                    .json(&report)
+                    .send().await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    tracing::warn!("Failed to report the error to the webhook: {}", e);
+                }
+            }
+        }
+    });
+    Ok(ReportLayer { reports })
+}
+
+// Panics are logged as errors in the span they happened in, so they are reported with the game
+pub fn capture_panics() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic| {
+        tracing::error!("{}", panic);
+        default(panic);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn test_sentry_dsn_is_parsed() {
+        let dsn = SentryDsn::parse("https://abc@o1.ingest.sentry.io/42").unwrap();
+        assert_eq!(dsn.key, "abc");
+        assert_eq!(dsn.store_url, "https://o1.ingest.sentry.io/api/42/store/");
+        let dsn = SentryDsn::parse("http://abc@localhost:9000/sentry/7").unwrap();
+        assert_eq!(dsn.store_url, "http://localhost:9000/sentry/api/7/store/");
+        assert!(SentryDsn::parse("https://o1.ingest.sentry.io/42").is_err());
+        assert!(SentryDsn::parse("https://abc@o1.ingest.sentry.io/").is_err());
+    }
+
+    #[test]
+    fn test_error_is_reported_with_game_context() {
+        let (reports, mut received) = mpsc::unbounded_channel();
+        let subscriber = tracing_subscriber::registry().with(ReportLayer { reports });
+        tracing::subscriber::with_default(subscriber, || {
+            let session = tracing::info_span!("session", game = 12);
+            let _session = session.enter();
+            let player = tracing::info_span!("player", chat = 42, seat = tracing::field::Empty);
+            player.record("seat", 3);
+            let _player = player.enter();
+            tracing::warn!("Not reported");
+            tracing::error!("Engine stopped");
+        });
+
+        let report = received.try_recv().unwrap();
+        assert_eq!(report.message, "Engine stopped");
+        let context = report.context.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>();
+        assert_eq!(context, ["chat=42", "game=12", "seat=3"]);
+        assert!(received.try_recv().is_err());
+    }
+}
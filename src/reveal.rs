@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::types::MessageId;
+use tokio::time::Instant;
+
+use crate::game::{GameEvent, MissionVote};
+use crate::theme::ThemeTable;
+use crate::GameSession;
+
+// Pause after each card, the result of the mission comes after the last one
+pub const CARD_DELAY: Duration = Duration::from_millis(1500);
+
+// Cards of the mission shown one by one before its result. The next events of the game wait for it
+pub struct Reveal {
+    // Event with the result of the mission, it is handled when every card is shown
+    event: GameEvent,
+    shown: usize,
+    next_at: Instant,
+    messages: Vec<(ChatId, MessageId)>,
+}
+
+impl Reveal {
+    // When the session task should call on_deadline
+    pub fn deadline(&self) -> Instant {
+        self.next_at
+    }
+}
+
+fn text(theme: &ThemeTable, cards: &[MissionVote]) -> String {
+    cards.iter()
+        .enumerate()
+        .map(|(index, card)| format!("Revealing card {}... {}", index + 1, theme.mission(card)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Returns true when the event is held back until the cards are shown. Muted players only get the result
+pub async fn start(session: &mut GameSession, event: &GameEvent) -> bool {
+    let GameEvent::MissionResult(cards) = event else {
+        return false;
+    };
+    let Some(info) = session.info.clone().filter(|_| session.card_reveal && !cards.is_empty()) else {
+        return false;
+    };
+
+    let messages = crate::send_unmuted(&session.bot, &info, &text(info.theme.table(), &cards[..1])).await;
+    session.reveal = Some(Reveal { event: event.clone(), shown: 1, next_at: Instant::now() + CARD_DELAY, messages });
+    true
+}
+
+// Called by the session task: shows the next card, then the result of the mission
+pub async fn on_deadline(session: &mut GameSession) {
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    let Some(reveal) = session.reveal.as_mut() else {
+        return;
+    };
+    let GameEvent::MissionResult(cards) = &reveal.event else {
+        return;
+    };
+
+    if reveal.shown < cards.len() {
+        reveal.shown += 1;
+        reveal.next_at = Instant::now() + CARD_DELAY;
+        let text = text(info.theme.table(), &cards[..reveal.shown]);
+        for (chat_id, message_id) in &reveal.messages {
+            if let Err(e) = session.bot.edit_message_text(*chat_id, *message_id, &text).await {
+                tracing::warn!("Failed to reveal the card to {}: {}", chat_id, e);
+            }
+        }
+    } else if let Some(reveal) = session.reveal.take() {
+        crate::present_game_event(session, &reveal.event, &info).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::Theme;
+
+    #[test]
+    fn test_cards_text() {
+        let cards = [MissionVote::Success, MissionVote::Fail];
+        assert_eq!(text(Theme::Classic.table(), &cards), "Revealing card 1... 🏆\nRevealing card 2... 🗡️");
+    }
+}
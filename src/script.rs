@@ -0,0 +1,115 @@
+use crate::commands::{self, GameAction};
+use crate::game::{self, Phase, ID};
+use crate::journal::Move;
+
+// Sends the commands of the script to the engine after the same checks the bot does.
+// Each line is "<seat> <command>", e.g. "2 /team_approve". Used by the fuzz target in fuzz/
+// to show that no command text can crash the engine. Returns the phase the game ended up in
+pub async fn run(players: usize, script: &str) -> Phase {
+    let (mut game, mut cli) = game::Game::setup(players);
+    let engine = game.start();
+
+    let commands = async {
+        // Team of the crown holder selected with /suggest_N
+        let mut selection: Vec<ID> = Vec::new();
+        // Seats which acted in the phase. The engine takes every move it gets and leaves the phase
+        // only when it handles the last one, so repeated moves are stopped here like in the bot
+        let mut acted: (Phase, Vec<ID>) = (Phase::Finished, Vec::new());
+        for line in script.lines() {
+            let Some((seat, text)) = line.split_once(' ') else {
+                continue;
+            };
+            let Ok(seat) = seat.parse::<ID>() else {
+                continue;
+            };
+            if seat as usize >= players {
+                continue;
+            }
+            let Ok(action) = GameAction::parse(text) else {
+                continue;
+            };
+            // Phase is read before the checks, the engine may leave it in the meantime
+            let phase = cli.get_phase().await;
+            if acted.0 != phase {
+                acted = (phase, Vec::new());
+            }
+            if acted.1.contains(&seat) || !commands::is_allowed(&cli, seat, &action).await {
+                continue;
+            }
+
+            // Engine indexes the players by the chosen ids, the bot rejects unknown ones
+            let targets = match &action {
+                GameAction::ToggleSuggestion(id) | GameAction::MermaidCheck(id) | GameAction::NameMerlin(id) => vec![*id],
+                GameAction::SuggestTeam(team) => team.clone(),
+                _ => Vec::new(),
+            };
+            if targets.iter().any(|id| *id as usize >= players) {
+                continue;
+            }
+
+            let chosen = match action {
+                GameAction::ToggleSuggestion(id) => {
+                    match selection.iter().position(|selected| *selected == id) {
+                        Some(pos) => {
+                            selection.remove(pos);
+                        }
+                        None => selection.push(id),
+                    }
+                    continue;
+                }
+                GameAction::FinishSuggestion => Move::SuggestTeam(seat, selection.clone()),
+                GameAction::SuggestTeam(team) => Move::SuggestTeam(seat, team),
+                GameAction::TeamVote(vote) => Move::TeamVote(seat, vote),
+                GameAction::MissionVote(vote) => Move::Mission(seat, vote),
+                GameAction::MermaidCheck(id) => Move::MermaidCheck(id),
+                GameAction::MermaidWord(word) => Move::MermaidWord(word),
+                GameAction::NameMerlin(id) => Move::NameMerlin(id),
+            };
+            if chosen.apply(&mut cli).await.is_ok() {
+                acted.1.push(seat);
+                if let Move::SuggestTeam(..) = chosen {
+                    selection.clear();
+                }
+            }
+            // Lets the engine handle the move before the next command is checked
+            tokio::task::yield_now().await;
+        }
+    };
+
+    // Engine errors are reported to the players, only panics are bugs here. The engine is polled
+    // first, so it handles the last move before the end of the script is noticed
+    tokio::select! {
+        biased;
+        _ = engine => {}
+        _ = commands => {}
+    }
+    cli.get_phase().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_malformed_commands() {
+        let phase = run(5, "0 /suggest_\n0 /mermaid_999\n1 /team_x\n9 /team_approve\n0 /merlin_255\n0 /suggest_255\n\n0\nx /say_good").await;
+        assert_eq!(phase, Phase::TeamSuggestion);
+
+        // Everybody sends every command, so the game goes through all of its phases
+        let mut script = String::new();
+        for _ in 0..50 {
+            for seat in 0..8 {
+                // The selection grows by one after each finish, so every team size is suggested
+                for command in ["/suggest_0", "/suggest_finish", "/suggest_7", "/suggest_1", "/suggest_finish",
+                                "/suggest_2", "/suggest_finish", "/suggest_3", "/suggest_finish",
+                                "/suggest_4", "/suggest_finish", "/team_approve", "/mission_success",
+                                "/mission_fail", "/mermaid_6", "/mermaid_1", "/say_bad", "/merlin_9", "/merlin_0"] {
+                    script.push_str(&format!("{} {}\n", seat, command));
+                }
+            }
+        }
+        for players in game::MIN_PLAYERS..=game::MAX_PLAYERS {
+            assert_eq!(run(players, &script).await, Phase::Finished, "{} players", players);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use rand::seq::SliceRandom;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::session::SessionCommand;
+use crate::{users, BotCtx};
+
+// Callback data of the seat buttons starts with it, e.g. "seats up 2"
+pub const PREFIX: &str = "seats ";
+
+// Button of the /seats menu, the numbers are the seats counted from zero
+#[derive(Clone, Debug, PartialEq)]
+pub enum SeatMove {
+    Up(usize),
+    Down(usize),
+    Shuffle,
+}
+
+impl SeatMove {
+    fn data(&self) -> String {
+        let name = match self {
+            SeatMove::Up(seat) => format!("up {}", seat),
+            SeatMove::Down(seat) => format!("down {}", seat),
+            SeatMove::Shuffle => "shuffle".to_string(),
+        };
+        format!("{}{}", PREFIX, name)
+    }
+
+    fn parse(data: &str) -> Option<Self> {
+        let name = data.strip_prefix(PREFIX)?;
+        match name.split_once(' ') {
+            Some(("up", seat)) => seat.parse().ok().map(SeatMove::Up),
+            Some(("down", seat)) => seat.parse().ok().map(SeatMove::Down),
+            None if name == "shuffle" => Some(SeatMove::Shuffle),
+            _ => None,
+        }
+    }
+}
+
+// Seats of the lobby members in the order chosen by the leader. The members who joined
+// after the last change sit at the end. The crown goes around the table in this order
+pub fn arrange(members: &[ChatId], seating: &[ChatId]) -> Vec<ChatId> {
+    let mut newcomers = members.iter()
+        .filter(|member| !seating.contains(member))
+        .cloned()
+        .collect::<Vec<_>>();
+    newcomers.sort_by_key(|member| member.0);
+
+    seating.iter()
+        .filter(|seated| members.contains(seated))
+        .cloned()
+        .chain(newcomers)
+        .collect()
+}
+
+// Moves past the ends of the table are ignored
+pub fn apply(seats: &mut [ChatId], seat_move: &SeatMove) {
+    match *seat_move {
+        SeatMove::Up(seat) if seat > 0 && seat < seats.len() => seats.swap(seat - 1, seat),
+        SeatMove::Down(seat) if seat + 1 < seats.len() => seats.swap(seat, seat + 1),
+        SeatMove::Shuffle => seats.shuffle(&mut rand::thread_rng()),
+        _ => {}
+    }
+}
+
+fn text(seats: &[ChatId], ctx: &BotCtx) -> String {
+    let names = users::disambiguate(seats, &ctx.users);
+    let lines = seats.iter()
+        .enumerate()
+        .map(|(seat, chat_id)| format!("{}. {}", seat + 1, names[chat_id]))
+        .collect::<Vec<_>>();
+    format!("Seats around the table, the crown goes down the list:\n{}", lines.join("\n"))
+}
+
+fn keyboard(seats: &[ChatId]) -> InlineKeyboardMarkup {
+    let mut rows = (0..seats.len())
+        .map(|seat| vec![
+            InlineKeyboardButton::callback(format!("⬆️ {}", seat + 1), SeatMove::Up(seat).data()),
+            InlineKeyboardButton::callback(format!("⬇️ {}", seat + 1), SeatMove::Down(seat).data()),
+        ])
+        .collect::<Vec<_>>();
+    rows.push(vec![InlineKeyboardButton::callback("🔀 Shuffle", SeatMove::Shuffle.data())]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+// Everybody in the lobby sees the seats, only the leader gets the buttons before the start
+pub async fn show(ctx: &mut BotCtx, message: &Message) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some(game_id) = ctx.user_games.get(&chat_id).cloned() else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    let Some(session) = ctx.game_sessions.get(&game_id) else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+
+    let status = session.status();
+    let seats = arrange(&crate::lobby_members(ctx, game_id), &status.seating);
+    let mut request = ctx.bot.send_message(chat_id, text(&seats, ctx));
+    if crate::lobby_settings(ctx, chat_id).is_some() {
+        request = request.reply_markup(keyboard(&seats));
+    }
+    request.await?;
+    respond(())
+}
+
+// Button of the /seats menu was pressed, the menu is edited with the new seats.
+// Returns the text of the popup
+pub async fn change(ctx: &mut BotCtx, query: &CallbackQuery, data: &str) -> ResponseResult<Option<&'static str>>
+{
+    let Some(seat_move) = SeatMove::parse(data) else {
+        tracing::warn!("Unexpected seat button from {}: {}", query.from.id, data);
+        return Ok(None);
+    };
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some((session, _)) = crate::lobby_settings(ctx, chat_id) else {
+        return Ok(Some("Only game leader can change the seats before the start"));
+    };
+
+    let mut seats = arrange(&crate::lobby_members(ctx, session.id), &session.status().seating);
+    apply(&mut seats, &seat_move);
+    session.send(SessionCommand::SetSeating(seats.clone()));
+
+    if let Some(message) = &query.message {
+        ctx.bot.edit_message_text(message.chat.id, message.id, text(&seats, ctx))
+            .reply_markup(keyboard(&seats))
+            .await?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seats_keep_the_chosen_order() {
+        let members = [ChatId(3), ChatId(1), ChatId(4), ChatId(2)];
+        assert_eq!(arrange(&members, &[]), vec![ChatId(1), ChatId(2), ChatId(3), ChatId(4)]);
+        // The player 5 has left, the players 1 and 2 joined after the change
+        assert_eq!(arrange(&members, &[ChatId(4), ChatId(5), ChatId(3)]), vec![ChatId(4), ChatId(3), ChatId(1), ChatId(2)]);
+
+        let mut seats = vec![ChatId(1), ChatId(2), ChatId(3)];
+        apply(&mut seats, &SeatMove::Up(2));
+        assert_eq!(seats, vec![ChatId(1), ChatId(3), ChatId(2)]);
+        apply(&mut seats, &SeatMove::Up(0));
+        apply(&mut seats, &SeatMove::Down(2));
+        assert_eq!(seats, vec![ChatId(1), ChatId(3), ChatId(2)]);
+        apply(&mut seats, &SeatMove::Down(0));
+        assert_eq!(seats, vec![ChatId(3), ChatId(1), ChatId(2)]);
+    }
+
+    #[test]
+    fn test_seat_buttons_are_parsed() {
+        for seat_move in [SeatMove::Up(1), SeatMove::Down(0), SeatMove::Shuffle] {
+            assert_eq!(SeatMove::parse(&seat_move.data()), Some(seat_move));
+        }
+        assert_eq!(SeatMove::parse("seats up x"), None);
+        assert_eq!(SeatMove::parse("settings mermaid"), None);
+    }
+}
@@ -0,0 +1,589 @@
+use std::collections::{HashMap, HashSet};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::FutureExt;
+use teloxide::prelude::*;
+use teloxide::types::{InputFile, MessageId, ParseMode};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::audit::AuditAccess;
+use crate::commands::GameAction;
+use crate::game::{self, GameEvent, TeamVote};
+use crate::journal::LogEntry;
+use crate::outbox::Outbox;
+use crate::relay::RelayMode;
+use crate::theme::Theme;
+use crate::timeout::TimeoutSettings;
+use crate::transcript::{self, TranscriptFormat};
+use crate::{claims, debug, discussion, feedback, game_msg, house_rules, journal, notes, nudge, rating, relay, replay, reveal, timeout, typing, unreachable, vote_poll, whoami, GameSession};
+
+// How often the task checks whether players should be reminded or timed out
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// Failures the game is brought back from before it is given up as crashed
+pub const MAX_RECOVERIES: u32 = 3;
+
+pub enum SessionCommand {
+    // Starts the game with the lobby members, also used by /restart
+    Start { players: Vec<ChatId>, user_names: HashMap<ChatId, Arc<str>> },
+    // Starts the game of the leader with the AI in the other seats, see debug.rs
+    DebugStart { players: usize, name: String },
+    // Game action like /team_approve or /suggest_2
+    Action { chat_id: ChatId, action: GameAction },
+    // Answer to the team vote poll in the topic of the game, see vote_poll.rs
+    PollVote { chat_id: ChatId, poll_id: String, vote: TeamVote },
+    Transcript { chat_id: ChatId, format: TranscriptFormat },
+    // Step of the finished game, shown in the message of the previous step if there is one, see replay.rs
+    Replay { chat_id: ChatId, step: usize, message: Option<MessageId> },
+    // Stars of the finished game from the player, the buttons are in the message, see rating.rs
+    Rate { chat_id: ChatId, stars: u8, message: Option<MessageId> },
+    // /comment of the player who rated the game
+    RatingComment { chat_id: ChatId, text: String },
+    // The leader chose AI for the player who can't get the messages, see unreachable.rs
+    ReplaceWithAi(ChatId),
+    // Timed moves of the finished game, see audit.rs
+    Audit { chat_id: ChatId },
+    // Board of the running or finished game
+    Status { chat_id: ChatId },
+    // Seat, role and awaited action of the player, see whoami.rs
+    WhoAmI { chat_id: ChatId },
+    // /feedback of the player for the admin chats, the phase of the game is added to it
+    Feedback { chat_id: ChatId, name: String, text: String, to: Vec<ChatId> },
+    // Free text of the player for the others, the lobby members get it before the start
+    Chat { chat_id: ChatId, name: String, text: String, lobby: Vec<ChatId> },
+    // Message of the bad team chat, see relay.rs
+    EvilChat { chat_id: ChatId, text: String },
+    // Role or mermaid result claimed by the player, see claims.rs
+    Claim { chat_id: ChatId, text: String },
+    // Private notes of the player, see notes.rs
+    Note { chat_id: ChatId, text: String },
+    Notes { chat_id: ChatId },
+    SetTimeout(TimeoutSettings),
+    // Rules of the next game chosen in the /settings menu
+    SetOptions(game::GameOptions),
+    SetRelay(RelayMode),
+    SetEvilChat(bool),
+    SetDiscussion(Duration),
+    SetTheme(Theme),
+    SetNarration(bool),
+    SetCardReveal(bool),
+    SetStickers(bool),
+    SetAudit(AuditAccess),
+    // Description of the lobby set with /set_invite, None clears it
+    SetInvite(Option<String>),
+    // Rules of the table set with /house_rules, None clears them
+    SetHouseRules(Option<String>),
+    // Group with topics chosen with /forum, None stops posting there
+    SetForum(Option<ChatId>),
+    // Chat of the stream chosen with /commentator, None stops the commentary
+    SetCommentator(Option<ChatId>),
+    // Series of games started with /tournament, None stops it
+    SetTournament(Option<crate::tournament::Tournament>),
+    // The leader removed the user from the lobby with /ban
+    Ban(ChatId),
+    // Seats of the lobby members chosen in the /seats menu
+    SetSeating(Vec<ChatId>),
+    // Somebody joined the lobby, so it is not abandoned
+    Joined,
+    // Saves the running game and tells the players about the restart
+    Shutdown(oneshot::Sender<()>),
+}
+
+impl SessionCommand {
+    // Player whose seat the command is about
+    fn player(&self) -> Option<ChatId> {
+        match self {
+            SessionCommand::Action { chat_id, .. }
+            | SessionCommand::PollVote { chat_id, .. }
+            | SessionCommand::Transcript { chat_id, .. }
+            | SessionCommand::Replay { chat_id, .. }
+            | SessionCommand::Rate { chat_id, .. }
+            | SessionCommand::RatingComment { chat_id, .. }
+            | SessionCommand::Audit { chat_id }
+            | SessionCommand::Status { chat_id }
+            | SessionCommand::WhoAmI { chat_id }
+            | SessionCommand::Chat { chat_id, .. }
+            | SessionCommand::EvilChat { chat_id, .. }
+            | SessionCommand::Claim { chat_id, .. }
+            | SessionCommand::Note { chat_id, .. }
+            | SessionCommand::Notes { chat_id }
+            | SessionCommand::Feedback { chat_id, .. }
+            | SessionCommand::ReplaceWithAi(chat_id) => Some(*chat_id),
+            _ => None,
+        }
+    }
+}
+
+// State of the session which is visible outside of its task
+#[derive(Clone)]
+pub struct SessionStatus {
+    pub started: bool,
+    pub finished: bool,
+    // Engine stopped before the end of the game, /healthz of the http server reports it
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub stalled: bool,
+    // The game is finished by a failure, its players are freed by the cleanup
+    pub crashed: bool,
+    pub players: Vec<ChatId>,
+    pub ai_players: Vec<ChatId>,
+    pub timeout: TimeoutSettings,
+    pub options: game::GameOptions,
+    pub relay: RelayMode,
+    pub evil_chat: bool,
+    pub discussion: Duration,
+    pub theme: Theme,
+    pub narration: bool,
+    pub card_reveal: bool,
+    pub stickers: bool,
+    pub invite: Option<String>,
+    pub house_rules: Option<String>,
+    pub audit: AuditAccess,
+    pub seating: Vec<ChatId>,
+    pub banned: HashSet<ChatId>,
+    pub idle_since: Instant,
+}
+
+impl SessionStatus {
+    fn of(session: &GameSession) -> Self {
+        let players = session.info.as_ref().map(|info| info.players.clone()).unwrap_or_default();
+        let ai_players = session.ai_seats.iter()
+            .filter_map(|seat| players.get(*seat as usize).cloned())
+            .collect();
+        Self {
+            started: session.info.is_some(),
+            finished: session.finished,
+            stalled: session.stalled,
+            crashed: session.crashed,
+            players,
+            ai_players,
+            timeout: session.timeout,
+            options: session.options.clone(),
+            relay: session.relay,
+            evil_chat: session.evil_chat,
+            discussion: session.discussion_time,
+            theme: session.theme,
+            narration: session.narration,
+            card_reveal: session.card_reveal,
+            stickers: session.stickers,
+            invite: session.invite.clone(),
+            house_rules: session.house_rules.clone(),
+            audit: session.audit_access,
+            seating: session.seating.clone(),
+            banned: session.banned.clone(),
+            idle_since: session.idle_since,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionHandle {
+    pub id: u32,
+    pub leader: ChatId,
+    pub created_at: Instant,
+    commands: mpsc::UnboundedSender<SessionCommand>,
+    status: watch::Receiver<SessionStatus>,
+    cancel: CancellationToken,
+}
+
+impl SessionHandle {
+    pub fn send(&self, command: SessionCommand) {
+        if self.commands.send(command).is_err() {
+            tracing::warn!("Game {} is not running", self.id);
+        }
+    }
+
+    pub fn status(&self) -> SessionStatus {
+        self.status.borrow().clone()
+    }
+
+    // The task only ends when cancelled or on Shutdown, otherwise it has crashed
+    pub fn is_running(&self) -> bool {
+        !self.commands.is_closed()
+    }
+
+    // Stops the task together with the engine and the timers of the game, the commands sent
+    // before are dropped
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    // Token of the tasks which live as long as the game, e.g. the start countdown
+    pub fn token(&self) -> CancellationToken {
+        self.cancel.child_token()
+    }
+
+    pub async fn shutdown(&self) {
+        let (done_tx, done) = oneshot::channel();
+        self.send(SessionCommand::Shutdown(done_tx));
+        let _ = done.await;
+    }
+}
+
+// Engine of the game which was running before the restart
+pub enum Restored {
+    // Continues from the beginning of the turn saved in the snapshot
+    Snapshot(game::Game),
+    // Continues from the exact state replayed from the game log
+    Log(journal::Recovered),
+}
+
+// Each game runs in its own task which owns the session and handles its commands one by one,
+// so the handlers, the engine events and the timers never wait for each other's locks
+pub fn spawn(mut session: GameSession, restored: Option<Restored>) -> SessionHandle {
+    let (commands_tx, mut commands) = mpsc::unbounded_channel();
+    let (status_tx, status) = watch::channel(SessionStatus::of(&session));
+    let (id, leader, created_at) = (session.id, session.leader, session.created_at);
+    let (bot, watched) = (session.bot.clone(), status.clone());
+    // Everything the task and the engine log is marked with the game
+    let span = tracing::info_span!("session", game = id);
+    let cancel = CancellationToken::new();
+    let cancelled = cancel.clone();
+
+    let task = tokio::spawn(async move {
+        // The panicked task cancels the tasks of the game too
+        let _guard = cancelled.clone().drop_guard();
+        if let Some(restored) = restored {
+            resume(&mut session, restored).await;
+            status_tx.send_replace(SessionStatus::of(&session));
+        }
+
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut typing_interval = tokio::time::interval(typing::INTERVAL);
+        loop {
+            // The events after the mission wait until its cards are revealed
+            let events = session.info.clone().filter(|_| !session.finished && !session.stalled && session.reveal.is_none());
+            let discussion = session.discussion.as_ref().map(|discussion| discussion.deadline());
+            let reveal = session.reveal.as_ref().map(|reveal| reveal.deadline());
+            let step = tokio::select! {
+                _ = cancelled.cancelled() => break,
+                command = commands.recv() => match command {
+                    Some(command) => Step::Command(command),
+                    None => break,
+                },
+                event = next_event(events) => Step::Event(event),
+                _ = sleep_until(discussion) => Step::Deadline,
+                _ = sleep_until(reveal) => Step::Reveal,
+                _ = interval.tick() => Step::Tick,
+                _ = typing_interval.tick() => Step::Typing,
+            };
+            // The panic in the handling is recovered from like the failure of the engine
+            match AssertUnwindSafe(run_step(&mut session, step)).catch_unwind().await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(_) => recover(&mut session, "The session task panicked").await,
+            }
+            status_tx.send_replace(SessionStatus::of(&session));
+        }
+        // Otherwise the engine keeps waiting for the moves nobody sends
+        if let Some(engine) = session.engine.take() {
+            engine.abort();
+        }
+    }.instrument(span.clone()));
+
+    // The panicked task can't tell the players itself. The cleanup removes the session
+    // which is not running anymore
+    tokio::spawn(async move {
+        if let Err(e) = task.await {
+            if e.is_panic() {
+                tracing::error!("Session task of game {} panicked", id);
+                let players = watched.borrow().players.clone();
+                for player in players {
+                    let _ = bot.send_message(player, CRASHED).await;
+                }
+            }
+        }
+    }.instrument(span));
+
+    SessionHandle { id, leader, created_at, commands: commands_tx, status, cancel }
+}
+
+// What woke up the session task
+enum Step {
+    Command(SessionCommand),
+    Event(Result<GameEvent, String>),
+    Deadline,
+    Reveal,
+    Tick,
+    Typing,
+}
+
+// Returns false when the session is over
+async fn run_step(session: &mut GameSession, step: Step) -> bool {
+    match step {
+        Step::Command(command) => {
+            let span = player_span(session, command.player());
+            return handle_command(session, command).instrument(span).await;
+        }
+        Step::Event(Ok(event)) => crate::on_game_event(session, &event).await,
+        Step::Event(Err(e)) => recover(session, &format!("Engine stopped sending events: {}", e)).await,
+        Step::Deadline => discussion::on_deadline(session).await,
+        Step::Reveal => reveal::on_deadline(session).await,
+        Step::Tick => {
+            // Nobody is waited for while the team is discussed or the cards are revealed
+            if session.discussion.is_none() && session.reveal.is_none() {
+                nudge::nudge_idle_players(session).await;
+                timeout::apply_timeout(session).await;
+            }
+        }
+        Step::Typing => typing::show_to_pending(session).await,
+    }
+    true
+}
+
+// Marks the logs of the player's command with the chat and the seat of the player
+fn player_span(session: &GameSession, player: Option<ChatId>) -> tracing::Span {
+    let Some(chat_id) = player else {
+        return tracing::Span::none();
+    };
+    let seat = session.info.as_ref().and_then(|info| info.players.iter().position(|player| *player == chat_id));
+    tracing::info_span!("player", chat = %chat_id, seat)
+}
+
+// The engine stopped, or the handling of its event failed or panicked, e.g. on a Telegram error.
+// The engine is started again from the game log like after the restart of the bot, so the game
+// goes on instead of hanging. The game which keeps failing is given up as crashed
+pub async fn recover(session: &mut GameSession, error: &str) {
+    tracing::warn!("Game {} failed: {}", session.id, error);
+    let Some(info) = session.info.clone().filter(|_| !session.finished) else {
+        return;
+    };
+    if session.recoveries >= MAX_RECOVERIES {
+        return crash(session, &format!("Gave up after {} failures, the last one: {}", MAX_RECOVERIES + 1, error)).await;
+    }
+    session.recoveries += 1;
+    if let Some(engine) = session.engine.take() {
+        engine.abort();
+    }
+
+    let log = session.storage.load_log(session.id).unwrap_or_else(|e| {
+        tracing::warn!("Failed to load the log of game {}: {}", session.id, e);
+        Vec::new()
+    });
+    let (cli, restored) = match journal::recover(&log).await {
+        Ok(recovered) => (recovered.cli.clone(), Restored::Log(recovered)),
+        Err(e) => {
+            tracing::warn!("Failed to replay the log of game {}: {}. Restoring the snapshot", session.id, e);
+            // Moves after the beginning of the turn are lost, so the log starts over from it
+            let snapshot = info.cli.snapshot().await;
+            session.storage.append_log(session.id, &LogEntry::Started(snapshot.clone()));
+            let (game, cli) = game::Game::restore(snapshot);
+            (cli, Restored::Snapshot(game))
+        }
+    };
+    let notice = match restored {
+        Restored::Snapshot(_) => "⚠️ The game ran into an error. It continues from the current turn",
+        Restored::Log(_) => "⚠️ The game ran into an error. It continues from where it stopped",
+    };
+    crate::send_everybody(&session.bot, &info, notice).await;
+    if let Some(info) = session.info.as_mut() {
+        Arc::make_mut(info).cli = cli;
+    }
+    run_restored(session, restored).await;
+}
+
+const CRASHED: &str = "💥 The game crashed because of an internal error and can't go on. Sorry! Use /new_game to play again";
+
+// The game could not be recovered, so nobody would ever be asked to act again.
+// The game is finished instead of hanging, and the cleanup frees its players for other games
+pub async fn crash(session: &mut GameSession, error: &str) {
+    tracing::error!("Game {} crashed: {}", session.id, error);
+    session.stalled = true;
+    session.finished = true;
+    session.crashed = true;
+    if let Some(engine) = session.engine.take() {
+        engine.abort();
+    }
+    session.storage.save_session(session.id, session.leader, true);
+    if let Some(info) = session.info.clone() {
+        crate::send_everybody(&session.bot, &info, CRASHED).await;
+    }
+}
+
+async fn next_event(info: Option<Arc<crate::GameInfo>>) -> Result<GameEvent, String> {
+    match info {
+        Some(info) => info.cli.clone().recv_event().await.map_err(|e| e.to_string()),
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn resume(session: &mut GameSession, restored: Restored) {
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    tracing::info!("Restoring game {}", session.id);
+    let notice = match restored {
+        Restored::Snapshot(_) => "The bot was restarted. The game continues from the current turn",
+        Restored::Log(_) => "The bot was restarted. The game continues from where it stopped",
+    };
+    crate::send_everybody(&session.bot, &info, notice).await;
+    run_restored(session, restored).await;
+}
+
+// Runs the restored engine, the players who are asked to act get the prompt again
+async fn run_restored(session: &mut GameSession, restored: Restored) {
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    match restored {
+        Restored::Snapshot(game) => session.engine = Some(game::spawn_engine(game)),
+        Restored::Log(recovered) => {
+            session.engine = Some(recovered.engine);
+            // Control messages sent before the restart are not known, so the players get them again
+            if let Some(prompt) = recovered.prompt {
+                if let Err(e) = crate::process_game_event(session, &prompt, &info).await {
+                    tracing::warn!("Failed to repeat the last event of game {}: {}", session.id, e);
+                }
+            }
+            session.voted = recovered.voted.iter()
+                .filter_map(|id| info.players.get(*id as usize).cloned())
+                .collect();
+            let mut outbox = Outbox::default();
+            crate::update_tracker(session, &mut outbox).await;
+            outbox.flush(&session.bot).await;
+        }
+    }
+}
+
+// Returns false when the session is over
+async fn handle_command(session: &mut GameSession, command: SessionCommand) -> bool {
+    match command {
+        SessionCommand::Start { players, user_names } => {
+            session.bots.clear();
+            if let Err(e) = crate::start_game(session, players, user_names).await {
+                tracing::warn!("Failed to start game {}: {}", session.id, e);
+            }
+        }
+        SessionCommand::DebugStart { players, name } => debug::start(session, players, name).await,
+        SessionCommand::Action { chat_id, action } => {
+            if let Err(e) = crate::handle_game_action(session, chat_id, action.clone()).await {
+                tracing::warn!("Failed to handle {:?} from {}: {}", action, chat_id, e);
+            }
+        }
+        SessionCommand::PollVote { chat_id, poll_id, vote } => vote_poll::vote(session, chat_id, &poll_id, vote).await,
+        SessionCommand::Transcript { chat_id, format } => send_transcript(session, chat_id, format).await,
+        SessionCommand::Replay { chat_id, step, message } => replay::show(session, chat_id, step, message).await,
+        SessionCommand::Rate { chat_id, stars, message } => rating::rate(session, chat_id, stars, message).await,
+        SessionCommand::RatingComment { chat_id, text } => rating::add_comment(session, chat_id, text).await,
+        SessionCommand::ReplaceWithAi(chat_id) => unreachable::replace_with_ai(session, chat_id).await,
+        SessionCommand::Audit { chat_id } => send_audit(session, chat_id).await,
+        SessionCommand::Status { chat_id } => send_status(session, chat_id).await,
+        SessionCommand::WhoAmI { chat_id } => whoami::send(session, chat_id).await,
+        SessionCommand::Feedback { chat_id, name, text, to } => feedback::forward(session, chat_id, &name, &text, &to).await,
+        SessionCommand::Chat { chat_id, name, text, lobby } => relay::relay_chat(session, chat_id, name, &text, lobby).await,
+        SessionCommand::EvilChat { chat_id, text } => relay::relay_evil(session, chat_id, &text).await,
+        SessionCommand::Claim { chat_id, text } => claims::add(session, chat_id, &text).await,
+        SessionCommand::Note { chat_id, text } => notes::add(session, chat_id, text).await,
+        SessionCommand::Notes { chat_id } => notes::send(session, chat_id).await,
+        SessionCommand::SetTimeout(settings) => session.timeout = settings,
+        SessionCommand::SetOptions(options) => session.options = options,
+        SessionCommand::SetRelay(relay) => session.relay = relay,
+        SessionCommand::SetEvilChat(evil_chat) => session.evil_chat = evil_chat,
+        SessionCommand::SetDiscussion(duration) => session.discussion_time = duration,
+        SessionCommand::SetTheme(theme) => session.theme = theme,
+        SessionCommand::SetNarration(narration) => session.narration = narration,
+        SessionCommand::SetCardReveal(card_reveal) => session.card_reveal = card_reveal,
+        SessionCommand::SetStickers(stickers) => session.stickers = stickers,
+        SessionCommand::SetInvite(invite) => session.invite = invite,
+        SessionCommand::SetHouseRules(rules) => session.house_rules = rules,
+        SessionCommand::SetForum(forum) => session.forum = forum,
+        SessionCommand::SetCommentator(chat_id) => session.commentator = chat_id,
+        SessionCommand::SetTournament(tournament) => session.tournament = tournament,
+        SessionCommand::SetAudit(access) => session.audit_access = access,
+        SessionCommand::SetSeating(seats) => session.seating = seats,
+        SessionCommand::Ban(chat_id) => {
+            session.banned.insert(chat_id);
+            session.seating.retain(|seated| *seated != chat_id);
+        }
+        SessionCommand::Joined => session.idle_since = Instant::now(),
+        SessionCommand::Shutdown(done) => {
+            save_for_restart(session).await;
+            let _ = done.send(());
+            return false;
+        }
+    }
+    true
+}
+
+async fn send_transcript(session: &GameSession, chat_id: ChatId, format: TranscriptFormat) {
+    let mut outbox = Outbox::default();
+    match session.info.as_ref().filter(|_| session.finished) {
+        Some(info) => {
+            let roles = info.cli.get_player_roles().await;
+            let history = info.cli.get_history().await;
+            match transcript::build(session.id, info, &roles, &history, format) {
+                Ok((file_name, content)) => {
+                    let document = InputFile::memory(content.into_bytes()).file_name(file_name);
+                    if let Err(e) = session.bot.send_document(chat_id, document).await {
+                        tracing::warn!("Failed to send transcript: {}", e);
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to build transcript: {}", e);
+                    outbox.send(chat_id, "Transcript is not available now");
+                }
+            }
+        }
+        None => outbox.send(chat_id, "Transcript is available after the end of the game"),
+    }
+    outbox.flush(&session.bot).await;
+}
+
+async fn send_audit(session: &GameSession, chat_id: ChatId) {
+    let mut outbox = Outbox::default();
+    match session.info.as_ref().filter(|_| session.finished) {
+        Some(_) if session.audit_access == AuditAccess::Leader && chat_id != session.leader => {
+            outbox.send(chat_id, "Only game leader can see the audit of this game");
+        }
+        Some(info) if chat_id != session.leader && !info.players.contains(&chat_id) => {
+            outbox.send(chat_id, "You are not a player of this game");
+        }
+        Some(info) => {
+            for page in session.audit.render(session.id, info) {
+                outbox.send(chat_id, page);
+            }
+        }
+        None => outbox.send(chat_id, "Audit is available after the end of the game"),
+    }
+    outbox.flush(&session.bot).await;
+}
+
+async fn send_status(session: &GameSession, chat_id: ChatId) {
+    let result = match session.info.as_ref() {
+        Some(info) => {
+            let board = game_msg::join_parts(&game_msg::build_board(info).await, &game_msg::build_claims(info, &session.claims, &[]));
+            let board = game_msg::join_parts(&board, &house_rules::status(session));
+            session.bot.send_message(chat_id, board).parse_mode(ParseMode::Html).await
+        }
+        None => session.bot.send_message(chat_id, "The game is not started yet").await,
+    };
+    if let Err(e) = result {
+        tracing::warn!("Failed to send status: {}", e);
+    }
+}
+
+async fn save_for_restart(session: &mut GameSession) {
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    // Debug games are not restored
+    if session.finished || !info.bots.is_empty() {
+        return;
+    }
+
+    session.storage.save_game(session.id, &info.players, &info.cli.snapshot().await, info.theme, info.narration);
+    if let Some(engine) = session.engine.take() {
+        engine.abort();
+    }
+    crate::send_everybody(&session.bot, &info, "The bot is restarting. The game will continue after the restart").await;
+}
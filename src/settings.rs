@@ -0,0 +1,291 @@
+use std::time::Duration;
+
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::audit::AuditAccess;
+use crate::discussion;
+use crate::game::{self, GameOptions, Role};
+use crate::relay::RelayMode;
+use crate::theme::{capitalize, Theme};
+use crate::timeout::{TimeoutPolicy, TimeoutSettings};
+
+// Callback data of the menu buttons starts with it, e.g. "settings mermaid"
+const PREFIX: &str = "settings ";
+// Limits of the rejected teams in a row the leader can choose from
+const MIN_TRY_COUNT: u8 = 3;
+const MAX_TRY_COUNT: u8 = 7;
+
+// Button of the /settings menu. Every press toggles or cycles one value
+#[derive(Clone, Debug, PartialEq)]
+pub enum Setting {
+    // Personal, the other ones are shown only to the leader of the lobby
+    Notifications,
+    Role(Role),
+    Mermaid,
+    TryCount,
+    Timeout,
+    Relay,
+    EvilChat,
+    Discussion,
+    Theme,
+    Narration,
+    CardReveal,
+    Stickers,
+    Audit,
+}
+
+impl Setting {
+    fn data(&self) -> String {
+        let name = match self {
+            Setting::Notifications => "notifications".to_string(),
+            Setting::Role(role) => format!("role_{}", role.to_string().to_lowercase()),
+            Setting::Mermaid => "mermaid".to_string(),
+            Setting::TryCount => "try_count".to_string(),
+            Setting::Timeout => "timeout".to_string(),
+            Setting::Relay => "relay".to_string(),
+            Setting::EvilChat => "evil_chat".to_string(),
+            Setting::Discussion => "discussion".to_string(),
+            Setting::Theme => "theme".to_string(),
+            Setting::Narration => "narration".to_string(),
+            Setting::CardReveal => "card_reveal".to_string(),
+            Setting::Stickers => "stickers".to_string(),
+            Setting::Audit => "audit".to_string(),
+        };
+        format!("{}{}", PREFIX, name)
+    }
+
+    pub fn parse(data: &str) -> Option<Self> {
+        let name = data.strip_prefix(PREFIX)?;
+        let role = game::OPTIONAL_ROLES.iter()
+            .find(|role| name.strip_prefix("role_") == Some(role.to_string().to_lowercase().as_str()));
+        match name {
+            "notifications" => Some(Setting::Notifications),
+            "mermaid" => Some(Setting::Mermaid),
+            "try_count" => Some(Setting::TryCount),
+            "timeout" => Some(Setting::Timeout),
+            "relay" => Some(Setting::Relay),
+            "evil_chat" => Some(Setting::EvilChat),
+            "discussion" => Some(Setting::Discussion),
+            "theme" => Some(Setting::Theme),
+            "narration" => Some(Setting::Narration),
+            "card_reveal" => Some(Setting::CardReveal),
+            "stickers" => Some(Setting::Stickers),
+            "audit" => Some(Setting::Audit),
+            _ => role.map(|role| Setting::Role(role.clone())),
+        }
+    }
+}
+
+// Settings for /preset, the number in the name is the number of players they are made for
+const PRESETS: &[(&str, &str)] = &[
+    ("classic7", "Percival, Morgen, Oberon and the mermaid, five team tries"),
+    ("beginner5", "Only Merlin and Mordred among plain roles, no mermaid and no timeout"),
+    ("chaos", "Every role, the mermaid, three team tries and AI replaces the slow players"),
+];
+
+// Settings of the lobby which are applied to the next game
+#[derive(Clone, Debug, PartialEq)]
+pub struct LobbySettings {
+    pub options: GameOptions,
+    pub timeout: TimeoutSettings,
+    pub relay: RelayMode,
+    // The bad team talks in private with /evil, see relay.rs
+    pub evil_chat: bool,
+    pub discussion: Duration,
+    pub theme: Theme,
+    // Story of the theme between the game messages, see theme.rs
+    pub narration: bool,
+    // Mission cards are shown one by one, see reveal.rs
+    pub card_reveal: bool,
+    // Stickers of the big moments, see media.rs
+    pub stickers: bool,
+    pub audit: AuditAccess,
+}
+
+impl LobbySettings {
+    pub fn change(&mut self, setting: &Setting) {
+        let options = &mut self.options;
+        match setting {
+            Setting::Notifications => {}
+            Setting::Role(role) => match options.roles.iter().position(|enabled| enabled == role) {
+                Some(pos) => {
+                    options.roles.remove(pos);
+                }
+                None => options.roles.push(role.clone()),
+            },
+            Setting::Mermaid => options.mermaid = !options.mermaid,
+            Setting::TryCount => {
+                options.max_try_count = match options.max_try_count {
+                    count if count >= MAX_TRY_COUNT => MIN_TRY_COUNT,
+                    count => (count + 1).max(MIN_TRY_COUNT),
+                };
+            }
+            Setting::Timeout => {
+                self.timeout.policy = match self.timeout.policy {
+                    TimeoutPolicy::Off => TimeoutPolicy::Auto,
+                    TimeoutPolicy::Auto => TimeoutPolicy::Ai,
+                    TimeoutPolicy::Ai => TimeoutPolicy::Off,
+                };
+            }
+            Setting::Relay => {
+                self.relay = match self.relay {
+                    RelayMode::Names => RelayMode::Anonymous,
+                    RelayMode::Anonymous => RelayMode::Off,
+                    RelayMode::Off => RelayMode::Names,
+                };
+            }
+            Setting::EvilChat => self.evil_chat = !self.evil_chat,
+            Setting::Discussion => self.discussion = discussion::next_duration(self.discussion),
+            Setting::Theme => self.theme = self.theme.next(),
+            Setting::Narration => self.narration = !self.narration,
+            Setting::CardReveal => self.card_reveal = !self.card_reveal,
+            Setting::Stickers => self.stickers = !self.stickers,
+            Setting::Audit => self.audit = self.audit.next(),
+        }
+    }
+
+    // Returns the description of the preset
+    pub fn apply_preset(&mut self, name: &str) -> Result<&'static str, String> {
+        let (name, description) = PRESETS.iter()
+            .find(|(preset, _)| *preset == name)
+            .ok_or_else(|| {
+                let names = PRESETS.iter().map(|(preset, _)| *preset).collect::<Vec<_>>();
+                format!("Unknown preset '{}'. Use one of: {}", name, names.join(", "))
+            })?;
+
+        let (options, policy) = match *name {
+            "classic7" => (GameOptions::default(), self.timeout.policy),
+            "beginner5" => (GameOptions { roles: Vec::new(), mermaid: false, ..GameOptions::default() }, TimeoutPolicy::Off),
+            _ => (GameOptions { max_try_count: MIN_TRY_COUNT, ..GameOptions::default() }, TimeoutPolicy::Ai),
+        };
+        self.options = options;
+        self.timeout.policy = policy;
+        Ok(description)
+    }
+}
+
+pub fn notifications_text(muted: bool) -> &'static str {
+    if muted {
+        "Notifications are muted: you get the game news and the messages which need your action. Use /settings unmute to get everything"
+    } else {
+        "You get all notifications. Use /settings mute to skip the lobby news and the flavor text"
+    }
+}
+
+fn button(text: String, setting: Setting) -> Vec<InlineKeyboardButton> {
+    vec![InlineKeyboardButton::callback(text, setting.data())]
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "on" } else { "off" }
+}
+
+// Game settings are only shown to the leader while the lobby can still change them
+pub fn keyboard(muted: bool, lobby: Option<&LobbySettings>) -> InlineKeyboardMarkup {
+    let notifications = if muted { "🔕 Notifications: muted" } else { "🔔 Notifications: all" };
+    let mut rows = vec![button(notifications.to_string(), Setting::Notifications)];
+
+    if let Some(lobby) = lobby {
+        // Roles are named in the words of the chosen theme
+        let theme = lobby.theme.table();
+        for role in game::OPTIONAL_ROLES {
+            let mark = if lobby.options.roles.contains(&role) { "✅" } else { "❌" };
+            rows.push(button(format!("{} {}", mark, theme.role(&role)), Setting::Role(role)));
+        }
+        let mermaid = format!("{} {}: {}", theme.mermaid_icon, capitalize(theme.mermaid), on_off(lobby.options.mermaid));
+        rows.push(button(mermaid, Setting::Mermaid));
+        rows.push(button(format!("🔁 Team tries: {}", lobby.options.max_try_count), Setting::TryCount));
+        rows.push(button(format!("⏰ Timeout: {}", lobby.timeout), Setting::Timeout));
+        rows.push(button(format!("💬 Chat: {}", lobby.relay), Setting::Relay));
+        rows.push(button(format!("😈 Bad team chat: {}", on_off(lobby.evil_chat)), Setting::EvilChat));
+        rows.push(button(format!("🗣 Discussion: {}", discussion::describe(lobby.discussion)), Setting::Discussion));
+        rows.push(button(format!("🎨 Theme: {}", lobby.theme), Setting::Theme));
+        rows.push(button(format!("📜 Narration: {}", on_off(lobby.narration)), Setting::Narration));
+        rows.push(button(format!("🃏 Card reveal: {}", on_off(lobby.card_reveal)), Setting::CardReveal));
+        rows.push(button(format!("🎭 Stickers: {}", on_off(lobby.stickers)), Setting::Stickers));
+        rows.push(button(format!("🔎 Audit: {}", lobby.audit), Setting::Audit));
+    }
+
+    InlineKeyboardMarkup::new(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_are_changed_by_buttons() {
+        let mut lobby = LobbySettings {
+            options: GameOptions::default(),
+            timeout: TimeoutSettings { policy: TimeoutPolicy::Ai, duration: Duration::from_secs(60) },
+            relay: RelayMode::Names,
+            evil_chat: false,
+            discussion: Duration::ZERO,
+            theme: Theme::Classic,
+            narration: false,
+            card_reveal: true,
+            stickers: true,
+            audit: AuditAccess::Leader,
+        };
+        let keyboard = keyboard(false, Some(&lobby));
+        let settings = keyboard.inline_keyboard.iter()
+            .flatten()
+            .map(|button| match &button.kind {
+                teloxide::types::InlineKeyboardButtonKind::CallbackData(data) => Setting::parse(data).unwrap(),
+                _ => panic!("Callback button is expected"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(settings.len(), 15);
+        assert_eq!(settings[1], Setting::Role(Role::Percival));
+        assert_eq!(Setting::parse("settings role_merlin"), None);
+        assert_eq!(Setting::parse("mermaid"), None);
+
+        for setting in &settings {
+            lobby.change(setting);
+        }
+        assert!(lobby.options.roles.is_empty());
+        assert!(!lobby.options.mermaid);
+        assert_eq!(lobby.options.max_try_count, 6);
+        assert_eq!(lobby.timeout.policy, TimeoutPolicy::Off);
+        assert_eq!(lobby.relay, RelayMode::Anonymous);
+        assert!(lobby.evil_chat);
+        assert_eq!(lobby.discussion, Duration::from_secs(60));
+        assert_eq!(lobby.theme, Theme::SciFi);
+        assert!(lobby.narration);
+        assert!(!lobby.card_reveal);
+        assert!(!lobby.stickers);
+        assert_eq!(lobby.audit, AuditAccess::Players);
+
+        lobby.change(&Setting::TryCount);
+        lobby.change(&Setting::TryCount);
+        assert_eq!(lobby.options.max_try_count, MIN_TRY_COUNT);
+    }
+
+    #[test]
+    fn test_presets() {
+        let mut lobby = LobbySettings {
+            options: GameOptions::default(),
+            timeout: TimeoutSettings { policy: TimeoutPolicy::Auto, duration: Duration::from_secs(60) },
+            relay: RelayMode::Names,
+            evil_chat: false,
+            discussion: Duration::ZERO,
+            theme: Theme::Classic,
+            narration: false,
+            card_reveal: true,
+            stickers: true,
+            audit: AuditAccess::Leader,
+        };
+        lobby.apply_preset("beginner5").unwrap();
+        assert!(lobby.options.roles.is_empty() && !lobby.options.mermaid);
+        assert_eq!(lobby.timeout.policy, TimeoutPolicy::Off);
+
+        lobby.apply_preset("chaos").unwrap();
+        assert_eq!(lobby.options.max_try_count, 3);
+        assert_eq!(lobby.timeout.policy, TimeoutPolicy::Ai);
+
+        lobby.apply_preset("classic7").unwrap();
+        assert_eq!(lobby.options, GameOptions::default());
+        assert_eq!(lobby.timeout.duration, Duration::from_secs(60));
+        assert!(lobby.apply_preset("classic").unwrap_err().contains("classic7, beginner5, chaos"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+use crate::ai::{self, Strategy};
+use crate::game::{self, GameEvent, GameResult, History, MissionVote};
+
+// Strategies of the players of each team
+#[derive(Clone, Copy, Debug)]
+pub struct Strategies {
+    pub good: Strategy,
+    pub evil: Strategy,
+}
+
+// How the simulated games of one number of players ended
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct Outcomes {
+    pub players: usize,
+    pub games: usize,
+    pub good_wins: usize,
+    pub missions_failed: usize,
+    // Too many teams in a row were rejected
+    pub teams_rejected: usize,
+    pub merlin_named: usize,
+}
+
+impl Outcomes {
+    fn add(&mut self, history: &History) {
+        self.games += 1;
+        let fails = history.missions.iter().filter(|mission| mission.result == MissionVote::Fail).count();
+        match history.result {
+            Some(GameResult::GoodWins) => self.good_wins += 1,
+            _ if history.merlin_guess.is_some() => self.merlin_named += 1,
+            _ if fails >= 3 => self.missions_failed += 1,
+            _ => self.teams_rejected += 1,
+        }
+    }
+}
+
+// Finished game and the number of the events the engine sent during it
+pub struct Played {
+    pub history: History,
+    pub events: usize,
+}
+
+// Plays one game between the AI players and returns what happened in it
+pub async fn play_game(players: usize, strategies: Strategies) -> Result<History, String> {
+    play(players, strategies).await.map(|played| played.history)
+}
+
+// Same as play_game, also counts the events for the benchmarks
+pub async fn play(players: usize, strategies: Strategies) -> Result<Played, String> {
+    let (engine, mut cli) = game::Game::setup(players);
+    let engine = game::spawn_engine(engine);
+    let roles = cli.get_player_roles().await;
+
+    let played = async {
+        let mut events = 0;
+        loop {
+            let event = cli.recv_event().await.map_err(|e| e.to_string())?;
+            events += 1;
+            if let GameEvent::GameResult(_) = event {
+                return Ok(Played { history: cli.get_history().await, events });
+            }
+
+            let Some((phase, seats)) = ai::prompted_seats(&event, players) else {
+                continue;
+            };
+            for id in seats {
+                let strategy = if roles[id as usize].is_good() { strategies.good } else { strategies.evil };
+                if let Some(chosen) = ai::choose(&cli, id, phase, strategy).await? {
+                    chosen.apply(&mut cli).await?;
+                }
+            }
+        }
+    }.await;
+
+    engine.abort();
+    played
+}
+
+pub async fn simulate(players: usize, games: usize, strategies: Strategies) -> Result<Outcomes, String> {
+    let mut outcomes = Outcomes { players, ..Outcomes::default() };
+    for _ in 0..games {
+        outcomes.add(&play_game(players, strategies).await?);
+    }
+    Ok(outcomes)
+}
+
+// Win rates of the simulations, one row for each number of players
+pub struct Table(pub Vec<Outcomes>);
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>7} {:>7} {:>9} {:>15} {:>14} {:>12}",
+                 "Players", "Games", "Good wins", "Missions failed", "Teams rejected", "Merlin named")?;
+        for row in &self.0 {
+            let percent = |count: usize| format!("{:.1}%", 100.0 * count as f64 / row.games.max(1) as f64);
+            writeln!(f, "{:>7} {:>7} {:>9} {:>15} {:>14} {:>12}",
+                     row.players, row.games, percent(row.good_wins), percent(row.missions_failed),
+                     percent(row.teams_rejected), percent(row.merlin_named))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_simulation_counts_every_game() {
+        let outcomes = simulate(5, 20, Strategies { good: Strategy::Ai, evil: Strategy::Random }).await.unwrap();
+        assert_eq!(outcomes.games, 20);
+        assert_eq!(outcomes.good_wins + outcomes.missions_failed + outcomes.teams_rejected + outcomes.merlin_named, 20);
+
+        // Nobody fails the missions or rejects the teams, so only the guess of Merlin can save the evil team
+        let outcomes = simulate(5, 20, Strategies { good: Strategy::Default, evil: Strategy::Default }).await.unwrap();
+        assert_eq!(outcomes.missions_failed + outcomes.teams_rejected, 0);
+
+        let table = Table(vec![outcomes]).to_string();
+        assert_eq!(table.lines().count(), 2);
+        assert!(table.lines().nth(1).unwrap().trim_start().starts_with("5      20"));
+    }
+}
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+
+use crate::game::{GameResult, Role};
+
+// Finished game in the history of each of its players, see export.rs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FinishedGame {
+    pub game_id: u32,
+    // Seconds since the epoch
+    pub finished_at: u64,
+    pub players: Vec<ChatId>,
+    pub roles: Vec<Role>,
+    pub result: GameResult,
+}
+
+// Rating of the finished game by one of its players, see rating.rs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameRating {
+    pub game_id: u32,
+    // Seconds since the epoch, the lobby may play several games
+    pub finished_at: u64,
+    pub chat_id: ChatId,
+    // Rules and settings of the game, the ratings are compared by them
+    pub features: String,
+    // From 1 to 5
+    pub stars: u8,
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub games: u32,
+    pub good_wins: u32,
+    pub evil_wins: u32,
+    pub merlin_games: u32,
+    // Attempts to guess Merlin after good team completed the missions
+    pub guesses: u32,
+    pub correct_guesses: u32,
+}
+
+impl PlayerStats {
+    // Guess is known only for the player who tried to guess Merlin
+    pub fn add_game(&mut self, role: &Role, result: &GameResult, guessed: Option<bool>) {
+        self.games += 1;
+
+        match (role.is_good(), result) {
+            (true, GameResult::GoodWins) => self.good_wins += 1,
+            (false, GameResult::BadWins) => self.evil_wins += 1,
+            _ => {}
+        }
+
+        if *role == Role::Merlin {
+            self.merlin_games += 1;
+        }
+
+        if let Some(guessed) = guessed {
+            self.guesses += 1;
+            if guessed {
+                self.correct_guesses += 1;
+            }
+        }
+    }
+
+    pub fn wins(&self) -> u32 {
+        self.good_wins + self.evil_wins
+    }
+
+    pub fn render(&self, name: &str) -> String {
+        if self.games == 0 {
+            return format!("{} has not played any games yet", name);
+        }
+
+        let mut lines = vec![
+            format!("📊 Statistics of {}:", name),
+            format!("Games played: {}", self.games),
+            format!("Wins: {} ({}%)", self.wins(), percent(self.wins(), self.games)),
+            format!("Wins as good: {}", self.good_wins),
+            format!("Wins as evil: {}", self.evil_wins),
+            format!("Times as Merlin: {}", self.merlin_games),
+        ];
+
+        if self.guesses > 0 {
+            lines.push(format!("Merlin guessed: {} of {} ({}%)",
+                               self.correct_guesses, self.guesses,
+                               percent(self.correct_guesses, self.guesses)));
+        }
+
+        lines.join("\n")
+    }
+}
+
+fn percent(part: u32, total: u32) -> u32 {
+    part * 100 / total
+}
+
+pub const LEADERBOARD_PAGE_SIZE: u32 = 10;
+// The offset of the last page still fits into u32
+const MAX_LEADERBOARD_PAGE: u32 = u32::MAX / LEADERBOARD_PAGE_SIZE;
+// Win rate of a couple of lucky games says nothing about the player
+pub const MIN_RATED_GAMES: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LeaderboardOrder {
+    Wins,
+    // Win rate among players with enough games
+    Rating,
+}
+
+impl LeaderboardOrder {
+    fn name(&self) -> &'static str {
+        match self {
+            LeaderboardOrder::Wins => "wins",
+            LeaderboardOrder::Rating => "rating",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LeaderboardQuery {
+    pub order: LeaderboardOrder,
+    // Starts from 1
+    pub page: u32,
+    // Closed season to look at, None for the current one
+    pub season: Option<u32>,
+}
+
+impl LeaderboardQuery {
+    // Parses "[wins|rating] [page] [season:N]". The page links have "seasonN", as commands can't contain colons
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let mut query = Self { order: LeaderboardOrder::Wins, page: 1, season: None };
+        for arg in args.split_whitespace() {
+            match arg {
+                "wins" => query.order = LeaderboardOrder::Wins,
+                "rating" => query.order = LeaderboardOrder::Rating,
+                season if season.starts_with("season") => {
+                    let number = season.trim_start_matches("season").trim_start_matches(':');
+                    query.season = Some(number.parse::<u32>()
+                        .ok()
+                        .filter(|season| *season > 0)
+                        .ok_or(format!("Invalid season '{}', use season:N", number))?);
+                }
+                page => {
+                    query.page = page.parse::<u32>()
+                        .ok()
+                        .filter(|page| (1..=MAX_LEADERBOARD_PAGE).contains(page))
+                        .ok_or(format!("Unknown leaderboard option '{}'. Use wins, rating, page number or season:N", page))?;
+                }
+            }
+        }
+
+        Ok(query)
+    }
+
+    pub fn offset(&self) -> u32 {
+        (self.page - 1) * LEADERBOARD_PAGE_SIZE
+    }
+}
+
+// Current season is shown if the query has none
+pub fn render_leaderboard(query: &LeaderboardQuery, current: u32, total: u32, rows: &[(String, PlayerStats)]) -> String {
+    let season = query.season.unwrap_or(current);
+    let title = match query.order {
+        LeaderboardOrder::Wins => format!("🏆 Season {}, top players by wins", season),
+        LeaderboardOrder::Rating => format!("🏆 Season {}, top players by win rate (at least {} games)", season, MIN_RATED_GAMES),
+    };
+
+    if rows.is_empty() {
+        return format!("{}:\nNobody is here yet", title);
+    }
+
+    let mut lines = vec![format!("{}:", title)];
+    for (place, (name, stats)) in rows.iter().enumerate() {
+        let place = query.offset() as usize + place + 1;
+        lines.push(format!("{}. {} - {} wins of {} games ({}%)",
+                           place, name, stats.wins(), stats.games, percent(stats.wins(), stats.games)));
+    }
+
+    let pages = total.div_ceil(LEADERBOARD_PAGE_SIZE);
+    let link = |page: u32| match query.season {
+        Some(season) => format!("/leaderboard_{}_{}_season{}", query.order.name(), page, season),
+        None => format!("/leaderboard_{}_{}", query.order.name(), page),
+    };
+    let mut navigation = Vec::new();
+    if query.page > 1 {
+        navigation.push(link(query.page - 1));
+    }
+    if query.page < pages {
+        navigation.push(link(query.page + 1));
+    }
+
+    lines.push(format!("\nPage {} of {}", query.page, pages));
+    lines.extend(navigation);
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_games_are_counted() {
+        let mut stats = PlayerStats::default();
+        stats.add_game(&Role::Merlin, &GameResult::GoodWins, None);
+        stats.add_game(&Role::Assassin, &GameResult::BadWins, Some(true));
+        stats.add_game(&Role::Assassin, &GameResult::GoodWins, Some(false));
+        stats.add_game(&Role::Good, &GameResult::BadWins, None);
+
+        assert_eq!(stats, PlayerStats {
+            games: 4,
+            good_wins: 1,
+            evil_wins: 1,
+            merlin_games: 1,
+            guesses: 2,
+            correct_guesses: 1,
+        });
+        assert_eq!(stats.wins(), 2);
+    }
+
+    #[test]
+    fn test_parse_leaderboard_query() {
+        assert_eq!(LeaderboardQuery::parse("").unwrap(),
+                   LeaderboardQuery { order: LeaderboardOrder::Wins, page: 1, season: None });
+        assert_eq!(LeaderboardQuery::parse("rating 3").unwrap(),
+                   LeaderboardQuery { order: LeaderboardOrder::Rating, page: 3, season: None });
+        assert_eq!(LeaderboardQuery::parse("season:3").unwrap().season, Some(3));
+        assert_eq!(LeaderboardQuery::parse("wins 2 season3").unwrap(),
+                   LeaderboardQuery { order: LeaderboardOrder::Wins, page: 2, season: Some(3) });
+        assert!(LeaderboardQuery::parse("season:0").is_err());
+        assert!(LeaderboardQuery::parse("season:last").is_err());
+        assert_eq!(LeaderboardQuery::parse("2").unwrap().offset(), LEADERBOARD_PAGE_SIZE);
+        assert!(LeaderboardQuery::parse("0").is_err());
+        assert!(LeaderboardQuery::parse("500000000").is_err());
+        assert!(LeaderboardQuery::parse(&MAX_LEADERBOARD_PAGE.to_string()).unwrap().offset() > 0);
+        assert!(LeaderboardQuery::parse("losses").is_err());
+    }
+
+    #[test]
+    fn test_past_season_pages_keep_season() {
+        let query = LeaderboardQuery { order: LeaderboardOrder::Rating, page: 2, season: Some(3) };
+        let rows = vec![("Alice".to_string(), PlayerStats { games: 10, good_wins: 6, ..Default::default() })];
+        let text = render_leaderboard(&query, 5, 25, &rows);
+        assert!(text.starts_with("🏆 Season 3, top players by win rate"));
+        assert!(text.contains("11. Alice - 6 wins of 10 games (60%)"));
+        assert!(text.ends_with("/leaderboard_rating_1_season3\n/leaderboard_rating_3_season3"));
+    }
+}
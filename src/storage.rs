@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use teloxide::types::ChatId;
+
+use crate::game;
+use crate::journal::LogEntry;
+use crate::stats::{self, FinishedGame, GameRating, LeaderboardOrder, LeaderboardQuery, PlayerStats};
+use crate::theme::Theme;
+use crate::users::UserProfile;
+
+mod memory;
+#[cfg(feature = "persistence")]
+mod redis;
+#[cfg(feature = "persistence")]
+mod sqlite;
+
+pub use self::memory::MemoryStore;
+#[cfg(feature = "persistence")]
+pub use self::redis::RedisStore;
+#[cfg(feature = "persistence")]
+pub use self::sqlite::SqliteStore;
+
+pub type StoreResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+pub struct StoredGame {
+    pub players: Vec<ChatId>,
+    pub snapshot: game::GameInfo,
+    // Chosen in the lobby, the restored game keeps its look
+    pub theme: Theme,
+    pub narration: bool,
+}
+
+pub struct StoredSession {
+    pub id: u32,
+    pub leader: ChatId,
+    pub game: Option<StoredGame>,
+}
+
+pub struct StoredState {
+    pub users: HashMap<ChatId, UserProfile>,
+    pub user_games: HashMap<ChatId, u32>,
+    pub sessions: Vec<StoredSession>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Sqlite,
+    // Nothing survives the restart
+    Memory,
+    Redis,
+}
+
+// Users, lobbies, game snapshots and logs, and stats.
+// Saving is best effort: the game should go on even if the backend is broken, so errors are only logged
+pub trait GameStore: Send + Sync {
+    // Saves a new lobby and returns its id, unique among all bot instances using the store
+    fn create_session(&self, leader: ChatId) -> StoreResult<u32>;
+    fn save_user(&self, chat_id: ChatId, user: &UserProfile);
+    fn save_session(&self, id: u32, leader: ChatId, finished: bool);
+    fn save_user_game(&self, chat_id: ChatId, game_id: u32);
+    fn remove_user_game(&self, chat_id: ChatId);
+    fn save_game(&self, id: u32, players: &[ChatId], snapshot: &game::GameInfo, theme: Theme, narration: bool);
+    fn save_stats(&self, chat_id: ChatId, stats: &PlayerStats);
+    fn append_log(&self, game_id: u32, entry: &LogEntry);
+    // Adds the game to the history of every its player
+    fn save_history(&self, game: &FinishedGame);
+    // Games of the player in the order they were finished
+    fn load_history(&self, chat_id: ChatId) -> StoreResult<Vec<FinishedGame>>;
+    // Replaces the earlier rating of the same game by the player
+    fn save_rating(&self, rating: &GameRating);
+    fn load_ratings(&self) -> StoreResult<Vec<GameRating>>;
+    // Entries in the order they were appended
+    fn load_log(&self, game_id: u32) -> StoreResult<Vec<LogEntry>>;
+    fn load_user(&self, chat_id: ChatId) -> StoreResult<Option<UserProfile>>;
+    fn load_user_game(&self, chat_id: ChatId) -> StoreResult<Option<u32>>;
+    // Makes the instance the owner of the game for `ttl` unless another instance owns it.
+    // Returns the url of the other owner
+    fn claim_game(&self, game_id: u32, instance: &str, url: &str, ttl: Duration) -> StoreResult<Option<String>>;
+    fn release_game(&self, game_id: u32, instance: &str);
+    fn load_stats(&self, chat_id: ChatId) -> StoreResult<PlayerStats>;
+    // Returns the players on the page and the number of ranked players
+    fn load_leaderboard(&self, query: &LeaderboardQuery) -> StoreResult<(Vec<(ChatId, PlayerStats)>, u32)>;
+    // Seasons start from 1, the stats of the players are of the current one
+    fn current_season(&self) -> StoreResult<u32>;
+    // Archives the stats of the current season for its leaderboard and starts the next season
+    // with empty stats. Returns the closed season
+    fn close_season(&self) -> StoreResult<u32>;
+    // Every lobby ever created, also the finished ones, in the order of creation
+    fn load_session_ids(&self) -> StoreResult<Vec<u32>>;
+    // Unfinished sessions with their players, used to continue after restart
+    fn load(&self) -> StoreResult<StoredState>;
+}
+
+pub type Storage = Arc<dyn GameStore>;
+
+#[cfg_attr(not(feature = "persistence"), allow(unused_variables))]
+pub fn open(backend: StorageBackend, db: &str, redis_url: &str) -> StoreResult<Storage> {
+    Ok(match backend {
+        #[cfg(feature = "persistence")]
+        StorageBackend::Sqlite => Arc::new(SqliteStore::open(db)?),
+        StorageBackend::Memory => Arc::new(MemoryStore::default()),
+        #[cfg(feature = "persistence")]
+        StorageBackend::Redis => Arc::new(RedisStore::open(redis_url)?),
+        #[cfg(not(feature = "persistence"))]
+        StorageBackend::Sqlite | StorageBackend::Redis => return Err("The bot is built without the persistence feature".into()),
+    })
+}
+
+// Where the stats of the leaderboard come from
+#[derive(PartialEq, Debug)]
+enum SeasonStats {
+    Current,
+    Archived(u32),
+    // The season has not started yet
+    Empty,
+}
+
+fn season_stats(query: &LeaderboardQuery, current: u32) -> SeasonStats {
+    match query.season {
+        Some(season) if season < current => SeasonStats::Archived(season),
+        Some(season) if season > current => SeasonStats::Empty,
+        _ => SeasonStats::Current,
+    }
+}
+
+// Same order as in SqliteStore, for the backends which can't sort the stats themselves
+fn rank_players(mut players: Vec<(ChatId, PlayerStats)>, query: &LeaderboardQuery) -> (Vec<(ChatId, PlayerStats)>, u32) {
+    let wins = |stats: &PlayerStats| stats.good_wins + stats.evil_wins;
+    match query.order {
+        LeaderboardOrder::Wins => {
+            players.retain(|(_, stats)| stats.games > 0);
+            players.sort_by(|(_, a), (_, b)| wins(b).cmp(&wins(a)).then(a.games.cmp(&b.games)));
+        }
+        LeaderboardOrder::Rating => {
+            players.retain(|(_, stats)| stats.games >= stats::MIN_RATED_GAMES);
+            let rate = |stats: &PlayerStats| wins(stats) as f64 / stats.games as f64;
+            players.sort_by(|(_, a), (_, b)| rate(b).total_cmp(&rate(a)).then(b.games.cmp(&a.games)));
+        }
+    }
+
+    let total = players.len() as u32;
+    let page = players.into_iter()
+        .skip(query.offset() as usize)
+        .take(stats::LEADERBOARD_PAGE_SIZE as usize)
+        .collect();
+    (page, total)
+}
+
+// Builds the state to restore from the saved records in the same way as SqliteStore::load
+fn collect_state(users: HashMap<ChatId, UserProfile>,
+                 sessions: Vec<(u32, ChatId, bool)>,
+                 user_games: HashMap<ChatId, u32>,
+                 mut games: HashMap<u32, StoredGame>) -> StoredState {
+    let mut sessions = sessions.into_iter()
+        .filter(|(_, _, finished)| !finished)
+        .map(|(id, leader, _)| StoredSession { id, leader, game: games.remove(&id) })
+        .collect::<Vec<_>>();
+    sessions.sort_by_key(|session| session.id);
+
+    let user_games = user_games.into_iter()
+        .filter(|(_, game_id)| sessions.iter().any(|s| s.id == *game_id))
+        .collect();
+
+    StoredState {
+        users,
+        user_games,
+        sessions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Redis backend needs a running server, so it is not covered here
+    fn stores() -> Vec<Storage> {
+        vec![
+            #[cfg(feature = "persistence")]
+            Arc::new(SqliteStore::open(":memory:").unwrap()),
+            Arc::new(MemoryStore::default()),
+        ]
+    }
+
+    #[test]
+    fn test_lobby_is_restored() {
+        for storage in stores() {
+            let bob = UserProfile {
+                first_name: "Bob".to_string(),
+                username: Some("bob".to_string()),
+                nickname: Some("Bobby".to_string()),
+                muted: true,
+            };
+            storage.save_user(ChatId(10), &UserProfile {
+                first_name: "Alice".to_string(),
+                username: None,
+                nickname: None,
+                muted: false,
+            });
+            storage.save_user(ChatId(20), &bob);
+            assert_eq!(storage.create_session(ChatId(10)).unwrap(), 1);
+            storage.save_user_game(ChatId(10), 1);
+            storage.save_user_game(ChatId(20), 1);
+            storage.remove_user_game(ChatId(20));
+
+            let state = storage.load().unwrap();
+            assert_eq!(state.users.get(&ChatId(20)), Some(&bob));
+            assert_eq!(storage.load_user(ChatId(20)).unwrap(), Some(bob));
+            assert_eq!(storage.load_user_game(ChatId(10)).unwrap(), Some(1));
+            assert_eq!(storage.load_user_game(ChatId(20)).unwrap(), None);
+            assert_eq!(state.user_games.len(), 1);
+            assert_eq!(state.user_games.get(&ChatId(10)), Some(&1));
+            assert_eq!(state.sessions.len(), 1);
+            assert!(state.sessions[0].game.is_none());
+        }
+    }
+
+    #[test]
+    fn test_finished_session_is_not_restored() {
+        for storage in stores() {
+            storage.save_session(1, ChatId(10), false);
+            storage.save_session(2, ChatId(20), false);
+            storage.save_user_game(ChatId(10), 1);
+            storage.save_user_game(ChatId(20), 2);
+            storage.save_session(2, ChatId(20), true);
+
+            let state = storage.load().unwrap();
+            assert_eq!(state.sessions.len(), 1);
+            assert_eq!(state.sessions[0].id, 1);
+            assert_eq!(state.user_games.get(&ChatId(20)), None);
+            assert_eq!(storage.load_session_ids().unwrap(), vec![1, 2]);
+            // Ids of finished games are not reused
+            assert_eq!(storage.create_session(ChatId(30)).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn test_game_is_claimed_by_one_instance() {
+        let ttl = Duration::from_secs(30);
+        for storage in stores() {
+            assert_eq!(storage.claim_game(1, "a", "http://a", ttl).unwrap(), None);
+            assert_eq!(storage.claim_game(1, "b", "http://b", ttl).unwrap(), Some("http://a".to_string()));
+            // Lease is renewed by the owner
+            assert_eq!(storage.claim_game(1, "a", "http://a", ttl).unwrap(), None);
+
+            storage.release_game(1, "b");
+            assert_eq!(storage.claim_game(1, "b", "http://b", ttl).unwrap(), Some("http://a".to_string()));
+            storage.release_game(1, "a");
+            assert_eq!(storage.claim_game(1, "b", "http://b", ttl).unwrap(), None);
+
+            // Lease of a stopped instance expires
+            assert_eq!(storage.claim_game(2, "a", "http://a", Duration::ZERO).unwrap(), None);
+            std::thread::sleep(Duration::from_millis(5));
+            assert_eq!(storage.claim_game(2, "b", "http://b", ttl).unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_game_snapshot_is_restored() {
+        for storage in stores() {
+            let (_game, cli) = game::Game::setup(5);
+            let players = (1..=5).map(ChatId).collect::<Vec<_>>();
+            storage.save_session(1, ChatId(1), false);
+            storage.save_game(1, &players, &cli.snapshot().await, Theme::Pirates, true);
+
+            let state = storage.load().unwrap();
+            let stored = state.sessions[0].game.as_ref().unwrap();
+            assert_eq!(stored.players, players);
+            assert_eq!((stored.theme, stored.narration), (Theme::Pirates, true));
+
+            let (_game, restored) = game::Game::restore(stored.snapshot.clone());
+            assert_eq!(restored.get_player_roles().await, cli.get_player_roles().await);
+            assert_eq!(restored.get_crown_id().await, cli.get_crown_id().await);
+        }
+    }
+
+    #[test]
+    fn test_log_keeps_order() {
+        for storage in stores() {
+            let entries = vec![
+                LogEntry::Event(game::GameEvent::Turn(1, 2)),
+                LogEntry::Move(crate::journal::Move::SuggestTeam(1, vec![0, 1])),
+                LogEntry::Event(game::GameEvent::TeamSuggested(vec![0, 1])),
+            ];
+            for entry in &entries {
+                storage.append_log(1, entry);
+            }
+            storage.append_log(2, &LogEntry::Event(game::GameEvent::Turn(0, 2)));
+
+            assert_eq!(storage.load_log(1).unwrap(), entries);
+            assert!(storage.load_log(3).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_stats_are_saved() {
+        for storage in stores() {
+            assert_eq!(storage.load_stats(ChatId(1)).unwrap(), PlayerStats::default());
+
+            let mut stats = PlayerStats::default();
+            stats.add_game(&game::Role::Assassin, &game::GameResult::BadWins, Some(true));
+            storage.save_stats(ChatId(1), &stats);
+            assert_eq!(storage.load_stats(ChatId(1)).unwrap(), stats);
+        }
+    }
+
+    #[test]
+    fn test_leaderboard_order() {
+        for storage in stores() {
+            let player = |games, good_wins| PlayerStats { games, good_wins, ..Default::default() };
+            storage.save_stats(ChatId(1), &player(20, 8));
+            storage.save_stats(ChatId(2), &player(5, 5));
+            storage.save_stats(ChatId(3), &player(2, 2));
+
+            let wins = LeaderboardQuery { order: LeaderboardOrder::Wins, page: 1, season: None };
+            let (rows, total) = storage.load_leaderboard(&wins).unwrap();
+            assert_eq!(total, 3);
+            assert_eq!(rows.iter().map(|(id, _)| id.0).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+            let rating = LeaderboardQuery { order: LeaderboardOrder::Rating, page: 1, season: None };
+            let (rows, total) = storage.load_leaderboard(&rating).unwrap();
+            assert_eq!(total, 2);
+            assert_eq!(rows.iter().map(|(id, _)| id.0).collect::<Vec<_>>(), vec![2, 1]);
+        }
+    }
+
+    #[test]
+    fn test_closed_season_keeps_its_leaderboard() {
+        for storage in stores() {
+            let player = |games, good_wins| PlayerStats { games, good_wins, ..Default::default() };
+            assert_eq!(storage.current_season().unwrap(), 1);
+            storage.save_stats(ChatId(1), &player(3, 1));
+            storage.save_stats(ChatId(2), &player(4, 3));
+
+            assert_eq!(storage.close_season().unwrap(), 1);
+            assert_eq!(storage.current_season().unwrap(), 2);
+            assert_eq!(storage.load_stats(ChatId(1)).unwrap(), PlayerStats::default());
+            storage.save_stats(ChatId(3), &player(1, 1));
+
+            let season = |season| LeaderboardQuery { order: LeaderboardOrder::Wins, page: 1, season };
+            let (rows, total) = storage.load_leaderboard(&season(Some(1))).unwrap();
+            assert_eq!(total, 2);
+            assert_eq!(rows, vec![(ChatId(2), player(4, 3)), (ChatId(1), player(3, 1))]);
+            let (rows, _) = storage.load_leaderboard(&season(None)).unwrap();
+            assert_eq!(rows, vec![(ChatId(3), player(1, 1))]);
+            assert_eq!(storage.load_leaderboard(&season(Some(2))).unwrap().0, rows);
+            assert_eq!(storage.load_leaderboard(&season(Some(7))).unwrap(), (vec![], 0));
+        }
+    }
+
+    #[test]
+    fn test_history_of_every_player() {
+        for storage in stores() {
+            let game = |game_id, players: &[i64]| FinishedGame {
+                game_id,
+                finished_at: 1_700_000_000,
+                players: players.iter().map(|id| ChatId(*id)).collect(),
+                roles: vec![game::Role::Merlin, game::Role::Assassin],
+                result: game::GameResult::GoodWins,
+            };
+            storage.save_history(&game(1, &[1, 2]));
+            storage.save_history(&game(2, &[3, 1]));
+
+            assert_eq!(storage.load_history(ChatId(1)).unwrap(), vec![game(1, &[1, 2]), game(2, &[3, 1])]);
+            assert_eq!(storage.load_history(ChatId(2)).unwrap(), vec![game(1, &[1, 2])]);
+            assert_eq!(storage.load_history(ChatId(4)).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn test_rating_is_replaced_with_the_comment() {
+        for storage in stores() {
+            let rating = |chat_id, stars, comment: Option<&str>| GameRating {
+                game_id: 1,
+                finished_at: 1_700_000_000,
+                chat_id: ChatId(chat_id),
+                features: "no optional roles, classic Avalon".to_string(),
+                stars,
+                comment: comment.map(str::to_string),
+            };
+            storage.save_rating(&rating(1, 4, None));
+            storage.save_rating(&rating(2, 2, None));
+            storage.save_rating(&rating(1, 5, Some("Great")));
+
+            let mut ratings = storage.load_ratings().unwrap();
+            ratings.sort_by_key(|rating| rating.chat_id.0);
+            assert_eq!(ratings, vec![rating(1, 5, Some("Great")), rating(2, 2, None)]);
+        }
+    }
+}
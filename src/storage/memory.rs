@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use teloxide::types::ChatId;
+
+use super::{GameStore, LogEntry, SeasonStats, StoreResult, StoredGame, StoredState};
+use crate::game;
+use crate::stats::{FinishedGame, GameRating, LeaderboardQuery, PlayerStats};
+use crate::theme::Theme;
+use crate::users::UserProfile;
+
+#[derive(Default)]
+struct Records {
+    users: HashMap<ChatId, UserProfile>,
+    sessions: HashMap<u32, (ChatId, bool)>,
+    user_games: HashMap<ChatId, u32>,
+    games: HashMap<u32, (Vec<ChatId>, game::GameInfo, Theme, bool)>,
+    stats: HashMap<ChatId, PlayerStats>,
+    // Stats of the closed seasons in their order
+    seasons: Vec<HashMap<ChatId, PlayerStats>>,
+    logs: HashMap<u32, Vec<LogEntry>>,
+    history: HashMap<ChatId, Vec<FinishedGame>>,
+    // Game, its end and the player
+    ratings: HashMap<(u32, u64, ChatId), GameRating>,
+    // Instance, its url and the end of the lease
+    owners: HashMap<u32, (String, String, Instant)>,
+}
+
+// For local runs and tests: everything is lost when the bot stops
+#[derive(Default)]
+pub struct MemoryStore {
+    records: Mutex<Records>,
+}
+
+impl GameStore for MemoryStore {
+    fn create_session(&self, leader: ChatId) -> StoreResult<u32> {
+        let mut records = self.records.lock().unwrap();
+        let id = records.sessions.keys().max().copied().unwrap_or(0) + 1;
+        records.sessions.insert(id, (leader, false));
+        Ok(id)
+    }
+
+    fn save_user(&self, chat_id: ChatId, user: &UserProfile) {
+        self.records.lock().unwrap().users.insert(chat_id, user.clone());
+    }
+
+    fn save_session(&self, id: u32, leader: ChatId, finished: bool) {
+        self.records.lock().unwrap().sessions.insert(id, (leader, finished));
+    }
+
+    fn save_user_game(&self, chat_id: ChatId, game_id: u32) {
+        self.records.lock().unwrap().user_games.insert(chat_id, game_id);
+    }
+
+    fn remove_user_game(&self, chat_id: ChatId) {
+        self.records.lock().unwrap().user_games.remove(&chat_id);
+    }
+
+    fn save_game(&self, id: u32, players: &[ChatId], snapshot: &game::GameInfo, theme: Theme, narration: bool) {
+        self.records.lock().unwrap().games.insert(id, (players.to_vec(), snapshot.clone(), theme, narration));
+    }
+
+    fn save_stats(&self, chat_id: ChatId, stats: &PlayerStats) {
+        self.records.lock().unwrap().stats.insert(chat_id, stats.clone());
+    }
+
+    fn append_log(&self, game_id: u32, entry: &LogEntry) {
+        self.records.lock().unwrap().logs.entry(game_id).or_default().push(entry.clone());
+    }
+
+    fn save_history(&self, game: &FinishedGame) {
+        let mut records = self.records.lock().unwrap();
+        for chat_id in &game.players {
+            records.history.entry(*chat_id).or_default().push(game.clone());
+        }
+    }
+
+    fn load_history(&self, chat_id: ChatId) -> StoreResult<Vec<FinishedGame>> {
+        Ok(self.records.lock().unwrap().history.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    fn save_rating(&self, rating: &GameRating) {
+        let key = (rating.game_id, rating.finished_at, rating.chat_id);
+        self.records.lock().unwrap().ratings.insert(key, rating.clone());
+    }
+
+    fn load_ratings(&self) -> StoreResult<Vec<GameRating>> {
+        Ok(self.records.lock().unwrap().ratings.values().cloned().collect())
+    }
+
+    fn load_log(&self, game_id: u32) -> StoreResult<Vec<LogEntry>> {
+        Ok(self.records.lock().unwrap().logs.get(&game_id).cloned().unwrap_or_default())
+    }
+
+    fn load_user(&self, chat_id: ChatId) -> StoreResult<Option<UserProfile>> {
+        Ok(self.records.lock().unwrap().users.get(&chat_id).cloned())
+    }
+
+    fn load_user_game(&self, chat_id: ChatId) -> StoreResult<Option<u32>> {
+        Ok(self.records.lock().unwrap().user_games.get(&chat_id).copied())
+    }
+
+    fn claim_game(&self, game_id: u32, instance: &str, url: &str, ttl: Duration) -> StoreResult<Option<String>> {
+        let now = Instant::now();
+        let mut records = self.records.lock().unwrap();
+        match records.owners.get(&game_id) {
+            Some((owner, url, expires)) if owner != instance && *expires > now => Ok(Some(url.clone())),
+            _ => {
+                records.owners.insert(game_id, (instance.to_string(), url.to_string(), now + ttl));
+                Ok(None)
+            }
+        }
+    }
+
+    fn release_game(&self, game_id: u32, instance: &str) {
+        let mut records = self.records.lock().unwrap();
+        if records.owners.get(&game_id).is_some_and(|(owner, _, _)| owner == instance) {
+            records.owners.remove(&game_id);
+        }
+    }
+
+    fn load_stats(&self, chat_id: ChatId) -> StoreResult<PlayerStats> {
+        Ok(self.records.lock().unwrap().stats.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    fn load_leaderboard(&self, query: &LeaderboardQuery) -> StoreResult<(Vec<(ChatId, PlayerStats)>, u32)> {
+        let records = self.records.lock().unwrap();
+        let stats = match super::season_stats(query, records.seasons.len() as u32 + 1) {
+            SeasonStats::Current => &records.stats,
+            SeasonStats::Archived(season) => &records.seasons[season as usize - 1],
+            SeasonStats::Empty => return Ok((Vec::new(), 0)),
+        };
+        let players = stats.iter()
+            .map(|(chat_id, stats)| (*chat_id, stats.clone()))
+            .collect();
+        Ok(super::rank_players(players, query))
+    }
+
+    fn current_season(&self) -> StoreResult<u32> {
+        Ok(self.records.lock().unwrap().seasons.len() as u32 + 1)
+    }
+
+    fn close_season(&self) -> StoreResult<u32> {
+        let mut records = self.records.lock().unwrap();
+        let stats = std::mem::take(&mut records.stats);
+        records.seasons.push(stats);
+        Ok(records.seasons.len() as u32)
+    }
+
+    fn load_session_ids(&self) -> StoreResult<Vec<u32>> {
+        let mut ids = self.records.lock().unwrap().sessions.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn load(&self) -> StoreResult<StoredState> {
+        let records = self.records.lock().unwrap();
+        let sessions = records.sessions.iter()
+            .map(|(id, (leader, finished))| (*id, *leader, *finished))
+            .collect();
+        let games = records.games.iter()
+            .map(|(id, (players, snapshot, theme, narration))| {
+                (*id, StoredGame { players: players.clone(), snapshot: snapshot.clone(), theme: *theme, narration: *narration })
+            })
+            .collect();
+        Ok(super::collect_state(records.users.clone(), sessions, records.user_games.clone(), games))
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use redis::{Commands, Connection, FromRedisValue, ToRedisArgs};
+use serde::{Deserialize, Serialize};
+use teloxide::types::ChatId;
+
+use super::{GameStore, LogEntry, SeasonStats, StoreResult, StoredGame, StoredState};
+use crate::game;
+use crate::stats::{FinishedGame, GameRating, LeaderboardQuery, PlayerStats};
+use crate::theme::Theme;
+use crate::users::UserProfile;
+
+// Every kind of record is a hash with JSON values keyed by the chat or game id,
+// except the game logs which are lists of JSON entries
+const USERS: &str = "avalon:users";
+const SESSIONS: &str = "avalon:sessions";
+const USER_GAMES: &str = "avalon:user_games";
+const GAMES: &str = "avalon:games";
+const STATS: &str = "avalon:stats";
+// Number of the closed seasons, their stats are kept in the season keys
+const CLOSED_SEASONS: &str = "avalon:closed_seasons";
+// Keyed by the game, its end and the player
+const RATINGS: &str = "avalon:ratings";
+const LAST_GAME_ID: &str = "avalon:last_game_id";
+
+fn log_key(game_id: u32) -> String {
+    format!("avalon:log:{}", game_id)
+}
+
+fn season_key(season: u32) -> String {
+    format!("avalon:stats:season:{}", season)
+}
+
+// List of the finished games of the player
+fn history_key(chat_id: ChatId) -> String {
+    format!("avalon:history:{}", chat_id.0)
+}
+
+// Expiring key with the owner of the game
+fn owner_key(game_id: u32) -> String {
+    format!("avalon:owner:{}", game_id)
+}
+
+#[derive(Serialize, Deserialize)]
+struct OwnerRecord {
+    instance: String,
+    url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionRecord {
+    leader: i64,
+    finished: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GameRecord {
+    players: Vec<i64>,
+    snapshot: game::GameInfo,
+    // Missing in the records of older versions
+    #[serde(default)]
+    theme: Theme,
+    #[serde(default)]
+    narration: bool,
+}
+
+pub struct RedisStore {
+    conn: Mutex<Connection>,
+}
+
+impl RedisStore {
+    pub fn open(url: &str) -> redis::RedisResult<Self> {
+        let mut conn = redis::Client::open(url)?.get_connection()?;
+        // Databases of older versions have sessions but no counter
+        let ids: Vec<u32> = conn.hkeys(SESSIONS)?;
+        conn.set_nx::<_, _, ()>(LAST_GAME_ID, ids.into_iter().max().unwrap_or(0))?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn set<F: ToRedisArgs, T: Serialize>(&self, key: &str, field: F, value: &T) {
+        let value = serde_json::to_string(value).unwrap();
+        let result: redis::RedisResult<()> = self.conn.lock().unwrap().hset(key, field, value);
+        if let Err(e) = result {
+            tracing::warn!("Storage error: {}", e);
+        }
+    }
+
+    fn get<F: ToRedisArgs, T: for<'de> Deserialize<'de>>(&self, key: &str, field: F) -> StoreResult<Option<T>> {
+        let value: Option<String> = self.conn.lock().unwrap().hget(key, field)?;
+        Ok(value.map(|value| serde_json::from_str(&value)).transpose()?)
+    }
+
+    // Broken records are skipped like broken snapshots in SqliteStore
+    fn get_all<F: FromRedisValue + Eq + std::hash::Hash + std::fmt::Display, T: for<'de> Deserialize<'de>>(&self, key: &str)
+        -> StoreResult<Vec<(F, T)>> {
+        let values: HashMap<F, String> = self.conn.lock().unwrap().hgetall(key)?;
+        Ok(values.into_iter()
+            .filter_map(|(field, value)| match serde_json::from_str(&value) {
+                Ok(value) => Some((field, value)),
+                Err(e) => {
+                    tracing::warn!("Skipping broken record {} of {}: {}", field, key, e);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+impl GameStore for RedisStore {
+    fn create_session(&self, leader: ChatId) -> StoreResult<u32> {
+        let id = self.conn.lock().unwrap().incr(LAST_GAME_ID, 1)?;
+        self.save_session(id, leader, false);
+        Ok(id)
+    }
+
+    fn save_user(&self, chat_id: ChatId, user: &UserProfile) {
+        self.set(USERS, chat_id.0, user);
+    }
+
+    fn save_session(&self, id: u32, leader: ChatId, finished: bool) {
+        self.set(SESSIONS, id, &SessionRecord { leader: leader.0, finished });
+    }
+
+    fn save_user_game(&self, chat_id: ChatId, game_id: u32) {
+        self.set(USER_GAMES, chat_id.0, &game_id);
+    }
+
+    fn remove_user_game(&self, chat_id: ChatId) {
+        let result: redis::RedisResult<()> = self.conn.lock().unwrap().hdel(USER_GAMES, chat_id.0);
+        if let Err(e) = result {
+            tracing::warn!("Storage error: {}", e);
+        }
+    }
+
+    fn save_game(&self, id: u32, players: &[ChatId], snapshot: &game::GameInfo, theme: Theme, narration: bool) {
+        let players = players.iter().map(|id| id.0).collect();
+        self.set(GAMES, id, &GameRecord { players, snapshot: snapshot.clone(), theme, narration });
+    }
+
+    fn save_stats(&self, chat_id: ChatId, stats: &PlayerStats) {
+        self.set(STATS, chat_id.0, stats);
+    }
+
+    fn append_log(&self, game_id: u32, entry: &LogEntry) {
+        let entry = serde_json::to_string(entry).unwrap();
+        let result: redis::RedisResult<()> = self.conn.lock().unwrap().rpush(log_key(game_id), entry);
+        if let Err(e) = result {
+            tracing::warn!(game = game_id, "Storage error: {}", e);
+        }
+    }
+
+    fn save_history(&self, game: &FinishedGame) {
+        let record = serde_json::to_string(game).unwrap();
+        let mut conn = self.conn.lock().unwrap();
+        for chat_id in &game.players {
+            let result: redis::RedisResult<()> = conn.rpush(history_key(*chat_id), &record);
+            if let Err(e) = result {
+                tracing::warn!(game = game.game_id, "Storage error: {}", e);
+            }
+        }
+    }
+
+    fn load_history(&self, chat_id: ChatId) -> StoreResult<Vec<FinishedGame>> {
+        let records: Vec<String> = self.conn.lock().unwrap().lrange(history_key(chat_id), 0, -1)?;
+        Ok(records.iter()
+            .map(|record| serde_json::from_str(record))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn save_rating(&self, rating: &GameRating) {
+        let field = format!("{}:{}:{}", rating.game_id, rating.finished_at, rating.chat_id.0);
+        let record = serde_json::to_string(rating).unwrap();
+        let result: redis::RedisResult<()> = self.conn.lock().unwrap().hset(RATINGS, field, record);
+        if let Err(e) = result {
+            tracing::warn!(game = rating.game_id, "Storage error: {}", e);
+        }
+    }
+
+    fn load_ratings(&self) -> StoreResult<Vec<GameRating>> {
+        let records: HashMap<String, String> = self.conn.lock().unwrap().hgetall(RATINGS)?;
+        Ok(records.values()
+            .map(|record| serde_json::from_str(record))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn load_log(&self, game_id: u32) -> StoreResult<Vec<LogEntry>> {
+        let entries: Vec<String> = self.conn.lock().unwrap().lrange(log_key(game_id), 0, -1)?;
+        Ok(entries.iter()
+            .map(|entry| serde_json::from_str(entry))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn load_user(&self, chat_id: ChatId) -> StoreResult<Option<UserProfile>> {
+        self.get(USERS, chat_id.0)
+    }
+
+    fn load_user_game(&self, chat_id: ChatId) -> StoreResult<Option<u32>> {
+        self.get(USER_GAMES, chat_id.0)
+    }
+
+    fn claim_game(&self, game_id: u32, instance: &str, url: &str, ttl: Duration) -> StoreResult<Option<String>> {
+        let key = owner_key(game_id);
+        let record = serde_json::to_string(&OwnerRecord { instance: instance.to_string(), url: url.to_string() })?;
+        // Redis rejects zero expiration time
+        let ttl = ttl.as_millis().max(1) as usize;
+        let mut conn = self.conn.lock().unwrap();
+
+        let claimed: bool = redis::cmd("SET").arg(&key).arg(&record).arg("NX").arg("PX").arg(ttl)
+            .query::<Option<String>>(&mut *conn)?
+            .is_some();
+        if claimed {
+            return Ok(None);
+        }
+
+        let owner: Option<String> = conn.get(&key)?;
+        match owner.map(|owner| serde_json::from_str::<OwnerRecord>(&owner)).transpose()? {
+            Some(owner) if owner.instance != instance => Ok(Some(owner.url)),
+            // Renews the lease, or takes the game if it has just expired
+            _ => {
+                conn.pset_ex::<_, _, ()>(&key, record, ttl)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn release_game(&self, game_id: u32, instance: &str) {
+        let key = owner_key(game_id);
+        let mut conn = self.conn.lock().unwrap();
+        let owner: redis::RedisResult<Option<String>> = conn.get(&key);
+        let owned = match owner {
+            Ok(owner) => owner
+                .and_then(|owner| serde_json::from_str::<OwnerRecord>(&owner).ok())
+                .is_some_and(|owner| owner.instance == instance),
+            Err(e) => {
+                tracing::warn!(game = game_id, "Storage error: {}", e);
+                false
+            }
+        };
+        if owned {
+            let result: redis::RedisResult<()> = conn.del(&key);
+            if let Err(e) = result {
+                tracing::warn!(game = game_id, "Storage error: {}", e);
+            }
+        }
+    }
+
+    fn load_stats(&self, chat_id: ChatId) -> StoreResult<PlayerStats> {
+        Ok(self.get(STATS, chat_id.0)?.unwrap_or_default())
+    }
+
+    fn load_leaderboard(&self, query: &LeaderboardQuery) -> StoreResult<(Vec<(ChatId, PlayerStats)>, u32)> {
+        let key = match super::season_stats(query, self.current_season()?) {
+            SeasonStats::Current => STATS.to_string(),
+            SeasonStats::Archived(season) => season_key(season),
+            SeasonStats::Empty => return Ok((Vec::new(), 0)),
+        };
+        let players = self.get_all::<i64, PlayerStats>(&key)?.into_iter()
+            .map(|(chat_id, stats)| (ChatId(chat_id), stats))
+            .collect();
+        Ok(super::rank_players(players, query))
+    }
+
+    fn current_season(&self) -> StoreResult<u32> {
+        let closed: Option<u32> = self.conn.lock().unwrap().get(CLOSED_SEASONS)?;
+        Ok(closed.unwrap_or(0) + 1)
+    }
+
+    // The stats are renamed and the counter is increased in one transaction,
+    // so the stats of the closed season are never counted in the new one
+    fn close_season(&self) -> StoreResult<u32> {
+        let mut conn = self.conn.lock().unwrap();
+        let (season,): (u32,) = redis::transaction(&mut *conn, &[STATS, CLOSED_SEASONS], |conn, pipe| {
+            let closed: Option<u32> = conn.get(CLOSED_SEASONS)?;
+            let season = closed.unwrap_or(0) + 1;
+            if conn.exists(STATS)? {
+                pipe.rename(STATS, season_key(season)).ignore();
+            }
+            pipe.incr(CLOSED_SEASONS, 1).query(conn)
+        })?;
+        Ok(season)
+    }
+
+    fn load_session_ids(&self) -> StoreResult<Vec<u32>> {
+        let mut ids: Vec<u32> = self.conn.lock().unwrap().hkeys(SESSIONS)?;
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn load(&self) -> StoreResult<StoredState> {
+        let users = self.get_all::<i64, UserProfile>(USERS)?.into_iter()
+            .map(|(chat_id, user)| (ChatId(chat_id), user))
+            .collect();
+        let sessions = self.get_all::<u32, SessionRecord>(SESSIONS)?.into_iter()
+            .map(|(id, session)| (id, ChatId(session.leader), session.finished))
+            .collect();
+        let user_games = self.get_all::<i64, u32>(USER_GAMES)?.into_iter()
+            .map(|(chat_id, game_id)| (ChatId(chat_id), game_id))
+            .collect();
+        let games = self.get_all::<u32, GameRecord>(GAMES)?.into_iter()
+            .map(|(id, game)| {
+                let players = game.players.into_iter().map(ChatId).collect();
+                (id, StoredGame { players, snapshot: game.snapshot, theme: game.theme, narration: game.narration })
+            })
+            .collect();
+        Ok(super::collect_state(users, sessions, user_games, games))
+    }
+}
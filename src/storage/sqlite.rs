@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Params};
+use teloxide::types::ChatId;
+
+use super::{GameStore, LogEntry, SeasonStats, StoreResult, StoredGame, StoredSession, StoredState};
+use crate::game;
+use crate::stats::{self, FinishedGame, GameRating, LeaderboardOrder, LeaderboardQuery, PlayerStats};
+use crate::theme::Theme;
+use crate::users::UserProfile;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users (
+        chat_id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        leader INTEGER NOT NULL,
+        finished INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS user_games (
+        chat_id INTEGER PRIMARY KEY,
+        game_id INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS games (
+        id INTEGER PRIMARY KEY,
+        players TEXT NOT NULL,
+        snapshot TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS stats (
+        chat_id INTEGER PRIMARY KEY,
+        games INTEGER NOT NULL DEFAULT 0,
+        good_wins INTEGER NOT NULL DEFAULT 0,
+        evil_wins INTEGER NOT NULL DEFAULT 0,
+        merlin_games INTEGER NOT NULL DEFAULT 0,
+        guesses INTEGER NOT NULL DEFAULT 0,
+        correct_guesses INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS seasons (
+        id INTEGER PRIMARY KEY,
+        closed_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS season_stats (
+        season INTEGER NOT NULL,
+        chat_id INTEGER NOT NULL,
+        games INTEGER NOT NULL DEFAULT 0,
+        good_wins INTEGER NOT NULL DEFAULT 0,
+        evil_wins INTEGER NOT NULL DEFAULT 0,
+        merlin_games INTEGER NOT NULL DEFAULT 0,
+        guesses INTEGER NOT NULL DEFAULT 0,
+        correct_guesses INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (season, chat_id)
+    );
+    CREATE TABLE IF NOT EXISTS game_log (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        game_id INTEGER NOT NULL,
+        entry TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS game_log_game_id ON game_log (game_id);
+    CREATE TABLE IF NOT EXISTS history (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id INTEGER NOT NULL,
+        game TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_chat_id ON history (chat_id);
+    CREATE TABLE IF NOT EXISTS ratings (
+        game_id INTEGER NOT NULL,
+        finished_at INTEGER NOT NULL,
+        chat_id INTEGER NOT NULL,
+        features TEXT NOT NULL,
+        stars INTEGER NOT NULL,
+        comment TEXT,
+        PRIMARY KEY (game_id, finished_at, chat_id)
+    );
+    CREATE TABLE IF NOT EXISTS owners (
+        game_id INTEGER PRIMARY KEY,
+        instance TEXT NOT NULL,
+        url TEXT NOT NULL,
+        expires INTEGER NOT NULL
+    );
+";
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Self::add_column(&conn, "users", "username", "TEXT")?;
+        Self::add_column(&conn, "users", "nickname", "TEXT")?;
+        Self::add_column(&conn, "users", "muted", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::add_column(&conn, "games", "theme", "TEXT")?;
+        Self::add_column(&conn, "games", "narration", "INTEGER NOT NULL DEFAULT 0")?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    // Upgrades databases created by older versions of the bot
+    fn add_column(conn: &Connection, table: &str, column: &str, column_type: &str) -> rusqlite::Result<()> {
+        let exists = conn.prepare(&format!("PRAGMA table_info({})", table))?
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .any(|name| name == column);
+
+        if !exists {
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, column_type), [])?;
+        }
+        Ok(())
+    }
+
+    // Persistence is best effort: the game should go on even if the database is broken
+    fn execute<P: Params>(&self, sql: &str, params: P) {
+        let conn = self.conn.lock().unwrap();
+        if let Err(e) = conn.execute(sql, params) {
+            tracing::warn!("Storage error: {}", e);
+        }
+    }
+}
+
+// Milliseconds since the epoch, the same on all instances sharing the database
+fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+impl GameStore for SqliteStore {
+    fn create_session(&self, leader: ChatId) -> StoreResult<u32> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("INSERT INTO sessions (leader, finished) VALUES (?1, 0)", params![leader.0])?;
+        Ok(conn.last_insert_rowid() as u32)
+    }
+
+    fn save_user(&self, chat_id: ChatId, user: &UserProfile) {
+        self.execute("INSERT OR REPLACE INTO users (chat_id, name, username, nickname, muted) VALUES (?1, ?2, ?3, ?4, ?5)",
+                     params![chat_id.0, user.first_name, user.username, user.nickname, user.muted]);
+    }
+
+    fn save_session(&self, id: u32, leader: ChatId, finished: bool) {
+        self.execute("INSERT OR REPLACE INTO sessions (id, leader, finished) VALUES (?1, ?2, ?3)",
+                     params![id, leader.0, finished]);
+    }
+
+    fn save_user_game(&self, chat_id: ChatId, game_id: u32) {
+        self.execute("INSERT OR REPLACE INTO user_games (chat_id, game_id) VALUES (?1, ?2)",
+                     params![chat_id.0, game_id]);
+    }
+
+    fn remove_user_game(&self, chat_id: ChatId) {
+        self.execute("DELETE FROM user_games WHERE chat_id = ?1", params![chat_id.0]);
+    }
+
+    fn save_game(&self, id: u32, players: &[ChatId], snapshot: &game::GameInfo, theme: Theme, narration: bool) {
+        let players = players.iter().map(|id| id.0).collect::<Vec<_>>();
+        let players = serde_json::to_string(&players).unwrap();
+        let snapshot = serde_json::to_string(snapshot).unwrap();
+        let theme = serde_json::to_string(&theme).unwrap();
+        self.execute("INSERT OR REPLACE INTO games (id, players, snapshot, theme, narration) VALUES (?1, ?2, ?3, ?4, ?5)",
+                     params![id, players, snapshot, theme, narration]);
+    }
+
+    fn save_stats(&self, chat_id: ChatId, stats: &PlayerStats) {
+        self.execute("INSERT OR REPLACE INTO stats (chat_id, games, good_wins, evil_wins, merlin_games, guesses, correct_guesses)
+                      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                     params![chat_id.0, stats.games, stats.good_wins, stats.evil_wins,
+                             stats.merlin_games, stats.guesses, stats.correct_guesses]);
+    }
+
+    fn append_log(&self, game_id: u32, entry: &LogEntry) {
+        let entry = serde_json::to_string(entry).unwrap();
+        self.execute("INSERT INTO game_log (game_id, entry) VALUES (?1, ?2)", params![game_id, entry]);
+    }
+
+    fn save_history(&self, game: &FinishedGame) {
+        let record = serde_json::to_string(game).unwrap();
+        for chat_id in &game.players {
+            self.execute("INSERT INTO history (chat_id, game) VALUES (?1, ?2)", params![chat_id.0, record]);
+        }
+    }
+
+    fn load_history(&self, chat_id: ChatId) -> StoreResult<Vec<FinishedGame>> {
+        let conn = self.conn.lock().unwrap();
+        let records = conn.prepare("SELECT game FROM history WHERE chat_id = ?1 ORDER BY seq")?
+            .query_map(params![chat_id.0], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(records.iter()
+            .map(|record| serde_json::from_str(record))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn save_rating(&self, rating: &GameRating) {
+        self.execute("INSERT OR REPLACE INTO ratings (game_id, finished_at, chat_id, features, stars, comment) \
+                      VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                     params![rating.game_id, rating.finished_at as i64, rating.chat_id.0, rating.features, rating.stars, rating.comment]);
+    }
+
+    fn load_ratings(&self) -> StoreResult<Vec<GameRating>> {
+        let conn = self.conn.lock().unwrap();
+        let ratings = conn.prepare("SELECT game_id, finished_at, chat_id, features, stars, comment FROM ratings")?
+            .query_map([], |row| Ok(GameRating {
+                game_id: row.get(0)?,
+                finished_at: row.get::<_, i64>(1)? as u64,
+                chat_id: ChatId(row.get(2)?),
+                features: row.get(3)?,
+                stars: row.get(4)?,
+                comment: row.get(5)?,
+            }))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ratings)
+    }
+
+    fn load_log(&self, game_id: u32) -> StoreResult<Vec<LogEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let entries = conn.prepare("SELECT entry FROM game_log WHERE game_id = ?1 ORDER BY seq")?
+            .query_map(params![game_id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries.iter()
+            .map(|entry| serde_json::from_str(entry))
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn load_user(&self, chat_id: ChatId) -> StoreResult<Option<UserProfile>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row("SELECT name, username, nickname, muted FROM users WHERE chat_id = ?1",
+                          params![chat_id.0],
+                          |row| Ok(UserProfile {
+                              first_name: row.get(0)?,
+                              username: row.get(1)?,
+                              nickname: row.get(2)?,
+                              muted: row.get(3)?,
+                          }))
+            .optional()?)
+    }
+
+    fn load_user_game(&self, chat_id: ChatId) -> StoreResult<Option<u32>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row("SELECT game_id FROM user_games WHERE chat_id = ?1", params![chat_id.0],
+                          |row| row.get(0))
+            .optional()?)
+    }
+
+    fn claim_game(&self, game_id: u32, instance: &str, url: &str, ttl: Duration) -> StoreResult<Option<String>> {
+        let now = now_millis();
+        let conn = self.conn.lock().unwrap();
+        // Takes the game if it has no owner, renews the lease of the same instance or takes an expired one
+        let claimed = conn.execute("INSERT INTO owners (game_id, instance, url, expires) VALUES (?1, ?2, ?3, ?4)
+                                    ON CONFLICT (game_id) DO UPDATE
+                                    SET instance = excluded.instance, url = excluded.url, expires = excluded.expires
+                                    WHERE owners.instance = excluded.instance OR owners.expires < ?5",
+                                   params![game_id, instance, url, now + ttl.as_millis() as i64, now])?;
+        if claimed > 0 {
+            return Ok(None);
+        }
+        Ok(Some(conn.query_row("SELECT url FROM owners WHERE game_id = ?1", params![game_id], |row| row.get(0))?))
+    }
+
+    fn release_game(&self, game_id: u32, instance: &str) {
+        self.execute("DELETE FROM owners WHERE game_id = ?1 AND instance = ?2", params![game_id, instance]);
+    }
+
+    fn load_stats(&self, chat_id: ChatId) -> StoreResult<PlayerStats> {
+        let conn = self.conn.lock().unwrap();
+        let stats = conn.query_row("SELECT games, good_wins, evil_wins, merlin_games, guesses, correct_guesses
+                                    FROM stats WHERE chat_id = ?1",
+                                   params![chat_id.0],
+                                   |row| Ok(PlayerStats {
+                                       games: row.get(0)?,
+                                       good_wins: row.get(1)?,
+                                       evil_wins: row.get(2)?,
+                                       merlin_games: row.get(3)?,
+                                       guesses: row.get(4)?,
+                                       correct_guesses: row.get(5)?,
+                                   }))
+            .optional()?;
+        Ok(stats.unwrap_or_default())
+    }
+
+    // Returns the players on the page and the number of ranked players
+    fn load_leaderboard(&self, query: &LeaderboardQuery) -> StoreResult<(Vec<(ChatId, PlayerStats)>, u32)> {
+        let (filter, order) = match query.order {
+            LeaderboardOrder::Wins => (0, "good_wins + evil_wins DESC, games ASC"),
+            LeaderboardOrder::Rating => (stats::MIN_RATED_GAMES - 1,
+                                         "CAST(good_wins + evil_wins AS REAL) / games DESC, games DESC"),
+        };
+
+        // Stats of the closed seasons have the same columns
+        let source = match super::season_stats(query, self.current_season()?) {
+            SeasonStats::Current => "stats WHERE".to_string(),
+            SeasonStats::Archived(season) => format!("season_stats WHERE season = {} AND", season),
+            SeasonStats::Empty => return Ok((Vec::new(), 0)),
+        };
+
+        let conn = self.conn.lock().unwrap();
+        let total = conn.query_row(&format!("SELECT COUNT(*) FROM {} games > ?1", source), params![filter],
+                                   |row| row.get(0))?;
+
+        let rows = conn.prepare(&format!(
+                "SELECT chat_id, games, good_wins, evil_wins, merlin_games, guesses, correct_guesses
+                 FROM {} games > ?1 ORDER BY {} LIMIT ?2 OFFSET ?3", source, order))?
+            .query_map(params![filter, stats::LEADERBOARD_PAGE_SIZE, query.offset()], |row| {
+                Ok((ChatId(row.get(0)?), PlayerStats {
+                    games: row.get(1)?,
+                    good_wins: row.get(2)?,
+                    evil_wins: row.get(3)?,
+                    merlin_games: row.get(4)?,
+                    guesses: row.get(5)?,
+                    correct_guesses: row.get(6)?,
+                }))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((rows, total))
+    }
+
+    fn current_season(&self) -> StoreResult<u32> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM seasons", [], |row| row.get(0))?)
+    }
+
+    fn close_season(&self) -> StoreResult<u32> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let season: u32 = tx.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM seasons", [], |row| row.get(0))?;
+        tx.execute("INSERT INTO season_stats (season, chat_id, games, good_wins, evil_wins, merlin_games, guesses, correct_guesses)
+                    SELECT ?1, chat_id, games, good_wins, evil_wins, merlin_games, guesses, correct_guesses FROM stats",
+                   params![season])?;
+        tx.execute("DELETE FROM stats", [])?;
+        tx.execute("INSERT INTO seasons (id, closed_at) VALUES (?1, ?2)", params![season, now_millis()])?;
+        tx.commit()?;
+        Ok(season)
+    }
+
+    fn load_session_ids(&self) -> StoreResult<Vec<u32>> {
+        let conn = self.conn.lock().unwrap();
+        let ids = conn.prepare("SELECT id FROM sessions ORDER BY id")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ids)
+    }
+
+    fn load(&self) -> StoreResult<StoredState> {
+        let conn = self.conn.lock().unwrap();
+
+        let users = conn.prepare("SELECT chat_id, name, username, nickname, muted FROM users")?
+            .query_map([], |row| {
+                let user = UserProfile {
+                    first_name: row.get(1)?,
+                    username: row.get(2)?,
+                    nickname: row.get(3)?,
+                    muted: row.get(4)?,
+                };
+                Ok((ChatId(row.get(0)?), user))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let mut games = conn.prepare("SELECT id, players, snapshot, theme, narration FROM games")?
+            .query_map([], |row| {
+                let id: u32 = row.get(0)?;
+                let players: String = row.get(1)?;
+                let snapshot: String = row.get(2)?;
+                let theme: Option<String> = row.get(3)?;
+                let narration: bool = row.get(4)?;
+                Ok((id, players, snapshot, theme, narration))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|(id, players, snapshot, theme, narration)| {
+                let players = serde_json::from_str::<Vec<i64>>(&players);
+                let snapshot = serde_json::from_str::<game::GameInfo>(&snapshot);
+                // Games saved by older versions have no theme
+                let theme = theme.and_then(|theme| serde_json::from_str::<Theme>(&theme).ok()).unwrap_or_default();
+                match (players, snapshot) {
+                    (Ok(players), Ok(snapshot)) => {
+                        let players = players.into_iter().map(ChatId).collect();
+                        Some((id, StoredGame { players, snapshot, theme, narration }))
+                    }
+                    _ => {
+                        tracing::warn!(game = id, "Skipping broken snapshot of game {}", id);
+                        None
+                    }
+                }
+            })
+            .collect::<HashMap<_, _>>();
+
+        let sessions = conn.prepare("SELECT id, leader FROM sessions WHERE finished = 0")?
+            .query_map([], |row| Ok((row.get::<_, u32>(0)?, ChatId(row.get(1)?))))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(id, leader)| StoredSession { id, leader, game: games.remove(&id) })
+            .collect::<Vec<_>>();
+
+        let user_games = conn.prepare("SELECT chat_id, game_id FROM user_games")?
+            .query_map([], |row| Ok((ChatId(row.get(0)?), row.get(1)?)))?
+            .collect::<Result<HashMap<_, u32>, _>>()?
+            .into_iter()
+            .filter(|(_, game_id)| sessions.iter().any(|s| s.id == *game_id))
+            .collect();
+
+        Ok(StoredState {
+            users,
+            user_games,
+            sessions,
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::session::SessionHandle;
+use crate::BotCtx;
+
+// Callback data of the switcher buttons starts with it, e.g. "switch 42"
+pub const PREFIX: &str = "switch ";
+
+// Games of the users besides the active one in user_games, e.g. the lobby of the evening while
+// another game is played. Shared with the game sessions, so the messages of every game are tagged
+// for the users who are in several games. Not stored, after a restart the user is only in the active game
+#[derive(Clone, Default)]
+pub struct OtherGames(Arc<Mutex<HashMap<ChatId, Vec<u32>>>>);
+
+impl OtherGames {
+    pub fn get(&self, chat_id: ChatId) -> Vec<u32> {
+        self.0.lock().unwrap().get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    pub fn has_any(&self, chat_id: ChatId) -> bool {
+        self.0.lock().unwrap().contains_key(&chat_id)
+    }
+
+    fn add(&self, chat_id: ChatId, game_id: u32) {
+        let mut games = self.0.lock().unwrap();
+        let games = games.entry(chat_id).or_default();
+        if !games.contains(&game_id) {
+            games.push(game_id);
+        }
+    }
+
+    // Returns true if the user was in the game
+    pub fn remove(&self, chat_id: ChatId, game_id: u32) -> bool {
+        let mut games = self.0.lock().unwrap();
+        let Some(other) = games.get_mut(&chat_id) else {
+            return false;
+        };
+        let found = other.contains(&game_id);
+        other.retain(|id| *id != game_id);
+        if other.is_empty() {
+            games.remove(&chat_id);
+        }
+        found
+    }
+
+    // The game is over for everybody who waited in it
+    pub fn remove_game(&self, game_id: u32) {
+        let mut games = self.0.lock().unwrap();
+        games.values_mut().for_each(|other| other.retain(|id| *id != game_id));
+        games.retain(|_, other| !other.is_empty());
+    }
+
+    fn take_first(&self, chat_id: ChatId) -> Option<u32> {
+        let mut games = self.0.lock().unwrap();
+        let other = games.get_mut(&chat_id)?;
+        let game_id = other.remove(0);
+        if other.is_empty() {
+            games.remove(&chat_id);
+        }
+        Some(game_id)
+    }
+
+    // Lobby members who are waiting in the game while another one is active
+    pub fn members(&self, game_id: u32) -> Vec<ChatId> {
+        self.0.lock().unwrap().iter()
+            .filter(|(_, other)| other.contains(&game_id))
+            .map(|(chat_id, _)| *chat_id)
+            .collect()
+    }
+}
+
+// Tag of the game message for the users who are in several games, so they know which game it is from
+pub fn tag(others: &OtherGames, chat_id: ChatId, game_id: u32, text: &str) -> String {
+    match others.has_any(chat_id) {
+        true => format!("🎲 #{}\n{}", game_id, text),
+        false => text.to_string(),
+    }
+}
+
+// The joined game becomes active, the previous one still counts the user in
+pub fn join(ctx: &mut BotCtx, chat_id: ChatId, game_id: u32) {
+    ctx.other_games.remove(chat_id, game_id);
+    let previous = ctx.user_games.get(&chat_id).cloned()
+        .filter(|id| *id != game_id && ctx.game_sessions.get(id).is_some_and(|session| !session.status().finished));
+    if let Some(previous) = previous {
+        ctx.other_games.add(chat_id, previous);
+    }
+    ctx.storage.save_user_game(chat_id, game_id);
+    ctx.user_games.insert(chat_id, game_id);
+}
+
+// The user left the active game. Returns the next active one, if the user is in any other game
+pub fn leave(ctx: &mut BotCtx, chat_id: ChatId) -> Option<u32> {
+    ctx.user_games.remove(&chat_id);
+    match ctx.other_games.take_first(chat_id) {
+        Some(next) => {
+            ctx.storage.save_user_game(chat_id, next);
+            ctx.user_games.insert(chat_id, next);
+            Some(next)
+        }
+        None => {
+            ctx.storage.remove_user_game(chat_id);
+            None
+        }
+    }
+}
+
+fn activate(ctx: &mut BotCtx, chat_id: ChatId, game_id: u32) -> Result<String, &'static str> {
+    if ctx.user_games.get(&chat_id) == Some(&game_id) {
+        return Err("This game is already active");
+    }
+    if !ctx.other_games.remove(chat_id, game_id) {
+        return Err("You are not in this game");
+    }
+    join(ctx, chat_id, game_id);
+    Ok(format!("Game #{} is active now, your commands go to it", game_id))
+}
+
+// Game whose actions the user sends: the active one if the user plays it, otherwise the other
+// started game of the user. The buttons of the control messages have no game in them
+pub fn action_game(ctx: &BotCtx, chat_id: ChatId) -> Option<SessionHandle> {
+    let plays = |game_id: &u32| ctx.game_sessions.get(game_id)
+        .filter(|session| {
+            let status = session.status();
+            !status.finished && status.players.contains(&chat_id)
+        })
+        .cloned();
+    let active = ctx.user_games.get(&chat_id);
+    active.and_then(plays)
+        .or_else(|| ctx.other_games.get(chat_id).iter().find_map(plays))
+        .or_else(|| active.and_then(|game_id| ctx.game_sessions.get(game_id)).cloned())
+}
+
+fn describe(session: &SessionHandle) -> String {
+    let status = session.status();
+    let state = match (status.started, status.finished) {
+        (false, _) => "lobby",
+        (true, false) => "playing",
+        (true, true) => "finished",
+    };
+    format!("#{} {}", session.id, state)
+}
+
+// /switch lists the games of the user with the buttons, /switch <id> makes the game active
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    if let Ok(game_id) = args.trim().parse::<u32>() {
+        let reply = activate(ctx, chat_id, game_id).unwrap_or_else(str::to_string);
+        ctx.bot.send_message(chat_id, reply).await?;
+        return respond(());
+    }
+
+    let Some(active) = ctx.user_games.get(&chat_id).and_then(|game_id| ctx.game_sessions.get(game_id)) else {
+        return crate::send_not_in_game(&ctx.bot, chat_id).await;
+    };
+    let others = ctx.other_games.get(chat_id).into_iter()
+        .filter_map(|game_id| ctx.game_sessions.get(&game_id))
+        .collect::<Vec<_>>();
+    let text = format!("Active game: {}", describe(active));
+    if others.is_empty() {
+        ctx.bot.send_message(chat_id, format!("{}. Join another game with its invite link to switch between them", text)).await?;
+        return respond(());
+    }
+    let buttons = others.iter()
+        .map(|session| vec![InlineKeyboardButton::callback(format!("Switch to {}", describe(session)), format!("{}{}", PREFIX, session.id))])
+        .collect::<Vec<_>>();
+    ctx.bot.send_message(chat_id, text).reply_markup(InlineKeyboardMarkup::new(buttons)).await?;
+
+    respond(())
+}
+
+// A switcher button was pressed, the popup tells the result
+pub async fn choose(ctx: &mut BotCtx, query: &CallbackQuery, data: &str) -> ResponseResult<Option<&'static str>>
+{
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some(game_id) = data.strip_prefix(PREFIX).and_then(|game_id| game_id.parse().ok()) else {
+        tracing::warn!("Unexpected switch button from {}: {}", chat_id, data);
+        return Ok(None);
+    };
+    Ok(Some(match activate(ctx, chat_id, game_id) {
+        Ok(_) => "The game is active now",
+        Err(e) => e,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_other_games() {
+        let others = OtherGames::default();
+        others.add(ChatId(1), 10);
+        others.add(ChatId(1), 11);
+        others.add(ChatId(1), 10);
+        others.add(ChatId(2), 11);
+        assert_eq!(others.get(ChatId(1)), vec![10, 11]);
+        assert_eq!(others.members(11).len(), 2);
+        assert_eq!(tag(&others, ChatId(1), 12, "Hi"), "🎲 #12\nHi");
+        assert_eq!(tag(&others, ChatId(3), 12, "Hi"), "Hi");
+
+        assert!(others.remove(ChatId(1), 10));
+        assert!(!others.remove(ChatId(1), 10));
+        others.remove_game(11);
+        assert!(!others.has_any(ChatId(1)) && others.members(11).is_empty());
+        assert_eq!(others.take_first(ChatId(2)), None);
+    }
+}
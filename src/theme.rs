@@ -0,0 +1,245 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{MissionVote, Role, Team};
+
+// Look of the game chosen by the leader. Only the words and icons of the messages change,
+// the commands and the rules stay the same
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    #[default]
+    Classic,
+    SciFi,
+    Pirates,
+    // Plain words of the base Resistance game for the groups who play it without the Avalon story
+    Resistance,
+}
+
+pub const THEMES: [Theme; 4] = [Theme::Classic, Theme::SciFi, Theme::Pirates, Theme::Resistance];
+
+// Words of the theme, the team names are in lower case to be used inside the sentences
+pub struct ThemeTable {
+    pub name: &'static str,
+    // Name of the directory of the theme's assets, e.g. the stickers
+    pub key: &'static str,
+    // Loyalty in "Bob is Bad"
+    pub good: &'static str,
+    pub bad: &'static str,
+    pub good_team: &'static str,
+    pub bad_team: &'static str,
+    pub success: &'static str,
+    pub fail: &'static str,
+    pub crown: &'static str,
+    // The mermaid of the rules is a Lady of the Lake which checks the loyalty of a player
+    pub mermaid: &'static str,
+    pub mermaid_icon: &'static str,
+    // Merlin, Percival, Good, Mordred, Morgen, Oberon, Assassin, Bad
+    roles: [&'static str; 8],
+    pub narration: Narration,
+}
+
+// Story told between the game messages when the leader turns the narration on in /settings
+pub struct Narration {
+    pub intro: &'static str,
+    // Told when the team of the mission is approved, one for each mission
+    pub briefings: [&'static str; 5],
+    // Told right before the cards of the mission are shown
+    pub success: &'static str,
+    pub fail: &'static str,
+}
+
+const CLASSIC: ThemeTable = ThemeTable {
+    name: "classic Avalon",
+    key: "classic",
+    good: "Good",
+    bad: "Bad",
+    good_team: "good team",
+    bad_team: "bad team",
+    success: "🏆",
+    fail: "🗡️",
+    crown: "👑",
+    mermaid: "mermaid",
+    mermaid_icon: "🧜",
+    roles: ["Merlin", "Percival", "Good", "Mordred", "Morgen", "Oberon", "Assassin", "Bad"],
+    narration: Narration {
+        intro: "📜 The knights gather at the Round Table. Arthur's kingdom is strong, but Mordred's servants hide among the loyal ones",
+        briefings: [
+            "📜 Rumours of a traitor reach Camelot. The knights ride out to the village at the edge of the forest",
+            "📜 A messenger must cross the misty marshes with the king's letter",
+            "📜 Bandits have taken the bridge on the road to the north. It must be won back before the winter",
+            "📜 The Grail is said to be hidden in a ruined chapel. Only a true company can find it",
+            "📜 The last battle is near. The fate of Camelot is in the hands of these knights",
+        ],
+        success: "📜 Horns sound from the walls of Camelot as the riders return...",
+        fail: "📜 The riders return in silence, and one of them does not meet the king's eyes...",
+    },
+};
+
+const SCI_FI: ThemeTable = ThemeTable {
+    name: "sci-fi Resistance",
+    key: "sci_fi",
+    good: "Rebel",
+    bad: "Spy",
+    good_team: "the resistance",
+    bad_team: "the spy ring",
+    success: "🛰️",
+    fail: "💥",
+    crown: "🎖️",
+    mermaid: "scanner",
+    mermaid_icon: "📡",
+    roles: ["Commander", "Bodyguard", "Rebel", "Spy Chief", "Double Agent", "Rogue Agent", "Hunter", "Spy"],
+    narration: Narration {
+        intro: "📜 The rebel fleet hides in the shadow of a dead moon. Somewhere in the crew the spy ring is listening",
+        briefings: [
+            "📜 A supply convoy must slip past the blockade of the orbital station",
+            "📜 The team is sent to steal the codes of the planetary shield",
+            "📜 A captured officer knows where the enemy fleet gathers. Get them out before dawn",
+            "📜 The reactor of the enemy flagship must be sabotaged from the inside",
+            "📜 The final strike on the capital begins. There is no way back",
+        ],
+        success: "📜 The comms crackle, and the signal of the team comes through clear...",
+        fail: "📜 The comms go dead. Somebody sent the coordinates to the enemy...",
+    },
+};
+
+const PIRATES: ThemeTable = ThemeTable {
+    name: "pirates",
+    key: "pirates",
+    good: "Loyal",
+    bad: "Mutineer",
+    good_team: "the crew",
+    bad_team: "the mutiny",
+    success: "💰",
+    fail: "☠️",
+    crown: "🏴‍☠️",
+    mermaid: "mermaid",
+    mermaid_icon: "🧜",
+    roles: ["Navigator", "First Mate", "Sailor", "Dread Captain", "Sea Witch", "Stowaway", "Cutthroat", "Mutineer"],
+    narration: Narration {
+        intro: "📜 The ship leaves the harbour with a full hold and a restless crew. Not every sailor is loyal to the captain",
+        briefings: [
+            "📜 A merchant ship is spotted on the horizon. A boarding party is chosen",
+            "📜 The crew lands on a nameless island to search for fresh water and buried gold",
+            "📜 The navy is on the tail of the ship. Someone must lead it through the reefs at night",
+            "📜 The old map points to a cave that floods with the tide",
+            "📜 The treasure is within reach. One last raid decides who sails home rich",
+        ],
+        success: "📜 The boats come back heavy, and the crew cheers on the deck...",
+        fail: "📜 The boats come back half empty, and whispers of mutiny spread below deck...",
+    },
+};
+
+// Names of the special roles are the ones of the Resistance expansions,
+// the mermaid is the Inquisitor who checks loyalty there
+const RESISTANCE: ThemeTable = ThemeTable {
+    name: "The Resistance",
+    key: "resistance",
+    good: "Resistance",
+    bad: "Spy",
+    good_team: "the resistance",
+    bad_team: "the spy team",
+    success: "🔵",
+    fail: "🔴",
+    crown: "⭐",
+    mermaid: "inquisitor",
+    mermaid_icon: "🔍",
+    roles: ["Commander", "Bodyguard", "Resistance", "Deep Cover", "False Commander", "Blind Spy", "Assassin", "Spy"],
+    narration: Narration {
+        intro: "📜 The resistance meets in a cellar of the occupied city. The government has planted its spies among them",
+        briefings: [
+            "📜 Leaflets must be printed and spread across the city before the curfew",
+            "📜 The team is to bug the office of the district governor",
+            "📜 A radio transmitter must be moved to a new safe house under the eyes of the patrols",
+            "📜 The arms depot on the outskirts is lightly guarded tonight",
+            "📜 The uprising begins at dawn. Everything depends on this operation",
+        ],
+        success: "📜 The safe house phone rings twice, the agreed signal...",
+        fail: "📜 Sirens fill the streets. The government knew they were coming...",
+    },
+};
+
+impl Theme {
+    pub fn table(self) -> &'static ThemeTable {
+        match self {
+            Theme::Classic => &CLASSIC,
+            Theme::SciFi => &SCI_FI,
+            Theme::Pirates => &PIRATES,
+            Theme::Resistance => &RESISTANCE,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let pos = THEMES.iter().position(|theme| *theme == self).unwrap_or_default();
+        THEMES[(pos + 1) % THEMES.len()]
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.table().name)
+    }
+}
+
+impl ThemeTable {
+    pub fn role(&self, role: &Role) -> &'static str {
+        let index = match role {
+            Role::Merlin => 0,
+            Role::Percival => 1,
+            Role::Good | Role::Good2 => 2,
+            Role::Mordred => 3,
+            Role::Morgen => 4,
+            Role::Oberon => 5,
+            Role::Assassin => 6,
+            Role::Bad => 7,
+        };
+        self.roles[index]
+    }
+
+    pub fn team(&self, team: &Team) -> &'static str {
+        match team {
+            Team::Good => self.good,
+            Team::Bad => self.bad,
+        }
+    }
+
+    pub fn mission(&self, vote: &MissionVote) -> &'static str {
+        match vote {
+            MissionVote::Success => self.success,
+            MissionVote::Fail => self.fail,
+        }
+    }
+}
+
+// For the team names at the start of the sentence
+pub fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classic_theme_keeps_role_names() {
+        for role in [Role::Merlin, Role::Percival, Role::Morgen, Role::Oberon, Role::Mordred, Role::Assassin, Role::Bad, Role::Good2] {
+            assert_eq!(Theme::Classic.table().role(&role), role.to_string());
+        }
+        assert_eq!(Theme::Pirates.table().role(&Role::Merlin), "Navigator");
+        assert_eq!(capitalize(Theme::SciFi.table().bad_team), "The spy ring");
+        assert_eq!(Theme::Pirates.next().next(), Theme::Classic);
+    }
+
+    #[test]
+    fn test_resistance_has_no_avalon_names() {
+        let table = Theme::Resistance.table();
+        let classic = Theme::Classic.table();
+        for role in [Role::Merlin, Role::Percival, Role::Morgen, Role::Oberon, Role::Mordred, Role::Good, Role::Bad] {
+            assert_ne!(table.role(&role), classic.role(&role));
+        }
+        assert_eq!(table.team(&Team::Bad), "Spy");
+        assert_eq!(capitalize(table.good_team), "The resistance");
+    }
+}
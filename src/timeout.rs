@@ -0,0 +1,147 @@
+use std::fmt;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::ai::Strategy;
+use crate::outbox::Outbox;
+use crate::{game_msg, GameSession};
+
+// A day, longer waits are the same as no timeout
+const MAX_MINUTES: u64 = 24 * 60;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeoutPolicy {
+    Off,
+    // Apply a neutral action: approve the team, succeed the mission, random choice otherwise
+    Auto,
+    // Replace the player with an AI seat for the rest of the game
+    Ai,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeoutSettings {
+    pub policy: TimeoutPolicy,
+    pub duration: Duration,
+}
+
+impl TimeoutSettings {
+    // Parses "<off|auto|ai> [minutes]"
+    pub fn parse(args: &str, current: Self) -> Result<Self, String> {
+        let mut args = args.split_whitespace();
+        let policy = match args.next() {
+            Some("off") => TimeoutPolicy::Off,
+            Some("auto") => TimeoutPolicy::Auto,
+            Some("ai") => TimeoutPolicy::Ai,
+            Some(other) => return Err(format!("Unknown timeout mode '{}'. Use off, auto or ai", other)),
+            None => return Err("Specify timeout mode: off, auto or ai".to_string()),
+        };
+
+        let duration = match args.next() {
+            Some(minutes) => {
+                let minutes = minutes.parse::<u64>()
+                    .ok()
+                    .filter(|minutes| (1..=MAX_MINUTES).contains(minutes))
+                    .ok_or(format!("Invalid number of minutes '{}', use 1 to {}", minutes, MAX_MINUTES))?;
+                Duration::from_secs(minutes * 60)
+            }
+            None => current.duration,
+        };
+
+        Ok(Self { policy, duration })
+    }
+}
+
+impl fmt::Display for TimeoutSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let minutes = self.duration.as_secs() / 60;
+        match self.policy {
+            TimeoutPolicy::Off => write!(f, "no timeout"),
+            TimeoutPolicy::Auto => write!(f, "default action after {} min", minutes),
+            TimeoutPolicy::Ai => write!(f, "replace with AI after {} min", minutes),
+        }
+    }
+}
+
+// Called periodically by the session task
+pub async fn apply_timeout(session: &mut GameSession) {
+    let Some(info) = session.info.clone() else {
+        return;
+    };
+    let settings = session.timeout;
+    if session.finished || settings.policy == TimeoutPolicy::Off || session.waiting_since.elapsed() < settings.duration {
+        return;
+    }
+    session.waiting_since = Instant::now();
+
+    let cli = info.cli.clone();
+    let phase = cli.get_phase().await;
+    let waiting = cli.get_waiting_for().await.into_iter()
+        .filter(|id| !session.ai_seats.contains(id))
+        .collect::<Vec<_>>();
+    if waiting.is_empty() {
+        return;
+    }
+
+    tracing::info!("Timeout for players {:?} in phase {:?}", waiting, phase);
+    let replace = settings.policy == TimeoutPolicy::Ai;
+    let messages = game_msg::build_timeout_messages(&info, &waiting, replace);
+    if let Err(e) = crate::send_game_messages(&session.bot, &info, messages).await {
+        tracing::warn!("Timeout notification error: {}", e);
+    }
+
+    let mut outbox = Outbox::default();
+    for id in &waiting {
+        crate::close_control_message(session, &mut outbox, info.players[*id as usize], "⏰ Time is up");
+    }
+
+    let strategy = if replace {
+        session.ai_seats.extend(waiting.iter().cloned());
+        Strategy::Ai
+    } else {
+        Strategy::Default
+    };
+
+    for id in waiting {
+        match session.play_for(id, phase, strategy).await {
+            Ok(()) => {
+                session.voted.insert(info.players[id as usize]);
+            }
+            Err(e) => tracing::warn!("Failed to act for {} on timeout: {}", id, e),
+        }
+    }
+    crate::update_tracker(session, &mut outbox).await;
+    outbox.flush(&session.bot).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: TimeoutSettings = TimeoutSettings {
+        policy: TimeoutPolicy::Auto,
+        duration: Duration::from_secs(600),
+    };
+
+    #[test]
+    fn test_parse_timeout_settings() {
+        assert_eq!(TimeoutSettings::parse("off", DEFAULT).unwrap().policy, TimeoutPolicy::Off);
+        assert_eq!(TimeoutSettings::parse("ai", DEFAULT).unwrap(), TimeoutSettings {
+            policy: TimeoutPolicy::Ai,
+            duration: Duration::from_secs(600),
+        });
+        assert_eq!(TimeoutSettings::parse("auto 3", DEFAULT).unwrap(), TimeoutSettings {
+            policy: TimeoutPolicy::Auto,
+            duration: Duration::from_secs(180),
+        });
+    }
+
+    #[test]
+    fn test_parse_invalid_timeout_settings() {
+        assert!(TimeoutSettings::parse("", DEFAULT).is_err());
+        assert!(TimeoutSettings::parse("kick", DEFAULT).is_err());
+        assert!(TimeoutSettings::parse("auto 0", DEFAULT).is_err());
+        assert!(TimeoutSettings::parse("auto x", DEFAULT).is_err());
+        assert!(TimeoutSettings::parse("auto 999999999999999999", DEFAULT).is_err());
+    }
+}
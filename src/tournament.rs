@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use crate::game::{GameResult, Role};
+use crate::game_msg;
+use crate::session::SessionCommand;
+use crate::BotCtx;
+
+// Every member of the winning team gets these
+pub const WIN_POINTS: u32 = 2;
+// Merlin who was not guessed at the end of the game gets these in addition to the win
+pub const MERLIN_POINTS: u32 = 1;
+const MAX_GAMES: usize = 10;
+
+// Series of games of the same lobby, the leader starts each of them with /restart
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tournament {
+    games: usize,
+    played: usize,
+    points: HashMap<ChatId, u32>,
+    // Names of the last game the player was in
+    names: HashMap<ChatId, Arc<str>>,
+}
+
+impl Tournament {
+    pub fn new(games: usize) -> Self {
+        Self { games, played: 0, points: HashMap::new(), names: HashMap::new() }
+    }
+
+    // The good team wins only when Merlin survives the guess, so their win also means Merlin survived
+    pub fn add_game(&mut self, players: &[ChatId], roles: &[Role], result: &GameResult, names: &HashMap<ChatId, Arc<str>>) {
+        self.played += 1;
+        for (chat_id, role) in players.iter().zip(roles) {
+            let points = self.points.entry(*chat_id).or_default();
+            let won = role.is_good() == (*result == GameResult::GoodWins);
+            if won {
+                *points += WIN_POINTS;
+                if *role == Role::Merlin {
+                    *points += MERLIN_POINTS;
+                }
+            }
+            if let Some(name) = names.get(chat_id) {
+                self.names.insert(*chat_id, name.clone());
+            }
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.played >= self.games
+    }
+
+    // Players with the same points share the place. The text is sent as HTML
+    pub fn standings(&self) -> String {
+        let mut table = self.points.iter()
+            .map(|(chat_id, points)| {
+                let name = self.names.get(chat_id).map_or_else(|| chat_id.to_string(), |name| name.to_string());
+                (name, *points)
+            })
+            .collect::<Vec<_>>();
+        table.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+
+        let title = match self.is_over() {
+            true => format!("🏆 The tournament is over after {} games. Final standings:", self.games),
+            false => format!("🏆 Standings after game {} of {}:", self.played, self.games),
+        };
+        let mut lines = vec![title];
+        let mut place = 0;
+        for (index, (name, points)) in table.iter().enumerate() {
+            if index == 0 || table[index - 1].1 != *points {
+                place = index + 1;
+            }
+            lines.push(format!("{}. {} — {}", place, game_msg::escape(name), points));
+        }
+        if !self.is_over() {
+            lines.push("The leader starts the next game with /restart".to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+// Number of games of /tournament, None stops the tournament
+fn parse_games(args: &str) -> Result<Option<usize>, String> {
+    let usage = format!("Use /tournament <games> with 2 to {} games, or /tournament off", MAX_GAMES);
+    match args.trim() {
+        "off" => Ok(None),
+        args => match args.parse::<usize>() {
+            Ok(games) if (2..=MAX_GAMES).contains(&games) => Ok(Some(games)),
+            _ => Err(usage),
+        },
+    }
+}
+
+// The leader turns the next games of the lobby into a tournament, the points start from zero
+pub async fn handle(ctx: &mut BotCtx, message: &Message, args: &str) -> ResponseResult<()>
+{
+    let chat_id = message.chat.id;
+    let Some((session, _)) = crate::lobby_settings(ctx, chat_id) else {
+        ctx.bot.send_message(chat_id, "Only game leader can start a tournament before the game").await?;
+        return respond(());
+    };
+
+    let reply = match parse_games(args) {
+        Ok(Some(games)) => {
+            session.send(SessionCommand::SetTournament(Some(Tournament::new(games))));
+            format!("The next {} games are a tournament. Winners get {} points, Merlin who survives gets {} more. \
+                     Start the first game with /start_game", games, WIN_POINTS, MERLIN_POINTS)
+        }
+        Ok(None) => {
+            session.send(SessionCommand::SetTournament(None));
+            "The tournament is stopped".to_string()
+        }
+        Err(e) => e,
+    };
+    ctx.bot.send_message(chat_id, reply).await?;
+    respond(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(players: &[ChatId]) -> HashMap<ChatId, Arc<str>> {
+        players.iter().map(|chat_id| (*chat_id, Arc::from(format!("P{}", chat_id.0)))).collect()
+    }
+
+    #[test]
+    fn test_points_and_standings() {
+        let players = [ChatId(1), ChatId(2), ChatId(3)];
+        let mut tournament = Tournament::new(2);
+        tournament.add_game(&players, &[Role::Merlin, Role::Good, Role::Assassin], &GameResult::GoodWins, &names(&players));
+        assert!(!tournament.is_over());
+        assert_eq!(tournament.standings(), "🏆 Standings after game 1 of 2:\n1. P1 — 3\n2. P2 — 2\n3. P3 — 0\n\
+                                            The leader starts the next game with /restart");
+
+        tournament.add_game(&players, &[Role::Assassin, Role::Good, Role::Merlin], &GameResult::GoodWins, &names(&players));
+        assert!(tournament.is_over());
+        assert_eq!(tournament.standings(), "🏆 The tournament is over after 2 games. Final standings:\n\
+                                            1. P2 — 4\n2. P1 — 3\n2. P3 — 3");
+    }
+
+    #[test]
+    fn test_standings_escape_names() {
+        let players = [ChatId(1)];
+        let mut tournament = Tournament::new(1);
+        let names = HashMap::from([(ChatId(1), Arc::from("<Bob> & Co"))]);
+        tournament.add_game(&players, &[Role::Merlin], &GameResult::GoodWins, &names);
+        assert!(tournament.standings().ends_with("1. &lt;Bob&gt; &amp; Co — 3"));
+    }
+
+    #[test]
+    fn test_parse_games() {
+        assert_eq!(parse_games(" 3 "), Ok(Some(3)));
+        assert_eq!(parse_games("off"), Ok(None));
+        assert!(parse_games("1").is_err());
+        assert!(parse_games("").is_err());
+    }
+}
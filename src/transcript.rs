@@ -0,0 +1,88 @@
+use serde::Serialize;
+
+use crate::game::{GameResult, History, Role};
+use crate::theme::capitalize;
+use crate::{game_msg, GameInfo};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TranscriptFormat {
+    Text,
+    Json,
+}
+
+impl TranscriptFormat {
+    // Parses "[text|json]"
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        match arg.trim() {
+            "" | "text" => Ok(TranscriptFormat::Text),
+            "json" => Ok(TranscriptFormat::Json),
+            other => Err(format!("Unknown transcript format '{}'. Use text or json", other)),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Text => "txt",
+            TranscriptFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PlayerRecord<'a> {
+    id: usize,
+    name: &'a str,
+    role: &'a Role,
+}
+
+#[derive(Serialize)]
+struct Transcript<'a> {
+    game_id: u32,
+    players: Vec<PlayerRecord<'a>>,
+    history: &'a History,
+}
+
+// Returns the file name and the content of the document
+pub fn build(game_id: u32, info: &GameInfo, roles: &[Role], history: &History, format: TranscriptFormat)
+    -> Result<(String, String), serde_json::Error>
+{
+    let file_name = format!("avalon_game_{}.{}", game_id, format.extension());
+    let content = match format {
+        TranscriptFormat::Text => {
+            let theme = info.theme.table();
+            let result = match history.result {
+                Some(GameResult::GoodWins) => format!("{} won", capitalize(theme.good_team)),
+                Some(GameResult::BadWins) => format!("{} won", capitalize(theme.bad_team)),
+                None => "Game is not finished".to_string(),
+            };
+            format!("Avalon game #{}\n{}\n\n{}\n",
+                    game_id, result, game_msg::to_plain(&game_msg::build_summary(info, roles, history)))
+        }
+        TranscriptFormat::Json => {
+            let players = info.players.iter()
+                .zip(roles)
+                .enumerate()
+                .map(|(id, (chat_id, role))| PlayerRecord {
+                    id,
+                    name: info.user_names.get(chat_id).map(|name| name.as_ref()).unwrap_or_default(),
+                    role,
+                })
+                .collect();
+            serde_json::to_string_pretty(&Transcript { game_id, players, history })?
+        }
+    };
+
+    Ok((file_name, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_transcript_format() {
+        assert_eq!(TranscriptFormat::parse(""), Ok(TranscriptFormat::Text));
+        assert_eq!(TranscriptFormat::parse(" json "), Ok(TranscriptFormat::Json));
+        assert!(TranscriptFormat::parse("pdf").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::types::ChatAction;
+use tokio::time::Instant;
+
+use crate::game::Phase;
+use crate::GameSession;
+
+// Telegram shows the action for five seconds, so it is sent again a bit earlier
+pub const INTERVAL: Duration = Duration::from_secs(4);
+// Players who act right away don't see it
+const DELAY: Duration = Duration::from_secs(10);
+
+// Players the game waits for in the phase and since when
+#[derive(Default)]
+pub struct Pending {
+    phase: Phase,
+    since: HashMap<ChatId, Instant>,
+}
+
+impl Pending {
+    // Forgets the players who acted and returns the ones who wait for longer than DELAY.
+    // The crown who suggested the team is waited for again in the vote, it starts from scratch
+    fn update(&mut self, phase: Phase, waiting: &[ChatId], now: Instant) -> Vec<ChatId> {
+        if phase != self.phase {
+            self.phase = phase;
+            self.since.clear();
+        }
+        self.since.retain(|chat_id, _| waiting.contains(chat_id));
+        waiting.iter()
+            .filter(|chat_id| now - *self.since.entry(**chat_id).or_insert(now) >= DELAY)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.since.clear();
+    }
+}
+
+// Called by the session task every INTERVAL. The bot seems to be "typing" in the chat of the
+// player the game waits for, which reminds of the move without a message
+pub async fn show_to_pending(session: &mut GameSession) {
+    let info = session.info.clone()
+        .filter(|_| session.nudge.typing && !session.finished && !session.stalled && session.discussion.is_none());
+    let Some(info) = info else {
+        session.typing.clear();
+        return;
+    };
+
+    let phase = info.cli.get_phase().await;
+    let waiting = info.cli.get_waiting_for().await.into_iter()
+        .filter(|seat| !session.ai_seats.contains(seat))
+        .filter_map(|seat| info.players.get(seat as usize).cloned())
+        .filter(|chat_id| !info.bots.contains(chat_id))
+        .collect::<Vec<_>>();
+    for chat_id in session.typing.update(phase, &waiting, Instant::now()) {
+        if let Err(e) = session.bot.send_chat_action(chat_id, ChatAction::Typing).await {
+            tracing::debug!("Failed to show typing to {}: {}", chat_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_typing_is_shown_after_delay_until_the_move() {
+        let mut pending = Pending::default();
+        let (alice, bob) = (ChatId(1), ChatId(2));
+        let vote = Phase::TeamVote;
+        assert!(pending.update(vote, &[alice, bob], Instant::now()).is_empty());
+
+        tokio::time::advance(DELAY).await;
+        assert_eq!(pending.update(vote, &[alice, bob], Instant::now()), vec![alice, bob]);
+        // Alice voted, so the next vote waits for Alice from the start again
+        assert_eq!(pending.update(vote, &[bob], Instant::now()), vec![bob]);
+        assert_eq!(pending.update(vote, &[alice, bob], Instant::now()), vec![bob]);
+        // Bob goes on the mission right after the vote
+        assert!(pending.update(Phase::Mission, &[bob], Instant::now()).is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::ai::Strategy;
+use crate::outbox::Outbox;
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameSession};
+
+// Callback data of the leader's buttons starts with it, e.g. "unreachable ai 42"
+pub const PREFIX: &str = "unreachable ";
+
+// What the leader does with the player who can't get the messages of the bot
+#[derive(Clone, Debug, PartialEq)]
+enum Choice {
+    Ai(ChatId),
+    Abort,
+}
+
+impl Choice {
+    fn data(&self) -> String {
+        match self {
+            Choice::Ai(chat_id) => format!("{}ai {}", PREFIX, chat_id),
+            Choice::Abort => format!("{}abort", PREFIX),
+        }
+    }
+
+    fn parse(data: &str) -> Option<Self> {
+        match data.strip_prefix(PREFIX)?.split_once(' ') {
+            Some(("ai", chat_id)) => chat_id.parse().ok().map(|id| Choice::Ai(ChatId(id))),
+            None if data == Choice::Abort.data() => Some(Choice::Abort),
+            _ => None,
+        }
+    }
+}
+
+// Blocked bot and deleted chat are told apart from the network problems which may pass
+pub fn notice(name: &str, permanent: bool) -> String {
+    if permanent {
+        format!("⚠️ {} has blocked the bot or deleted the chat with it, so they don't get the game messages", name)
+    } else {
+        format!("⚠️ {} does not receive messages from the bot", name)
+    }
+}
+
+pub fn keyboard(chat_id: ChatId) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("🤖 Replace with AI", Choice::Ai(chat_id).data()),
+        InlineKeyboardButton::callback("🛑 Abort the game", Choice::Abort.data()),
+    ]])
+}
+
+// Button under the leader's notice was pressed. Returns the text of the popup
+pub async fn choose(ctx: &mut BotCtx, query: &CallbackQuery, data: &str) -> ResponseResult<Option<&'static str>>
+{
+    let chat_id = ChatId(query.from.id.0 as i64);
+    let Some(choice) = Choice::parse(data) else {
+        tracing::warn!("Unexpected unreachable player button from {}: {}", chat_id, data);
+        return Ok(None);
+    };
+    let session = ctx.user_games.get(&chat_id).and_then(|game_id| ctx.game_sessions.get(game_id)).cloned();
+    let Some(session) = session.filter(|session| session.leader == chat_id) else {
+        return Ok(Some("Only game leader can decide"));
+    };
+    let status = session.status();
+    if !status.started || status.finished {
+        return Ok(Some("The game is over"));
+    }
+
+    let text = match choice {
+        Choice::Ai(player) if status.players.contains(&player) => {
+            session.send(SessionCommand::ReplaceWithAi(player));
+            format!("🤖 AI plays for {} from now on", crate::get_display_name(ctx, player))
+        }
+        Choice::Ai(_) => return Ok(Some("The player is not in this game")),
+        Choice::Abort => {
+            crate::stop_game(ctx, session.id, "The game was aborted by the leader").await;
+            "🛑 The game is aborted".to_string()
+        }
+    };
+    if let Some(message) = &query.message {
+        ctx.bot.edit_message_text(message.chat.id, message.id, text).await?;
+    }
+    Ok(None)
+}
+
+// Called by the session task. The AI also makes the move the game is waiting for right now
+pub async fn replace_with_ai(session: &mut GameSession, chat_id: ChatId) {
+    let Some(info) = session.info.clone().filter(|_| !session.finished) else {
+        return;
+    };
+    let Some(seat) = info.players.iter().position(|player| *player == chat_id) else {
+        return;
+    };
+    let seat = seat as crate::game::ID;
+    if session.ai_seats.contains(&seat) {
+        return;
+    }
+    session.ai_seats.push(seat);
+
+    let mut outbox = Outbox::default();
+    let name = info.user_names.get(&chat_id).cloned().unwrap_or_else(|| chat_id.to_string().into());
+    for player in info.players.iter().filter(|player| **player != chat_id) {
+        outbox.send(*player, format!("🤖 AI plays for {} from now on", name));
+    }
+
+    let phase = info.cli.get_phase().await;
+    if info.cli.get_waiting_for().await.contains(&seat) && !session.voted.contains(&chat_id) {
+        match session.play_for(seat, phase, Strategy::Ai).await {
+            Ok(()) => {
+                session.voted.insert(chat_id);
+            }
+            Err(e) => tracing::warn!("AI failed to act for {}: {}", chat_id, e),
+        }
+        crate::update_tracker(session, &mut outbox).await;
+    }
+    outbox.flush(&session.bot).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leader_buttons_are_parsed() {
+        for choice in [Choice::Ai(ChatId(42)), Choice::Ai(ChatId(-7)), Choice::Abort] {
+            assert_eq!(Choice::parse(&choice.data()), Some(choice));
+        }
+        assert_eq!(Choice::parse("unreachable ai bob"), None);
+        assert_eq!(Choice::parse("unreachable abort now"), None);
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, Message, User};
+
+const MAX_NICKNAME_LEN: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub first_name: String,
+    pub username: Option<String>,
+    pub nickname: Option<String>,
+    // Lobby news and flavor text are not sent, the messages which ask to act always are
+    #[serde(default)]
+    pub muted: bool,
+}
+
+impl UserProfile {
+    pub fn from_user(user: &User) -> Self {
+        Self {
+            first_name: user.first_name.clone(),
+            username: user.username.clone(),
+            nickname: None,
+            muted: false,
+        }
+    }
+
+    pub fn from_message(message: &Message) -> Self {
+        match message.from() {
+            Some(user) => Self::from_user(user),
+            None => Self {
+                first_name: message.chat.id.to_string(),
+                username: None,
+                nickname: None,
+                muted: false,
+            },
+        }
+    }
+
+    // Telegram data is refreshed on every join, but the nickname chosen by the user is kept
+    pub fn update(&mut self, fresh: UserProfile) {
+        self.first_name = fresh.first_name;
+        self.username = fresh.username;
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.nickname.as_deref().unwrap_or(&self.first_name)
+    }
+}
+
+// Chats of the users who muted the notifications. Shared by the handlers and the game sessions,
+// so /settings applies to the running games at once
+#[derive(Clone, Default)]
+pub struct MutedChats(Arc<Mutex<HashSet<ChatId>>>);
+
+impl MutedChats {
+    pub fn from_users(users: &HashMap<ChatId, UserProfile>) -> Self {
+        let muted = users.iter()
+            .filter(|(_, user)| user.muted)
+            .map(|(chat_id, _)| *chat_id)
+            .collect();
+        Self(Arc::new(Mutex::new(muted)))
+    }
+
+    pub fn set(&self, chat_id: ChatId, muted: bool) {
+        let mut chats = self.0.lock().unwrap();
+        if muted {
+            chats.insert(chat_id);
+        } else {
+            chats.remove(&chat_id);
+        }
+    }
+
+    pub fn contains(&self, chat_id: ChatId) -> bool {
+        self.0.lock().unwrap().contains(&chat_id)
+    }
+}
+
+pub fn validate_nickname(nickname: &str) -> Result<Option<String>, String> {
+    let nickname = nickname.trim();
+    if nickname.is_empty() {
+        return Ok(None);
+    }
+
+    if nickname.chars().count() > MAX_NICKNAME_LEN {
+        return Err(format!("Nickname should be at most {} characters long", MAX_NICKNAME_LEN));
+    }
+
+    Ok(Some(nickname.to_string()))
+}
+
+// Builds names for the game players, so nobody in the game has the same name
+pub fn disambiguate(players: &[ChatId], users: &HashMap<ChatId, UserProfile>) -> HashMap<ChatId, Arc<str>> {
+    let base_name = |player: &ChatId| {
+        users.get(player)
+            .map(|user| user.display_name().to_string())
+            .unwrap_or_else(|| player.to_string())
+    };
+
+    let mut names = HashMap::new();
+    let mut taken = Vec::new();
+    for player in players {
+        let name = base_name(player);
+        let has_duplicates = players.iter()
+            .filter(|other| base_name(other) == name)
+            .count() > 1;
+
+        let mut unique_name = match users.get(player).and_then(|user| user.username.as_ref()) {
+            Some(username) if has_duplicates => format!("{} (@{})", name, username),
+            _ => name.clone(),
+        };
+
+        let mut suffix = 2;
+        while taken.contains(&unique_name) {
+            unique_name = format!("{} #{}", name, suffix);
+            suffix += 1;
+        }
+
+        taken.push(unique_name.clone());
+        names.insert(*player, Arc::from(unique_name));
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(first_name: &str, username: Option<&str>, nickname: Option<&str>) -> UserProfile {
+        UserProfile {
+            first_name: first_name.to_string(),
+            username: username.map(str::to_string),
+            nickname: nickname.map(str::to_string),
+            muted: false,
+        }
+    }
+
+    #[test]
+    fn test_unique_names_are_not_changed() {
+        let users = HashMap::from([
+            (ChatId(1), user("Alex", Some("alex"), None)),
+            (ChatId(2), user("Bob", None, None)),
+        ]);
+        let names = disambiguate(&[ChatId(1), ChatId(2)], &users);
+        assert_eq!(&*names[&ChatId(1)], "Alex");
+        assert_eq!(&*names[&ChatId(2)], "Bob");
+    }
+
+    #[test]
+    fn test_duplicate_names_are_disambiguated() {
+        let users = HashMap::from([
+            (ChatId(1), user("Alex", Some("alex_k"), None)),
+            (ChatId(2), user("Alex", Some("alex_m"), None)),
+            (ChatId(3), user("Alex", None, None)),
+            (ChatId(4), user("Alex", None, None)),
+        ]);
+        let names = disambiguate(&[ChatId(1), ChatId(2), ChatId(3), ChatId(4)], &users);
+        assert_eq!(&*names[&ChatId(1)], "Alex (@alex_k)");
+        assert_eq!(&*names[&ChatId(2)], "Alex (@alex_m)");
+        assert_eq!(&*names[&ChatId(3)], "Alex");
+        assert_eq!(&*names[&ChatId(4)], "Alex #2");
+    }
+
+    #[test]
+    fn test_nickname_is_used() {
+        let users = HashMap::from([
+            (ChatId(1), user("Alex", None, Some("Merlin fan"))),
+            (ChatId(2), user("Alex", None, None)),
+        ]);
+        let names = disambiguate(&[ChatId(1), ChatId(2)], &users);
+        assert_eq!(&*names[&ChatId(1)], "Merlin fan");
+        assert_eq!(&*names[&ChatId(2)], "Alex");
+    }
+
+    #[test]
+    fn test_muted_chats() {
+        let mut muted = user("Alex", None, None);
+        muted.muted = true;
+        let users = HashMap::from([(ChatId(1), muted), (ChatId(2), user("Bob", None, None))]);
+        let chats = MutedChats::from_users(&users);
+        assert!(chats.contains(ChatId(1)));
+        assert!(!chats.contains(ChatId(2)));
+
+        chats.clone().set(ChatId(2), true);
+        chats.set(ChatId(1), false);
+        assert!(!chats.contains(ChatId(1)));
+        assert!(chats.contains(ChatId(2)));
+    }
+
+    #[test]
+    fn test_nickname_validation() {
+        assert_eq!(validate_nickname("  "), Ok(None));
+        assert_eq!(validate_nickname(" Lancelot "), Ok(Some("Lancelot".to_string())));
+        assert!(validate_nickname(&"x".repeat(33)).is_err());
+    }
+}
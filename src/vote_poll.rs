@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use teloxide::prelude::*;
+use teloxide::types::{MessageId, PollAnswer};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::commands::GameAction;
+use crate::game::{self, TeamVote};
+use crate::session::SessionCommand;
+use crate::{BotCtx, GameInfo, GameSession};
+
+// Option of the poll is the index of the vote here
+const OPTIONS: [TeamVote; 2] = [TeamVote::Approve, TeamVote::Reject];
+
+// Games of the open polls by the poll id. Shared with the game sessions, which open and close the polls,
+// so the answers which come to the bot are passed to the right game. Not stored, the buttons still work after a restart
+#[derive(Clone, Default)]
+pub struct VotePolls(Arc<Mutex<HashMap<String, u32>>>);
+
+impl VotePolls {
+    fn game(&self, poll_id: &str) -> Option<u32> {
+        self.0.lock().unwrap().get(poll_id).cloned()
+    }
+}
+
+// Team vote of the game in the topic of the group, the players still have the buttons in private
+pub struct VotePoll {
+    id: String,
+    chat_id: ChatId,
+    msg_id: MessageId,
+}
+
+// The poll can't be anonymous: anonymous polls don't send PollAnswer updates,
+// so the answers couldn't be passed to add_team_vote for the right player
+pub async fn open(session: &mut GameSession, info: &GameInfo, team: &[game::ID]) {
+    let Some(topic) = info.topic else {
+        return;
+    };
+    let names = team.iter().map(|id| crate::player_name(info, *id)).collect::<Vec<_>>();
+    let question = format!("Vote for the team: {}", names.join(", "));
+    let sent = session.bot.send_poll(topic.chat_id, question, OPTIONS.iter().map(TeamVote::to_string))
+        .is_anonymous(false)
+        .message_thread_id(topic.thread_id)
+        .await;
+    match sent {
+        Ok(message) => match message.poll() {
+            Some(poll) => {
+                session.polls.0.lock().unwrap().insert(poll.id.clone(), session.id);
+                session.poll = Some(VotePoll { id: poll.id.clone(), chat_id: topic.chat_id, msg_id: message.id });
+            }
+            None => tracing::warn!("The vote of game {} was sent without a poll", session.id),
+        },
+        Err(e) => tracing::warn!("Failed to send the vote poll of game {}: {}", session.id, e),
+    }
+}
+
+// Called on the next event of the game: everybody voted or the timeout voted for them
+pub async fn close(session: &mut GameSession) {
+    let Some(poll) = session.poll.take() else {
+        return;
+    };
+    session.polls.0.lock().unwrap().remove(&poll.id);
+    if let Err(e) = session.bot.stop_poll(poll.chat_id, poll.msg_id).await {
+        tracing::warn!("Failed to close the vote poll of game {}: {}", session.id, e);
+    }
+}
+
+// Answers come from the group, but the player is the same user as the private chat of the seat
+pub async fn handle_answer(answer: PollAnswer, ctx: Arc<AsyncMutex<BotCtx>>) -> ResponseResult<()>
+{
+    let ctx = &mut *ctx.lock().await;
+    let chat_id = ChatId(answer.user.id.0 as i64);
+    let Some(game_id) = ctx.polls.game(&answer.poll_id) else {
+        tracing::warn!("Unexpected answer to poll {} from {}", answer.poll_id, chat_id);
+        return respond(());
+    };
+    // The retracted vote is still counted by the game
+    let Some(vote) = answer.option_ids.first().and_then(|option| OPTIONS.get(*option as usize)) else {
+        return respond(());
+    };
+    let Some(session) = ctx.game_sessions.get(&game_id) else {
+        return respond(());
+    };
+    if session.status().ai_players.contains(&chat_id) {
+        ctx.bot.send_message(chat_id, "You were replaced by AI because you did not act in time").await?;
+        return respond(());
+    }
+
+    session.send(SessionCommand::PollVote { chat_id, poll_id: answer.poll_id, vote: vote.clone() });
+    respond(())
+}
+
+// Called by the session task. The answer of the poll can't be tapped twice, so Reject is not confirmed on the last try
+pub async fn vote(session: &mut GameSession, chat_id: ChatId, poll_id: &str, vote: TeamVote) {
+    // The answer to the poll of the previous team could still be on its way
+    if session.poll.as_ref().map(|poll| poll.id.as_str()) != Some(poll_id) {
+        return;
+    }
+    if vote == TeamVote::Reject {
+        session.rejecting.insert(chat_id);
+    }
+    let already_voted = session.voted.contains(&chat_id);
+    let action = GameAction::TeamVote(vote);
+    if let Err(e) = crate::handle_game_action(session, chat_id, action.clone()).await {
+        tracing::warn!("Failed to handle {:?} from {}: {}", action, chat_id, e);
+    }
+    // The poll still shows the answer, so the player is told it is not their vote.
+    // The poll is closed by the vote which finishes the voting
+    let is_open = session.poll.as_ref().is_some_and(|poll| poll.id == poll_id);
+    if already_voted || (is_open && !session.voted.contains(&chat_id)) {
+        let text = "⚠️ Your answer to the poll is not counted, the vote in this chat is the one that counts";
+        if let Err(e) = session.bot.send_message(chat_id, text).await {
+            tracing::warn!("Failed to send the ignored poll answer to {}: {}", chat_id, e);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use reqwest::Url;
+use serde::Deserialize;
+use teloxide::types::{ButtonRequest, KeyboardButton, KeyboardMarkup, ReplyMarkup, WebAppInfo};
+
+use crate::commands::{GameAction, ParseError};
+
+// Telegram Mini App for the team selection, see assets/webapp/team.html.
+// The page gets the team size and the player names in the query and sends back
+// the chosen seats as web_app_data, e.g. {"team":[0,3]}
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebAppConfig {
+    // Public HTTPS URL the page is hosted at
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+struct TeamSelection {
+    team: Vec<u8>,
+}
+
+impl WebAppConfig {
+    pub fn team_url(&self, names: &[String], team_size: usize) -> Result<Url, String> {
+        let players = serde_json::to_string(names).map_err(|e| e.to_string())?;
+        Url::parse_with_params(&self.url, &[("size", team_size.to_string()), ("players", players)])
+            .map_err(|e| format!("Invalid web app url {}: {}", self.url, e))
+    }
+
+    // Button under the input field of the crown holder. It is removed when the team is suggested
+    pub fn team_keyboard(&self, names: &[String], team_size: usize) -> Result<ReplyMarkup, String> {
+        let button = KeyboardButton::new("👥 Choose the team")
+            .request(ButtonRequest::WebApp(WebAppInfo { url: self.team_url(names, team_size)? }));
+        Ok(ReplyMarkup::Keyboard(KeyboardMarkup::new([[button]])
+            .resize_keyboard(true)
+            .one_time_keyboard(true)))
+    }
+}
+
+pub fn parse_selection(data: &str) -> Result<GameAction, ParseError> {
+    serde_json::from_str::<TeamSelection>(data)
+        .map(|selection| GameAction::SuggestTeam(selection.team))
+        .map_err(|_| ParseError::InvalidArgument {
+            command: "team selection",
+            argument: data.to_string(),
+            expected: "the list of players",
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_team_selection() {
+        let config = WebAppConfig { url: "https://example.com/avalon/team.html".to_string() };
+        let url = config.team_url(&["Alice".to_string(), "Bob & Co".to_string()], 2).unwrap();
+        let query = url.query_pairs().collect::<Vec<_>>();
+        assert_eq!(query[0].1, "2");
+        assert_eq!(query[1].1, r#"["Alice","Bob & Co"]"#);
+
+        assert_eq!(parse_selection(r#"{"team":[0,3]}"#), Ok(GameAction::SuggestTeam(vec![0, 3])));
+        assert!(parse_selection("[0, 3]").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use teloxide::prelude::*;
+
+use crate::game::{Phase, Role, ID};
+use crate::game_msg::awaited_action;
+use crate::outbox::Outbox;
+use crate::theme::ThemeTable;
+use crate::{media, GameSession};
+
+// Seats the role sees at the start of the game by the rules described on the role card
+fn known_seats(roles: &[Role], me: usize) -> Vec<usize> {
+    let sees = |other: &Role| match roles[me] {
+        Role::Merlin => !other.is_good() && *other != Role::Mordred,
+        Role::Percival => matches!(other, Role::Merlin | Role::Morgen),
+        Role::Oberon => false,
+        ref role if !role.is_good() => !other.is_good() && *other != Role::Oberon,
+        _ => false,
+    };
+    (0..roles.len())
+        .filter(|seat| *seat != me && sees(&roles[*seat]))
+        .collect()
+}
+
+fn knowledge(theme: &ThemeTable, role: &Role, names: &[Arc<str>]) -> Option<String> {
+    if names.is_empty() {
+        return None;
+    }
+    let names = names.join(", ");
+    match role {
+        Role::Merlin => Some(format!("You know in {}: {}", theme.bad_team, names)),
+        Role::Percival => Some(format!("{} or {}: {}", theme.role(&Role::Merlin), theme.role(&Role::Morgen), names)),
+        _ => Some(format!("With you in {}: {}", theme.bad_team, names)),
+    }
+}
+
+// Private reminder of the player's seat, role and what the game needs from them,
+// sent by the session task which owns the game
+pub async fn send(session: &GameSession, chat_id: ChatId) {
+    let mut outbox = Outbox::default();
+    let Some(info) = session.info.as_ref() else {
+        outbox.send(chat_id, "The game is not started yet");
+        outbox.flush(&session.bot).await;
+        return;
+    };
+    let Some(seat) = info.players.iter().position(|player| *player == chat_id) else {
+        outbox.send(chat_id, "You are not a player of this game");
+        outbox.flush(&session.bot).await;
+        return;
+    };
+
+    let theme = info.theme.table();
+    let cli = &info.cli;
+    let roles = cli.get_player_roles().await;
+    let role = &roles[seat];
+    let name = |seat: usize| info.user_names.get(&info.players[seat]).cloned().unwrap_or_default();
+    let known = known_seats(&roles, seat).into_iter().map(name).collect::<Vec<_>>();
+
+    let mut lines = vec![
+        format!("You are {}, seat {} of {}", name(seat), seat + 1, info.players.len()),
+        media::role_caption(role, info.theme),
+    ];
+    lines.extend(knowledge(theme, role, &known));
+
+    if cli.get_crown_id().await as usize == seat {
+        lines.push(format!("{} You hold the crown", theme.crown));
+    }
+    if cli.get_options().await.has_mermaid(info.players.len()) && cli.get_mermaid_id().await as usize == seat {
+        lines.push(format!("{} You hold the {}", theme.mermaid_icon, theme.mermaid));
+    }
+
+    let phase = cli.get_phase().await;
+    let waiting = cli.get_waiting_for().await.contains(&(seat as ID)) && !session.voted.contains(&chat_id);
+    let now = match phase {
+        Phase::Finished => "The game is over".to_string(),
+        Phase::TeamVote if waiting && session.discussion.is_some() => "The team vote opens after the discussion".to_string(),
+        _ if waiting => format!("The game is waiting for {}", awaited_action(theme, phase)),
+        _ => "The game is not waiting for you now".to_string(),
+    };
+    lines.push(now);
+
+    outbox.send(chat_id, lines.join("\n"));
+    outbox.flush(&session.bot).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_see_by_the_rules() {
+        let roles = [Role::Merlin, Role::Percival, Role::Good, Role::Morgen, Role::Mordred, Role::Oberon, Role::Assassin];
+        assert_eq!(known_seats(&roles, 0), vec![3, 5, 6]);
+        assert_eq!(known_seats(&roles, 1), vec![0, 3]);
+        assert_eq!(known_seats(&roles, 2), Vec::<usize>::new());
+        assert_eq!(known_seats(&roles, 4), vec![3, 6]);
+        assert_eq!(known_seats(&roles, 5), Vec::<usize>::new());
+    }
+}